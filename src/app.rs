@@ -3,26 +3,50 @@
 
 // Standard library imports
 use std::borrow::Cow;
-use std::path::{Component::CurDir, PathBuf};
+use std::collections::BTreeSet;
+use std::fs::create_dir_all;
+use std::path::{Component::CurDir, Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 // 3rd-party crate imports
-use anyhow::Result;
+use anyhow::{anyhow, bail, Context, Result};
 use clap::{
-    builder::styling::{AnsiColor, Styles},
-    builder::{PathBufValueParser, TypedValueParser},
-    Parser,
+    builder::{
+        styling::{AnsiColor, Styles},
+        PathBufValueParser, TypedValueParser,
+    },
+    parser::ValueSource,
+    ArgMatches, Parser,
 };
 use clap_verbosity_flag::{Verbosity, WarnLevel};
+use encoding_rs::Encoding;
+use log::{info, warn};
 
 // Local Imports
-use crate::validators::{dir_writable, path_readable};
-use crate::{platform, subcommands};
+use crate::config::{write_starter_config, Config};
+use crate::hash_cache::HashCache;
+use crate::messages::{self, Lang};
+use crate::platform::ProviderBuilder;
+use crate::profile::ProfileQueue;
+use crate::sector_reader::DEFAULT_READ_BUFFER;
+use crate::sink::LocalSink;
+use crate::summary::DiscMetadata;
+use crate::validators::{dir_writable, filename_valid_portable, path_readable};
+use crate::{disc_set, platform, subcommands};
 
 // TODO: The retrode path should incorporate the current username
 // TODO: Allow overriding in a config file (Perhaps via .env with
 //       https://siciarz.net/24-days-rust-environment-variables)
 /// Default path to read from if none is specified
-const DEFAULT_INPATH: &str = "/dev/sr0";
+#[cfg(not(windows))]
+pub const DEFAULT_INPATH: &str = "/dev/sr0";
+/// Default path to read from if none is specified
+#[cfg(windows)]
+pub const DEFAULT_INPATH: &str = "D:";
+
+/// Largest `--read-buffer` to accept (Far past the point of diminishing returns)
+const MAX_READ_BUFFER: usize = 16 * 1024 * 1024;
 // const RETRODE_INPATH: &str = "/media/ssokolow/RETRODE";
 // TODO: Use libblkid to look up RETRODE at runtime:
 // https://www.kernel.org/pub/linux/utils/util-linux/v2.21/libblkid-docs/libblkid-Tags-and-Spec-evaluation.html
@@ -43,6 +67,7 @@ fn styles() -> Styles {
 /// Command-line argument schema
 // NOTE: long_about must begin with '\n' for compatibility with help2man
 // FIXME: clap-rs issue #694
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Parser, Debug)]
 #[command(
     version,
@@ -59,23 +84,36 @@ pub struct CliOpts {
     #[arg(short, long, value_name = "resolution")]
     pub timestamp: Option<stderrlog::Timestamp>,
 
+    /// Read default settings from this config file (Overriding any in
+    /// $XDG_CONFIG_HOME/rip_media/config.toml) or, for init-config, where to write one
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Exit code to use if the drive never becomes ready (eg. no disc inserted) [default: 75]
+    #[arg(long, global = true, value_name = "CODE")]
+    pub device_timeout_exit_code: Option<u8>,
+
     // -- Common Arguments --
     // TODO: Test (using something like `assert_cmd`) that inpath is required
-    /// Path to source medium (device, image file, etc.)
+    /// Path to source medium (device, image file, etc.) or a `LABEL=...`/`UUID=...` token
+    /// [default: /dev/sr0, or D: on Windows]
+    // (No clap default, since clap would run the validator on it too, and the default drive
+    //  needn't exist to, say, validate an existing dump. Use `CliOpts::inpath()` to read it.)
+    //
+    // The validator only catches obviously bad input early. Whatever it checked can still
+    // become unreadable before the rip actually opens it, so that can still fail.
     #[arg(
         short,
         long,
         global = true,
         value_name = "PATH",
         required = false,
-        value_parser,
-        // TODO: Fix unit test
-        // value_parser = PathBufValueParser::new().try_map(path_readable),
-        default_value = DEFAULT_INPATH
+        value_parser = PathBufValueParser::new().try_map(inpath_readable)
     )]
-    inpath: PathBuf,
+    inpath: Option<PathBuf>,
 
     /// Path to parent directory for output file(s)
+    // (Validated in main() so that --mkdir gets a chance to create it first)
     #[arg(
         short,
         long,
@@ -83,20 +121,352 @@ pub struct CliOpts {
         value_name = "PATH",
         required = false,
         default_value_os = CurDir.as_os_str(),
-        value_parser = PathBufValueParser::new().try_map(dir_writable),
     )]
     outdir: PathBuf,
 
+    /// Create the output directory (and any missing parents) if it doesn't exist
+    #[arg(short = 'p', long, global = true)]
+    mkdir: bool,
+
     /// Specify the output file/folder name [default: <the volume label>]
     #[arg(long, global = true, value_name = "NAME")] // TODO: Use filename_valid_portable
     name: Option<String>, // TODO: Decide how to combine this default with --set-size
 
+    /// Don't strip media file extensions (eg. `.iso` or `.cue`) from the end of --name
+    #[arg(long, global = true)]
+    keep_extension: bool,
+
+    /// Title of the disc's contents, to save alongside the dump and in --webhook summaries
+    #[arg(long, global = true, value_name = "TITLE")]
+    title: Option<String>,
+
+    /// Platform the disc is for (eg. PSX or Windows 95), to save alongside the dump
+    #[arg(long, global = true, value_name = "PLATFORM")]
+    platform: Option<String>,
+
+    /// Free-form notes about the disc (eg. its condition), to save alongside the dump
+    #[arg(long, global = true, value_name = "TEXT")]
+    notes: Option<String>,
+
+    /// Prompt for any of --title, --platform, and --notes which weren't given
+    /// (Skipped for audio CDs, which have CD-TEXT, and when not interactive)
+    #[arg(long, global = true)]
+    ask_metadata: bool,
+
+    /// Name the output after --title (or the title given when prompted) instead of the
+    /// volume label, unless --name is given
+    #[arg(long, global = true)]
+    name_from_title: bool,
+
+    /// How to arrange ripped discs within the output directory
+    #[arg(long, global = true, value_name = "LAYOUT", value_enum, default_value_t)]
+    layout: subcommands::Layout,
+
+    /// What to do when a disc has already been ripped into the output directory: leave it alone,
+    /// replace it once the new rip succeeds, or rip under a `-1`/`-2`/etc. suffixed name
+    /// (If not given, the rip fails instead)
+    #[arg(long, global = true, value_name = "POLICY", value_enum)]
+    overwrite_policy: Option<subcommands::OverwritePolicy>,
+
+    /// Convert output names (including volume labels) to this letter case
+    #[arg(long, global = true, value_name = "CASE", value_enum, default_value_t)]
+    output_name_case: subcommands::NameCase,
+
     /// Number of discs/cartridges/etc. to process under the same name
-    /// (eg. multi-disc games/albums)
+    /// (eg. multi-disc games/albums), ripped into disc1, disc2, etc. within one folder
     #[arg(long, global = true, value_name = "NUM", default_value = "1",
         value_parser = clap::value_parser!(u16).range(1..))]
     set_size: u16,
 
+    /// Rip the discs in these slots of a changer (eg. `1-5,7`) one after another, unattended
+    #[arg(long, global = true, value_name = "RANGES", value_parser = subcommands::parse_slot_list)]
+    slots: Option<BTreeSet<usize>>,
+
+    /// Continue an interrupted --set-size run, skipping discs whose output is already complete
+    #[arg(long, global = true)]
+    resume_set: bool,
+
+    /// Stop starting new discs of a --set-size or --slots run after this long (eg. `6h30m`),
+    /// finishing the one in progress
+    #[arg(long, global = true, value_name = "DURATION", value_parser = parse_duration)]
+    max_total_time: Option<Duration>,
+
+    /// Take per-disc settings (mode, name, and tool options) from this file, using each disc
+    /// entry in it for one disc and then removing it
+    /// [default: `rip_media.toml` in the output directory, if present]
+    #[arg(long, global = true, value_name = "PATH")]
+    profile: Option<PathBuf>,
+
+    /// Seconds to wait before ejecting the disc when finished
+    /// [default: 2 for tray drives, 0 for image files]
+    #[arg(long, global = true, value_name = "SECONDS")]
+    eject_delay: Option<u64>,
+
+    /// Character set of the disc's ISO9660 volume label (eg. `sjis`)
+    /// [default: UTF-8, falling back to Shift-JIS]
+    #[arg(long, global = true, value_name = "CHARSET", value_parser = parse_encoding)]
+    encoding: Option<&'static Encoding>,
+
+    /// Don't try to close the drive tray before ripping
+    #[arg(long, global = true)]
+    no_load: bool,
+
+    /// Don't try to unmount the disc before ripping
+    #[arg(long, global = true)]
+    no_unmount: bool,
+
+    /// Fail rather than ripping a disc which couldn't be unmounted (so nothing else can read
+    /// from it mid-rip)
+    #[arg(long, global = true, conflicts_with = "no_unmount")]
+    require_unmount: bool,
+
+    /// How long to wait (in seconds) for the drive to become ready (0 to check just once)
+    #[arg(long, global = true, value_name = "SECONDS", default_value_t = platform::DEFAULT_TIMEOUT)]
+    timeout: u64,
+
+    /// How often to check (in milliseconds) whether the drive has become ready
+    #[arg(long, global = true, value_name = "MILLISECONDS", default_value_t = 1000,
+        value_parser = clap::value_parser!(u64).range(1..))]
+    poll_interval: u64,
+
+    /// If the drive never becomes ready, let the user reseat the disc and try again this many
+    /// times before giving up (Never retried when not interactive)
+    #[arg(long, global = true, value_name = "NUM", default_value_t = subcommands::DEFAULT_READY_RETRIES)]
+    ready_retries: u32,
+
+    /// Prompt for input even if stdin/stdout aren't terminals
+    #[arg(long, global = true, conflicts_with = "non_interactive")]
+    interactive: bool,
+
+    /// Never prompt for input, skipping optional questions and the eject delay
+    /// [default: when stdin or stdout isn't a terminal]
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// Where to show prompts. `tty` keeps stdout and stderr clean for other programs by using
+    /// the controlling terminal (or stderr, if there isn't one).
+    #[arg(long, global = true, value_name = "STREAM", value_enum, default_value_t)]
+    prompt_stream: platform::PromptStream,
+
+    /// Language for prompts and notifications [default: from the locale]
+    #[arg(long, global = true, value_name = "LANG", value_enum)]
+    lang: Option<Lang>,
+
+    /// Don't load or save the history used to recall previous answers to prompts
+    /// (CD keys are never recorded either way)
+    #[arg(long, global = true)]
+    no_history: bool,
+
+    /// Sound to play when a rip finishes (Skipped if the file doesn't exist)
+    #[arg(long, global = true, value_name = "PATH", default_value = subcommands::DONE_SOUND)]
+    done_sound: PathBuf,
+
+    /// Sound to play when a rip fails (Skipped if the file doesn't exist)
+    #[arg(long, global = true, value_name = "PATH", default_value = subcommands::FAIL_SOUND)]
+    fail_sound: PathBuf,
+
+    /// Don't play any sounds (Unlike --quiet, this leaves logging alone)
+    #[arg(long, global = true)]
+    no_sound: bool,
+
+    /// Answer "Insert disc and press Enter" and other confirmations without asking
+    /// (Unlabeled discs get an automatic name rather than being prompted for one, and later
+    /// discs of a --set-size set are ripped as soon as the previous one is swapped out)
+    #[arg(short = 'y', long = "yes", global = true)]
+    assume_yes: bool,
+
+    /// Compression level for FLAC-encoded audio tracks (0 = fastest, 8 = smallest)
+    #[arg(long, global = true, value_name = "0-8",
+        default_value_t = subcommands::DEFAULT_FLAC_COMPRESSION,
+        value_parser = clap::value_parser!(u8).range(0..=8))]
+    flac_compression: u8,
+
+    /// Write an M3U playlist of the tracks when ripping audio
+    #[arg(long, global = true)]
+    playlist: bool,
+
+    /// Tag ripped audio tracks using the disc's CD-TEXT and ISRCs, if present
+    #[arg(long, global = true)]
+    cd_text: bool,
+
+    /// Keep encoding the rest of an audio CD's tracks if one of them fails
+    #[arg(long, global = true)]
+    keep_going: bool,
+
+    /// Turn off cdparanoia's verification and error correction entirely when ripping audio
+    /// (Fastest, but scratches will come through as clicks and dropouts)
+    #[arg(long, global = true)]
+    disable_paranoia: bool,
+
+    /// Skip cdparanoia's extra overlap checks when ripping audio, but still verify reads
+    #[arg(long, global = true)]
+    disable_extra_paranoia: bool,
+
+    /// Let cdparanoia give up on unreadable audio and carry on, rather than aborting the rip
+    /// (for getting what's possible off a badly scratched CD)
+    #[arg(long, global = true)]
+    allow_skip: bool,
+
+    /// Extra arguments to pass to cdparanoia, separated by spaces (eg. "-S 8").
+    /// Options which would interfere with the rip, like -B and -d, are refused.
+    #[arg(long, global = true, value_name = "ARGS", value_delimiter = ' ',
+        allow_hyphen_values = true, value_parser = subcommands::check_cdparanoia_arg)]
+    cdparanoia_args: Vec<String>,
+
+    /// Never retry unreadable areas of discs with a second, slower ddrescue pass
+    /// (By default, it's skipped only if the first pass recovered everything)
+    #[arg(long, global = true)]
+    no_second_pass: bool,
+
+    /// Check BIN/TOC/CUE sets for consistency after dumping them
+    #[arg(long, global = true)]
+    verify_toc: bool,
+
+    /// Split BIN dumps into one BIN per track, named like `Name (Track 1).bin`, and rewrite the
+    /// CUE sheet to match
+    #[arg(long, global = true)]
+    split_tracks: bool,
+
+    /// Split BIN dumps by track and name the BINs, CUE, and TOC after the disc the way Redump
+    /// does (eg. `Name (Track 1).bin`, or just `Name.bin` for one track), keeping the TOC
+    #[arg(long, global = true)]
+    redump_naming: bool,
+
+    /// After ripping, hash the image and prompt for ring codes to help with Redump submissions
+    #[arg(long, global = true)]
+    redump_info: bool,
+
+    /// POST a JSON summary to this URL when each rip finishes or fails
+    /// (eg. for a Discord/Slack/ntfy bridge. Requires the `webhook` build feature.)
+    #[arg(long, global = true, value_name = "URL")]
+    webhook: Option<String>,
+
+    /// Treat problems found by consistency checks (and a lack of free space for a dump) as
+    /// errors rather than warnings
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// Abort if a dump makes no progress for this long (eg. the drive hung on a bad sector)
+    #[arg(long, global = true, value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..))]
+    stall_timeout: Option<u64>,
+
+    /// Recovery strategies for the damaged mode to attempt, in order
+    /// [default: a BIN dump, followed by whatever its TOC says is worth trying]
+    #[arg(long = "try", global = true, value_name = "STRATEGIES", value_delimiter = ',')]
+    try_strategies: Vec<subcommands::RecoveryStrategy>,
+
+    /// Write a .sha256 file listing the checksums of all of a disc's files once it's ripped
+    #[arg(long, global = true)]
+    checksums: bool,
+
+    /// Write PAR2 recovery data for all of a disc's files once it's ripped (needs par2create)
+    #[arg(long, global = true)]
+    par2: bool,
+
+    /// Compress each disc's output into a .7z archive once it's ripped, removing the
+    /// uncompressed files (needs 7z)
+    #[arg(long, global = true)]
+    compress: bool,
+
+    /// Don't rip anything. Just re-check the existing dump named by --name in the output
+    /// directory (consistency, size, and any .sha256 checksum files)
+    #[arg(long, global = true)]
+    validate_only: bool,
+
+    /// Where --validate-only caches checksums, so files which haven't changed since the last
+    /// check aren't rehashed [default: checksums.json in the XDG cache directory]
+    #[arg(long, global = true, value_name = "PATH")]
+    cache: Option<PathBuf>,
+
+    /// Rehash every file --validate-only checks, ignoring (but still updating) the cache
+    #[arg(long, global = true, conflicts_with = "cache")]
+    no_cache: bool,
+
+    /// Don't rip anything. Just read the disc and compare it, sector by sector, to this
+    /// known-good image (eg. to check that an archived disc hasn't degraded)
+    #[arg(long, global = true, value_name = "IMAGE", conflicts_with = "validate_only")]
+    compare_to: Option<PathBuf>,
+
+    /// Eject the disc even if ripping it failed, so it can be taken out
+    /// [default: on when interactive]
+    #[arg(long, global = true, overrides_with = "no_eject_on_error")]
+    force_eject_on_error: bool,
+
+    /// Leave the disc in the drive if ripping it failed
+    #[arg(long, global = true, overrides_with = "force_eject_on_error")]
+    no_eject_on_error: bool,
+
+    /// Dump CSS-protected video DVDs anyway (The image will need libdvdcss to play)
+    #[arg(long, global = true)]
+    allow_encrypted: bool,
+
+    /// Hash ISO dumps as they're written, saving a .sha256 file without re-reading the image
+    /// (Falls back to re-reading it if ddrescue needs a second pass to retry bad areas)
+    #[arg(long, global = true)]
+    hash_while_reading: bool,
+
+    /// Set ISO dumps' modification time to the creation date recorded on the disc, rather than
+    /// when they were dumped
+    #[arg(long, global = true)]
+    stamp_mtime: bool,
+
+    /// Keep ddrescue's mapfile (.log) after dumping an ISO and print a summary of what it
+    /// recovered [default]
+    #[arg(long, global = true, overrides_with = "no_keep_log")]
+    keep_log: bool,
+
+    /// Write a map of which sectors of an ISO dump were recovered to this path (relative to
+    /// the disc's output folder): a PGM image shaded from white (recovered) to black (lost)
+    /// if it ends in .pgm, otherwise a CSV of sector ranges
+    #[arg(long, global = true, value_name = "PATH")]
+    error_map: Option<PathBuf>,
+
+    /// Only dump from this sector onward in the ISO modes (output is named after the range so
+    /// it isn't mistaken for a full dump)
+    #[arg(long, global = true, value_name = "SECTORS", alias = "input-offset")]
+    offset: Option<u64>,
+
+    /// Only dump this many sectors in the ISO modes [default: to the end of the disc]
+    #[arg(long, global = true, value_name = "SECTORS",
+        value_parser = clap::value_parser!(u64).range(1..))]
+    length: Option<u64>,
+
+    /// Encode each audio track to FLAC as soon as it's ripped, rather than ripping them all
+    /// first (Needs less free space, but reads the disc one track at a time)
+    #[arg(long, global = true)]
+    encode_as_ripped: bool,
+
+    /// Correct for this drive read offset (in samples) when ripping audio, so tracks can match
+    /// AccurateRip. An incorrect offset means they never will.
+    /// [default: looked up from the drive's model, or 0 if it's unknown]
+    #[arg(long, global = true, value_name = "SAMPLES", allow_negative_numbers = true)]
+    read_offset: Option<i32>,
+
+    /// cdrdao driver to dump BIN/TOC pairs with [default: generic-mmc-raw]
+    #[arg(long, global = true, value_name = "DRIVER")]
+    cdrdao_driver: Option<String>,
+
+    /// If cdrdao fails with the default driver, retry with the one `cdrdao scanbus` suggests
+    /// for the drive
+    #[arg(long, global = true, conflicts_with = "cdrdao_driver")]
+    driver_autodetect: bool,
+
+    /// Also capture this kind of subchannel data into a .sub file when dumping BIN/CUE pairs
+    /// (Some copy protection schemes can't be preserved without it)
+    #[arg(long, global = true, value_enum, value_name = "MODE")]
+    include_subchannel: Option<subcommands::SubchannelMode>,
+
+    /// Delete ddrescue's mapfile (.log) after dumping an ISO
+    #[arg(long, global = true, overrides_with = "keep_log")]
+    no_keep_log: bool,
+
+    /// Size of the blocks to read in when examining a disc's filesystem, as bytes or with a
+    /// K or M suffix (rounded up to a multiple of 2048)
+    #[arg(long, global = true, value_name = "SIZE", value_parser = parse_read_buffer,
+        default_value_t = DEFAULT_READ_BUFFER)]
+    read_buffer: usize,
+
     /// Which subcommand to invoke
     #[command(subcommand)]
     cmd: Command,
@@ -104,14 +474,18 @@ pub struct CliOpts {
 
 /// Valid subcommands
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Clone)]
 #[command(rename_all = "kebab-case", about = "\nSimple frontend for backing up physical media")]
 pub enum Command {
     /// Rip an audio CD
     #[command(display_order = 1)]
-    Audio,
+    Audio {
+        /// Only rip these tracks (eg. `3-5,8`), keeping the disc's track numbering
+        #[arg(long, value_name = "RANGES", value_parser = subcommands::parse_track_list)]
+        tracks: Option<BTreeSet<usize>>,
+    },
 
-    /// Rip a PC CD-ROM
+    /// Rip a PC CD-ROM (or, going by its tracks, an audio or mixed-mode CD)
     #[command(display_order = 1)]
     CD,
 
@@ -123,6 +497,12 @@ pub enum Command {
     #[command(display_order = 1)]
     PSX,
 
+    /// Rip the low-density area of a Sega Dreamcast GD-ROM into a GDI/BIN set
+    /// (The high-density area, which holds the game itself, needs a GD-ROM drive or the swap
+    /// trick, so this is only enough to identify the disc)
+    #[command(display_order = 1)]
+    Dreamcast,
+
     /// Rip a Sony PlayStation 2 disc into a PCSX2-compatible format
     #[command(display_order = 1)]
     PS2,
@@ -137,46 +517,506 @@ pub enum Command {
 
     /// Validate and process a disc image dumped by a Wii running CleanRip
     #[command(display_order = 2)]
-    Cleanrip {
-        // TODO: Can I make this an --in-place option shared among subcommands?
-        /// Only run the hash-validation without processing further
-        #[arg(long)]
-        just_validate: bool,
-    },
+    Cleanrip,
 
     /// Recover a damaged CD
     #[command(display_order = 1)]
     Damaged,
+
+    /// List the drives cdrdao can see, with suggested drivers for each
+    #[command(display_order = 3)]
+    ScanBus,
+
+    /// Report a disc's size, tracks, filesystem, and label (and estimate how long it would
+    /// take to rip) without ripping it
+    #[command(display_order = 3)]
+    Probe,
+
+    /// Run post-processing steps (eg. --checksums) on an existing dump in the --inpath folder
+    /// without touching the drive
+    #[command(display_order = 3)]
+    Process,
+
+    /// Write a commented config file containing the built-in defaults
+    #[command(display_order = 3)]
+    InitConfig {
+        /// Replace the config file if it already exists
+        #[arg(long)]
+        force: bool,
+    },
 }
 
-/// Program entry point
-pub fn main(opts: CliOpts) -> Result<()> {
-    let subcommand_func = match opts.cmd {
-        Command::Audio => subcommands::rip_audio,
+impl Command {
+    /// Name of the folder `--layout by-type` groups this subcommand's rips under
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            Command::Audio { .. } => "Audio",
+            Command::CD => "CD",
+            Command::DVD => "DVD",
+            Command::PSX => "PSX",
+            Command::Dreamcast => "Dreamcast",
+            Command::PS2 => "PS2",
+            Command::Retrode => "Retrode",
+            Command::UMD => "UMD",
+            Command::Cleanrip => "Cleanrip",
+            Command::Damaged => "Damaged",
+            Command::ScanBus | Command::Probe | Command::Process | Command::InitConfig { .. } => {
+                "Other"
+            },
+        }
+    }
+}
+
+impl CliOpts {
+    /// The device or image to rip from (`DEFAULT_INPATH` if none was given)
+    fn inpath(&self) -> &Path {
+        self.inpath.as_deref().unwrap_or_else(|| Path::new(DEFAULT_INPATH))
+    }
+
+    /// Use values from `config` for any settings which weren't given on the command line
+    fn apply_config(&mut self, config: &Config, matches: &ArgMatches) {
+        macro_rules! fill {
+            ($($field:ident $(=> $wrap:path)?),* $(,)?) => {$(
+                if let Some(value) = config.$field.clone() {
+                    if matches.value_source(stringify!($field)) != Some(ValueSource::CommandLine) {
+                        self.$field = $($wrap)?(value);
+                    }
+                }
+            )*};
+        }
+        fill!(
+            inpath => Some,
+            outdir,
+            mkdir,
+            eject_delay => Some,
+            no_history,
+            flac_compression,
+            playlist,
+            cd_text,
+            keep_going,
+            verify_toc,
+            split_tracks,
+            strict,
+            stall_timeout => Some,
+            output_name_case,
+            layout,
+        );
+
+        // --keep-log and --no-keep-log override each other, so either one beats the config
+        if let Some(keep_log) = config.keep_log {
+            let from_cli = |id| matches.value_source(id) == Some(ValueSource::CommandLine);
+            if !from_cli("keep_log") && !from_cli("no_keep_log") {
+                self.no_keep_log = !keep_log;
+            }
+        }
+    }
+}
+
+/// Check that an `--inpath` given on the command line exists and can be read
+///
+/// `LABEL=...` and `UUID=...` tokens are let through, since they're only resolved to a device
+/// once ripping starts.
+fn inpath_readable(path: PathBuf) -> Result<PathBuf, String> {
+    let is_token = path.to_str().and_then(platform::parse_device_token).is_some();
+    path_readable(path.clone()).or_else(|err| if is_token { Ok(path) } else { Err(err) })
+}
+
+/// Look up a `--encoding` character set by any of its WHATWG labels (eg. `sjis`, `latin1`)
+fn parse_encoding(label: &str) -> Result<&'static Encoding, String> {
+    Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| format!("Unrecognized character set: {}", label))
+}
+
+/// Parse a `--read-buffer` size like `65536`, `64K`, or `1M`
+fn parse_read_buffer(value: &str) -> Result<usize, String> {
+    let (digits, multiplier) = match value.trim().to_ascii_uppercase() {
+        upper if upper.ends_with('K') => (upper.trim_end_matches('K').to_owned(), 1024),
+        upper if upper.ends_with('M') => (upper.trim_end_matches('M').to_owned(), 1024 * 1024),
+        upper => (upper, 1),
+    };
+    let size = digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|num| num.checked_mul(multiplier))
+        .ok_or_else(|| format!("Not a valid size: {}", value))?;
+    if size == 0 || size > MAX_READ_BUFFER {
+        return Err(format!("Must be between 1 byte and {}M", MAX_READ_BUFFER / 1024 / 1024));
+    }
+    Ok(size)
+}
+
+/// Parse a duration like `6h30m`, `90m`, or `1d` (as accepted by `--max-total-time`)
+///
+/// (A bare number counts seconds, like the other time options.)
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Not a valid duration (eg. 6h30m): {}", value);
+    let mut spec = value.trim().to_ascii_lowercase();
+    if spec.bytes().all(|byte| byte.is_ascii_digit()) {
+        spec.push('s');
+    }
+
+    let (mut secs, mut digits) = (0u64, String::new());
+    for ch in spec.chars() {
+        let unit = match ch {
+            '0'..='9' => {
+                digits.push(ch);
+                continue;
+            },
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+        secs = digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|num| num.checked_mul(unit))
+            .and_then(|part| secs.checked_add(part))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() {
+        return Err(invalid());
+    }
+    if secs == 0 {
+        return Err("Must be at least 1 second".to_owned());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Create `path` and any missing parents, refusing names which aren't portable
+///
+/// Every component is checked before anything is created so that a typo can't leave a trail of
+/// half-created directories behind.
+fn make_outdir(path: &Path) -> Result<()> {
+    let missing = path
+        .ancestors()
+        .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists());
+    for dir in missing {
+        if let Some(name) = dir.file_name() {
+            filename_valid_portable(name).map_err(|e| anyhow!("{}", e.to_string_lossy()))?;
+        }
+    }
+    create_dir_all(path).with_context(|| format!("Could not create {}", path.display()))
+}
+
+/// Write a starter config file, checking that it loads back in
+fn init_config(target: Option<&Path>, force: bool) -> Result<()> {
+    let path = target.map(Path::to_path_buf).or_else(platform::config_path).context(
+        "Could not find the config directory (Neither $XDG_CONFIG_HOME nor $HOME is set)",
+    )?;
+    write_starter_config(&path, force)?;
+    Config::load(&path)?;
+    info!("Wrote starter config to {}", path.display());
+    Ok(())
+}
+
+/// Fill in any settings not given on the command line from the config files
+///
+/// Precedence is: command-line arguments, then the `--config` file, then the config file in
+/// `$XDG_CONFIG_HOME`, then the built-in defaults.
+pub fn with_config(mut opts: CliOpts, matches: &ArgMatches) -> Result<CliOpts> {
+    if matches!(opts.cmd, Command::InitConfig { .. }) {
+        return Ok(opts); // Don't let a broken config file get in the way of replacing it
+    }
+
+    let mut config = match platform::config_path().filter(|path| path.exists()) {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    if let Some(path) = &opts.config {
+        path_readable(path.clone()).map_err(|e| anyhow!("Could not read config file: {}", e))?;
+        config = Config::load(path)?.or(config);
+    }
+    opts.apply_config(&config, matches);
+    Ok(opts)
+}
+
+/// Re-check an existing dump without touching the drive
+fn validate_only(opts: &CliOpts) -> Result<()> {
+    let validator: subcommands::Validator = match opts.cmd {
+        Command::Audio { .. } => subcommands::validate_audio,
+        Command::CD | Command::PSX | Command::Dreamcast => subcommands::validate_bin,
+        Command::DVD | Command::PS2 | Command::Cleanrip => subcommands::validate_iso,
+        Command::Damaged => subcommands::validate_damaged,
+        ref cmd => bail!("--validate-only isn't supported for {:?}", cmd),
+    };
+    let name = output_name_arg(opts.name.as_deref(), opts.keep_extension)
+        .context("--validate-only requires --name")?;
+    let mut cache = opts
+        .cache
+        .clone()
+        .or_else(platform::hash_cache_path)
+        .map(|path| HashCache::load(path).with_rehash(opts.no_cache));
+    let result = subcommands::validate_existing(
+        validator,
+        &opts.outdir,
+        opts.layout,
+        opts.cmd.media_type(),
+        name,
+        cache.as_mut(),
+    );
+    if let Some(Err(e)) = cache.as_ref().map(HashCache::save) {
+        warn!("Could not save checksum cache: {:#}", e);
+    }
+    result
+}
+
+/// The `--name` to rip under, minus any media file extension it was given with
+fn output_name_arg(name: Option<&str>, keep_extension: bool) -> Option<&str> {
+    name.map(|given| if keep_extension { given } else { subcommands::strip_media_extension(given) })
+}
+
+/// Combine `--offset` and `--length` into the range to dump, rejecting them outside the ISO modes
+fn sector_range_arg(opts: &CliOpts) -> Result<Option<subcommands::SectorRange>> {
+    if opts.offset.is_none() && opts.length.is_none() {
+        return Ok(None);
+    }
+    if !matches!(opts.cmd, Command::DVD | Command::PS2 | Command::Damaged) {
+        bail!("--offset and --length only apply to the dvd, ps2, and damaged subcommands");
+    }
+    Ok(Some(subcommands::SectorRange { offset: opts.offset.unwrap_or(0), length: opts.length }))
+}
+
+/// Check that `--include-subchannel` was only given for subcommands which dump BIN/CUE pairs
+fn subchannel_arg(opts: &CliOpts) -> Result<Option<subcommands::SubchannelMode>> {
+    if opts.include_subchannel.is_some()
+        && !matches!(opts.cmd, Command::CD | Command::PSX | Command::Dreamcast | Command::Damaged)
+    {
+        bail!(
+            "--include-subchannel only applies to the cd, psx, dreamcast, and damaged subcommands"
+        );
+    }
+    Ok(opts.include_subchannel)
+}
+
+/// Check that `--redump-naming` was only given for subcommands which leave a BIN/CUE set as-is
+///
+/// (The dreamcast and damaged subcommands go on to work with the files under their usual names.)
+fn redump_naming_arg(opts: &CliOpts) -> Result<bool> {
+    if opts.redump_naming && !matches!(opts.cmd, Command::CD | Command::PSX) {
+        bail!("--redump-naming only applies to the cd and psx subcommands");
+    }
+    Ok(opts.redump_naming)
+}
+
+/// Collect the archival steps to run on each dump
+fn post_options(opts: &CliOpts) -> subcommands::PostProcessOptions {
+    subcommands::PostProcessOptions { checksums: opts.checksums, par2: opts.par2 }
+}
+
+/// Collect the options which control how each kind of disc is ripped
+fn mode_options(opts: &CliOpts) -> Result<subcommands::ModeOptions> {
+    Ok(subcommands::ModeOptions {
+        flac_compression: opts.flac_compression,
+        playlist: opts.playlist,
+        cd_text: opts.cd_text,
+        second_pass: !opts.no_second_pass,
+        verify_toc: opts.verify_toc,
+        strict: opts.strict,
+        stall_timeout: opts.stall_timeout.map(Duration::from_secs),
+        recovery: opts.try_strategies.clone(),
+        allow_encrypted: opts.allow_encrypted,
+        keep_going: opts.keep_going,
+        hash_while_reading: opts.hash_while_reading,
+        stamp_mtime: opts.stamp_mtime,
+        keep_log: !opts.no_keep_log,
+        paranoia: subcommands::ParanoiaPolicy {
+            disable_paranoia: opts.disable_paranoia,
+            disable_extra_paranoia: opts.disable_extra_paranoia,
+            allow_skip: opts.allow_skip,
+        },
+        cdparanoia_args: opts.cdparanoia_args.clone(),
+        split_tracks: opts.split_tracks,
+        redump_naming: redump_naming_arg(opts)?,
+        tracks: match &opts.cmd {
+            Command::Audio { tracks } => tracks.clone(),
+            _ => None,
+        },
+        mode: None,
+        error_map: opts.error_map.clone(),
+        range: sector_range_arg(opts)?,
+        encode_as_ripped: opts.encode_as_ripped,
+        read_offset: opts.read_offset,
+        cdrdao_driver: opts.cdrdao_driver.clone(),
+        driver_autodetect: opts.driver_autodetect,
+        subchannel: subchannel_arg(opts)?,
+        sink: Rc::new(LocalSink::default()),
+        sounds: subcommands::Sounds {
+            done: opts.done_sound.clone(),
+            fail: opts.fail_sound.clone(),
+            muted: opts.no_sound,
+        },
+        assume_yes: opts.assume_yes,
+    })
+}
+
+/// A function which rips one kind of disc
+type ModeFunc =
+    fn(&mut platform::NativePlatformProvider, &str, &subcommands::ModeOptions) -> Result<()>;
+
+/// The function which rips discs for `cmd` (`None` if it doesn't rip discs)
+fn mode_func(cmd: &Command) -> Option<ModeFunc> {
+    Some(match cmd {
+        Command::Audio { .. } => subcommands::rip_audio,
         Command::CD => subcommands::rip_cd,
         Command::DVD => subcommands::rip_dvd,
         Command::PSX => subcommands::rip_psx,
+        Command::Dreamcast => subcommands::rip_dreamcast,
         Command::PS2 => subcommands::rip_ps2,
         Command::Damaged => subcommands::rip_damaged,
-        e => panic!("TODO: Implement subcommand: {:?}", e),
+        _ => return None,
+    })
+}
+
+/// Wrap `default` so that disc profiles can switch individual discs to a different mode
+fn with_profile_modes(
+    default: ModeFunc,
+) -> impl Fn(&mut platform::NativePlatformProvider, &str, &subcommands::ModeOptions) -> Result<()> {
+    move |provider, disc_name, mode_opts| {
+        let func = mode_opts.mode.as_deref().and_then(rip_mode).as_ref().and_then(mode_func);
+        func.unwrap_or(default)(provider, disc_name, mode_opts)
+    }
+}
+
+/// Look up a subcommand which rips discs by name (as given for `mode` in a disc profile)
+pub fn rip_mode(name: &str) -> Option<Command> {
+    let cmd = CliOpts::try_parse_from(["rip_media", name]).ok()?.cmd;
+    mode_func(&cmd).is_some().then_some(cmd)
+}
+
+/// Combine a `--foo`/`--no-foo` pair of flags (`None` if neither was given)
+fn flag_pair(enable: bool, disable: bool) -> Option<bool> {
+    match (enable, disable) {
+        (true, _) => Some(true),
+        (_, true) => Some(false),
+        _ => None,
+    }
+}
+
+/// Set up the platform provider for the device or image given by `--inpath`
+fn make_provider(opts: &CliOpts) -> Result<platform::NativePlatformProvider> {
+    let mut inpath = platform::resolve_inpath(opts.inpath())?;
+    if inpath.is_file() {
+        // Image files are read from inside the output directory, so they need absolute paths
+        inpath = std::path::absolute(&inpath)
+            .with_context(|| format!("Could not resolve {}", inpath.display()))?;
+    }
+    Ok(platform::NativePlatformProvider::new(Cow::Owned(inpath.into_os_string()))
+        .with_read_buffer(opts.read_buffer)
+        .with_label_encoding(opts.encoding)
+        .with_interactive(flag_pair(opts.interactive, opts.non_interactive))
+        .with_prompt_stream(opts.prompt_stream)
+        .with_poll_interval(Duration::from_millis(opts.poll_interval)))
+}
+
+/// Program entry point
+pub fn main(opts: CliOpts) -> Result<()> {
+    messages::set_lang(opts.lang.unwrap_or_else(Lang::from_env));
+    if opts.validate_only {
+        return validate_only(&opts);
+    }
+    if let Some(reference) = &opts.compare_to {
+        path_readable(reference.clone())
+            .map_err(|e| anyhow!("Could not read reference image: {}", e))?;
+        return subcommands::compare_to_reference(
+            &mut make_provider(&opts)?,
+            reference,
+            opts.no_load,
+            Duration::from_secs(opts.timeout),
+        );
+    }
+    let default_func = match opts.cmd {
+        Command::ScanBus => return subcommands::scan_bus(),
+        Command::Probe => {
+            return subcommands::probe(
+                &mut make_provider(&opts)?,
+                opts.no_load,
+                Duration::from_secs(opts.timeout),
+            )
+        },
+        Command::InitConfig { force } => return init_config(opts.config.as_deref(), force),
+        Command::Process => {
+            let name = output_name_arg(opts.name.as_deref(), opts.keep_extension);
+            return subcommands::process_existing(opts.inpath(), name, post_options(&opts));
+        },
+        ref cmd => {
+            mode_func(cmd).unwrap_or_else(|| panic!("TODO: Implement subcommand: {:?}", cmd))
+        },
     };
+    let subcommand_func = with_profile_modes(default_func);
 
     // IDEA: Could I adapt the "parameterized impl for verified state machine"
     //       trick to compile-time verify that code which may be called in
     //       non-interactive-mode doesn't depend on interactive calls like
     //       prompt()?
 
-    // TODO:
-    // 1. Do common setup for disc set
-    // 2. For each disc...
-    //      ...call the ripping command appropriate to the subcommand
-
-    //with _containing_workdir(args.outdir or os.getcwdu()):
-    //    for _ in range(0, args.set_size):
-    // TODO: Actually put set_size things in the same folder
     // TODO: Unify error-handling and replace expect() with ok_or() and ?
-    let mut provider = platform::LinuxPlatformProvider::new(Cow::Borrowed(opts.inpath.as_os_str()));
-    subcommands::rip(&mut provider, subcommand_func, opts.name.as_ref().map(String::as_ref))?;
+    if opts.webhook.is_some() && !cfg!(feature = "webhook") {
+        bail!("--webhook requires rip_media to be built with the `webhook` feature");
+    }
+    if opts.mkdir {
+        make_outdir(&opts.outdir)?;
+    }
+    dir_writable(opts.outdir.clone()).map_err(|e| anyhow!("Invalid output directory: {}", e))?;
+    let profile_path = ProfileQueue::find(opts.profile.as_deref(), &opts.outdir)?;
+    let mode_opts = mode_options(&opts)?;
+    let post_opts = post_options(&opts);
+
+    let mut provider = make_provider(&opts)?;
+    if !opts.no_history {
+        if let Some(path) = platform::history_path() {
+            provider = provider.with_history(path);
+        }
+    }
+    let rip_opts = subcommands::RipOptions {
+        name: output_name_arg(opts.name.as_deref(), opts.keep_extension),
+        eject_delay: opts.eject_delay.map(Duration::from_secs),
+        eject_on_error: flag_pair(opts.force_eject_on_error, opts.no_eject_on_error),
+        redump_info: opts.redump_info,
+        webhook: opts.webhook.as_deref(),
+        set_position: None,
+        set_dir: None,
+        previous_serial: None,
+        slot: None,
+        deadline: opts.max_total_time.and_then(|budget| Instant::now().checked_add(budget)),
+        name_case: opts.output_name_case,
+        layout: opts.layout,
+        media_type: opts.cmd.media_type(),
+        metadata: DiscMetadata {
+            title: opts.title,
+            platform: opts.platform,
+            notes: opts.notes,
+            drive: None,
+            volume_dates: None,
+        },
+        ask_metadata: opts.ask_metadata && !matches!(opts.cmd, Command::Audio { .. }),
+        name_from_title: opts.name_from_title,
+        no_load: opts.no_load,
+        no_unmount: opts.no_unmount,
+        require_unmount: opts.require_unmount,
+        ready_timeout: Duration::from_secs(opts.timeout),
+        ready_retries: opts.ready_retries,
+        profile: profile_path.as_deref(),
+        overwrite: opts.overwrite_policy,
+        post: post_opts,
+        compress: opts.compress,
+        mode: mode_opts,
+    };
+    if opts.slots.is_some() && (opts.set_size > 1 || opts.resume_set) {
+        bail!("--slots can't be combined with --set-size or --resume-set");
+    }
+    subcommands::with_output_dir(&opts.outdir, || match &opts.slots {
+        Some(slots) => subcommands::rip_slots(&mut provider, subcommand_func, rip_opts, slots),
+        None => disc_set::rip_set(
+            &mut provider,
+            subcommand_func,
+            rip_opts,
+            opts.set_size,
+            opts.resume_set,
+        ),
+    })?;
 
     Ok(()) // TODO
 }
@@ -185,6 +1025,7 @@ pub fn main(opts: CliOpts) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::scratch_path;
     use std::path::Path;
 
     /// TODO: Use a macro to generate the positionality/default-validation tests and also apply
@@ -192,12 +1033,14 @@ mod tests {
     ///
     #[test]
     fn inpath_has_expected_default_if_not_given() {
+        // (The default drive won't exist on a build machine, so this also checks that the
+        //  validator only runs on paths which were actually given)
         let opts = CliOpts::parse_from(&["rip_media", "cd"]);
         assert!(
-            opts.inpath == Path::new(DEFAULT_INPATH),
+            opts.inpath() == Path::new(DEFAULT_INPATH),
             "Expected default inpath to be {:?} but got {:?}",
             DEFAULT_INPATH,
-            opts.inpath
+            opts.inpath()
         )
     }
 
@@ -212,14 +1055,31 @@ mod tests {
         )
     }
 
+    #[test]
+    fn make_outdir_creates_missing_parents() {
+        let base = scratch_path("mkdir");
+        let target = base.join("set").join("disc");
+        make_outdir(&target).expect("portable path should be created");
+        assert!(target.is_dir());
+        make_outdir(&target).expect("existing directory should be accepted");
+        std::fs::remove_dir_all(&base).expect("cleanup");
+    }
+
+    #[test]
+    fn make_outdir_rejects_unportable_names() {
+        let base = scratch_path("badmkdir");
+        assert!(make_outdir(&base.join("bad:name").join("disc")).is_err());
+        assert!(!base.exists(), "Nothing should be created if any component is invalid");
+    }
+
     #[test]
     /// Can override `DEFAULT_INPATH` when specifying -i before the subcommand
     fn test_can_override_inpath_before() {
         let opts = CliOpts::parse_from(&["rip_media", "-i/", "cd"]);
         assert!(
-            opts.inpath == Path::new("/"),
+            opts.inpath() == Path::new("/"),
             "\"-i/ cd\" should have produced \"/\" but actually produced \"{:?}\"",
-            opts.inpath
+            opts.inpath()
         )
     }
 
@@ -228,35 +1088,301 @@ mod tests {
     fn test_can_override_inpath_after() {
         let opts = CliOpts::parse_from(&["rip_media", "cd", "-i/"]);
         assert!(
-            opts.inpath == Path::new("/"),
+            opts.inpath() == Path::new("/"),
             "\"cd -i/\" should have produced \"/\" but actually produced \"{:?}\"",
-            opts.inpath
+            opts.inpath()
         )
     }
 
-    //#[test]
-    ///// Validator doesn't get run on the default inpath if -i was specified
-    //fn test_only_validates_inpath_to_be_used_before() {
-    //    let defaults = AppConfig { inpath: Cow::Borrowed("/etc/shadow") };
-    //    let matches = make_clap_parser(&defaults)
-    //        .get_matches_from_safe(&["rip_media", "-i/", "cd"])
-    //        .unwrap_or_else(|e| { panic!("Undesired failure on input: {}", e) });
-    //    let inpath = matches.value_of("inpath").unwrap();
-    //    assert!(inpath == "/",
-    //            "\"cd -i/\" should have produced \"/\" but actually produced \"{}\"", inpath)
-    //}
-
-    //#[test]
-    ///// Validator doesn't get run on the default inpath if -i was specified
-    //fn test_only_validates_inpath_to_be_used_after() {
-    //    let defaults = AppConfig { inpath: Cow::Borrowed("/etc/shadow") };
-    //    let matches = make_clap_parser(&defaults)
-    //        .get_matches_from_safe(&["rip_media", "cd", "-i/"])
-    //        .unwrap_or_else(|e| { panic!("Undesired failure on input: {}", e) });
-    //    let inpath = matches.value_of("inpath").unwrap();
-    //    assert!(inpath == "/",
-    //            "\"cd -i/\" should have produced \"/\" but actually produced \"{}\"", inpath)
-    //}
+    #[test]
+    /// Unreadable input paths are rejected up front, on either side of the subcommand
+    fn inpath_is_validated_when_given() {
+        for args in [
+            ["rip_media", "-i/nonexistant_test_path", "cd"],
+            ["rip_media", "cd", "-i/nonexistant_test_path"],
+        ] {
+            assert!(CliOpts::try_parse_from(args).is_err(), "{:?} should be rejected", args);
+        }
+        let fixture = CliOpts::try_parse_from(["rip_media", "cd", "-ifixture.iso"]);
+        assert_eq!(fixture.expect("fixture is readable").inpath(), Path::new("fixture.iso"));
+
+        // Tokens name a device which blkid has to find, not a path
+        let token = CliOpts::try_parse_from(["rip_media", "cd", "-iLABEL=MY_DISC"]);
+        assert_eq!(token.expect("tokens aren't paths").inpath(), Path::new("LABEL=MY_DISC"));
+        assert!(CliOpts::try_parse_from(["rip_media", "cd", "-iLABEL="]).is_err());
+    }
+
+    #[test]
+    fn timeout_defaults_to_default_timeout() {
+        let timeout_for = |args: &[&str]| CliOpts::try_parse_from(args).map(|opts| opts.timeout);
+        assert_eq!(timeout_for(&["rip_media", "cd"]).ok(), Some(platform::DEFAULT_TIMEOUT));
+        assert_eq!(timeout_for(&["rip_media", "--timeout", "30", "cd"]).ok(), Some(30));
+        assert_eq!(timeout_for(&["rip_media", "cd", "--timeout", "0"]).ok(), Some(0));
+        assert!(timeout_for(&["rip_media", "cd", "--timeout", "-1"]).is_err());
+    }
+
+    #[test]
+    fn poll_interval_defaults_to_a_second_and_must_be_positive() {
+        let interval_for = |args: &[&str]| CliOpts::try_parse_from(args).map(|o| o.poll_interval);
+        assert_eq!(interval_for(&["rip_media", "cd"]).ok(), Some(1000));
+        assert_eq!(interval_for(&["rip_media", "cd", "--poll-interval", "50"]).ok(), Some(50));
+        assert!(interval_for(&["rip_media", "cd", "--poll-interval", "0"]).is_err());
+    }
+
+    #[test]
+    fn sounds_can_be_overridden() {
+        let sounds_for =
+            |args: &[&str]| mode_options(&CliOpts::parse_from(args)).expect("valid options").sounds;
+        assert_eq!(sounds_for(&["rip_media", "cd"]), subcommands::Sounds::default());
+
+        let args = ["rip_media", "--done-sound", "done.ogg", "cd", "--fail-sound", "fail.wav"];
+        let sounds = sounds_for(&args);
+        assert_eq!(sounds.done, Path::new("done.ogg"));
+        assert_eq!(sounds.fail, Path::new("fail.wav"));
+        assert!(!sounds.muted);
+        assert!(sounds_for(&["rip_media", "-q", "cd", "--no-sound"]).muted);
+    }
+
+    #[test]
+    fn yes_can_be_given_on_either_side_of_the_subcommand() {
+        let assumes_yes =
+            |args: &[&str]| mode_options(&CliOpts::parse_from(args)).map(|o| o.assume_yes).ok();
+        assert_eq!(assumes_yes(&["rip_media", "cd"]), Some(false));
+        assert_eq!(assumes_yes(&["rip_media", "-y", "cd"]), Some(true));
+        assert_eq!(assumes_yes(&["rip_media", "cd", "--yes"]), Some(true));
+    }
+
+    #[test]
+    fn eject_delay_defaults_to_provider_hint() {
+        let opts = CliOpts::parse_from(&["rip_media", "cd"]);
+        assert_eq!(opts.eject_delay, None);
+
+        let opts = CliOpts::parse_from(&["rip_media", "cd", "--eject-delay", "0"]);
+        assert_eq!(opts.eject_delay, Some(0));
+    }
+
+    #[test]
+    fn flac_compression_is_range_checked() {
+        let opts = CliOpts::parse_from(&["rip_media", "audio", "--flac-compression", "5"]);
+        assert_eq!(opts.flac_compression, 5);
+
+        assert!(
+            CliOpts::try_parse_from(&["rip_media", "audio", "--flac-compression", "9"]).is_err()
+        );
+    }
+
+    #[test]
+    fn encoding_accepts_whatwg_labels() {
+        let opts = CliOpts::parse_from(["rip_media", "cd", "--encoding", "sjis"]);
+        assert_eq!(opts.encoding, Some(encoding_rs::SHIFT_JIS));
+        assert!(CliOpts::try_parse_from(["rip_media", "cd", "--encoding", "klingon"]).is_err());
+    }
+
+    #[test]
+    fn init_config_takes_target_and_force() {
+        let opts =
+            CliOpts::parse_from(["rip_media", "init-config", "--config", "x.toml", "--force"]);
+        assert_eq!(opts.config.as_deref(), Some(Path::new("x.toml")));
+        assert!(matches!(opts.cmd, Command::InitConfig { force: true }));
+    }
+
+    /// Parse `args`, then apply an explicit and an XDG config the way `with_config` does
+    fn parse_with_configs(args: &[&str], explicit: &str, xdg: &str) -> CliOpts {
+        use clap::{CommandFactory, FromArgMatches};
+        let matches = CliOpts::command().get_matches_from(args);
+        let mut opts = CliOpts::from_arg_matches(&matches).expect("valid arguments");
+        let config = Config::parse(explicit)
+            .expect("valid explicit config")
+            .or(Config::parse(xdg).expect("valid XDG config"));
+        opts.apply_config(&config, &matches);
+        opts
+    }
+
+    #[test]
+    fn config_precedence_chain() {
+        let xdg =
+            "flac-compression = 3\nplaylist = true\nstall-timeout = 60\ninpath = \"/dev/sr1\"";
+        let explicit = "flac-compression = 5\nplaylist = false";
+
+        // Compiled defaults apply when nothing else sets a value
+        let defaults = parse_with_configs(&["rip_media", "audio"], "", "");
+        assert_eq!(defaults.flac_compression, subcommands::DEFAULT_FLAC_COMPRESSION);
+        assert_eq!(defaults.stall_timeout, None);
+
+        // The XDG config beats the compiled defaults...
+        let from_xdg = parse_with_configs(&["rip_media", "audio"], "", xdg);
+        assert_eq!(from_xdg.flac_compression, 3);
+        assert!(from_xdg.playlist);
+        assert_eq!(from_xdg.stall_timeout, Some(60));
+        assert_eq!(from_xdg.inpath(), Path::new("/dev/sr1"));
+
+        // ...the explicit config beats the XDG config...
+        let from_explicit = parse_with_configs(&["rip_media", "audio"], explicit, xdg);
+        assert_eq!(from_explicit.flac_compression, 5);
+        assert!(!from_explicit.playlist);
+        assert_eq!(from_explicit.stall_timeout, Some(60));
+
+        // ...and the command line beats both, on either side of the subcommand
+        let args = ["rip_media", "-i/", "audio", "--flac-compression", "8", "--playlist"];
+        let from_cli = parse_with_configs(&args, explicit, xdg);
+        assert_eq!(from_cli.flac_compression, 8);
+        assert!(from_cli.playlist);
+        assert_eq!(from_cli.inpath(), Path::new("/"));
+        assert_eq!(from_cli.stall_timeout, Some(60));
+    }
+
+    #[test]
+    fn keep_log_flags_override_each_other_and_config() {
+        assert!(!parse_with_configs(&["rip_media", "dvd"], "", "").no_keep_log);
+        assert!(parse_with_configs(&["rip_media", "dvd"], "keep-log = false", "").no_keep_log);
+
+        let last_wins = ["rip_media", "dvd", "--no-keep-log", "--keep-log"];
+        assert!(!parse_with_configs(&last_wins, "", "").no_keep_log);
+        let keep = ["rip_media", "dvd", "--keep-log"];
+        assert!(!parse_with_configs(&keep, "keep-log = false", "").no_keep_log);
+        let discard = ["rip_media", "dvd", "--no-keep-log"];
+        assert!(parse_with_configs(&discard, "keep-log = true", "").no_keep_log);
+    }
+
+    #[test]
+    fn eject_on_error_flags_override_each_other() {
+        let eject_on_error = |args: &[&str]| {
+            let opts = CliOpts::parse_from(args);
+            flag_pair(opts.force_eject_on_error, opts.no_eject_on_error)
+        };
+        assert_eq!(eject_on_error(&["rip_media", "cd"]), None);
+        let never = ["rip_media", "cd", "--force-eject-on-error", "--no-eject-on-error"];
+        assert_eq!(eject_on_error(&never), Some(false));
+        let always = ["rip_media", "cd", "--no-eject-on-error", "--force-eject-on-error"];
+        assert_eq!(eject_on_error(&always), Some(true));
+    }
+
+    #[test]
+    fn cdparanoia_args_are_split_and_checked() {
+        let split = CliOpts::parse_from(["rip_media", "audio", "--cdparanoia-args", "-S 8 -v"]);
+        assert_eq!(split.cdparanoia_args, ["-S", "8", "-v"]);
+        let attached = CliOpts::parse_from(["rip_media", "audio", "--cdparanoia-args=-Z"]);
+        assert_eq!(attached.cdparanoia_args, ["-Z"]);
+        assert!(CliOpts::try_parse_from([
+            "rip_media",
+            "audio",
+            "--cdparanoia-args",
+            "-v -d /dev/sr1"
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn compare_to_is_exclusive_with_validate_only() {
+        let opts = CliOpts::parse_from(["rip_media", "dvd", "--compare-to", "archive/disc.iso"]);
+        assert_eq!(opts.compare_to.as_deref(), Some(Path::new("archive/disc.iso")));
+        let both = ["rip_media", "dvd", "--compare-to", "disc.iso", "--validate-only"];
+        assert!(CliOpts::try_parse_from(both).is_err());
+    }
+
+    #[test]
+    fn validate_only_requires_name() {
+        let opts = CliOpts::parse_from(["rip_media", "cd", "--validate-only"]);
+        let err = validate_only(&opts).expect_err("there's no name to find the dump by");
+        assert!(err.to_string().contains("--name"), "{}", err);
+
+        let retrode =
+            CliOpts::parse_from(["rip_media", "--validate-only", "retrode", "--name", "X"]);
+        assert!(validate_only(&retrode).is_err(), "Retrode dumps can't be validated yet");
+    }
+
+    #[test]
+    fn name_loses_media_extensions_unless_kept() {
+        let name_for = |args: &[&str]| {
+            let opts = CliOpts::parse_from(args);
+            output_name_arg(opts.name.as_deref(), opts.keep_extension).map(str::to_owned)
+        };
+        assert_eq!(name_for(&["rip_media", "dvd", "--name", "Game.iso"]).as_deref(), Some("Game"));
+        assert_eq!(name_for(&["rip_media", "audio", "--name", "Album"]).as_deref(), Some("Album"));
+        assert_eq!(name_for(&["rip_media", "cd", "--name", "foo.bar"]).as_deref(), Some("foo.bar"));
+        assert_eq!(
+            name_for(&["rip_media", "dvd", "--name", "Game.iso", "--keep-extension"]).as_deref(),
+            Some("Game.iso")
+        );
+        assert_eq!(name_for(&["rip_media", "dvd"]), None);
+    }
+
+    #[test]
+    fn sector_ranges_only_apply_to_iso_modes() {
+        let range_for = |args: &[&str]| sector_range_arg(&CliOpts::parse_from(args));
+        assert_eq!(range_for(&["rip_media", "dvd"]).expect("no range"), None);
+        assert_eq!(
+            range_for(&["rip_media", "ps2", "--input-offset", "16"]).expect("offset only"),
+            Some(subcommands::SectorRange { offset: 16, length: None })
+        );
+        assert_eq!(
+            range_for(&["rip_media", "damaged", "--length", "4"]).expect("length only"),
+            Some(subcommands::SectorRange { offset: 0, length: Some(4) })
+        );
+        assert!(range_for(&["rip_media", "audio", "--offset", "16"]).is_err());
+        assert!(CliOpts::try_parse_from(["rip_media", "dvd", "--length", "0"]).is_err());
+    }
+
+    #[test]
+    fn subchannels_only_apply_to_bin_modes() {
+        let mode_for = |args: &[&str]| subchannel_arg(&CliOpts::parse_from(args));
+        assert_eq!(mode_for(&["rip_media", "cd"]).expect("not requested"), None);
+        assert_eq!(
+            mode_for(&["rip_media", "psx", "--include-subchannel", "rw_raw"]).expect("bin mode"),
+            Some(subcommands::SubchannelMode::RwRaw)
+        );
+        assert!(mode_for(&["rip_media", "dvd", "--include-subchannel", "rw"]).is_err());
+        assert!(CliOpts::try_parse_from(["rip_media", "cd", "--include-subchannel", "pq"]).is_err());
+    }
+
+    #[test]
+    fn redump_naming_only_applies_to_plain_bin_modes() {
+        let naming_for = |args: &[&str]| redump_naming_arg(&CliOpts::parse_from(args));
+        assert!(!naming_for(&["rip_media", "cd"]).expect("not requested"));
+        assert!(naming_for(&["rip_media", "psx", "--redump-naming"]).expect("bin mode"));
+        assert!(naming_for(&["rip_media", "dreamcast", "--redump-naming"]).is_err());
+        assert!(naming_for(&["rip_media", "dvd", "--redump-naming"]).is_err());
+    }
+
+    #[test]
+    fn rip_mode_only_accepts_ripping_subcommands() {
+        assert!(matches!(rip_mode("psx"), Some(Command::PSX)));
+        assert!(matches!(rip_mode("audio"), Some(Command::Audio { tracks: None })));
+        assert_eq!(rip_mode("dreamcast").map(|cmd| cmd.media_type()), Some("Dreamcast"));
+        for bad in ["probe", "process", "init-config", "retrode", "PSX", "--help", ""] {
+            assert!(rip_mode(bad).is_none(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn interactivity_overrides_conflict() {
+        let opts = CliOpts::parse_from(["rip_media", "cd", "--non-interactive"]);
+        assert!(opts.non_interactive && !opts.interactive);
+        assert!(CliOpts::try_parse_from(["rip_media", "cd", "--interactive", "--non-interactive"])
+            .is_err());
+    }
+
+    #[test]
+    fn parse_read_buffer_accepts_suffixes() {
+        assert_eq!(parse_read_buffer("65536"), Ok(65536));
+        assert_eq!(parse_read_buffer("64k"), Ok(65536));
+        assert_eq!(parse_read_buffer("2M"), Ok(2 * 1024 * 1024));
+        for bad in ["", "0", "K", "-1", "1.5M", "17M", "99999999999999999999"] {
+            assert!(parse_read_buffer(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_duration_accepts_units() {
+        let secs = |value| parse_duration(value).map(|duration| duration.as_secs());
+        assert_eq!(secs("6h30m"), Ok(6 * 3600 + 30 * 60));
+        assert_eq!(secs("90M"), Ok(90 * 60));
+        assert_eq!(secs("1d"), Ok(86400));
+        assert_eq!(secs(" 45 "), Ok(45));
+        for bad in ["", "0", "0h", "h", "6h30", "1.5h", "-1h", "6 hours", "99999999999999999999d"] {
+            assert!(parse_duration(bad).is_err(), "{:?}", bad);
+        }
+    }
 
     // TODO: More unit tests
 }
@@ -0,0 +1,187 @@
+//! Sector-by-sector comparison of a disc against a known-good reference image
+
+use std::fmt;
+use std::io::{self, Read};
+
+use crate::sector_reader::{DEFAULT_READ_BUFFER, SECTOR_LEN};
+
+/// The outcome of comparing two streams sector by sector
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Comparison {
+    /// Bytes read from the disc
+    pub disc_len: u64,
+    /// Bytes read from the reference image
+    pub reference_len: u64,
+    /// Index of the first sector which differs (or is missing from one side)
+    pub first_mismatch: Option<u64>,
+    /// Number of sectors which differ (or are missing from one side)
+    pub mismatched: u64,
+}
+
+impl Comparison {
+    /// Whether both streams were identical
+    pub fn is_match(&self) -> bool {
+        self.mismatched == 0
+    }
+
+    /// Number of sectors covered by the longer of the two streams
+    pub fn sectors(&self) -> u64 {
+        self.disc_len.max(self.reference_len).div_ceil(SECTOR_LEN as u64)
+    }
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.first_mismatch {
+            None => write!(f, "Disc matches the reference image ({} sectors)", self.sectors())?,
+            Some(first) => write!(
+                f,
+                "{} of {} sectors differ from the reference image (first at sector {}, byte \
+                 offset {})",
+                self.mismatched,
+                self.sectors(),
+                first,
+                first * SECTOR_LEN as u64
+            )?,
+        }
+        if self.disc_len != self.reference_len {
+            write!(
+                f,
+                ". The disc is {} bytes but the reference image is {} bytes.",
+                self.disc_len, self.reference_len
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Fill as much of `buf` as possible, stopping early only at the end of the input
+///
+/// (A single `read` may return less than asked for, which would throw off the alignment)
+fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(buf.get_mut(filled..).unwrap_or_default()) {
+            Ok(0) => break,
+            Ok(len) => filled += len,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Compare `disc` to `reference` in `chunk_len`-byte chunks (a multiple of `SECTOR_LEN`)
+///
+/// Neither stream is ever held in memory in full. Sectors present in only one of the two
+/// count as mismatches.
+pub fn compare_streams_chunked<A: Read, B: Read>(
+    mut disc: A,
+    mut reference: B,
+    chunk_len: usize,
+) -> io::Result<Comparison> {
+    debug_assert!(chunk_len > 0 && chunk_len.is_multiple_of(SECTOR_LEN));
+    let mut disc_buf = vec![0; chunk_len];
+    let mut ref_buf = vec![0; chunk_len];
+    let mut result = Comparison::default();
+    let mut sector = 0_u64;
+    loop {
+        let disc_read = read_full(&mut disc, &mut disc_buf)?;
+        let ref_read = read_full(&mut reference, &mut ref_buf)?;
+        if disc_read == 0 && ref_read == 0 {
+            return Ok(result);
+        }
+        result.disc_len += disc_read as u64;
+        result.reference_len += ref_read as u64;
+
+        let disc_data = disc_buf.get(..disc_read).unwrap_or_default().chunks(SECTOR_LEN);
+        let ref_data = ref_buf.get(..ref_read).unwrap_or_default().chunks(SECTOR_LEN);
+        let sector_count = disc_read.max(ref_read).div_ceil(SECTOR_LEN);
+        let mut disc_sectors = disc_data.map(Some).chain(std::iter::repeat(None));
+        let mut ref_sectors = ref_data.map(Some).chain(std::iter::repeat(None));
+        for _ in 0..sector_count {
+            if disc_sectors.next() != ref_sectors.next() {
+                result.first_mismatch.get_or_insert(sector);
+                result.mismatched += 1;
+            }
+            sector += 1;
+        }
+    }
+}
+
+/// Compare `disc` to `reference` using the default read buffer size
+pub fn compare_streams<A: Read, B: Read>(disc: A, reference: B) -> io::Result<Comparison> {
+    compare_streams_chunked(disc, reference, DEFAULT_READ_BUFFER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A reader which only ever returns a few bytes at a time, like a pipe might
+    struct Trickle<R>(R);
+
+    impl<R: Read> Read for Trickle<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(100);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    /// Build an image of `sectors` sectors, each filled with its own index
+    fn image(sectors: u8) -> Vec<u8> {
+        (0..sectors).flat_map(|idx| vec![idx; SECTOR_LEN]).collect()
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let result = compare_streams(Cursor::new(image(40)), Trickle(Cursor::new(image(40))))
+            .expect("in-memory reads");
+        assert!(result.is_match());
+        assert_eq!(result.sectors(), 40);
+        assert_eq!(result.first_mismatch, None);
+        assert_eq!(result.to_string(), "Disc matches the reference image (40 sectors)");
+    }
+
+    #[test]
+    fn reports_first_and_count_of_differing_sectors() {
+        let mut disc = image(40);
+        disc[5 * SECTOR_LEN + 7] ^= 0xFF;
+        disc[33 * SECTOR_LEN] ^= 0xFF;
+        disc[39 * SECTOR_LEN + SECTOR_LEN - 1] ^= 0xFF;
+
+        // Small chunks exercise the carrying of sector numbers across chunk boundaries
+        let result = compare_streams_chunked(
+            Trickle(Cursor::new(disc)),
+            Cursor::new(image(40)),
+            SECTOR_LEN * 4,
+        )
+        .expect("in-memory reads");
+        assert_eq!(result.first_mismatch, Some(5));
+        assert_eq!(result.mismatched, 3);
+        assert_eq!(
+            result.to_string(),
+            "3 of 40 sectors differ from the reference image (first at sector 5, byte offset 10240)"
+        );
+    }
+
+    #[test]
+    fn missing_sectors_count_as_mismatches() {
+        let result =
+            compare_streams_chunked(Cursor::new(image(10)), Cursor::new(image(13)), SECTOR_LEN * 4)
+                .expect("in-memory reads");
+        assert_eq!(result.first_mismatch, Some(10));
+        assert_eq!(result.mismatched, 3);
+        assert!(result
+            .to_string()
+            .ends_with("The disc is 20480 bytes but the reference image is 26624 bytes."));
+
+        let mut partial = image(2);
+        partial.truncate(SECTOR_LEN + 100);
+        let short = compare_streams(Cursor::new(partial), Cursor::new(image(2))).expect("reads");
+        assert_eq!((short.first_mismatch, short.mismatched), (Some(1), 1));
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,238 @@
+//! Loading of user-customized defaults from a TOML config file
+
+use std::fs::{self, create_dir_all};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+use crate::app::DEFAULT_INPATH;
+use crate::subcommands::{Layout, NameCase, DEFAULT_FLAC_COMPRESSION};
+
+/// Defaults loaded from a config file (`None` for anything the file doesn't set)
+///
+/// Keys are named after the command-line options they provide defaults for.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Default for `--inpath`
+    pub inpath: Option<PathBuf>,
+    /// Default for `--outdir`
+    pub outdir: Option<PathBuf>,
+    /// Default for `--mkdir`
+    pub mkdir: Option<bool>,
+    /// Default for `--eject-delay`
+    pub eject_delay: Option<u64>,
+    /// Default for `--no-history`
+    pub no_history: Option<bool>,
+    /// Default for `--flac-compression`
+    pub flac_compression: Option<u8>,
+    /// Default for `--playlist`
+    pub playlist: Option<bool>,
+    /// Default for `--cd-text`
+    pub cd_text: Option<bool>,
+    /// Default for `--keep-going`
+    pub keep_going: Option<bool>,
+    /// Default for `--verify-toc`
+    pub verify_toc: Option<bool>,
+    /// Default for `--split-tracks`
+    pub split_tracks: Option<bool>,
+    /// Default for `--strict`
+    pub strict: Option<bool>,
+    /// Default for `--stall-timeout`
+    pub stall_timeout: Option<u64>,
+    /// Default for `--keep-log` (`false` for `--no-keep-log`)
+    pub keep_log: Option<bool>,
+    /// Default for `--output-name-case`
+    pub output_name_case: Option<NameCase>,
+    /// Default for `--layout`
+    pub layout: Option<Layout>,
+}
+
+impl Config {
+    /// Parse the contents of a config file, rejecting values the command line would reject
+    pub fn parse(text: &str) -> Result<Self> {
+        let config: Config = toml::from_str(text)?;
+        if config.flac_compression.is_some_and(|level| level > 8) {
+            bail!("flac-compression must be between 0 and 8");
+        }
+        if config.stall_timeout == Some(0) {
+            bail!("stall-timeout must be at least 1 second");
+        }
+        Ok(config)
+    }
+
+    /// Fill in anything this config doesn't set from `fallback`
+    pub fn or(self, fallback: Self) -> Self {
+        Config {
+            inpath: self.inpath.or(fallback.inpath),
+            outdir: self.outdir.or(fallback.outdir),
+            mkdir: self.mkdir.or(fallback.mkdir),
+            eject_delay: self.eject_delay.or(fallback.eject_delay),
+            no_history: self.no_history.or(fallback.no_history),
+            flac_compression: self.flac_compression.or(fallback.flac_compression),
+            playlist: self.playlist.or(fallback.playlist),
+            cd_text: self.cd_text.or(fallback.cd_text),
+            keep_going: self.keep_going.or(fallback.keep_going),
+            verify_toc: self.verify_toc.or(fallback.verify_toc),
+            split_tracks: self.split_tracks.or(fallback.split_tracks),
+            strict: self.strict.or(fallback.strict),
+            stall_timeout: self.stall_timeout.or(fallback.stall_timeout),
+            keep_log: self.keep_log.or(fallback.keep_log),
+            output_name_case: self.output_name_case.or(fallback.output_name_case),
+            layout: self.layout.or(fallback.layout),
+        }
+    }
+
+    /// Load the config file at `path`
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
+
+/// Render a commented config file containing the built-in defaults
+pub fn starter_config() -> String {
+    format!(
+        r#"# rip_media configuration
+#
+# Settings here replace rip_media's built-in defaults. Each one is named after the
+# command-line option it provides a default for, and options given on the command line
+# still take precedence.
+
+# Device or image file to rip from (or a LABEL=... or UUID=... token)
+inpath = "{inpath}"
+
+# Parent directory for ripped files
+outdir = "."
+
+# How to arrange ripped discs within outdir: flat (no subfolders), by-type (eg. DVD/<name>/),
+# or by-name (<name>/)
+layout = "by-name"
+
+# Create the output directory if it doesn't exist
+mkdir = false
+
+# Seconds to wait before ejecting the disc when finished
+# (If unset, 2 for tray drives and 0 for image files)
+#eject-delay = 2
+
+# Don't remember answers to prompts between runs
+no-history = false
+
+# FLAC compression level for audio tracks (0 = fastest, 8 = smallest)
+flac-compression = {flac_compression}
+
+# Write an M3U playlist when ripping audio CDs
+playlist = false
+
+# Tag audio tracks using the disc's CD-TEXT and ISRCs
+cd-text = false
+
+# Keep encoding the rest of an album's tracks if one of them fails
+keep-going = false
+
+# Check BIN/TOC/CUE sets for consistency after dumping them
+verify-toc = false
+
+# Split BIN dumps into one file per track, as Redump distributes them
+split-tracks = false
+
+# Treat problems found by consistency checks as errors rather than warnings
+strict = false
+
+# Abort a dump which makes no progress for this many seconds
+#stall-timeout = 600
+
+# Keep ddrescue's mapfile after dumping an ISO and print a summary of what it recovered
+keep-log = true
+
+# Letter case to convert output names to (lower, upper, or preserve)
+output-name-case = "preserve"
+"#,
+        inpath = DEFAULT_INPATH,
+        flac_compression = DEFAULT_FLAC_COMPRESSION,
+    )
+}
+
+/// Write a starter config file to `path`, refusing to replace an existing one unless `force`
+pub fn write_starter_config(path: &Path, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!("{} already exists (Use --force to replace it)", path.display());
+    }
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        create_dir_all(parent).with_context(|| format!("Could not create {}", parent.display()))?;
+    }
+    fs::write(path, starter_config()).with_context(|| format!("Could not write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_path;
+
+    #[test]
+    fn starter_config_round_trips() {
+        let config = Config::parse(&starter_config()).expect("starter config must parse");
+        assert_eq!(config.inpath.as_deref(), Some(Path::new(DEFAULT_INPATH)));
+        assert_eq!(config.flac_compression, Some(DEFAULT_FLAC_COMPRESSION));
+        assert_eq!(config.keep_going, Some(false));
+        assert_eq!(config.output_name_case, Some(NameCase::Preserve));
+        assert_eq!(config.layout, Some(Layout::ByName));
+        assert_eq!(config.eject_delay, None);
+        assert_eq!(config.stall_timeout, None);
+    }
+
+    #[test]
+    fn parse_rejects_bad_values() {
+        assert_eq!(Config::parse("").expect("empty config is valid"), Config::default());
+        for bad in [
+            "flac-compression = 9",
+            "stall-timeout = 0",
+            "typo = true",
+            "mkdir = 1",
+            "output-name-case = \"title\"",
+        ] {
+            assert!(Config::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_errors_point_at_the_line() {
+        let err = Config::parse("strict = true\nplaylist = yes\n").expect_err("invalid TOML");
+        assert!(format!("{:#}", err).contains("line 2"), "{:#}", err);
+    }
+
+    #[test]
+    fn or_prefers_own_values() {
+        let explicit = Config::parse("strict = true\nplaylist = false").expect("valid");
+        let fallback = Config::parse("playlist = true\ncd-text = true").expect("valid");
+        let merged = explicit.or(fallback);
+        assert_eq!(merged.strict, Some(true));
+        assert_eq!(merged.playlist, Some(false));
+        assert_eq!(merged.cd_text, Some(true));
+        assert_eq!(merged.mkdir, None);
+    }
+
+    #[test]
+    fn write_starter_config_refuses_to_overwrite() {
+        let dir = scratch_path("initcfg");
+        let path = dir.join("rip_media").join("config.toml");
+
+        write_starter_config(&path, false).expect("missing parents should be created");
+        assert_eq!(
+            Config::load(&path).expect("written config loads"),
+            Config::parse(&starter_config()).expect("valid")
+        );
+
+        fs::write(&path, "strict = true\n").expect("customize config");
+        assert!(write_starter_config(&path, false).is_err());
+        assert_eq!(Config::load(&path).expect("config kept").strict, Some(true));
+        write_starter_config(&path, true).expect("--force replaces the config");
+        assert_eq!(Config::load(&path).expect("config replaced").strict, Some(false));
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
+
+// vim: set sw=4 sts=4 :
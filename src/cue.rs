@@ -0,0 +1,408 @@
+//! Parsing and sanity-checking for the CUE sheets written by `toc2cue`
+//!
+//! See the CDRWIN appendix of the cdrtools documentation or
+//! <https://wiki.hydrogenaud.io/index.php?title=Cue_sheet> for the format.
+
+use std::ops::Range;
+
+use anyhow::{bail, Context, Result};
+
+/// Number of CD frames (sectors) per second of MSF time
+const FRAMES_PER_SEC: u64 = 75;
+
+/// Size of a raw CD sector, as stored in a BIN file
+pub const RAW_SECTOR_LEN: u64 = 2352;
+
+/// A `TRACK` entry from a CUE sheet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CueTrack {
+    /// The track number as declared in the CUE sheet
+    pub number: u32,
+    /// Index into `CueSheet::files` of the `FILE` this track's data is in
+    pub file: usize,
+    /// `(index number, offset in frames from the start of the file)` for each `INDEX` line
+    pub indexes: Vec<(u32, u64)>,
+}
+
+/// The parts of a CUE sheet needed to check it against its BIN file(s)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CueSheet {
+    /// The filenames referenced by `FILE` lines, in order
+    pub files: Vec<String>,
+    /// The tracks, in order
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Convert an `mm:ss:ff` timestamp into a count of frames
+pub fn msf_to_frames(msf: &str) -> Option<u64> {
+    let mut parts = msf.split(':').map(str::parse::<u64>);
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(mins)), Some(Ok(secs)), Some(Ok(frames)), None)
+            if secs < 60 && frames < FRAMES_PER_SEC =>
+        {
+            Some((mins * 60 + secs) * FRAMES_PER_SEC + frames)
+        },
+        _ => None,
+    }
+}
+
+/// Convert a count of frames into an `mm:ss:ff` timestamp
+pub fn frames_to_msf(frames: u64) -> String {
+    let secs = frames / FRAMES_PER_SEC;
+    format!("{:02}:{:02}:{:02}", secs / 60, secs % 60, frames % FRAMES_PER_SEC)
+}
+
+/// Extract the filename from the arguments of a `FILE` line, quoted or not
+///
+/// (`toc2cue` doesn't quote filenames, so an unquoted one runs up to the file type keyword)
+fn parse_file_arg(args: &str) -> Option<String> {
+    if let Some(quoted) = args.strip_prefix('"') {
+        return quoted.split_once('"').map(|(name, _)| name.to_owned());
+    }
+    args.rsplit_once(char::is_whitespace)
+        .map(|(name, _)| name.trim_end().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+impl CueSheet {
+    /// Parse the contents of a CUE sheet, ignoring commands which don't affect layout
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut sheet = CueSheet::default();
+        for (idx, raw_line) in text.lines().enumerate() {
+            let line_num = idx + 1;
+            let line = raw_line.trim();
+            let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match command.to_ascii_uppercase().as_str() {
+                "FILE" => sheet.files.push(
+                    parse_file_arg(args.trim())
+                        .with_context(|| format!("Bad FILE line at line {}", line_num))?,
+                ),
+                "TRACK" => {
+                    if sheet.files.is_empty() {
+                        bail!("TRACK before any FILE at line {}", line_num);
+                    }
+                    let number = args
+                        .split_whitespace()
+                        .next()
+                        .and_then(|num| num.parse().ok())
+                        .with_context(|| format!("Bad TRACK line at line {}", line_num))?;
+                    sheet.tracks.push(CueTrack {
+                        number,
+                        file: sheet.files.len() - 1,
+                        indexes: Vec::new(),
+                    });
+                },
+                "INDEX" => {
+                    let mut fields = args.split_whitespace();
+                    let index = fields.next().and_then(|num| num.parse().ok());
+                    let offset = fields.next().and_then(msf_to_frames);
+                    match (sheet.tracks.last_mut(), index, offset) {
+                        (Some(track), Some(index_num), Some(frames)) => {
+                            track.indexes.push((index_num, frames));
+                        },
+                        _ => bail!("Bad INDEX line at line {}", line_num),
+                    }
+                },
+                _ => {},
+            }
+        }
+        Ok(sheet)
+    }
+
+    /// Compute the byte range each track occupies in the sheet's (single) BIN file
+    ///
+    /// Each track starts at its first `INDEX` (so pregaps stay with the track they precede, as
+    /// Redump does it) and runs up to the start of the next one. The first track also claims
+    /// anything before its first index, so the ranges always cover the whole file.
+    pub fn track_ranges(&self, file_len: u64) -> Result<Vec<Range<u64>>> {
+        if self.files.len() != 1 {
+            bail!("Expected a CUE sheet with one FILE, but it has {}", self.files.len());
+        }
+        if !file_len.is_multiple_of(RAW_SECTOR_LEN) {
+            bail!("{} is not a whole number of {}-byte sectors", self.files[0], RAW_SECTOR_LEN);
+        }
+
+        let mut starts = Vec::with_capacity(self.tracks.len());
+        for track in &self.tracks {
+            let first = track.indexes.iter().map(|&(_, offset)| offset).min();
+            let Some(start) = first.and_then(|offset| offset.checked_mul(RAW_SECTOR_LEN)) else {
+                bail!("Track {} has no INDEX lines", track.number);
+            };
+            if starts.last().is_some_and(|&prev| start <= prev) || start >= file_len {
+                bail!("Track {} starts out of order or past the end of the file", track.number);
+            }
+            starts.push(start);
+        }
+        if let Some(first) = starts.first_mut() {
+            *first = 0;
+        }
+
+        let ends = starts.iter().skip(1).copied().chain(std::iter::once(file_len));
+        Ok(starts.iter().zip(ends).map(|(&start, end)| start..end).collect())
+    }
+
+    /// Check the CUE sheet against the sizes of the files it references and the track count
+    /// from the TOC it was generated from, returning a description of each inconsistency
+    ///
+    /// `file_lens` holds the length in bytes of each entry in `files` (`None` if missing).
+    pub fn problems(&self, file_lens: &[Option<u64>], toc_tracks: usize) -> Vec<String> {
+        let mut problems = Vec::new();
+        if self.tracks.len() != toc_tracks {
+            problems.push(format!(
+                "CUE sheet has {} tracks but TOC has {}",
+                self.tracks.len(),
+                toc_tracks
+            ));
+        }
+
+        for (name, maybe_len) in self.files.iter().zip(file_lens) {
+            match maybe_len {
+                None => problems.push(format!("Referenced file is missing: {}", name)),
+                Some(len) if len % RAW_SECTOR_LEN != 0 => problems.push(format!(
+                    "{} is not a whole number of {}-byte sectors ({} bytes)",
+                    name, RAW_SECTOR_LEN, len
+                )),
+                Some(_) => {},
+            }
+        }
+
+        for track in &self.tracks {
+            let Some(Some(file_len)) = file_lens.get(track.file) else { continue };
+            for &(index, offset) in &track.indexes {
+                let start = offset.saturating_mul(RAW_SECTOR_LEN);
+                if start >= *file_len {
+                    problems.push(format!(
+                        "Track {} index {} starts at byte {}, past the end of {} ({} bytes)",
+                        track.number, index, start, self.files[track.file], file_len
+                    ));
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Wrap the filenames of the `FILE` lines in the text of a CUE sheet in double quotes, as
+/// `toc2cue` leaves them bare (and so breaks on spaces)
+///
+/// Already-quoted filenames are left alone. CUE sheets have no way to escape a double quote, so
+/// a bare filename containing one is an error.
+pub fn quote_file_names(text: &str) -> Result<String> {
+    let mut output = String::with_capacity(text.len());
+    for (idx, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim_start();
+        let indent = raw_line.get(..raw_line.len() - line.len()).unwrap_or_default();
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        if command.eq_ignore_ascii_case("FILE") && !args.trim_start().starts_with('"') {
+            let name = parse_file_arg(args)
+                .with_context(|| format!("Bad FILE line at line {}", idx + 1))?;
+            if name.contains('"') {
+                bail!("Can't quote a filename containing quotes at line {}: {}", idx + 1, name);
+            }
+            let (_, file_type) =
+                args.trim_end().rsplit_once(char::is_whitespace).unwrap_or_default();
+            output.push_str(&format!("{}{} \"{}\" {}\n", indent, command, name, file_type));
+        } else {
+            output.push_str(raw_line);
+            output.push('\n');
+        }
+    }
+    Ok(output)
+}
+
+/// Rewrite the text of a single-file CUE sheet to reference one file per track
+///
+/// `tracks` holds the filename and starting frame (within the original file) of each track, in
+/// order. `INDEX` offsets are rebased onto those starts and everything else is kept as-is.
+pub fn split_cue_text(text: &str, tracks: &[(String, u64)]) -> Result<String> {
+    let mut remaining = tracks.iter();
+    let mut base = 0;
+    let mut output = String::with_capacity(text.len());
+    for (idx, raw_line) in text.lines().enumerate() {
+        let line = raw_line.trim_start();
+        let indent = raw_line.get(..raw_line.len() - line.len()).unwrap_or_default();
+        let (command, args) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        match command.to_ascii_uppercase().as_str() {
+            "FILE" => continue,
+            "TRACK" => {
+                let Some((name, start)) = remaining.next() else {
+                    bail!("More TRACK lines than track files at line {}", idx + 1);
+                };
+                base = *start;
+                output.push_str(&format!("FILE \"{}\" BINARY\n", name));
+            },
+            "INDEX" => {
+                let mut fields = args.split_whitespace();
+                let (Some(index), Some(Some(frames))) =
+                    (fields.next(), fields.next().map(msf_to_frames))
+                else {
+                    bail!("Bad INDEX line at line {}", idx + 1);
+                };
+                let Some(offset) = frames.checked_sub(base) else {
+                    bail!("INDEX before the start of its track at line {}", idx + 1);
+                };
+                output.push_str(&format!("{}INDEX {} {}\n", indent, index, frames_to_msf(offset)));
+                continue;
+            },
+            _ => {},
+        }
+        output.push_str(raw_line);
+        output.push('\n');
+    }
+    if remaining.next().is_some() {
+        bail!("Fewer TRACK lines than track files");
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CUE sheet for a mixed-mode disc, as written by `toc2cue`
+    const MIXED_CUE: &str = "FILE Some_Game.bin BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:10:00
+  TRACK 03 AUDIO
+    INDEX 00 00:20:00
+    INDEX 01 00:22:00
+";
+
+    #[test]
+    fn msf_to_frames_converts_and_validates() {
+        assert_eq!(msf_to_frames("00:00:00"), Some(0));
+        assert_eq!(msf_to_frames("00:02:00"), Some(150));
+        assert_eq!(msf_to_frames("79:59:74"), Some(359_999));
+        for bad in &["", "00:00", "00:60:00", "00:00:75", "00:00:00:00", "a:b:c"] {
+            assert_eq!(msf_to_frames(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_reads_files_tracks_and_indexes() {
+        let sheet = CueSheet::parse(MIXED_CUE).expect("valid CUE sheet");
+        assert_eq!(sheet.files, ["Some_Game.bin"]);
+        assert_eq!(sheet.tracks.len(), 3);
+        assert_eq!(sheet.tracks[1], CueTrack { number: 2, file: 0, indexes: vec![(1, 750)] });
+        assert_eq!(sheet.tracks[2].indexes, [(0, 1500), (1, 1650)]);
+
+        let quoted = CueSheet::parse("FILE \"My Game.bin\" BINARY\n").expect("valid CUE sheet");
+        assert_eq!(quoted.files, ["My Game.bin"]);
+    }
+
+    #[test]
+    fn file_names_are_quoted() {
+        let spaced = "FILE My Game (Disc 1).bin BINARY\n  TRACK 01 MODE1/2352\n";
+        let quoted = quote_file_names(spaced).expect("quotable");
+        assert_eq!(quoted, "FILE \"My Game (Disc 1).bin\" BINARY\n  TRACK 01 MODE1/2352\n");
+        assert_eq!(
+            CueSheet::parse(&quoted).expect("valid CUE sheet").files,
+            ["My Game (Disc 1).bin"]
+        );
+
+        assert_eq!(quote_file_names(&quoted).expect("already quoted"), quoted);
+        assert!(quote_file_names(MIXED_CUE)
+            .expect("quotable")
+            .starts_with("FILE \"Some_Game.bin\" BINARY\n  TRACK 01"));
+        assert!(quote_file_names("FILE Say \"Hi\".bin BINARY\n").is_err());
+        assert!(quote_file_names("FILE\n").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        for bad in &[
+            "TRACK 01 MODE1/2352\n",
+            "FILE\n",
+            "FILE x.bin BINARY\nINDEX 01 00:00:00\n",
+            "FILE x.bin BINARY\nTRACK 01 MODE1/2352\nINDEX 01 00:99:00\n",
+        ] {
+            assert!(CueSheet::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn problems_checks_offsets_against_bin_size() {
+        let sheet = CueSheet::parse(MIXED_CUE).expect("valid CUE sheet");
+        let bin_len = 2000 * RAW_SECTOR_LEN;
+        assert!(sheet.problems(&[Some(bin_len)], 3).is_empty());
+
+        // Last index is at frame 1650, so a BIN that ends there is truncated
+        let problems = sheet.problems(&[Some(1650 * RAW_SECTOR_LEN)], 3);
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("Track 3 index 1"), "{:?}", problems);
+
+        assert_eq!(sheet.problems(&[Some(bin_len + 1)], 3).len(), 1);
+        assert_eq!(sheet.problems(&[Some(bin_len)], 2).len(), 1);
+        assert_eq!(sheet.problems(&[None], 3).len(), 1);
+    }
+
+    #[test]
+    fn frames_to_msf_round_trips() {
+        for frames in [0, 74, 75, 150, 4499, 4500, 359_999] {
+            assert_eq!(msf_to_frames(&frames_to_msf(frames)), Some(frames));
+        }
+        assert_eq!(frames_to_msf(1650), "00:22:00");
+    }
+
+    #[test]
+    fn track_ranges_cover_the_whole_file() {
+        let sheet = CueSheet::parse(MIXED_CUE).expect("valid CUE sheet");
+        let bin_len = 2000 * RAW_SECTOR_LEN;
+        let ranges = sheet.track_ranges(bin_len).expect("consistent CUE sheet");
+        assert_eq!(
+            ranges,
+            [
+                0..750 * RAW_SECTOR_LEN,
+                750 * RAW_SECTOR_LEN..1500 * RAW_SECTOR_LEN,
+                1500 * RAW_SECTOR_LEN..bin_len
+            ]
+        );
+        assert_eq!(ranges.iter().map(|range| range.end - range.start).sum::<u64>(), bin_len);
+
+        assert!(sheet.track_ranges(1500 * RAW_SECTOR_LEN).is_err());
+        assert!(sheet.track_ranges(bin_len + 1).is_err());
+        let two_files = CueSheet::parse("FILE a.bin BINARY\nFILE b.bin BINARY\n").expect("valid");
+        assert!(two_files.track_ranges(bin_len).is_err());
+    }
+
+    #[test]
+    fn split_cue_text_rebases_indexes() {
+        let tracks = [
+            ("Game (Track 1).bin".to_owned(), 0),
+            ("Game (Track 2).bin".to_owned(), 750),
+            ("Game (Track 3).bin".to_owned(), 1500),
+        ];
+        let split = split_cue_text(MIXED_CUE, &tracks).expect("consistent CUE sheet");
+        assert_eq!(
+            split,
+            "FILE \"Game (Track 1).bin\" BINARY
+  TRACK 01 MODE2/2352
+    INDEX 01 00:00:00
+FILE \"Game (Track 2).bin\" BINARY
+  TRACK 02 AUDIO
+    PREGAP 00:02:00
+    INDEX 01 00:00:00
+FILE \"Game (Track 3).bin\" BINARY
+  TRACK 03 AUDIO
+    INDEX 00 00:00:00
+    INDEX 01 00:02:00
+"
+        );
+
+        let sheet = CueSheet::parse(&split).expect("split CUE sheet parses");
+        assert_eq!(sheet.files.len(), 3);
+        assert_eq!(sheet.tracks[2].file, 2);
+
+        assert!(split_cue_text(MIXED_CUE, &tracks[..2]).is_err());
+        assert!(split_cue_text(
+            MIXED_CUE,
+            &[("x".to_owned(), 0), ("y".to_owned(), 800), ("z".to_owned(), 1500)]
+        )
+        .is_err());
+    }
+}
+
+// vim: set sw=4 sts=4 :
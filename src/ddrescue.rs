@@ -0,0 +1,388 @@
+//! Parsing for GNU ddrescue mapfiles
+//!
+//! See the "Mapfile structure" section of the ddrescue manual for the format:
+//! <https://www.gnu.org/software/ddrescue/manual/ddrescue_manual.html#Mapfile-structure>
+
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+
+/// The state of a block of the input, as recorded in a mapfile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// `?`: Not yet read
+    NonTried,
+    /// `*`: Failed during the copying pass and not yet trimmed
+    NonTrimmed,
+    /// `/`: Trimmed but not yet scraped
+    NonScraped,
+    /// `-`: Bad sector(s) which failed scraping
+    BadSector,
+    /// `+`: Successfully recovered
+    Finished,
+}
+
+impl BlockStatus {
+    /// Convert a status character from a mapfile
+    fn from_char(status: char) -> Option<Self> {
+        match status {
+            '?' => Some(BlockStatus::NonTried),
+            '*' => Some(BlockStatus::NonTrimmed),
+            '/' => Some(BlockStatus::NonScraped),
+            '-' => Some(BlockStatus::BadSector),
+            '+' => Some(BlockStatus::Finished),
+            _ => None,
+        }
+    }
+
+    /// Name for the status in human-readable output
+    pub fn name(self) -> &'static str {
+        match self {
+            BlockStatus::NonTried => "non-tried",
+            BlockStatus::NonTrimmed => "non-trimmed",
+            BlockStatus::NonScraped => "non-scraped",
+            BlockStatus::BadSector => "bad-sector",
+            BlockStatus::Finished => "finished",
+        }
+    }
+}
+
+/// A contiguous range of the input which shares the same status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    /// Offset of the start of the block in bytes
+    pub pos: u64,
+    /// Length of the block in bytes
+    pub size: u64,
+    /// How far recovery of the block got
+    pub status: BlockStatus,
+}
+
+/// The contents of a ddrescue mapfile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapfile {
+    /// The position ddrescue was at when the mapfile was written
+    pub current_pos: u64,
+    /// The blocks making up the input, in order
+    pub blocks: Vec<Block>,
+}
+
+/// Parse a mapfile integer, which may be hexadecimal (as ddrescue writes them) or decimal
+fn parse_int(value: &str) -> Result<u64> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .with_context(|| format!("Invalid integer: {:?}", value))
+}
+
+impl Mapfile {
+    /// Parse the contents of a mapfile
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(idx, line)| (idx + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        // The first non-comment line is the status line: current_pos current_status [pass]
+        let current_pos = match lines.next() {
+            Some((line_num, line)) => {
+                let pos = line.split_whitespace().next().unwrap_or_default();
+                parse_int(pos).with_context(|| format!("Bad status line at line {}", line_num))?
+            },
+            None => bail!("Mapfile contains no status line"),
+        };
+
+        let mut blocks = Vec::new();
+        for (line_num, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (pos, size, status_field) = match fields.as_slice() {
+                [pos, size, status_field] => (pos, size, status_field),
+                _ => bail!("Expected 3 fields at line {}: {:?}", line_num, line),
+            };
+            let mut status_chars = status_field.chars();
+            let status =
+                match (status_chars.next().and_then(BlockStatus::from_char), status_chars.next()) {
+                    (Some(status), None) => status,
+                    _ => {
+                        bail!("Unrecognized block status at line {}: {:?}", line_num, status_field)
+                    },
+                };
+            blocks.push(Block {
+                pos: parse_int(pos)
+                    .with_context(|| format!("Bad position at line {}", line_num))?,
+                size: parse_int(size).with_context(|| format!("Bad size at line {}", line_num))?,
+                status,
+            });
+        }
+        Ok(Mapfile { current_pos, blocks })
+    }
+
+    /// Whether every block of the input was successfully recovered
+    pub fn is_complete(&self) -> bool {
+        self.blocks.iter().all(|block| block.status == BlockStatus::Finished)
+    }
+
+    /// Size of the input in bytes (as far as the mapfile covers it)
+    fn total_len(&self) -> u64 {
+        self.blocks.last().map_or(0, |last| last.pos + last.size)
+    }
+
+    /// List the blocks as CSV, with positions and lengths in `sector_len`-byte sectors
+    pub fn error_map_csv(&self, sector_len: u64) -> String {
+        let mut csv = String::from("start_lba,sectors,status\n");
+        for block in &self.blocks {
+            csv.push_str(&format!(
+                "{},{},{}\n",
+                block.pos / sector_len,
+                block.size.div_ceil(sector_len),
+                block.status.name()
+            ));
+        }
+        csv
+    }
+
+    /// Render a binary PGM image of what was recovered, where each pixel is a run of sectors
+    /// shaded from white (all recovered) to black (none recovered)
+    ///
+    /// Pixels cover the disc in reading order, `width` to a row, with at most `max_rows` rows.
+    /// Any loss at all makes a pixel darker than pure white, so small errors stay visible.
+    pub fn error_map_pgm(&self, sector_len: u64, width: u64, max_rows: u64) -> Vec<u8> {
+        let total = self.total_len();
+        let sectors = total.div_ceil(sector_len);
+        let cols = width.min(sectors).max(1);
+        let rows = (sectors / cols).clamp(1, max_rows.max(1));
+        let count = cols * rows;
+
+        // Split the disc proportionally so the last row doesn't need padding
+        let offset = |pixel: u64| {
+            u64::try_from(u128::from(total) * u128::from(pixel) / u128::from(count))
+                .unwrap_or(total)
+        };
+        let mut image =
+            format!("P5\n# White: recovered, black: unrecovered\n{} {}\n255\n", cols, rows)
+                .into_bytes();
+        let mut first = 0;
+        for pixel in 0..count {
+            let (start, end) = (offset(pixel), offset(pixel + 1));
+            while self.blocks.get(first).is_some_and(|block| block.pos + block.size <= start) {
+                first += 1;
+            }
+            let recovered: u64 = self.blocks[first..]
+                .iter()
+                .take_while(|block| block.pos < end)
+                .filter(|block| block.status == BlockStatus::Finished)
+                .map(|block| (block.pos + block.size).min(end) - block.pos.max(start))
+                .sum();
+            let shade = match end - start {
+                0 => 0,
+                len => recovered * 255 / len,
+            };
+            image.push(u8::try_from(shade).unwrap_or(u8::MAX));
+        }
+        image
+    }
+
+    /// Tally up how much of the input was recovered
+    pub fn summary(&self) -> RescueSummary {
+        let mut summary = RescueSummary::default();
+        let mut in_error = false;
+        for block in &self.blocks {
+            summary.total += block.size;
+            match block.status {
+                BlockStatus::Finished => summary.rescued += block.size,
+                BlockStatus::BadSector => summary.bad_sectors += block.size,
+                BlockStatus::NonTried | BlockStatus::NonTrimmed | BlockStatus::NonScraped => {},
+            }
+
+            // Adjacent unrecovered blocks (eg. a bad sector between two unscraped ones) are
+            // a single damaged area as far as the user is concerned
+            let unrecovered = block.status != BlockStatus::Finished;
+            if unrecovered && !in_error {
+                summary.error_regions += 1;
+            }
+            in_error = unrecovered;
+        }
+        summary
+    }
+}
+
+/// How much of the input a ddrescue run recovered, as tallied from its mapfile
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RescueSummary {
+    /// Size of the input in bytes
+    pub total: u64,
+    /// Bytes successfully recovered
+    pub rescued: u64,
+    /// Bytes in sectors which failed even after scraping
+    pub bad_sectors: u64,
+    /// Number of contiguous areas which weren't recovered
+    pub error_regions: usize,
+}
+
+/// Format a byte count using binary prefixes (eg. `1.5 MiB`)
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    #[allow(clippy::cast_precision_loss)] // Only used for display
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = UNITS[0];
+    for next_unit in &UNITS[1..] {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next_unit;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+impl fmt::Display for RescueSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total size:     {} ({} bytes)", format_size(self.total), self.total)?;
+        writeln!(f, "Rescued:        {} ({} bytes)", format_size(self.rescued), self.rescued)?;
+        writeln!(
+            f,
+            "Bad sectors:    {} ({} bytes)",
+            format_size(self.bad_sectors),
+            self.bad_sectors
+        )?;
+        write!(f, "Error regions:  {}", self.error_regions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mapfile for a disc with a couple of unreadable regions, as written by ddrescue 1.27
+    const DAMAGED_MAP: &str = "# Mapfile. Created by GNU ddrescue version 1.27
+# Command line: ddrescue -b 2048 /dev/sr0 disc.iso disc.log
+# Start time:   2024-01-01 12:00:00
+# Current time: 2024-01-01 12:30:00
+# Finished
+# current_pos  current_status  current_pass
+0x2BC00000     +               1
+#      pos        size  status
+0x00000000  0x0E000000  +
+0x0E000000  0x00010000  -
+0x0E010000  0x1D000000  +
+0x2B010000  0x00000800  /
+0x2B010800  0x00BEF800  +
+";
+
+    #[test]
+    fn parse_reads_status_and_blocks() {
+        let map = Mapfile::parse(DAMAGED_MAP).expect("valid mapfile");
+        assert_eq!(map.current_pos, 0x2BC0_0000);
+        assert_eq!(map.blocks.len(), 5);
+        assert_eq!(
+            map.blocks[1],
+            Block { pos: 0x0E00_0000, size: 0x1_0000, status: BlockStatus::BadSector }
+        );
+        assert_eq!(map.blocks[3].status, BlockStatus::NonScraped);
+        assert!(!map.is_complete());
+    }
+
+    #[test]
+    fn is_complete_for_clean_rip() {
+        let map = Mapfile::parse("0x2BC00000 + 1\n0x00000000 0x2BC00000 +\n").expect("valid");
+        assert!(map.is_complete());
+    }
+
+    #[test]
+    fn parse_accepts_decimal_and_missing_pass() {
+        let map = Mapfile::parse("0 ?\n0 4096 ?\n").expect("valid mapfile");
+        assert_eq!(map.blocks, [Block { pos: 0, size: 4096, status: BlockStatus::NonTried }]);
+        assert!(!map.is_complete());
+    }
+
+    #[test]
+    fn summary_tallies_damage() {
+        let summary = Mapfile::parse(DAMAGED_MAP).expect("valid mapfile").summary();
+        assert_eq!(
+            summary,
+            RescueSummary {
+                total: 0x2BC0_0000,
+                rescued: 0x2BC0_0000 - 0x1_0000 - 0x800,
+                bad_sectors: 0x1_0000,
+                error_regions: 2,
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "Total size:     700.0 MiB (734003200 bytes)\n\
+             Rescued:        699.9 MiB (733935616 bytes)\n\
+             Bad sectors:    64.0 KiB (65536 bytes)\n\
+             Error regions:  2"
+        );
+    }
+
+    #[test]
+    fn summary_merges_adjacent_errors() {
+        let map = Mapfile::parse("0 +\n0 2048 +\n2048 2048 *\n4096 2048 -\n6144 2048 /\n")
+            .expect("valid mapfile");
+        let summary = map.summary();
+        assert_eq!(summary.error_regions, 1);
+        assert_eq!((summary.total, summary.rescued, summary.bad_sectors), (8192, 2048, 2048));
+    }
+
+    #[test]
+    fn error_map_csv_lists_blocks_in_sectors() {
+        let map = Mapfile::parse("0 +\n0 4096 +\n4096 2048 -\n6144 1000 ?\n").expect("valid");
+        assert_eq!(
+            map.error_map_csv(2048),
+            "start_lba,sectors,status\n0,2,finished\n2,1,bad-sector\n3,1,non-tried\n"
+        );
+    }
+
+    #[test]
+    fn error_map_pgm_shades_by_recovery() {
+        // 8 sectors: 4 good, 1 bad, 1 unscraped, 2 good
+        let map = Mapfile::parse("0 +\n0 8192 +\n8192 2048 -\n10240 2048 /\n12288 4096 +\n")
+            .expect("valid mapfile");
+        let header = b"P5\n# White: recovered, black: unrecovered\n";
+
+        let full = map.error_map_pgm(2048, 4, 16);
+        assert!(full.starts_with(header));
+        assert_eq!(full[header.len()..], *b"4 2\n255\n\xff\xff\xff\xff\x00\x00\xff\xff");
+
+        // Two sectors per pixel, and a partly-recovered pixel is grey
+        let half = map.error_map_pgm(2048, 2, 2);
+        assert_eq!(half[header.len()..], *b"2 2\n255\n\xff\xff\x00\xff");
+        let uneven = Mapfile::parse("0 +\n0 2048 +\n2048 2048 -\n").expect("valid mapfile");
+        assert_eq!(uneven.error_map_pgm(2048, 1, 1)[header.len()..], *b"1 1\n255\n\x7f");
+
+        // Images never end up empty, even for an empty map
+        let empty = Mapfile::parse("0 ?\n").expect("valid mapfile").error_map_pgm(2048, 256, 256);
+        assert_eq!(empty[header.len()..], *b"1 1\n255\n\x00");
+    }
+
+    #[test]
+    fn format_size_picks_units() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(4_700_000_000), "4.4 GiB");
+    }
+
+    #[test]
+    fn parse_rejects_malformed_input() {
+        for bad in &[
+            "",
+            "# Only comments\n",
+            "bogus +\n",
+            "0x0 +\n0x0 0x800\n",
+            "0x0 +\n0x0 0x800 X\n",
+            "0x0 +\n0x0 0x800 ++\n",
+            "0x0 +\n0xZZ 0x800 +\n",
+        ] {
+            assert!(Mapfile::parse(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
+
+// vim: set sw=4 sts=4 :
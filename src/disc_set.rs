@@ -0,0 +1,359 @@
+//! Progress tracking for multi-disc sets, so an interrupted run can pick up where it left off
+
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::hash_reader;
+use crate::platform::{MediaProvider, NotificationProvider, RawMediaProvider};
+use crate::subcommands::{out_of_time, rip, Layout, ModeOptions, RipOptions, RipOutput};
+
+/// Name of the file (in the directory the set is being ripped into) which tracks progress
+pub const SET_STATE_FILE: &str = ".rip_media_set.json";
+
+/// An output file from a completed disc, as it was when the disc finished ripping
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RippedFile {
+    /// Filename, relative to the disc's output directory
+    pub name: String,
+    /// Length in bytes
+    pub size: u64,
+    /// SHA-1 of the contents as lowercase hex
+    pub sha1: String,
+}
+
+/// A disc in the set which has been ripped successfully
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RippedDisc {
+    /// Position of the disc in the set (starting from 1)
+    pub number: u16,
+    /// The directory the disc was ripped into
+    pub dir: PathBuf,
+    /// The files that were in `dir` when the rip finished
+    pub files: Vec<RippedFile>,
+}
+
+/// Hash the contents of `path`
+fn hash_file(path: &Path) -> Result<RippedFile> {
+    let file = File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+    let hashes = hash_reader(file).with_context(|| format!("Could not read {}", path.display()))?;
+    Ok(RippedFile {
+        name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+        size: hashes.size,
+        sha1: hashes.sha1_hex(),
+    })
+}
+
+impl RippedDisc {
+    /// Record the current contents of `dir` as the output of disc `number`
+    pub fn new(number: u16, dir: PathBuf) -> Result<Self> {
+        let mut paths = Vec::new();
+        for entry in
+            fs::read_dir(&dir).with_context(|| format!("Could not list {}", dir.display()))?
+        {
+            let path = entry.with_context(|| format!("Could not list {}", dir.display()))?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        let files = paths.iter().map(|path| hash_file(path)).collect::<Result<_>>()?;
+        Ok(RippedDisc { number, dir, files })
+    }
+
+    /// Record only the named files in `dir` as the output of disc `number`
+    ///
+    /// (For when discs share a directory, as with `--layout flat`)
+    pub fn with_files(number: u16, dir: PathBuf, names: &[String]) -> Result<Self> {
+        let files = names.iter().map(|name| hash_file(&dir.join(name))).collect::<Result<_>>()?;
+        Ok(RippedDisc { number, dir, files })
+    }
+
+    /// Check that every recorded file is still present with the same contents
+    pub fn verify(&self) -> Result<()> {
+        for expected in &self.files {
+            let actual = hash_file(&self.dir.join(&expected.name))?;
+            if actual != *expected {
+                bail!(
+                    "{} has changed since it was ripped",
+                    self.dir.join(&expected.name).display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which discs of a set have been ripped so far
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetState {
+    /// Total number of discs in the set
+    pub set_size: u16,
+    /// The discs which have been ripped successfully
+    pub discs: Vec<RippedDisc>,
+    /// The folder the discs are being ripped into (`None` for flat layouts, or until the first
+    /// disc is done)
+    #[serde(default)]
+    pub set_dir: Option<PathBuf>,
+}
+
+impl SetState {
+    /// Start tracking a new set with no discs ripped
+    pub fn new(set_size: u16) -> Self {
+        SetState { set_size, discs: Vec::new(), set_dir: None }
+    }
+
+    /// Load the state of the unfinished set at `path` if `resume` is set, or start a new one
+    ///
+    /// Fails if the saved state doesn't match `resume` or is for a different size of set.
+    pub fn resume_or_start(path: &Path, set_size: u16, resume: bool) -> Result<Self> {
+        match Self::load(path)? {
+            Some(state) if resume => {
+                if state.set_size != set_size {
+                    bail!(
+                        "{} is for a set of {} discs, not {}",
+                        path.display(),
+                        state.set_size,
+                        set_size
+                    );
+                }
+                Ok(state)
+            },
+            Some(_) => bail!(
+                "Found an unfinished set ({}). Use --resume-set to continue it.",
+                path.display()
+            ),
+            None if resume => bail!("No unfinished set to resume ({} not found)", path.display()),
+            None => Ok(Self::new(set_size)),
+        }
+    }
+
+    /// Load the state saved at `path`, returning `None` if there isn't any
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        let json = match fs::read_to_string(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Could not read {}", path.display())),
+        };
+        serde_json::from_str(&json)
+            .map(Some)
+            .with_context(|| format!("Could not parse {}", path.display()))
+    }
+
+    /// Save the state to `path`, replacing it atomically so an interruption can't corrupt it
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut tmp_name = path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let json = serde_json::to_string_pretty(self).context("Could not serialize set state")?;
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("Could not write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path).with_context(|| format!("Could not replace {}", path.display()))
+    }
+
+    /// The record for disc `number`, if it has been ripped
+    pub fn disc(&self, number: u16) -> Option<&RippedDisc> {
+        self.discs.iter().find(|disc| disc.number == number)
+    }
+
+    /// Whether disc `number` has already been ripped, failing if its output has changed since
+    pub fn already_ripped(&self, number: u16) -> Result<bool> {
+        let Some(done) = self.disc(number) else { return Ok(false) };
+        done.verify().with_context(|| {
+            format!(
+                "Disc {} of {} was ripped to {} but failed verification. Move it aside to rip \
+                 the disc again.",
+                number,
+                self.set_size,
+                done.dir.display()
+            )
+        })?;
+        info!(
+            "Disc {} of {} already ripped to {}. Skipping.",
+            number,
+            self.set_size,
+            done.dir.display()
+        );
+        Ok(true)
+    }
+
+    /// Add (or replace) the record for a completed disc
+    pub fn record(&mut self, disc: RippedDisc) {
+        self.discs.retain(|existing| existing.number != disc.number);
+        self.discs.push(disc);
+        self.discs.sort_by_key(|existing| existing.number);
+    }
+
+    /// Record `output` as the output of disc `number`
+    ///
+    /// If `shared_dir` is set, only the files it produced are recorded, since the other discs'
+    /// files (or archives) are in the same directory.
+    pub fn record_output(
+        &mut self,
+        number: u16,
+        output: RipOutput,
+        shared_dir: bool,
+    ) -> Result<()> {
+        self.set_dir = output.set_dir;
+        self.record(if shared_dir {
+            RippedDisc::with_files(number, output.dir, &output.files)?
+        } else {
+            RippedDisc::new(number, output.dir)?
+        });
+        Ok(())
+    }
+}
+
+/// Rip each disc of a `set_size`-disc set in turn
+///
+/// Progress is recorded in `SET_STATE_FILE` so that, if `resume` is set, discs which were
+/// already ripped (and whose output still matches its recorded checksums) can be skipped.
+pub fn rip_set<P, F>(
+    plat_provider: &mut P,
+    mode_func: F,
+    mut opts: RipOptions<'_>,
+    set_size: u16,
+    resume: bool,
+) -> Result<()>
+where
+    P: MediaProvider + RawMediaProvider + NotificationProvider,
+    F: Fn(&mut P, &str, &ModeOptions) -> Result<()>,
+{
+    if set_size == 1 && !resume {
+        return rip(plat_provider, mode_func, &opts).map(|_| ());
+    }
+
+    let state_path = Path::new(SET_STATE_FILE);
+    let mut state = SetState::resume_or_start(state_path, set_size, resume)?;
+    opts.set_dir.clone_from(&state.set_dir);
+    // Every disc's files (or archives) are in the same directory, so only count each one's own
+    let shared_dir = opts.layout == Layout::Flat || opts.compress;
+
+    let mut ripped = 0;
+    for number in 1..=set_size {
+        if state.already_ripped(number)? {
+            continue;
+        }
+        if out_of_time(opts.deadline, ripped) {
+            state.save(state_path)?;
+            info!("Use --resume-set to rip discs {} to {} later.", number, set_size);
+            return Ok(());
+        }
+
+        opts.set_position = Some((number, set_size));
+        let output = rip(plat_provider, &mode_func, &opts)
+            .with_context(|| format!("Failed to rip disc {} of {}", number, set_size))?;
+        opts.previous_serial.clone_from(&output.serial);
+        opts.set_dir.clone_from(&output.set_dir);
+        ripped += 1;
+        state.record_output(number, output, shared_dir)?;
+        state.save(state_path)?;
+    }
+
+    fs::remove_file(state_path)
+        .with_context(|| format!("Could not remove {}", state_path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn ripped_disc_verify_detects_changes() {
+        let dir = scratch_dir("set_verify");
+        fs::write(dir.join("Disc.cue"), b"FILE Disc.bin BINARY\n").expect("write cue");
+        fs::write(dir.join("Disc.bin"), vec![0_u8; 2352]).expect("write bin");
+
+        let disc = RippedDisc::new(2, dir.clone()).expect("hash outputs");
+        assert_eq!(
+            disc.files.iter().map(|f| &*f.name).collect::<Vec<_>>(),
+            ["Disc.bin", "Disc.cue"]
+        );
+        assert_eq!(disc.files[0].size, 2352);
+        disc.verify().expect("nothing has changed yet");
+
+        fs::write(dir.join("Disc.bin"), vec![1_u8; 2352]).expect("corrupt bin");
+        assert!(disc.verify().is_err());
+        fs::remove_file(dir.join("Disc.bin")).expect("delete bin");
+        assert!(disc.verify().is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn ripped_disc_with_files_ignores_other_discs() {
+        let dir = scratch_dir("set_with_files");
+        fs::write(dir.join("Disc_1.iso"), vec![1_u8; 2048]).expect("write disc 1");
+        fs::write(dir.join("Disc_2.iso"), vec![2_u8; 2048]).expect("write disc 2");
+
+        let disc = RippedDisc::with_files(2, dir.clone(), &["Disc_2.iso".to_owned()])
+            .expect("hash outputs");
+        assert_eq!(disc.files.iter().map(|f| &*f.name).collect::<Vec<_>>(), ["Disc_2.iso"]);
+        fs::write(dir.join("Disc_1.iso"), b"changed").expect("change other disc");
+        disc.verify().expect("other discs' files aren't part of this one");
+        assert!(RippedDisc::with_files(3, dir.clone(), &["Disc_3.iso".to_owned()]).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn set_state_round_trips_and_replaces_records() {
+        let dir = scratch_dir("set_state");
+        let path = dir.join(SET_STATE_FILE);
+        assert_eq!(SetState::load(&path).expect("missing state isn't an error"), None);
+
+        let mut state = SetState::new(3);
+        let disc = |number, sha1: &str| RippedDisc {
+            number,
+            dir: PathBuf::from(format!("Game_(Disc_{})", number)),
+            files: vec![RippedFile { name: "Game.iso".to_owned(), size: 1, sha1: sha1.to_owned() }],
+        };
+        state.record(disc(2, "aa"));
+        state.record(disc(1, "bb"));
+        state.record(disc(2, "cc"));
+        assert_eq!(state.discs.iter().map(|d| d.number).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(state.disc(2).map(|d| &*d.files[0].sha1), Some("cc"));
+        assert_eq!(state.disc(3), None);
+
+        state.save(&path).expect("save state");
+        assert_eq!(SetState::load(&path).expect("load state"), Some(state));
+
+        fs::write(&path, b"{").expect("truncate state");
+        assert!(SetState::load(&path).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn only_matching_unfinished_sets_are_resumed() {
+        let dir = scratch_dir("set_resume");
+        let path = dir.join(SET_STATE_FILE);
+        assert_eq!(SetState::resume_or_start(&path, 3, false).expect("new set"), SetState::new(3));
+        assert!(SetState::resume_or_start(&path, 3, true).is_err(), "Nothing to resume");
+
+        SetState::new(3).save(&path).expect("save state");
+        assert!(SetState::resume_or_start(&path, 3, false).is_err(), "Would clobber the set");
+        assert!(SetState::resume_or_start(&path, 2, true).is_err(), "Wrong set size");
+        assert_eq!(SetState::resume_or_start(&path, 3, true).expect("resume"), SetState::new(3));
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn already_ripped_discs_must_still_match() {
+        let dir = scratch_dir("set_already_ripped");
+        fs::write(dir.join("Game.iso"), vec![1_u8; 2048]).expect("write disc 1");
+        let mut state = SetState::new(2);
+        state.record(RippedDisc::new(1, dir.clone()).expect("hash outputs"));
+
+        assert!(state.already_ripped(1).expect("unchanged"));
+        assert!(!state.already_ripped(2).expect("not ripped yet"));
+        fs::write(dir.join("Game.iso"), b"changed").expect("change disc 1");
+        assert!(state.already_ripped(1).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
+
+// vim: set sw=4 sts=4 :
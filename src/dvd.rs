@@ -0,0 +1,106 @@
+//! Detection of DVD-Video copy protection, so users know what they're getting before dumping
+
+use std::io::{Read, Seek, SeekFrom};
+
+use anyhow::Result;
+use log::debug;
+
+use crate::iso9660::{find_path, read_dir};
+
+/// Size of a DVD sector (and of the MPEG-2 program stream packs in a VOB)
+const SECTOR_LEN: usize = 2048;
+
+/// Number of sectors at the start of a VOB to check for scrambling
+///
+/// (The first few packs of a title are often unscrambled navigation and audio packs.)
+const SAMPLE_SECTORS: usize = 128;
+
+/// Whether a DVD's video is protected by CSS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CssStatus {
+    /// No `VIDEO_TS` directory, so not a DVD-Video disc
+    NotVideo,
+    /// DVD-Video with no scrambled sectors found
+    Clear,
+    /// DVD-Video with scrambled sectors
+    Scrambled,
+    /// DVD-Video where the drive refused to read the video (It wants CSS authentication first)
+    Unreadable,
+}
+
+/// Check whether a VOB sector holds a PES packet marked as scrambled
+///
+/// Mirrors the check in libdvdcss: byte `0x14` holds the `PES_scrambling_control` bits for
+/// every stream type which has them (ie. not system headers, padding, or `private_stream_2`).
+pub fn is_scrambled(sector: &[u8]) -> bool {
+    let is_pack = sector.starts_with(&[0, 0, 1, 0xBA]);
+    let has_scrambling_bits = !matches!(sector.get(0x11), Some(0xBB | 0xBE | 0xBF) | None);
+    is_pack && has_scrambling_bits && sector.get(0x14).is_some_and(|flags| flags & 0x30 != 0)
+}
+
+/// Look for CSS-scrambled video on a disc or image by sampling the start of its largest VOB
+pub fn detect_css<R: Read + Seek>(reader: &mut R) -> Result<CssStatus> {
+    let Some(video_ts) = find_path(reader, &["VIDEO_TS"])? else {
+        return Ok(CssStatus::NotVideo);
+    };
+    let Some(vob) = read_dir(reader, &video_ts)?
+        .into_iter()
+        .filter(|entry| !entry.is_dir && entry.name.to_ascii_uppercase().ends_with(".VOB"))
+        .max_by_key(|entry| entry.size)
+    else {
+        return Ok(CssStatus::Clear);
+    };
+
+    let sample_len = (vob.size as usize).min(SAMPLE_SECTORS * SECTOR_LEN);
+    let mut sample = vec![0; sample_len - sample_len % SECTOR_LEN];
+    let read_result = reader
+        .seek(SeekFrom::Start(u64::from(vob.extent) * SECTOR_LEN as u64))
+        .and_then(|_| reader.read_exact(&mut sample));
+    if let Err(e) = read_result {
+        debug!("Could not read {}: {}", vob.name, e);
+        return Ok(CssStatus::Unreadable);
+    }
+
+    Ok(if sample.chunks(SECTOR_LEN).any(is_scrambled) {
+        CssStatus::Scrambled
+    } else {
+        CssStatus::Clear
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    /// Build a VOB sector holding a PES packet for `stream_id` with the given flags byte
+    fn pes_sector(stream_id: u8, flags: u8) -> Vec<u8> {
+        let mut sector = vec![0; SECTOR_LEN];
+        sector[..4].copy_from_slice(&[0, 0, 1, 0xBA]);
+        sector[0x0E..0x12].copy_from_slice(&[0, 0, 1, stream_id]);
+        sector[0x14] = flags;
+        sector
+    }
+
+    #[test]
+    fn is_scrambled_checks_pes_scrambling_control() {
+        assert!(is_scrambled(&pes_sector(0xE0, 0x80 | 0x10))); // Video
+        assert!(is_scrambled(&pes_sector(0xBD, 0x80 | 0x20))); // Private stream 1 (AC3 audio)
+        assert!(!is_scrambled(&pes_sector(0xE0, 0x80)));
+        assert!(!is_scrambled(&pes_sector(0xBB, 0xFF))); // System header (NAV pack)
+        assert!(!is_scrambled(&pes_sector(0xBF, 0xFF))); // Private stream 2 (NAV data)
+
+        let mut not_a_pack = pes_sector(0xE0, 0x30);
+        not_a_pack[3] = 0;
+        assert!(!is_scrambled(&not_a_pack));
+        assert!(!is_scrambled(&[]));
+    }
+
+    #[test]
+    fn detect_css_ignores_non_video_discs() {
+        let mut iso = File::open("fixture.iso").expect("fixture.iso exists");
+        assert_eq!(detect_css(&mut iso).expect("readable fixture"), CssStatus::NotVideo);
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,223 @@
+//! On-disk cache of file checksums, so re-validating a large library only rehashes what changed
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::hash_reader;
+
+/// What a file looked like when it was last hashed, and the hash it had
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Size in bytes
+    size: u64,
+    /// Modification time, in nanoseconds since the Unix epoch
+    mtime_ns: u128,
+    /// SHA-256 digest as lowercase hex
+    sha256: String,
+}
+
+impl CacheEntry {
+    /// Whether this entry still describes a file with the given metadata
+    fn matches(&self, meta: &Metadata) -> bool {
+        Some((self.size, self.mtime_ns)) == fingerprint(meta)
+    }
+}
+
+/// The `(size, mtime)` pair used to decide whether a file has changed since it was hashed
+///
+/// (`None` if the platform can't report modification times, which makes everything a miss.)
+fn fingerprint(meta: &Metadata) -> Option<(u64, u128)> {
+    let mtime = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((meta.len(), mtime.as_nanos()))
+}
+
+/// SHA-256 digests of files, keyed by absolute path and invalidated by size or mtime changes
+#[derive(Debug, Default)]
+pub struct HashCache {
+    /// Where the cache is loaded from and saved to
+    path: PathBuf,
+    /// The cached digests
+    entries: BTreeMap<PathBuf, CacheEntry>,
+    /// Whether `entries` has changed since it was loaded
+    dirty: bool,
+    /// Whether to ignore cached digests (while still recording new ones)
+    rehash: bool,
+}
+
+impl HashCache {
+    /// Load the cache saved at `path`
+    ///
+    /// A missing or unreadable cache just means everything gets hashed again, so it's never
+    /// an error.
+    pub fn load(path: PathBuf) -> Self {
+        let entries = match fs::read_to_string(&path) {
+            Ok(json) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                warn!("Ignoring corrupt checksum cache {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(e) => {
+                if e.kind() != io::ErrorKind::NotFound {
+                    warn!("Could not read checksum cache {}: {}", path.display(), e);
+                }
+                BTreeMap::new()
+            },
+        };
+        HashCache { path, entries, dirty: false, rehash: false }
+    }
+
+    /// Ignore the cached digests and hash every file again, recording the fresh results
+    pub fn with_rehash(mut self, rehash: bool) -> Self {
+        self.rehash = rehash;
+        self
+    }
+
+    /// Get the SHA-256 digest of `path` as lowercase hex, hashing it only if it's not in the
+    /// cache or has changed since it was
+    pub fn sha256_hex(&mut self, path: &Path) -> io::Result<String> {
+        let key = fs::canonicalize(path)?;
+        let before = fs::metadata(&key)?;
+        let cached = self.entries.get(&key).filter(|entry| !self.rehash && entry.matches(&before));
+        if let Some(entry) = cached {
+            debug!("Using cached checksum for {}", key.display());
+            return Ok(entry.sha256.clone());
+        }
+
+        let sha256 = hash_reader(File::open(&key)?)?.sha256_hex();
+
+        // Don't cache a hash of something that was being modified while we read it
+        let after = fs::metadata(&key)?;
+        match (fingerprint(&before), fingerprint(&after)) {
+            (Some((size, mtime_ns)), Some(now)) if (size, mtime_ns) == now => {
+                let entry = CacheEntry { size, mtime_ns, sha256: sha256.clone() };
+                self.entries.insert(key, entry);
+                self.dirty = true;
+            },
+            _ => {
+                if self.entries.remove(&key).is_some() {
+                    self.dirty = true;
+                }
+            },
+        }
+        Ok(sha256)
+    }
+
+    /// Save the cache back to where it was loaded from (if anything changed), replacing it
+    /// atomically so an interruption can't corrupt it
+    pub fn save(&self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create {}", parent.display()))?;
+        }
+        let mut tmp_name = self.path.as_os_str().to_owned();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+
+        let json = serde_json::to_string(&self.entries).context("Could not serialize cache")?;
+        fs::write(&tmp_path, json)
+            .with_context(|| format!("Could not write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, &self.path)
+            .with_context(|| format!("Could not replace {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use std::time::{Duration, SystemTime};
+
+    /// Replace the contents of `path` without changing its size or modification time
+    fn swap_contents_behind_cache(path: &Path, contents: &[u8]) {
+        let mtime = fs::metadata(path).and_then(|meta| meta.modified()).expect("stat");
+        fs::write(path, contents).expect("rewrite file");
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(mtime))
+            .expect("restore mtime");
+    }
+
+    #[test]
+    fn unchanged_files_are_not_rehashed() {
+        let dir = scratch_dir("hash_cache_hit");
+        let file = dir.join("Disc.iso");
+        fs::write(&file, b"original").expect("write file");
+        let original = hash_reader(&b"original"[..]).expect("in-memory").sha256_hex();
+
+        let mut cache = HashCache::load(dir.join("cache.json"));
+        assert_eq!(cache.sha256_hex(&file).expect("hash"), original);
+
+        // Same size and mtime, so the stale digest proves the cache was used
+        swap_contents_behind_cache(&file, b"modified");
+        assert_eq!(cache.sha256_hex(&file).expect("cached"), original);
+
+        // ...unless rehashing was forced, which then updates the cache
+        let modified = hash_reader(&b"modified"[..]).expect("in-memory").sha256_hex();
+        let mut forced = cache.with_rehash(true);
+        assert_eq!(forced.sha256_hex(&file).expect("rehash"), modified);
+        assert_eq!(forced.with_rehash(false).sha256_hex(&file).expect("cached"), modified);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn size_or_mtime_changes_invalidate_entries() {
+        let dir = scratch_dir("hash_cache_invalidate");
+        let file = dir.join("Disc.iso");
+        fs::write(&file, b"original").expect("write file");
+        let mut cache = HashCache::load(dir.join("cache.json"));
+        cache.sha256_hex(&file).expect("hash");
+
+        fs::write(&file, b"longer contents").expect("resize file");
+        let resized = hash_reader(&b"longer contents"[..]).expect("in-memory").sha256_hex();
+        assert_eq!(cache.sha256_hex(&file).expect("rehash"), resized);
+
+        // Same size, but a different mtime
+        swap_contents_behind_cache(&file, b"longer Contents");
+        File::options()
+            .write(true)
+            .open(&file)
+            .and_then(|handle| handle.set_modified(SystemTime::now() + Duration::from_secs(5)))
+            .expect("touch file");
+        let touched = hash_reader(&b"longer Contents"[..]).expect("in-memory").sha256_hex();
+        assert_eq!(cache.sha256_hex(&file).expect("rehash"), touched);
+
+        fs::remove_file(&file).expect("delete file");
+        assert!(cache.sha256_hex(&file).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn cache_round_trips_and_tolerates_corruption() {
+        let dir = scratch_dir("hash_cache_save");
+        let file = dir.join("Disc.iso");
+        let cache_path = dir.join("cache").join("hashes.json");
+        fs::write(&file, b"original").expect("write file");
+
+        let mut cache = HashCache::load(cache_path.clone());
+        cache.save().expect("nothing to save");
+        assert!(!cache_path.exists(), "An unchanged cache shouldn't be written");
+        let original = cache.sha256_hex(&file).expect("hash");
+        cache.save().expect("save cache");
+
+        swap_contents_behind_cache(&file, b"modified");
+        let mut reloaded = HashCache::load(cache_path.clone());
+        assert_eq!(reloaded.sha256_hex(&file).expect("cached"), original);
+
+        fs::write(&cache_path, b"{").expect("corrupt cache");
+        let mut fresh = HashCache::load(cache_path);
+        assert_ne!(fresh.sha256_hex(&file).expect("rehash"), original);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+}
+
+// vim: set sw=4 sts=4 :
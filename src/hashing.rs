@@ -0,0 +1,383 @@
+//! Computing, recording, and checking the checksums used to identify dumps (CRC32, MD5, SHA-1,
+//! SHA-256)
+
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::hash_cache::HashCache;
+use crate::sink::OutputSink;
+use crate::subcommands::{file_names, files_with_extension, has_extension, volume_file};
+
+/// Size of the chunks data is hashed in
+const HASH_BUF_LEN: usize = 64 * 1024;
+
+/// How often to check for newly-dumped data when hashing an image as it's written
+const HASH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Format bytes as lowercase hexadecimal
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut out, byte| {
+        let _ = write!(out, "{:02x}", byte);
+        out
+    })
+}
+
+/// The checksums of a completed stream of data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hashes {
+    /// Number of bytes hashed
+    pub size: u64,
+    /// CRC32 (as used by ZIP and No-Intro/Redump DATs)
+    pub crc32: u32,
+    /// MD5 digest
+    pub md5: [u8; 16],
+    /// SHA-1 digest
+    pub sha1: [u8; 20],
+    /// SHA-256 digest
+    pub sha256: [u8; 32],
+}
+
+impl Hashes {
+    /// The CRC32 as 8 lowercase hex digits
+    pub fn crc32_hex(&self) -> String {
+        format!("{:08x}", self.crc32)
+    }
+
+    /// The MD5 digest as lowercase hex
+    pub fn md5_hex(&self) -> String {
+        to_hex(&self.md5)
+    }
+
+    /// The SHA-1 digest as lowercase hex
+    pub fn sha1_hex(&self) -> String {
+        to_hex(&self.sha1)
+    }
+
+    /// The SHA-256 digest as lowercase hex
+    pub fn sha256_hex(&self) -> String {
+        to_hex(&self.sha256)
+    }
+}
+
+/// Computes all of the supported checksums in a single pass over the data
+#[derive(Default)]
+pub struct MultiHasher {
+    /// Running byte count
+    size: u64,
+    /// Running CRC32 state
+    crc32: crc32fast::Hasher,
+    /// Running MD5 state
+    md5: Md5,
+    /// Running SHA-1 state
+    sha1: Sha1,
+    /// Running SHA-256 state
+    sha256: Sha256,
+}
+
+impl MultiHasher {
+    /// Feed a chunk of data into all of the hashes
+    pub fn update(&mut self, data: &[u8]) {
+        self.size = self.size.saturating_add(data.len() as u64);
+        self.crc32.update(data);
+        self.md5.update(data);
+        self.sha1.update(data);
+        self.sha256.update(data);
+    }
+
+    /// Consume the hasher and return the final checksums
+    pub fn finish(self) -> Hashes {
+        Hashes {
+            size: self.size,
+            crc32: self.crc32.finalize(),
+            md5: self.md5.finalize().into(),
+            sha1: self.sha1.finalize().into(),
+            sha256: self.sha256.finalize().into(),
+        }
+    }
+}
+
+/// Hash everything that can be read from `reader`
+pub fn hash_reader<R: Read>(mut reader: R) -> io::Result<Hashes> {
+    let mut hasher = MultiHasher::default();
+    let mut buf = vec![0; HASH_BUF_LEN];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => return Ok(hasher.finish()),
+            Ok(len) => hasher.update(buf.get(..len).unwrap_or_default()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Hash a file while another process is still appending to it, finishing once `writer_done`
+/// is signalled (or its sender is dropped) and the last of the data has been read
+///
+/// Bytes are hashed as soon as they appear, so this is only correct if the writer never goes
+/// back to change data it has already written.
+pub fn hash_growing_file(
+    path: &Path,
+    writer_done: &Receiver<()>,
+    poll_interval: Duration,
+) -> io::Result<Hashes> {
+    let is_done = || !matches!(writer_done.try_recv(), Err(TryRecvError::Empty));
+
+    // The writer may not have created the file yet
+    let mut file = loop {
+        // (Check before trying so a file created just before the writer finished isn't missed)
+        let done = is_done();
+        match File::open(path) {
+            Ok(file) => break file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !done => sleep(poll_interval),
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut hasher = MultiHasher::default();
+    let mut buf = vec![0; HASH_BUF_LEN];
+    loop {
+        let done = is_done();
+        match file.read(&mut buf) {
+            Ok(0) if done => return Ok(hasher.finish()),
+            Ok(0) => sleep(poll_interval),
+            Ok(len) => hasher.update(buf.get(..len).unwrap_or_default()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run `write`, which writes `outfile` front-to-back, while hashing `outfile` as it grows
+///
+/// (ddrescue seeks around in its output, so it can't write into a FIFO we could hash from.
+///  Following the file as it's written gets the same result, as long as nothing goes back to
+///  rewrite data we've already hashed.)
+pub fn hash_while_writing<F: FnOnce() -> Result<()>>(outfile: &Path, write: F) -> Result<Hashes> {
+    let (finished, writer_done) = mpsc::channel::<()>();
+    let path = outfile.to_owned();
+    let hasher = thread::spawn(move || hash_growing_file(&path, &writer_done, HASH_POLL_INTERVAL));
+    let result = write();
+    drop(finished);
+    let hash_result = hasher.join().map_err(|_| anyhow!("Hashing thread panicked"))?;
+    result?;
+    hash_result.with_context(|| format!("Could not hash {} while dumping it", outfile.display()))
+}
+
+/// Format a line of a `sha256sum`-compatible checksum file
+pub fn sha256sum_line(hashes: &Hashes, name: &str) -> String {
+    format!("{}  {}\n", hashes.sha256_hex(), name)
+}
+
+/// Write `<isofile>.sha256`, hashing `isofile` now unless it was `streamed` while dumping
+pub fn write_sha256_file(
+    sink: &dyn OutputSink,
+    isofile: &Path,
+    streamed: Option<Hashes>,
+) -> Result<()> {
+    let hashes = if let Some(streamed_hashes) = streamed {
+        streamed_hashes
+    } else {
+        info!("Hashing {}...", isofile.display());
+        let file =
+            File::open(isofile).with_context(|| format!("Could not open {}", isofile.display()))?;
+        hash_reader(file).with_context(|| format!("Could not read {}", isofile.display()))?
+    };
+    let mut sumfile = isofile.as_os_str().to_owned();
+    sumfile.push(".sha256");
+    let name = isofile.file_name().unwrap_or_default().to_string_lossy();
+    sink.write(Path::new(&sumfile), sha256sum_line(&hashes, &name).as_bytes())
+        .with_context(|| format!("Could not write {}", Path::new(&sumfile).display()))
+}
+
+/// Check every `sha256sum`-style checksum file in `dir` against the files it lists
+///
+/// If a `cache` is given, files which haven't changed since it last hashed them aren't reread.
+pub fn verify_checksum_files(dir: &Path, mut cache: Option<&mut HashCache>) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    for sumfile in files_with_extension(dir, "sha256")? {
+        let text = fs::read_to_string(&sumfile)
+            .with_context(|| format!("Could not read {}", sumfile.display()))?;
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            // (`sha256sum` marks binary-mode entries with a `*` before the filename)
+            let Some((expected, name)) = line.split_once(' ') else {
+                problems.push(format!("Malformed line in {}: {}", sumfile.display(), line));
+                continue;
+            };
+            let filename = name.trim_start_matches([' ', '*']);
+            let path = dir.join(filename);
+            info!("Hashing {}...", path.display());
+            let digest = match cache.as_deref_mut() {
+                Some(hashes) => hashes.sha256_hex(&path),
+                None => File::open(&path).and_then(hash_reader).map(|hashes| hashes.sha256_hex()),
+            };
+            match digest {
+                Ok(actual) if actual.eq_ignore_ascii_case(expected) => {},
+                Ok(_) => {
+                    problems.push(format!("{} doesn't match its SHA-256 checksum", filename));
+                },
+                Err(e) => problems.push(format!("Could not hash {}: {}", filename, e)),
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// Write `<disc_name>.sha256` into `dir`, listing the SHA-256 of every other file there
+///
+/// (Checksum files, including any older copy of this one, are left out since they can't
+///  usefully be checked against themselves.)
+pub fn write_checksums(dir: &Path, disc_name: &str) -> Result<PathBuf> {
+    let sumfile = dir.join(volume_file(Path::new(disc_name), "sha256"));
+    let mut sums = String::new();
+    for name in file_names(dir)? {
+        if has_extension(&name, "sha256") || has_extension(&name, "par2") {
+            continue;
+        }
+        let path = dir.join(&name);
+        info!("Hashing {}...", path.display());
+        let hashes = File::open(&path)
+            .and_then(hash_reader)
+            .with_context(|| format!("Could not hash {}", path.display()))?;
+        sums.push_str(&sha256sum_line(&hashes, &name));
+    }
+    fs::write(&sumfile, sums).with_context(|| format!("Could not write {}", sumfile.display()))?;
+    Ok(sumfile)
+}
+
+/// Escape a string for use in an XML attribute value
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for chr in text.chars() {
+        match chr {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(chr),
+        }
+    }
+    escaped
+}
+
+/// Format a `<rom>` entry, as used in Redump/No-Intro DAT files, for a dumped file
+pub fn dat_rom_entry(name: &str, hashes: &Hashes) -> String {
+    format!(
+        "<rom name=\"{}\" size=\"{}\" crc=\"{}\" md5=\"{}\" sha1=\"{}\" />",
+        xml_escape(name),
+        hashes.size,
+        hashes.crc32_hex(),
+        hashes.md5_hex(),
+        hashes.sha1_hex()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_path;
+    use std::io::{Cursor, Write};
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn hash_reader_matches_known_vectors() {
+        let hashes = hash_reader(Cursor::new(b"The quick brown fox jumps over the lazy dog"))
+            .expect("in-memory reads can't fail");
+        assert_eq!(hashes.size, 43);
+        assert_eq!(hashes.crc32_hex(), "414fa339");
+        assert_eq!(hashes.md5_hex(), "9e107d9d372bb6826bd81d3542a419d6");
+        assert_eq!(hashes.sha1_hex(), "2fd4e1c67a2d28fced849ee1bb76e7391b93eb12");
+        assert_eq!(
+            hashes.sha256_hex(),
+            "d7a8fbb307d7809469ca9abcb0082e4f8d5651e46d3cdb762d02d0bf37c9e592"
+        );
+    }
+
+    #[test]
+    fn hash_reader_handles_empty_and_multi_chunk_input() {
+        let empty = hash_reader(Cursor::new(Vec::new())).expect("in-memory reads can't fail");
+        assert_eq!(empty.crc32_hex(), "00000000");
+        assert_eq!(empty.md5_hex(), "d41d8cd98f00b204e9800998ecf8427e");
+
+        // Crossing a chunk boundary must give the same result as hashing in one go
+        let data = vec![0xA5_u8; HASH_BUF_LEN * 2 + 7];
+        let mut oneshot = MultiHasher::default();
+        oneshot.update(&data);
+        assert_eq!(
+            hash_reader(Cursor::new(&data)).expect("in-memory reads can't fail"),
+            oneshot.finish()
+        );
+    }
+
+    #[test]
+    fn hash_growing_file_follows_appends() {
+        let path = scratch_path("growing.iso");
+        let (finished, writer_done) = channel();
+        let writer_path = path.clone();
+        let writer = std::thread::spawn(move || {
+            sleep(Duration::from_millis(20)); // Start hashing before the file exists
+            let mut file = File::create(&writer_path).expect("create growing file");
+            for chunk in 0..5_u8 {
+                file.write_all(&vec![chunk; HASH_BUF_LEN + 100]).expect("append");
+                sleep(Duration::from_millis(10));
+            }
+            drop(finished);
+        });
+
+        let hashes = hash_growing_file(&path, &writer_done, Duration::from_millis(5))
+            .expect("growing file is readable");
+        writer.join().expect("writer thread");
+        let expected = hash_reader(File::open(&path).expect("finished file")).expect("readable");
+        assert_eq!(hashes, expected);
+        assert_eq!(hashes.size, 5 * (HASH_BUF_LEN as u64 + 100));
+        std::fs::remove_file(&path).expect("cleanup");
+    }
+
+    #[test]
+    fn hash_growing_file_gives_up_if_nothing_was_written() {
+        let (finished, writer_done) = channel::<()>();
+        drop(finished);
+        let missing = Path::new("/nonexistent/rip_media-never-written.iso");
+        let err = hash_growing_file(missing, &writer_done, Duration::from_millis(5))
+            .expect_err("file was never created");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn hash_while_writing_hashes_what_was_written() {
+        let path = scratch_path("hash_while_writing.iso");
+        let data = vec![7_u8; HASH_BUF_LEN * 2];
+        let hashes =
+            hash_while_writing(&path, || fs::write(&path, &data).context("Could not write"))
+                .expect("written file is readable");
+        assert_eq!(hashes, hash_reader(data.as_slice()).expect("in-memory reads can't fail"));
+
+        let failed = hash_while_writing(&path, || Err(anyhow!("ddrescue failed")));
+        assert!(format!("{:#}", failed.expect_err("writer failed")).contains("ddrescue"));
+        fs::remove_file(&path).expect("cleanup");
+    }
+
+    #[test]
+    fn dat_rom_entry_escapes_names() {
+        let hashes = hash_reader(std::io::Cursor::new(b"")).expect("in-memory reads can't fail");
+        assert_eq!(
+            dat_rom_entry("Tom & Jerry <Disc 1>.bin", &hashes),
+            "<rom name=\"Tom &amp; Jerry &lt;Disc 1&gt;.bin\" size=\"0\" crc=\"00000000\" \
+             md5=\"d41d8cd98f00b204e9800998ecf8427e\" \
+             sha1=\"da39a3ee5e6b4b0d3255bfef95601890afd80709\" />"
+        );
+    }
+}
+
+// vim: set sw=4 sts=4 :
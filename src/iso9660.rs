@@ -0,0 +1,636 @@
+//! Parsing for ISO9660 volume descriptors
+//!
+//! See ECMA-119 (the freely-available equivalent of ISO 9660) for the format:
+//! <https://www.ecma-international.org/publications-and-standards/standards/ecma-119/>
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Error, Result};
+use serde::{Serialize, Serializer};
+
+use crate::platform::MediaError;
+
+/// Offset of the Primary Volume Descriptor (The first sector after the 32KiB system area)
+const PVD_OFFSET: u64 = 16 * 2048;
+
+/// Offsets of the volume creation, modification, expiration, and effective dates in the PVD
+const PVD_DATE_OFFSETS: [usize; 4] = [813, 830, 847, 864];
+
+/// Length of a "dec-datetime" field (ECMA-119 section 8.4.26.1)
+const DEC_DATETIME_LEN: usize = 17;
+
+/// Offset of the volume identifier (ie. the volume label) in the PVD
+const PVD_VOLUME_ID_OFFSET: usize = 40;
+
+/// Length of the volume identifier
+const VOLUME_ID_LEN: usize = 32;
+
+/// Offset of the volume sequence number (ie. which disc of a set this is) in the PVD
+const PVD_SEQUENCE_NUMBER_OFFSET: usize = 124;
+
+/// Offset of the volume set identifier in the PVD
+const PVD_VOLUME_SET_ID_OFFSET: usize = 190;
+
+/// Length of the volume set identifier
+const VOLUME_SET_ID_LEN: usize = 128;
+
+/// Offset of the escape sequences in a Supplementary Volume Descriptor
+const SVD_ESCAPES_OFFSET: usize = 88;
+
+/// Escape sequences marking a Supplementary Volume Descriptor as Joliet's (UCS-2 levels 1 to 3)
+const JOLIET_ESCAPES: [&[u8; 3]; 3] = [b"%/@", b"%/C", b"%/E"];
+
+/// Most volume descriptors to look through for a terminator (Real discs have a handful, but
+/// garbage could go on forever)
+const MAX_VOLUME_DESCRIPTORS: u64 = 64;
+
+/// Offset of the volume space size (in logical blocks) in the PVD
+const PVD_VOLUME_SIZE_OFFSET: usize = 80;
+
+/// Offset of the logical block size in the PVD
+const PVD_BLOCK_SIZE_OFFSET: usize = 128;
+
+/// Offset of the root directory's record in the PVD
+const PVD_ROOT_RECORD_OFFSET: usize = 156;
+
+/// Length of the fixed-size part of a directory record (ECMA-119 section 9.1)
+const DIR_RECORD_HEADER_LEN: usize = 33;
+
+/// Size of a logical block (The only size seen in practice on optical media)
+const BLOCK_LEN: usize = 2048;
+
+/// Largest directory to read (Far larger than anything real, but keeps garbage from exhausting
+/// memory)
+const MAX_DIR_LEN: u32 = 16 * 1024 * 1024;
+
+/// A timestamp from a volume descriptor, as recorded on the disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecDateTime {
+    /// Year (1 to 9999)
+    pub year: u16,
+    /// Month of the year (1 to 12)
+    pub month: u8,
+    /// Day of the month (1 to 31)
+    pub day: u8,
+    /// Hour of the day (0 to 23)
+    pub hour: u8,
+    /// Minute of the hour (0 to 59)
+    pub minute: u8,
+    /// Second of the minute (0 to 59)
+    pub second: u8,
+    /// Hundredths of a second (0 to 99)
+    pub hundredths: u8,
+    /// Offset from GMT in 15-minute intervals (-48 to 52)
+    pub gmt_offset: i8,
+}
+
+/// Parse a run of ASCII digits, as used by dec-datetime fields
+fn parse_digits(digits: &[u8]) -> Option<u16> {
+    digits.iter().try_fold(0_u16, |acc, &digit| {
+        digit.is_ascii_digit().then(|| acc * 10 + u16::from(digit - b'0'))
+    })
+}
+
+/// Count the days from 1970-01-01 to a date in the proleptic Gregorian calendar
+///
+/// (Adapted from Howard Hinnant's `days_from_civil` algorithm)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Treat January and February as the end of the previous year to simplify leap day handling
+    let march_year = if month <= 2 { year - 1 } else { year };
+    let era = march_year.div_euclid(400);
+    let year_of_era = march_year.rem_euclid(400);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+impl DecDateTime {
+    /// Parse a dec-datetime field, returning `None` if it's unset or malformed
+    pub fn parse(field: &[u8]) -> Option<Self> {
+        if field.len() != DEC_DATETIME_LEN {
+            return None;
+        }
+
+        // The narrowing is safe because parse_digits can't return more than 99 for two digits
+        #[allow(clippy::cast_possible_truncation)]
+        let two_digits = |idx: usize| parse_digits(&field[idx..idx + 2]).map(|x| x as u8);
+        let parsed = DecDateTime {
+            year: parse_digits(&field[0..4])?,
+            month: two_digits(4)?,
+            day: two_digits(6)?,
+            hour: two_digits(8)?,
+            minute: two_digits(10)?,
+            second: two_digits(12)?,
+            hundredths: two_digits(14)?,
+            gmt_offset: i8::from_ne_bytes([field[16]]),
+        };
+
+        // An all-zero date means "not specified"
+        let valid = (1..=12).contains(&parsed.month)
+            && (1..=31).contains(&parsed.day)
+            && parsed.hour < 24
+            && parsed.minute < 60
+            && parsed.second < 60
+            && (-48..=52).contains(&parsed.gmt_offset);
+        (parsed.year > 0 && valid).then_some(parsed)
+    }
+
+    /// Seconds since the Unix epoch, in UTC (Negative for dates before 1970)
+    pub fn unix_timestamp(self) -> i64 {
+        let days = days_from_civil(self.year.into(), self.month.into(), self.day.into());
+        let local_secs = days * 86_400
+            + i64::from(self.hour) * 3_600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second);
+        local_secs - i64::from(self.gmt_offset) * 15 * 60
+    }
+
+    /// Convert to a `SystemTime` suitable for setting file timestamps
+    pub fn to_system_time(self) -> SystemTime {
+        let secs = self.unix_timestamp();
+        let subsec = Duration::from_millis(u64::from(self.hundredths) * 10);
+        if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs.unsigned_abs()) + subsec
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + subsec
+        }
+    }
+}
+
+impl fmt::Display for DecDateTime {
+    /// Format as an ISO 8601 timestamp in the disc's own timezone
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let offset_mins = i32::from(self.gmt_offset) * 15;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:02}{}{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second,
+            self.hundredths,
+            if offset_mins < 0 { '-' } else { '+' },
+            offset_mins.abs() / 60,
+            offset_mins.abs() % 60
+        )
+    }
+}
+
+impl Serialize for DecDateTime {
+    /// Serialize as the same ISO 8601 timestamp `Display` produces
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The dates recorded in a Primary Volume Descriptor (`None` where unspecified)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VolumeDates {
+    /// When the volume was created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<DecDateTime>,
+    /// When the volume was last modified
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified: Option<DecDateTime>,
+    /// When the volume's contents should be considered obsolete
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires: Option<DecDateTime>,
+    /// When the volume's contents may start being used
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective: Option<DecDateTime>,
+}
+
+impl VolumeDates {
+    /// Whether the volume descriptor left all of the dates unspecified
+    pub fn is_empty(&self) -> bool {
+        *self == VolumeDates::default()
+    }
+}
+
+/// An entry in an ISO9660 directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The name, without the `;1`-style version suffix
+    pub name: String,
+    /// The first logical block of the file or directory's data
+    pub extent: u32,
+    /// The length of the file or directory's data in bytes
+    pub size: u32,
+    /// Whether this is a subdirectory
+    pub is_dir: bool,
+}
+
+impl DirEntry {
+    /// Parse a directory record, returning `None` if it's truncated
+    fn parse(record: &[u8]) -> Option<Self> {
+        let le_u32 =
+            |offset: usize| record.get(offset..offset + 4)?.try_into().ok().map(u32::from_le_bytes);
+        let name_len = usize::from(*record.get(32)?);
+        let raw_name = record.get(DIR_RECORD_HEADER_LEN..DIR_RECORD_HEADER_LEN + name_len)?;
+        let name = String::from_utf8_lossy(raw_name);
+        Some(DirEntry {
+            name: name.split(';').next().unwrap_or_default().to_owned(),
+            extent: le_u32(2)?,
+            size: le_u32(10)?,
+            is_dir: record.get(25)? & 0x02 != 0,
+        })
+    }
+}
+
+/// Read the Primary Volume Descriptor of an ISO9660 image or disc
+fn read_pvd<R: Read + Seek>(reader: &mut R) -> Result<[u8; 2048]> {
+    read_pvd_at(reader, PVD_OFFSET)
+}
+
+/// Read a Primary Volume Descriptor from `offset` bytes into an image or disc
+fn read_pvd_at<R: Read + Seek>(reader: &mut R, offset: u64) -> Result<[u8; 2048]> {
+    let mut pvd = [0_u8; 2048];
+    reader.seek(SeekFrom::Start(offset)).context("Failed to seek to volume descriptor")?;
+    let complete = match reader.read_exact(&mut pvd) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false, // Too short to hold one
+        result => result.map(|()| true).context("Failed to read volume descriptor")?,
+    };
+    if !complete || pvd[0] != 1 || &pvd[1..6] != b"CD001" {
+        return Err(Error::from(MediaError::UnrecognizedFormat)
+            .context("No ISO9660 Primary Volume Descriptor found"));
+    }
+    Ok(pvd)
+}
+
+/// Get the entry for the root directory of an ISO9660 image or disc
+pub fn root_dir<R: Read + Seek>(reader: &mut R) -> Result<DirEntry> {
+    let pvd = read_pvd(reader)?;
+    DirEntry::parse(&pvd[PVD_ROOT_RECORD_OFFSET..PVD_ROOT_RECORD_OFFSET + 34])
+        .context("Malformed root directory record")
+}
+
+/// List the contents of `dir`, leaving out its `.` and `..` entries
+pub fn read_dir<R: Read + Seek>(reader: &mut R, dir: &DirEntry) -> Result<Vec<DirEntry>> {
+    if !dir.is_dir {
+        bail!("Not a directory: {}", dir.name);
+    } else if dir.size > MAX_DIR_LEN {
+        bail!("Implausibly large directory ({} bytes): {}", dir.size, dir.name);
+    }
+
+    let mut data = vec![0; dir.size as usize];
+    reader
+        .seek(SeekFrom::Start(u64::from(dir.extent) * BLOCK_LEN as u64))
+        .and_then(|_| reader.read_exact(&mut data))
+        .with_context(|| format!("Could not read directory: {}", dir.name))?;
+
+    // Records never span sectors, so a zero length means "skip to the next sector"
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while let Some(&record_len) = data.get(pos) {
+        if record_len == 0 {
+            pos = (pos / BLOCK_LEN + 1) * BLOCK_LEN;
+            continue;
+        }
+        let record = data.get(pos..pos + usize::from(record_len)).unwrap_or_default();
+        let entry = DirEntry::parse(record)
+            .with_context(|| format!("Malformed record at byte {} of {}", pos, dir.name))?;
+        if entry.name != "\0" && entry.name != "\u{1}" {
+            entries.push(entry);
+        }
+        pos += usize::from(record_len);
+    }
+    Ok(entries)
+}
+
+/// Look up a file or directory by its path components (ignoring case), starting from the root
+pub fn find_path<R: Read + Seek>(reader: &mut R, path: &[&str]) -> Result<Option<DirEntry>> {
+    let mut current = root_dir(reader)?;
+    for component in path {
+        if !current.is_dir {
+            return Ok(None);
+        }
+        match read_dir(reader, &current)?
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(component))
+        {
+            Some(entry) => current = entry,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(current))
+}
+
+/// Read the raw (space-padded) volume identifier from the Primary Volume Descriptor of the
+/// session starting `session_start` bytes into an image or disc
+///
+/// (Decoding it is left to the caller, since discs don't agree on a character set.)
+pub fn volume_id<R: Read + Seek>(
+    reader: &mut R,
+    session_start: u64,
+) -> Result<[u8; VOLUME_ID_LEN]> {
+    let pvd = read_pvd_at(reader, session_start + PVD_OFFSET)?;
+    let mut id = [0; VOLUME_ID_LEN];
+    id.copy_from_slice(&pvd[PVD_VOLUME_ID_OFFSET..PVD_VOLUME_ID_OFFSET + VOLUME_ID_LEN]);
+    Ok(id)
+}
+
+/// Read the volume identifier from the Joliet Supplementary Volume Descriptor of the session
+/// starting `session_start` bytes into an image or disc (`None` if it has no Joliet extensions)
+///
+/// Unlike the primary one, this is unambiguously UCS-2, so it's returned already decoded.
+pub fn joliet_volume_id<R: Read + Seek>(
+    reader: &mut R,
+    session_start: u64,
+) -> Result<Option<String>> {
+    let mut descriptor = [0_u8; BLOCK_LEN];
+    for index in 0..MAX_VOLUME_DESCRIPTORS {
+        let offset = session_start + PVD_OFFSET + index * BLOCK_LEN as u64;
+        reader.seek(SeekFrom::Start(offset)).context("Failed to seek to volume descriptor")?;
+        match reader.read_exact(&mut descriptor) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            result => result.context("Failed to read volume descriptor")?,
+        }
+
+        // Type 255 is the Volume Descriptor Set Terminator
+        if &descriptor[1..6] != b"CD001" || descriptor[0] == 255 {
+            break;
+        }
+        let escapes = &descriptor[SVD_ESCAPES_OFFSET..SVD_ESCAPES_OFFSET + 3];
+        if descriptor[0] == 2 && JOLIET_ESCAPES.iter().any(|joliet| escapes == *joliet) {
+            let field = &descriptor[PVD_VOLUME_ID_OFFSET..PVD_VOLUME_ID_OFFSET + VOLUME_ID_LEN];
+            let units = field.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]]));
+            let label: String = char::decode_utf16(units)
+                .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                .collect();
+            return Ok(Some(label.trim_end_matches(['\0', ' ']).to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Fingerprint the volume using its Primary Volume Descriptor, to tell discs apart without
+/// reading them in full
+///
+/// This combines the volume label, volume set identifier, sequence number, and creation time,
+/// so the discs of a set differ even if they share a label.
+pub fn volume_fingerprint<R: Read + Seek>(reader: &mut R) -> Result<String> {
+    let pvd = read_pvd(reader)?;
+    let text = |offset: usize, len: usize| {
+        String::from_utf8_lossy(&pvd[offset..offset + len]).trim_end().to_owned()
+    };
+    let sequence = u16::from_le_bytes(
+        pvd[PVD_SEQUENCE_NUMBER_OFFSET..PVD_SEQUENCE_NUMBER_OFFSET + 2]
+            .try_into()
+            .unwrap_or_default(),
+    );
+    let created_offset = PVD_DATE_OFFSETS[0];
+    let created = DecDateTime::parse(&pvd[created_offset..created_offset + DEC_DATETIME_LEN])
+        .map_or_else(|| "unset".to_owned(), |date| date.to_string());
+    Ok(format!(
+        "{}|{}|{}|{}",
+        text(PVD_VOLUME_ID_OFFSET, VOLUME_ID_LEN),
+        text(PVD_VOLUME_SET_ID_OFFSET, VOLUME_SET_ID_LEN),
+        sequence,
+        created
+    ))
+}
+
+/// Get the size in bytes that the Primary Volume Descriptor says the volume takes up
+///
+/// (A complete image can't be any smaller than this)
+pub fn volume_size<R: Read + Seek>(reader: &mut R) -> Result<u64> {
+    let pvd = read_pvd(reader)?;
+    let blocks = u32::from_le_bytes(
+        pvd[PVD_VOLUME_SIZE_OFFSET..PVD_VOLUME_SIZE_OFFSET + 4].try_into().unwrap_or_default(),
+    );
+    let block_len = u16::from_le_bytes(
+        pvd[PVD_BLOCK_SIZE_OFFSET..PVD_BLOCK_SIZE_OFFSET + 2].try_into().unwrap_or_default(),
+    );
+    Ok(u64::from(blocks) * u64::from(block_len))
+}
+
+/// Find the layer break of a dual-layer DVD image, in logical blocks from the start
+///
+/// Dual-layer PS2 discs give each layer its own volume descriptors, with the first
+/// layer's volume size ending at the layer break, so a second Primary Volume Descriptor there
+/// marks a dual-layer disc. (This is how PCSX2 detects them.) Returns `None` if there isn't one.
+pub fn layer_break<R: Read + Seek>(reader: &mut R) -> Result<Option<u64>> {
+    let pvd = read_pvd(reader)?;
+    let blocks = u32::from_le_bytes(
+        pvd[PVD_VOLUME_SIZE_OFFSET..PVD_VOLUME_SIZE_OFFSET + 4].try_into().unwrap_or_default(),
+    );
+    let second_layer = u64::from(blocks) * BLOCK_LEN as u64;
+    Ok(read_pvd_at(reader, second_layer + PVD_OFFSET).is_ok().then_some(u64::from(blocks)))
+}
+
+/// Get the logical block (sector) size the Primary Volume Descriptor declares
+pub fn logical_block_size<R: Read + Seek>(reader: &mut R) -> Result<u16> {
+    let pvd = read_pvd(reader)?;
+    Ok(u16::from_le_bytes(
+        pvd[PVD_BLOCK_SIZE_OFFSET..PVD_BLOCK_SIZE_OFFSET + 2].try_into().unwrap_or_default(),
+    ))
+}
+
+/// Read the volume dates from the Primary Volume Descriptor of an ISO9660 image or disc
+pub fn read_volume_dates<R: Read + Seek>(reader: &mut R) -> Result<VolumeDates> {
+    let pvd = read_pvd(reader)?;
+    let [created, modified, expires, effective] =
+        PVD_DATE_OFFSETS.map(|offset| DecDateTime::parse(&pvd[offset..offset + DEC_DATETIME_LEN]));
+    Ok(VolumeDates { created, modified, expires, effective })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::io::Cursor;
+
+    #[test]
+    fn parse_reads_fields_and_offset() {
+        let parsed = DecDateTime::parse(b"2017021309055342\xec").expect("valid date");
+        assert_eq!(
+            parsed,
+            DecDateTime {
+                year: 2017,
+                month: 2,
+                day: 13,
+                hour: 9,
+                minute: 5,
+                second: 53,
+                hundredths: 42,
+                gmt_offset: -20
+            }
+        );
+        assert_eq!(parsed.to_string(), "2017-02-13T09:05:53.42-05:00");
+    }
+
+    #[test]
+    fn parse_rejects_unset_and_malformed_dates() {
+        for bad in [
+            &b"0000000000000000\x00"[..],
+            b"                \x00",
+            b"2017021309055300",
+            b"2017131309055300\x00",
+            b"2017021325055300\x00",
+            b"2017021309055300\x40",
+        ] {
+            assert_eq!(DecDateTime::parse(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn unix_timestamp_accounts_for_gmt_offset() {
+        let epoch = DecDateTime::parse(b"1970010100000000\x00").expect("valid date");
+        assert_eq!(epoch.unix_timestamp(), 0);
+        let eastern = DecDateTime::parse(b"2017021309055300\xec").expect("valid date");
+        assert_eq!(eastern.unix_timestamp(), 1_486_994_753);
+        let tokyo = DecDateTime::parse(b"1969123109000000\x24").expect("valid date");
+        assert_eq!(tokyo.unix_timestamp(), -86_400);
+        assert_eq!(tokyo.to_system_time(), UNIX_EPOCH - Duration::from_secs(86_400));
+    }
+
+    #[test]
+    fn read_volume_dates_from_fixture() {
+        let mut iso = File::open("fixture.iso").expect("fixture.iso exists");
+        let dates = read_volume_dates(&mut iso).expect("fixture.iso has a PVD");
+        let expected = DecDateTime::parse(b"2017021309055300\xec");
+        assert_eq!(
+            dates,
+            VolumeDates {
+                created: expected,
+                modified: expected,
+                expires: None,
+                effective: expected
+            }
+        );
+        assert!(!dates.is_empty());
+        assert!(VolumeDates::default().is_empty());
+    }
+
+    #[test]
+    fn volume_dates_serialize_as_timestamps() {
+        let dates = VolumeDates {
+            created: DecDateTime::parse(b"2017021309055342\xec"),
+            ..VolumeDates::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&dates).expect("serializable"),
+            r#"{"created":"2017-02-13T09:05:53.42-05:00"}"#
+        );
+    }
+
+    #[test]
+    fn read_dir_lists_fixture_contents() {
+        let mut iso = File::open("fixture.iso").expect("fixture.iso exists");
+        let root = root_dir(&mut iso).expect("fixture.iso has a PVD");
+        assert!(root.is_dir);
+        assert_eq!(
+            read_dir(&mut iso, &root).expect("readable root directory"),
+            [DirEntry { name: "TEST.TXT".to_owned(), extent: 24, size: 12, is_dir: false }]
+        );
+
+        let found = find_path(&mut iso, &["test.txt"]).expect("readable root directory");
+        assert_eq!(found.map(|entry| entry.extent), Some(24));
+        assert_eq!(find_path(&mut iso, &["VIDEO_TS"]).expect("readable root directory"), None);
+        assert_eq!(find_path(&mut iso, &["TEST.TXT", "x"]).expect("readable root directory"), None);
+    }
+
+    #[test]
+    fn volume_size_matches_fixture() {
+        let mut iso = File::open("fixture.iso").expect("fixture.iso exists");
+        assert_eq!(volume_size(&mut iso).expect("fixture.iso has a PVD"), 25 * 2048);
+        assert!(volume_size(&mut Cursor::new(vec![0_u8; 40_000])).is_err());
+    }
+
+    #[test]
+    fn layer_break_finds_second_layer_pvd() {
+        let single = fs::read("fixture.iso").expect("fixture.iso exists");
+        assert_eq!(layer_break(&mut Cursor::new(&single)).expect("fixture.iso has a PVD"), None);
+
+        // A second copy of the volume right where the first one ends stands in for layer 1
+        let mut dual = single[..25 * 2048].to_vec();
+        dual.extend_from_slice(&single);
+        assert_eq!(layer_break(&mut Cursor::new(&dual)).expect("has a PVD"), Some(25));
+        assert!(layer_break(&mut Cursor::new(vec![0_u8; 40_000])).is_err());
+    }
+
+    #[test]
+    fn logical_block_size_matches_fixture() {
+        let mut iso = File::open("fixture.iso").expect("fixture.iso exists");
+        assert_eq!(logical_block_size(&mut iso).expect("fixture.iso has a PVD"), 2048);
+        assert!(logical_block_size(&mut Cursor::new(vec![0_u8; 40_000])).is_err());
+    }
+
+    #[test]
+    fn volume_id_is_read_from_the_given_session() {
+        let single = fs::read("fixture.iso").expect("fixture.iso exists");
+        let mut label = *b"CDROM                           ";
+        assert_eq!(volume_id(&mut Cursor::new(&single), 0).expect("fixture.iso has a PVD"), label);
+
+        // A later session's volume, as found on multi-session discs
+        let mut multi = vec![0_u8; 10 * 2048];
+        multi.extend_from_slice(&single);
+        multi[10 * 2048 + 16 * 2048 + PVD_VOLUME_ID_OFFSET] = b'X';
+        label[0] = b'X';
+        assert_eq!(volume_id(&mut Cursor::new(&multi), 10 * 2048).expect("has a PVD"), label);
+
+        for bad in [vec![0_u8; 40_000], vec![0_u8; 100]] {
+            let err = volume_id(&mut Cursor::new(bad), 0).expect_err("not an ISO");
+            assert!(matches!(MediaError::find(&err), Some(MediaError::UnrecognizedFormat)));
+        }
+    }
+
+    /// Append a Joliet Supplementary Volume Descriptor labelled `label` to a copy of the fixture
+    pub(crate) fn joliet_fixture(label: &str) -> Vec<u8> {
+        let mut image = fs::read("fixture.iso").expect("fixture.iso exists");
+        let terminator = image[17 * 2048..18 * 2048].to_vec();
+        let svd = &mut image[17 * 2048..18 * 2048];
+        svd[..6].copy_from_slice(b"\x02CD001");
+        svd[SVD_ESCAPES_OFFSET..SVD_ESCAPES_OFFSET + 3].copy_from_slice(b"%/E");
+        let id = &mut svd[PVD_VOLUME_ID_OFFSET..PVD_VOLUME_ID_OFFSET + VOLUME_ID_LEN];
+        id.copy_from_slice(&[0, b' '].repeat(VOLUME_ID_LEN / 2));
+        for (unit, pair) in label.encode_utf16().zip(id.chunks_exact_mut(2)) {
+            pair.copy_from_slice(&unit.to_be_bytes());
+        }
+        image[18 * 2048..19 * 2048].copy_from_slice(&terminator);
+        image
+    }
+
+    #[test]
+    fn joliet_volume_id_reads_unicode_labels() {
+        let plain = fs::read("fixture.iso").expect("fixture.iso exists");
+        assert_eq!(joliet_volume_id(&mut Cursor::new(&plain), 0).expect("readable"), None);
+
+        let joliet = joliet_fixture("Café ゲーム");
+        let label = joliet_volume_id(&mut Cursor::new(&joliet), 0).expect("readable");
+        assert_eq!(label.as_deref(), Some("Café ゲーム"));
+
+        // Supplementary descriptors without Joliet's escape sequences are some other extension
+        let mut other = joliet.clone();
+        other[17 * 2048 + SVD_ESCAPES_OFFSET..17 * 2048 + SVD_ESCAPES_OFFSET + 3]
+            .copy_from_slice(b"%/Z");
+        assert_eq!(joliet_volume_id(&mut Cursor::new(&other), 0).expect("readable"), None);
+        assert_eq!(joliet_volume_id(&mut Cursor::new(vec![0_u8; 100]), 0).expect("short"), None);
+    }
+
+    #[test]
+    fn volume_fingerprint_tells_discs_of_a_set_apart() {
+        let first = fs::read("fixture.iso").expect("fixture.iso exists");
+        let fingerprint = volume_fingerprint(&mut Cursor::new(&first)).expect("has a PVD");
+        assert_eq!(fingerprint, "CDROM||1|2017-02-13T09:05:53.00-05:00");
+
+        // The second disc of a set, with the same label and creation time
+        let mut second = first.clone();
+        second[16 * 2048 + PVD_SEQUENCE_NUMBER_OFFSET] = 2;
+        let other = volume_fingerprint(&mut Cursor::new(&second)).expect("has a PVD");
+        assert_ne!(other, fingerprint);
+        assert!(volume_fingerprint(&mut Cursor::new(vec![0_u8; 40_000])).is_err());
+    }
+
+    #[test]
+    fn read_volume_dates_rejects_non_iso() {
+        assert!(read_volume_dates(&mut Cursor::new(vec![0_u8; 40_000])).is_err());
+        assert!(read_volume_dates(&mut Cursor::new(Vec::new())).is_err());
+    }
+}
+
+// vim: set sw=4 sts=4 :
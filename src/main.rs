@@ -23,13 +23,32 @@ This file provided by [rust-cli-boilerplate](https://github.com/ssokolow/rust-cl
 #![forbid(unsafe_code)]
 
 // 3rd-party imports
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use log::error;
 
 // Local imports
 mod app;
+mod compare;
+mod config;
+mod cue;
+mod ddrescue;
+mod disc_set;
+mod dvd;
+mod hash_cache;
+mod hashing;
+mod iso9660;
+mod messages;
 mod platform;
+mod profile;
+mod read_offset;
+mod sector_reader;
+mod sink;
 mod subcommands;
+mod summary;
+mod tagging;
+#[cfg(test)]
+mod test_support;
+mod toc;
 mod validators;
 
 /// Boilerplate to parse command-line arguments, set up logging, and handle bubbled-up `Error`s.
@@ -37,7 +56,8 @@ mod validators;
 /// See `app::main` for the application-specific logic.
 fn main() {
     // Parse command-line arguments (exiting on parse error, --version, or --help)
-    let opts = app::CliOpts::parse();
+    let matches = app::CliOpts::command().get_matches();
+    let opts = app::CliOpts::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
 
     // Configure logging output so that -q is "decrease verbosity" rather than instant silence
     #[allow(clippy::expect_used)]
@@ -48,16 +68,20 @@ fn main() {
         .init()
         .expect("initialize logging output");
 
-    if let Err(ref e) = app::main(opts) {
+    let timeout_exit_code = opts.device_timeout_exit_code;
+    if let Err(ref e) = app::with_config(opts, &matches).and_then(app::main) {
         // Write the top-level error message, then chained errors, then backtrace if available
         error!("error: {}", e);
         for cause in e.chain() {
             error!("caused by: {}", cause);
         }
 
-        // Exit with a nonzero exit code
-        // TODO: Decide how to allow code to set this to something other than 1
-        std::process::exit(1);
+        // Exit with a nonzero exit code (more specific for failures scripts may want to handle)
+        let code = platform::MediaError::find(e).map_or(1, |err| match (err, timeout_exit_code) {
+            (platform::MediaError::Timeout { .. }, Some(code)) => code.into(),
+            _ => err.exit_code(),
+        });
+        std::process::exit(code);
     }
 }
 
@@ -0,0 +1,214 @@
+//! User-facing text for prompts and key status messages, kept in one place so it can be
+//! translated
+
+// Translations need the accented characters of the languages they're in
+#![allow(clippy::non_ascii_literal)]
+
+use std::env;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// Languages the prompts and key messages are available in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Lang {
+    /// English
+    #[default]
+    En,
+    /// German
+    De,
+}
+
+impl Lang {
+    /// Pick a language from a POSIX locale name like `de_DE.UTF-8` (`None` if unsupported)
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let code = locale.split(['_', '.', '@']).next().unwrap_or_default();
+        match code.to_ascii_lowercase().as_str() {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "de" => Some(Lang::De),
+            _ => None,
+        }
+    }
+
+    /// Pick a language from the environment, using the first of `LC_ALL`, `LC_MESSAGES`, and
+    /// `LANG` which is set (as POSIX does) and falling back to English
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|locale| Lang::from_locale(&locale))
+            .unwrap_or_default()
+    }
+
+    /// Whether `answer` means "yes" to a y/n prompt
+    pub fn is_yes(self, answer: &str) -> bool {
+        let lowered = answer.to_lowercase();
+        match self {
+            Lang::En => lowered == "y",
+            Lang::De => lowered == "j" || lowered == "y",
+        }
+    }
+}
+
+/// The language chosen for this run (English until `set_lang` is called)
+static LANG: OnceLock<Lang> = OnceLock::new();
+
+/// Choose the language for the rest of the run (Only the first call has any effect)
+pub fn set_lang(lang: Lang) {
+    let _ = LANG.set(lang);
+}
+
+/// The language chosen for this run
+pub fn lang() -> Lang {
+    LANG.get().copied().unwrap_or_default()
+}
+
+/// A user-facing message, along with whatever needs to be filled into it
+///
+/// Displaying one renders it in the language chosen with `set_lang`.
+#[derive(Clone, Copy, Debug)]
+pub enum Msg<'msg> {
+    /// Prompt to insert the next disc
+    InsertDisc,
+    /// Prompt to insert a specific disc of a set
+    InsertDiscOf {
+        /// Position of the disc in the set
+        number: u16,
+        /// Number of discs in the set
+        set_size: u16,
+    },
+    /// Prompt to retry after the drive didn't see a disc
+    ReinsertDisc,
+    /// Prompt to close whatever is keeping the disc from being unmounted
+    DiscBusy,
+    /// Prompt for a name for a disc with no volume label
+    AskDiscName,
+    /// Prompt for the disc's title
+    AskTitle,
+    /// Prompt for the disc's platform
+    AskPlatform,
+    /// Prompt for notes about the disc
+    AskNotes,
+    /// Prompt for the CD key of the named disc
+    AskCdKey(&'msg str),
+    /// Prompt to confirm that a disc has no CD key
+    ConfirmNoCdKey,
+    /// Prompt to confirm the CD key that was entered
+    ConfirmCdKey(&'msg str),
+    /// Log message naming the directory being ripped into
+    RippingInto(&'msg Path),
+    /// Desktop notification summary for a successful rip
+    RipFinished,
+    /// Desktop notification body for a successful rip of the named disc
+    FinishedRipping(&'msg str),
+    /// Desktop notification summary for a failed rip
+    RipFailed,
+    /// Desktop notification body for a failed rip, with the disc's name (if it got that far)
+    /// and the error
+    CouldNotRip(Option<&'msg str>, &'msg str),
+}
+
+impl Msg<'_> {
+    /// Render the message in `lang`
+    pub fn text(self, lang: Lang) -> String {
+        match (lang, self) {
+            (Lang::En, Msg::InsertDisc) => "Insert disc and press Enter...".to_owned(),
+            (Lang::De, Msg::InsertDisc) => "Disc einlegen und Enter drücken...".to_owned(),
+            (Lang::En, Msg::InsertDiscOf { number, set_size }) => {
+                format!("Insert disc {} of {} and press Enter...", number, set_size)
+            },
+            (Lang::De, Msg::InsertDiscOf { number, set_size }) => {
+                format!("Disc {} von {} einlegen und Enter drücken...", number, set_size)
+            },
+            (Lang::En, Msg::ReinsertDisc) => "Disc not detected. Reinsert it and press Enter to \
+                                              try again, or enter q to quit: "
+                .to_owned(),
+            (Lang::De, Msg::ReinsertDisc) => "Keine Disc erkannt. Erneut einlegen und Enter \
+                                              drücken, um es nochmal zu versuchen, oder q zum \
+                                              Beenden eingeben: "
+                .to_owned(),
+            (Lang::En, Msg::DiscBusy) => "Close any programs using the disc and press Enter to \
+                                          try again, or enter s to skip unmounting: "
+                .to_owned(),
+            (Lang::De, Msg::DiscBusy) => "Programme schließen, die die Disc verwenden, und Enter \
+                                          drücken, um es nochmal zu versuchen, oder s eingeben, \
+                                          um das Aushängen zu überspringen: "
+                .to_owned(),
+            (Lang::En, Msg::AskDiscName) => "Disc Name: ".to_owned(),
+            (Lang::De, Msg::AskDiscName) => "Disc-Name: ".to_owned(),
+            (Lang::En, Msg::AskTitle) => "Title (enter for none): ".to_owned(),
+            (Lang::De, Msg::AskTitle) => "Titel (Enter für keinen): ".to_owned(),
+            (Lang::En, Msg::AskPlatform) => "Platform (enter for none): ".to_owned(),
+            (Lang::De, Msg::AskPlatform) => "Plattform (Enter für keine): ".to_owned(),
+            (Lang::En, Msg::AskNotes) => "Notes (enter for none): ".to_owned(),
+            (Lang::De, Msg::AskNotes) => "Notizen (Enter für keine): ".to_owned(),
+            (Lang::En, Msg::AskCdKey(disc)) => {
+                format!("please enter cd-key for {} (enter for none): ", disc)
+            },
+            (Lang::De, Msg::AskCdKey(disc)) => {
+                format!("Bitte CD-Key für {} eingeben (Enter für keinen): ", disc)
+            },
+            (Lang::En, Msg::ConfirmNoCdKey) => "no cd key. is this correct? (y/n): ".to_owned(),
+            (Lang::De, Msg::ConfirmNoCdKey) => "Kein CD-Key. Ist das richtig? (j/n): ".to_owned(),
+            (Lang::En, Msg::ConfirmCdKey(key)) => format!("please confirm \"{}\" (y/n): ", key),
+            (Lang::De, Msg::ConfirmCdKey(key)) => format!("Bitte \"{}\" bestätigen (j/n): ", key),
+            (Lang::En, Msg::RippingInto(dir)) => format!("Ripping into {}", dir.display()),
+            (Lang::De, Msg::RippingInto(dir)) => format!("Rippe nach {}", dir.display()),
+            (Lang::En, Msg::RipFinished) => "Rip finished".to_owned(),
+            (Lang::De, Msg::RipFinished) => "Rip abgeschlossen".to_owned(),
+            (Lang::En, Msg::FinishedRipping(disc)) => format!("Finished ripping {}", disc),
+            (Lang::De, Msg::FinishedRipping(disc)) => format!("{} fertig gerippt", disc),
+            (Lang::En, Msg::RipFailed) => "Rip failed".to_owned(),
+            (Lang::De, Msg::RipFailed) => "Rip fehlgeschlagen".to_owned(),
+            (Lang::En, Msg::CouldNotRip(disc, err)) => {
+                format!("Could not rip {}: {}", disc.unwrap_or("the disc"), err)
+            },
+            (Lang::De, Msg::CouldNotRip(disc, err)) => {
+                format!("{} konnte nicht gerippt werden: {}", disc.unwrap_or("Die Disc"), err)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Msg<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text(lang()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locales_pick_languages() {
+        assert_eq!(Lang::from_locale("de_DE.UTF-8"), Some(Lang::De));
+        assert_eq!(Lang::from_locale("de_AT@euro"), Some(Lang::De));
+        assert_eq!(Lang::from_locale("en_CA.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("C"), Some(Lang::En));
+        assert_eq!(Lang::from_locale("fr_FR.UTF-8"), None);
+        assert_eq!(Lang::from_locale(""), None);
+    }
+
+    #[test]
+    fn english_text_is_unchanged() {
+        let msg = Msg::InsertDiscOf { number: 2, set_size: 3 };
+        assert_eq!(msg.text(Lang::En), "Insert disc 2 of 3 and press Enter...");
+        assert_eq!(msg.text(Lang::De), "Disc 2 von 3 einlegen und Enter drücken...");
+        assert_eq!(Msg::ConfirmCdKey("ABC").text(Lang::En), "please confirm \"ABC\" (y/n): ");
+        assert_eq!(Msg::CouldNotRip(None, "oops").text(Lang::En), "Could not rip the disc: oops");
+        assert_eq!(Msg::AskTitle.to_string(), Msg::AskTitle.text(lang()));
+    }
+
+    #[test]
+    fn yes_answers_follow_the_language() {
+        assert!(Lang::En.is_yes("Y"));
+        assert!(!Lang::En.is_yes("j"));
+        assert!(Lang::De.is_yes("J"));
+        assert!(Lang::De.is_yes("y"));
+        assert!(!Lang::De.is_yes("n"));
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -1,69 +1,857 @@
 //! Abstraction around the underlying OS functionality
 
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::env;
 use std::ffi::{OsStr, OsString};
-use std::fs::File;
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
-use std::process::Command;
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, BufRead, IsTerminal, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
 use std::thread::sleep;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{bail, Context, Result};
-use rustyline::DefaultEditor;
+use encoding_rs::{Encoding, SHIFT_JIS};
+use log::{debug, warn};
+use rustyline::{Behavior, Config, DefaultEditor};
+use thiserror::Error;
+
+use crate::iso9660;
+use crate::sector_reader::{SectorReader, DEFAULT_READ_BUFFER};
+
+#[cfg(any(target_os = "macos", test))]
+mod macos;
+#[cfg(any(windows, test))]
+mod windows;
+
+/// The platform provider for the OS we were built for
+#[cfg(all(unix, not(target_os = "macos")))]
+pub type NativePlatformProvider = LinuxPlatformProvider<'static>;
+/// The platform provider for the OS we were built for
+#[cfg(target_os = "macos")]
+pub type NativePlatformProvider = macos::MacOsPlatformProvider<'static>;
+/// The platform provider for the OS we were built for
+#[cfg(windows)]
+pub type NativePlatformProvider = windows::WindowsPlatformProvider<'static>;
 
 /// Default timeout duration (in seconds)
 pub const DEFAULT_TIMEOUT: u64 = 10;
 
+/// How often to check whether a disc has become readable while waiting for it
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to wait before ejecting a tray so the user has time to reach for the door
+const TRAY_EJECT_DELAY: Duration = Duration::from_secs(2);
+
+/// Failures which callers (and scripts calling us) may want to distinguish from other errors
+#[derive(Debug, Error)]
+pub enum MediaError {
+    /// The medium has no filesystem or data on it
+    #[error("Blank or unformatted disc")]
+    Blank,
+
+    /// A subprocess made no progress for too long and was killed
+    #[error("No progress for {} seconds. Assuming the drive is hung.", .0.as_secs())]
+    Stalled(Duration),
+
+    /// The disc was swapped or removed while it was being read
+    #[error("The disc was changed or removed mid-rip. The dump is unusable and must be redone.")]
+    MediaChanged,
+
+    /// The device never became ready (eg. no disc was inserted in time)
+    #[error("Timed out after {} seconds waiting for {} to be ready", .elapsed.as_secs(), .device.display())]
+    Timeout {
+        /// The device that was being waited on
+        device: PathBuf,
+        /// How long we waited
+        elapsed: Duration,
+    },
+
+    /// The disc is mounted and something is keeping it from being unmounted
+    #[error("{} is in use, so it could not be unmounted", .0.display())]
+    Busy(PathBuf),
+
+    /// The medium doesn't contain the filesystem or format we were looking for
+    #[error("Unrecognized file format")]
+    UnrecognizedFormat,
+
+    /// The device (or image file) couldn't be opened (eg. it doesn't exist or permission was
+    /// denied)
+    #[error(transparent)]
+    DeviceUnavailable(io::Error),
+
+    /// A subprocess ran, but reported failure
+    #[error("{} {}", .command, exit_description(*.code))]
+    SubprocessFailed {
+        /// The command which was run
+        command: String,
+        /// Its exit code (`None` if it was killed by a signal)
+        code: Option<i32>,
+    },
+
+    /// Any other failure (eg. an I/O error or a missing helper program)
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl From<anyhow::Error> for MediaError {
+    /// Unwrap a bare `MediaError`, and wrap anything else (including `MediaError`s with context
+    /// added, so the context isn't lost) as `MediaError::Other`
+    fn from(err: anyhow::Error) -> Self {
+        if err.chain().nth(1).is_some() {
+            return MediaError::Other(err);
+        }
+        err.downcast().unwrap_or_else(MediaError::Other)
+    }
+}
+
+impl MediaError {
+    /// Process exit code to use for this error (values taken from BSD's `sysexits.h`)
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MediaError::UnrecognizedFormat => 65, // EX_DATAERR
+            MediaError::Blank | MediaError::DeviceUnavailable(_) => 66, // EX_NOINPUT
+            MediaError::SubprocessFailed { .. } => 69, // EX_UNAVAILABLE
+            MediaError::Stalled(_) | MediaError::MediaChanged => 74, // EX_IOERR
+            MediaError::Timeout { .. } | MediaError::Busy(_) => 75, // EX_TEMPFAIL
+            MediaError::Other(_) => 1,
+        }
+    }
+
+    /// Find the `MediaError` among the causes of `err`, if there is one
+    ///
+    /// (This also looks among the context added to `err`, inside `io::Error`s, which is how
+    /// `subprocess_call!` reports `SubprocessFailed`, and inside `MediaError::Other`, which is
+    /// never returned itself.)
+    pub fn find(err: &anyhow::Error) -> Option<&MediaError> {
+        let in_chain = err.chain().filter_map(|cause| {
+            cause.downcast_ref::<MediaError>().or_else(|| {
+                let inner = cause.downcast_ref::<io::Error>()?.get_ref()?;
+                inner.downcast_ref::<MediaError>()
+            })
+        });
+        err.downcast_ref::<MediaError>().into_iter().chain(in_chain).find_map(|found| {
+            if let MediaError::Other(inner) = found {
+                MediaError::find(inner)
+            } else {
+                Some(found)
+            }
+        })
+    }
+}
+
+/// Describe how a subprocess exited, given its exit code
+fn exit_description(code: Option<i32>) -> String {
+    match code {
+        Some(exit_code) => format!("exited with code {}", exit_code),
+        None => "killed by signal".to_owned(),
+    }
+}
+
+/// Identification of the drive a disc was read with, for the provenance of its dump
+///
+/// (Some dumps depend on the drive, through things like read offsets and riplock.)
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct DriveInfo {
+    /// The manufacturer (eg. `PLEXTOR`)
+    pub vendor: String,
+    /// The model (eg. `DVDR   PX-716A`)
+    pub model: String,
+    /// The firmware revision
+    pub revision: String,
+}
+
+/// Read a drive's vendor, model, and firmware revision from its sysfs `device` directory
+///
+/// (The kernel pads the values out with spaces, as they are in the SCSI INQUIRY response.)
+fn read_drive_info(sysfs_device: &Path) -> Result<DriveInfo> {
+    let read = |name: &str| -> Result<String> {
+        let path = sysfs_device.join(name);
+        fs::read_to_string(&path)
+            .map(|value| value.trim().to_owned())
+            .with_context(|| format!("Could not read {}", path.display()))
+    };
+    Ok(DriveInfo { vendor: read("vendor")?, model: read("model")?, revision: read("rev")? })
+}
+
+/// The kind of filesystem found on a medium
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filesystem {
+    /// ISO 9660 (CD-ROMs and most DVD-ROMs), possibly with Joliet/Rock Ridge extensions
+    Iso9660,
+    /// Universal Disk Format (DVDs and Blu-rays)
+    Udf,
+    /// No filesystem and no data (eg. a blank CD-R)
+    Blank,
+    /// Data we can't identify (or no data track at all, as with audio CDs)
+    Unknown,
+}
+
+/// The kind of disc in a drive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaType {
+    /// A CD with only audio tracks
+    AudioCd,
+    /// A CD with at least one data track (including mixed-mode discs)
+    DataCd,
+    /// Any kind of DVD (pressed or recordable)
+    Dvd,
+    /// Any kind of Blu-ray disc (pressed or recordable)
+    BluRay,
+    /// Something else, or no way to tell
+    Unknown,
+}
+
+/// Name of the per-user folder (under the XDG base directories) for our files
+const APP_DIR_NAME: &str = "rip_media";
+
+/// Resolve an XDG base directory from its environment variable and `$HOME`-relative fallback
+///
+/// (Per the XDG Base Directory Specification, an empty or relative value must be ignored)
+fn xdg_dir(value: Option<OsString>, home: Option<OsString>, fallback: &str) -> Option<PathBuf> {
+    value
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home.map(|home_dir| Path::new(&home_dir).join(fallback)))
+}
+
+/// Default location for persisting prompt history between runs
+pub fn history_path() -> Option<PathBuf> {
+    xdg_dir(env::var_os("XDG_DATA_HOME"), env::var_os("HOME"), ".local/share")
+        .map(|path| path.join(APP_DIR_NAME).join("history"))
+}
+
+/// Default location for the cache of file checksums used by `--validate-only`
+pub fn hash_cache_path() -> Option<PathBuf> {
+    xdg_dir(env::var_os("XDG_CACHE_HOME"), env::var_os("HOME"), ".cache")
+        .map(|path| path.join(APP_DIR_NAME).join("checksums.json"))
+}
+
+/// Whether there's somebody at a terminal to answer prompts (ie. stdin and stdout are TTYs)
+///
+/// (Piped and cron invocations get `false`, so they skip prompts and cosmetic delays.)
+pub fn is_interactive() -> bool {
+    io::stdin().is_terminal() && io::stdout().is_terminal()
+}
+
+/// Where prompts are shown (and answered)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum PromptStream {
+    /// The controlling terminal (`/dev/tty`), so stdout and stderr stay machine-readable
+    /// (Falls back to stderr if there isn't one)
+    #[default]
+    Tty,
+    /// Standard error, with answers read from standard input (without line editing)
+    Stderr,
+    /// Standard output, with answers read from standard input
+    Stdout,
+}
+
+/// The file which refers to the process's controlling terminal (or console)
+#[cfg(not(windows))]
+const TTY_PATH: &str = "/dev/tty";
+/// The file which refers to the process's controlling terminal (or console)
+#[cfg(windows)]
+const TTY_PATH: &str = "CONIN$";
+
+/// Whether the process has a controlling terminal to prompt on
+fn tty_available() -> bool {
+    File::options().read(true).write(true).open(TTY_PATH).is_ok()
+}
+
+/// Write `prompt` to `output` and read one line of answer from `input`, without line editing
+fn prompt_plain<W: Write, R: BufRead>(mut output: W, mut input: R, prompt: &str) -> Result<String> {
+    output
+        .write_all(prompt.as_bytes())
+        .and_then(|()| output.flush())
+        .context("Could not write prompt")?;
+    let mut line = String::new();
+    if input.read_line(&mut line).context("Could not read answer")? == 0 {
+        bail!("Input ended before the prompt was answered");
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Default location of the config file
+pub fn config_path() -> Option<PathBuf> {
+    xdg_dir(env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME"), ".config")
+        .map(|path| path.join(APP_DIR_NAME).join("config.toml"))
+}
+
+/// Split a `blkid`-style `LABEL=...` or `UUID=...` token into the matching `blkid` lookup flag
+/// and the value to look up
+pub fn parse_device_token(spec: &str) -> Option<(&'static str, &str)> {
+    let (tag, value) = spec.split_once('=')?;
+    let flag = match tag {
+        "LABEL" => "-L",
+        "UUID" => "-U",
+        _ => return None,
+    };
+    (!value.is_empty()).then_some((flag, value))
+}
+
+/// Get how many bytes an unprivileged user can still write to the filesystem `dir` is on
+#[cfg(unix)]
+pub fn available_space(dir: &Path) -> Result<u64> {
+    let stats = nix::sys::statvfs::statvfs(dir)
+        .with_context(|| format!("Could not check the free space in {}", dir.display()))?;
+    Ok(stats.blocks_available().saturating_mul(stats.fragment_size()))
+}
+
+#[cfg(windows)]
+pub use windows::available_space;
+
+/// Resolve an input path given as a `LABEL=...` or `UUID=...` token into a device path
+///
+/// Anything else (including a file which really is named like a token) is returned unchanged.
+pub fn resolve_inpath(inpath: &Path) -> Result<PathBuf> {
+    let token = inpath.to_str().filter(|_| !inpath.exists()).and_then(parse_device_token);
+    let Some((flag, value)) = token else { return Ok(inpath.to_owned()) };
+
+    let output = Command::new("blkid")
+        .args([flag, value])
+        .output()
+        .with_context(|| format!("Could not run blkid to look up {}", inpath.display()))?;
+    let device = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || device.is_empty() {
+        bail!("No device found matching {}", inpath.display());
+    }
+    debug!("Resolved {} to {}", inpath.display(), device);
+    Ok(PathBuf::from(device))
+}
+
+/// Environment variable listing subprocesses to fake failures for (test builds only)
+///
+/// The value is a comma-separated list of `command` or `command=exit_code` entries. For example,
+/// `RIP_MEDIA_SIMULATE_ERRORS=ddrescue=2,flac` makes every `ddrescue` call "exit" with code 2 and
+/// every `flac` call exit with code 1, without actually running them.
+///
+/// **This is not for production use.** It's only compiled into test builds and builds with the
+/// `testing` feature enabled, so the recovery paths can be exercised without scratched discs.
+#[cfg(any(test, feature = "testing"))]
+pub const SIMULATE_ERRORS_VAR: &str = "RIP_MEDIA_SIMULATE_ERRORS";
+
+/// Look up the exit code `spec` (See [`SIMULATE_ERRORS_VAR`]) says to fake for `cmd`, if any
+///
+/// Fails if any entry in `spec` has an exit code which isn't a number.
+#[cfg(any(test, feature = "testing"))]
+fn parse_simulated_failure(spec: &str, cmd: &OsStr) -> Result<Option<i32>, String> {
+    let mut found = None;
+    for entry in spec.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+        let (entry_cmd, code_text) = entry.split_once('=').unwrap_or((entry, "1"));
+        let code: i32 = code_text
+            .trim()
+            .parse()
+            .map_err(|e| format!("Invalid {} entry {:?}: {}", SIMULATE_ERRORS_VAR, entry, e))?;
+        if found.is_none() && Some(OsStr::new(entry_cmd.trim())) == Path::new(cmd).file_name() {
+            found = Some(code);
+        }
+    }
+    Ok(found)
+}
+
+/// Return a synthetic failure for `cmd` if [`SIMULATE_ERRORS_VAR`] asks for one
+///
+/// The failure is the same `MediaError::SubprocessFailed` (inside an `io::Error`) that
+/// `subprocess_call!` returns when a command really fails. An invalid spec fails every command.
+#[cfg(any(test, feature = "testing"))]
+pub fn simulated_failure<S: AsRef<OsStr>>(cmd: S) -> Option<io::Error> {
+    let spec = env::var(SIMULATE_ERRORS_VAR).ok()?;
+    let code = match parse_simulated_failure(&spec, cmd.as_ref()) {
+        Ok(code) => code?,
+        Err(problem) => return Some(io::Error::new(io::ErrorKind::InvalidInput, problem)),
+    };
+    let command = Path::new(cmd.as_ref()).display().to_string();
+    warn!("Simulating failure of {} ({} is set)", command, SIMULATE_ERRORS_VAR);
+    Some(io::Error::other(MediaError::SubprocessFailed { command, code: Some(code) }))
+}
+
 /// Shorthand for calling subprocesses purely for side-effects
+///
+/// (In test builds, failures can be injected via [`SIMULATE_ERRORS_VAR`])
 #[macro_export]
 macro_rules! subprocess_call {
-    ( $cmd:expr, $( $arg:expr ), * ) => {
-        Command::new($cmd)
+    ( $cmd:expr, $( $arg:expr ), * ) => {{
+        #[cfg(any(test, feature = "testing"))]
+        let simulated = $crate::platform::simulated_failure($cmd);
+        #[cfg(not(any(test, feature = "testing")))]
+        let simulated: Option<::std::io::Error> = None;
+
+        match simulated {
+            Some(err) => Err(err),
+            None => Command::new($cmd)
                 $(.arg($arg))*
                 .status().and_then(|status| if status.success() {
                         Ok(())
                     } else {
-                        let cmd = ::std::path::Path::new($cmd).display();
-                        Err(::std::io::Error::new(::std::io::ErrorKind::Other, match status.code(){
-                                // TODO: Refactor to propagate the un-formatted code somehow
-                                Some(code) => format!("{} exited with code {}", cmd, code),
-                                None => format!("{} killed by signal", cmd)
-                            }))
-                })
+                        // (Use `MediaError::find` to get at the exit code)
+                        Err(::std::io::Error::other($crate::platform::MediaError::SubprocessFailed {
+                            command: ::std::path::Path::new($cmd).display().to_string(),
+                            code: status.code(),
+                        }))
+                }),
+        }
+    }}
+}
+
+/// How often `wait_with_watchdog` checks on the subprocess it's watching
+pub const WATCHDOG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Snapshot the size and modification time of each of `paths`, for detecting progress
+fn progress_marker(paths: &[&Path]) -> Vec<Option<(u64, Option<SystemTime>)>> {
+    paths
+        .iter()
+        .map(|path| fs::metadata(path).ok().map(|meta| (meta.len(), meta.modified().ok())))
+        .collect()
+}
+
+/// Remembers which disc was in the drive when a rip started, to notice if it's swapped or
+/// removed before the rip finishes
+pub struct MediaGuard<'prov> {
+    /// The drive to keep an eye on
+    provider: &'prov dyn RawMediaProvider,
+    /// What `provider` identified the disc as at the start (`None` if it can't tell)
+    identity: Option<String>,
+}
+
+impl<'prov> MediaGuard<'prov> {
+    /// Record the identity of the disc currently in `provider`'s drive
+    pub fn new(provider: &'prov dyn RawMediaProvider) -> Self {
+        MediaGuard { provider, identity: provider.media_identity() }
     }
+
+    /// Whether `check` can actually detect anything for this drive
+    pub fn is_active(&self) -> bool {
+        self.identity.is_some()
+    }
+
+    /// Fail with `MediaError::MediaChanged` if the disc is no longer the one we started with
+    pub fn check(&self) -> Result<()> {
+        match (&self.identity, self.provider.media_identity()) {
+            (Some(before), Some(now)) if now != *before => {
+                debug!("Disc identity changed from {:?} to {:?}", before, now);
+                Err(MediaError::MediaChanged.into())
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Wait for `child` to exit, killing it if none of the `watched` files change for `stall_timeout`
+/// or the disc it's reading is swapped out from under it
+///
+/// (Growth or modification of output files is the only progress indicator that works for every
+/// dumping tool without having to parse their individual progress displays.)
+pub fn wait_with_watchdog(
+    child: &mut Child,
+    watched: &[&Path],
+    stall_timeout: Option<Duration>,
+    poll_interval: Duration,
+    media: Option<&MediaGuard<'_>>,
+) -> Result<ExitStatus> {
+    let mut last_marker = progress_marker(watched);
+    let mut last_progress = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait().context("Could not check on subprocess")? {
+            return Ok(status);
+        }
+
+        if let Some(Err(e)) = media.map(MediaGuard::check) {
+            warn!("Killing subprocess because the disc was changed");
+            child.kill().context("Could not kill subprocess")?;
+            let _ = child.wait(); // Reap the zombie
+            return Err(e);
+        }
+
+        let marker = progress_marker(watched);
+        if marker == last_marker {
+            let stalled = stall_timeout.filter(|timeout| last_progress.elapsed() >= *timeout);
+            if let Some(timeout) = stalled {
+                warn!("Killing subprocess after {} seconds without progress", timeout.as_secs());
+                child.kill().context("Could not kill stalled subprocess")?;
+                let _ = child.wait(); // Reap the zombie
+                return Err(MediaError::Stalled(timeout).into());
+            }
+        } else {
+            last_marker = marker;
+            last_progress = Instant::now();
+        }
+        sleep(poll_interval);
+    }
+}
+
+/// Read the volume label straight out of the ISO9660 header of the session starting at byte
+/// `session_start`, for when the OS can't tell us
+///
+/// The Joliet label is preferred, since it's Unicode and not limited to `[A-Z0-9_]`. Otherwise,
+/// the primary label is decoded using `encoding` (`None` to guess).
+fn raw_volume_label<R: Read + Seek>(
+    dev: &mut R,
+    session_start: u64,
+    encoding: Option<&'static Encoding>,
+) -> Result<String> {
+    let primary = iso9660::volume_id(dev, session_start)?;
+    match iso9660::joliet_volume_id(dev, session_start) {
+        Ok(Some(label)) if !label.is_empty() => return Ok(label),
+        Ok(_) => {},
+        Err(e) => debug!("Could not read the Joliet volume descriptor: {:#}", e),
+    }
+    Ok(decode_label(&primary, encoding))
+}
+
+/// Read the volume label of the ISO9660 image (or disc) at `path` directly, without asking the
+/// OS or tools like `blkid`
+///
+/// Raw labels are decoded using `encoding` (`None` to guess).
+pub fn read_iso9660_label(path: &Path, encoding: Option<&'static Encoding>) -> Result<String> {
+    let file = File::open(path)
+        .map_err(MediaError::DeviceUnavailable)
+        .with_context(|| format!("Could not open for reading: {}", path.display()))?;
+    raw_volume_label(&mut SectorReader::new(file), 0, encoding)
 }
 
-/// Shorthand for reading byte substrings from `Seek`-ables
-macro_rules! read_exact_at {
-    ( $file:expr, $bytes:expr, $offset:expr ) => {{
-        // TODO: Find a way to silence `use_debug` complaints here rather than in every caller
-        let mut buf = [0; $bytes];
-        $file.seek($offset).with_context(|| format!("Failed to seek to offset {:?}", $offset))?;
-        $file
-            .read_exact(&mut buf)
-            .with_context(|| format!("Could not read {} bytes from {:?}", $bytes, $file))?;
-        buf
-    }};
+/// Ask UDisks2 (over the D-Bus system bus) for the label of the filesystem on `device`
+///
+/// Unlike `blkid`, this doesn't need permission to read the device itself.
+#[cfg(all(target_os = "linux", feature = "udisks"))]
+fn udisks_volume_label(device: &OsStr) -> Result<String> {
+    use std::collections::HashMap;
+    use zbus::blocking::{Connection, Proxy};
+    use zbus::zvariant::{OwnedObjectPath, Value};
+
+    let path = device.to_str().context("UDisks2 can't look up non-UTF-8 paths")?;
+    let conn = Connection::system().context("Could not connect to the D-Bus system bus")?;
+    let manager = Proxy::new(
+        &conn,
+        "org.freedesktop.UDisks2",
+        "/org/freedesktop/UDisks2/Manager",
+        "org.freedesktop.UDisks2.Manager",
+    )?;
+
+    // (ResolveDevice follows symlinks like /dev/cdrom for us)
+    let spec = HashMap::from([("path", Value::from(path))]);
+    let options: HashMap<&str, Value<'_>> = HashMap::new();
+    let objects: Vec<OwnedObjectPath> = manager
+        .call("ResolveDevice", &(spec, options))
+        .with_context(|| format!("UDisks2 could not resolve {}", path))?;
+    let object = objects.first().with_context(|| format!("UDisks2 doesn't know {}", path))?;
+
+    let block = Proxy::new(
+        &conn,
+        "org.freedesktop.UDisks2",
+        object.as_ref(),
+        "org.freedesktop.UDisks2.Block",
+    )?;
+    let label: String = block.get_property("IdLabel").context("Could not read IdLabel")?;
+    Ok(label.trim().to_owned())
+}
+
+/// Poll every `interval` until `device` can be opened (ie. it has a disc in it), for up to
+/// `timeout`
+fn wait_until_openable(device: &OsStr, timeout: Duration, interval: Duration) -> Result<()> {
+    let start_time = Instant::now();
+    loop {
+        // Poll for a disc and return early on success
+        // (According to https://lwn.net/Articles/462178/, this is probably
+        //  something we can't readily and reliably block on)
+        if File::open(device).is_ok() {
+            return Ok(());
+        }
+        if start_time.elapsed() >= timeout {
+            break;
+        }
+
+        sleep(interval);
+    }
+    Err(MediaError::Timeout { device: PathBuf::from(device), elapsed: start_time.elapsed() }.into())
+}
+
+/// Longest to block on udev between attempts to open the device (in case the drive wasn't
+/// quite ready yet when it announced the disc)
+#[cfg(all(target_os = "linux", feature = "udev"))]
+const UDEV_RECHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Wait until `device` can be opened, for up to `timeout`, retrying whenever udev announces a
+/// change to it rather than polling
+///
+/// Falls back to polling every `interval` if udev can't be listened to (eg. in a container).
+#[cfg(all(target_os = "linux", feature = "udev"))]
+fn wait_for_udev_change(device: &OsStr, timeout: Duration, interval: Duration) -> Result<()> {
+    use nix::errno::Errno;
+    use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+    use std::os::fd::AsFd;
+
+    let start_time = Instant::now();
+    if File::open(device).is_ok() {
+        return Ok(());
+    }
+    let socket = match udev::MonitorBuilder::new()
+        .and_then(|builder| builder.match_subsystem("block"))
+        .and_then(udev::MonitorBuilder::listen)
+    {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("Could not listen for udev events ({}). Polling instead.", e);
+            let remaining = timeout.saturating_sub(start_time.elapsed());
+            return wait_until_openable(device, remaining, interval);
+        },
+    };
+    // (udev reports the real device node, not symlinks like /dev/cdrom)
+    let devnode = fs::canonicalize(device).unwrap_or_else(|_| PathBuf::from(device));
+
+    // Check again now that we're subscribed, in case the disc arrived in the meantime
+    let mut changed = true;
+    loop {
+        if changed && File::open(device).is_ok() {
+            return Ok(());
+        }
+        let remaining = timeout.saturating_sub(start_time.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let wait =
+            PollTimeout::try_from(remaining.min(UDEV_RECHECK_INTERVAL)).unwrap_or(PollTimeout::MAX);
+        let mut fds = [PollFd::new(socket.as_fd(), PollFlags::POLLIN)];
+        changed = match poll(&mut fds, wait) {
+            Ok(0) => true,
+            Ok(_) | Err(Errno::EINTR) => {
+                // Drain everything that's queued so it doesn't wake us again
+                let events = socket.iter().filter(|event| event.devnode() == Some(&*devnode));
+                events.count() > 0
+            },
+            Err(e) => return Err(e).context("Could not wait for udev events"),
+        };
+    }
+    Err(MediaError::Timeout { device: PathBuf::from(device), elapsed: start_time.elapsed() }.into())
+}
+
+/// Identify the filesystem on a disc or disc image by its volume descriptors
+///
+/// Both ISO 9660 and UDF begin their volume recognition sequence at sector 16, which is
+/// never used for anything else, so a medium which is empty or zeroed there is unformatted.
+/// <http://www.cnwrecovery.co.uk/html/iso9660_disks.html>
+pub fn detect_filesystem<R: Read + Seek>(reader: &mut R) -> Result<Filesystem> {
+    let mut sector = Vec::with_capacity(2048);
+    reader.seek(SeekFrom::Start(16 * 2048)).context("Failed to seek to volume descriptors")?;
+    reader
+        .take(2048)
+        .read_to_end(&mut sector)
+        .context("Could not read the volume descriptor area")?;
+
+    Ok(match sector.get(1..6) {
+        Some(b"CD001") => Filesystem::Iso9660,
+        Some(b"BEA01" | b"NSR02" | b"NSR03" | b"TEA01") => Filesystem::Udf,
+        _ if sector.iter().all(|&byte| byte == 0) => Filesystem::Blank,
+        _ => Filesystem::Unknown,
+    })
+}
+
+/// Identify the filesystem on `provider`'s medium by reading it, for the platform providers'
+/// `filesystem` implementations to fall back on
+///
+/// Drives report read errors for audio CDs, so a medium which can't be read is `Unknown` rather
+/// than blank.
+fn read_filesystem<P: RawMediaProvider + ?Sized>(provider: &P) -> Result<Filesystem, MediaError> {
+    let mut dev = provider.open_buffered()?;
+    detect_filesystem(&mut dev).or_else(|e| {
+        debug!("Could not identify filesystem: {:#}", e);
+        Ok(Filesystem::Unknown)
+    })
+}
+
+/// Decode a raw volume label field, using `encoding` if given
+///
+/// Otherwise, labels which aren't valid UTF-8 (and hence not ASCII either) are assumed to be
+/// Shift-JIS, since Japanese discs are by far the most common source of non-ASCII labels
+/// outside of Joliet (which `blkid` already handles for us).
+fn decode_label(raw: &[u8], encoding: Option<&'static Encoding>) -> String {
+    let field = raw.split(|&byte| byte == 0).next().unwrap_or_default();
+    let text = match encoding {
+        Some(charset) => charset.decode_without_bom_handling(field).0,
+        None => match std::str::from_utf8(field) {
+            Ok(utf8) => utf8.into(),
+            Err(_) => match SHIFT_JIS.decode_without_bom_handling_and_without_replacement(field) {
+                Some(sjis) => sjis,
+                None => String::from_utf8_lossy(field),
+            },
+        },
+    };
+    text.trim().to_owned()
+}
+
+/// The session layout of a disc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Number of sessions burned to the disc
+    pub count: u32,
+    /// Byte offset of the start of the last session (where the current filesystem lives)
+    pub last_offset: u64,
+}
+
+impl Default for SessionInfo {
+    fn default() -> Self {
+        SessionInfo { count: 1, last_offset: 0 }
+    }
+}
+
+/// Extract the session layout from `udevadm info --query=property` output
+///
+/// (Set by udev's `cdrom_id` helper from the disc's TOC. Absent for non-optical media.)
+fn parse_udev_sessions(properties: &str) -> Option<SessionInfo> {
+    let mut count = None;
+    let mut last_offset = 0;
+    for line in properties.lines() {
+        match line.trim().split_once('=') {
+            Some(("ID_CDROM_MEDIA_SESSION_COUNT", value)) => count = value.parse().ok(),
+            Some(("ID_CDROM_MEDIA_SESSION_LAST_OFFSET", value)) => {
+                last_offset = value.parse().unwrap_or(0);
+            },
+            _ => {},
+        }
+    }
+    count.map(|session_count| SessionInfo { count: session_count, last_offset })
+}
+
+/// Identify the kind of disc from `udevadm info --query=property` output
+///
+/// (`cdrom_id` sets `ID_CDROM_MEDIA_<PROFILE>=1` from the drive's GET CONFIGURATION response,
+/// alongside `ID_CDROM_<PROFILE>` properties for what the drive itself supports.)
+fn parse_udev_media_type(properties: &str) -> MediaType {
+    let (mut is_cd, mut is_dvd, mut is_bluray) = (false, false, false);
+    let (mut audio_tracks, mut data_tracks) = (0_u32, 0_u32);
+    for line in properties.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let Some(profile) = key.strip_prefix("ID_CDROM_MEDIA_") else {
+            continue;
+        };
+        match profile {
+            "TRACK_COUNT_AUDIO" => audio_tracks = value.parse().unwrap_or(0),
+            "TRACK_COUNT_DATA" => data_tracks = value.parse().unwrap_or(0),
+            _ if value != "1" => {},
+            _ if profile == "CD" || profile.starts_with("CD_") => is_cd = true,
+            _ if profile == "DVD" || profile.starts_with("DVD_") => is_dvd = true,
+            _ if profile == "BD" || profile.starts_with("BD_") => is_bluray = true,
+            _ => {},
+        }
+    }
+
+    if is_bluray {
+        MediaType::BluRay
+    } else if is_dvd {
+        MediaType::Dvd
+    } else if is_cd && audio_tracks > 0 && data_tracks == 0 {
+        MediaType::AudioCd
+    } else if is_cd {
+        MediaType::DataCd
+    } else {
+        MediaType::Unknown
+    }
+}
+
+/// Reduce `udevadm info --query=property` output to the properties which describe the disc
+/// (rather than the drive), for comparing before and after
+///
+/// (udev's `cdrom_id` helper refreshes these whenever the drive reports a media change, and
+/// drops them entirely when the tray is empty, which yields an empty identity.)
+fn parse_udev_media_identity(properties: &str) -> String {
+    let mut relevant: Vec<&str> = properties
+        .lines()
+        .map(str::trim)
+        .filter(|line| {
+            line.starts_with("ID_CDROM_MEDIA")
+                || ["ID_FS_UUID=", "ID_FS_LABEL=", "ID_FS_TYPE="]
+                    .iter()
+                    .any(|prefix| line.starts_with(prefix))
+        })
+        .collect();
+    relevant.sort_unstable();
+    relevant.join("\n")
+}
+
+/// Whether `path` is a block device (as opposed to eg. an image file)
+#[cfg(unix)]
+fn is_block_device(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|meta| meta.file_type().is_block_device())
+}
+
+/// Whether `path` is a block device (Never, on platforms without them)
+#[cfg(not(unix))]
+fn is_block_device(_path: &Path) -> bool {
+    false
+}
+
+/// Whether `/proc/mounts`-style `mounts` lists `device` (which should already be canonicalized)
+/// as mounted somewhere
+fn is_listed_as_mounted(mounts: &str, device: &Path) -> bool {
+    mounts.lines().filter_map(|line| line.split_whitespace().next()).any(|field| {
+        // (Sources may be symlinks like /dev/cdrom, and spaces are escaped as \040)
+        let source = PathBuf::from(field.replace("\\040", " "));
+        fs::canonicalize(&source).unwrap_or(source) == device
+    })
 }
 
 /// Interface for manipulating media devices such as DVD drives
-/// TODO: Custom error type
+///
+/// Failures which callers may want to handle specially (eg. timeouts, unreadable devices, or
+/// discs in the wrong format) have `MediaError` variants of their own, and anything else is
+/// `MediaError::Other`. Since those may wrap one of the others (eg. with context added), use
+/// `MediaError::find` to check for one.
 pub trait MediaProvider {
     /// Eject the media if the hardware supports it
-    fn eject(&mut self) -> Result<()>;
+    fn eject(&mut self) -> Result<(), MediaError>;
 
     /// Load the media if the hardware supports it
-    fn load(&mut self) -> Result<()>;
+    fn load(&mut self) -> Result<(), MediaError>;
 
     /// Unmount the media if mounted
-    fn unmount(&mut self) -> Result<()>;
+    fn unmount(&mut self) -> Result<(), MediaError>;
 
     /// Retrieve the volume label, if one is set
-    fn volume_label(&self) -> Result<String>;
+    fn volume_label(&self) -> Result<String, MediaError>;
 
     /// Wait up to `timeout` seconds for the disc to be ready
-    fn wait_for_ready(&self, timeout: &Duration) -> Result<()>;
+    fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError>;
+
+    /// How long to wait after a rip before ejecting
+    ///
+    /// Defaults to giving the user time to reach for a tray drive's door if it got closed.
+    /// Providers for things like image files and changers, where nobody's waiting to catch
+    /// the disc, should return zero.
+    fn eject_delay_hint(&self) -> Duration {
+        TRAY_EJECT_DELAY
+    }
+
+    /// Identify the filesystem on the inserted medium
+    fn filesystem(&self) -> Result<Filesystem, MediaError>;
+
+    /// Retrieve the session layout of the inserted medium
+    ///
+    /// Defaults to reporting a single session, which is correct for anything but burned discs.
+    fn sessions(&self) -> Result<SessionInfo, MediaError> {
+        Ok(SessionInfo::default())
+    }
+
+    /// Identify what kind of disc is inserted (eg. to pick a ripping strategy)
+    ///
+    /// Defaults to `MediaType::Unknown`, for providers which can't tell.
+    fn media_type(&self) -> Result<MediaType, MediaError> {
+        Ok(MediaType::Unknown)
+    }
+
+    /// Fingerprint the inserted disc well enough to notice when it's been swapped for another
+    /// (eg. the next disc of a set)
+    ///
+    /// Should fail if there's no disc to read, so callers can wait for one. Defaults to `None`
+    /// (can't tell).
+    fn disc_serial(&self) -> Result<Option<String>, MediaError> {
+        Ok(None)
+    }
 }
 
 /// Interface for platform providers which support exposing raw device paths
@@ -71,16 +859,93 @@ pub trait RawMediaProvider {
     /// Return an `OsString` which can be used by APIs or subprocesses to
     /// reference the device
     fn device_path(&self) -> OsString;
+
+    /// Return the capacity of the medium in bytes
+    fn device_size(&self) -> Result<u64, MediaError>;
+
+    /// Identify the inserted medium well enough to notice if it gets swapped for another
+    ///
+    /// Defaults to `None` (can't tell), which disables mid-rip media change detection.
+    fn media_identity(&self) -> Option<String> {
+        None
+    }
+
+    /// Identify the drive doing the reading
+    ///
+    /// Defaults to `None`, for providers which don't read from a drive (eg. disc images).
+    fn drive_info(&self) -> Result<Option<DriveInfo>, MediaError> {
+        Ok(None)
+    }
+
+    /// Size of the blocks to read in when parsing on-disc filesystem structures
+    fn read_buffer(&self) -> usize {
+        DEFAULT_READ_BUFFER
+    }
+
+    /// Open the device for reading filesystem structures in blocks of `read_buffer()` bytes
+    fn open_buffered(&self) -> Result<SectorReader<File>> {
+        let device = self.device_path();
+        let file = File::open(&device)
+            .map_err(MediaError::DeviceUnavailable)
+            .with_context(|| format!("Could not open for reading: {}", device.to_string_lossy()))?;
+        Ok(SectorReader::with_block_size(file, self.read_buffer()))
+    }
+}
+
+/// Interface for drives which hold several discs at once (eg. CD/DVD changers and jukeboxes)
+pub trait ChangerProvider {
+    /// Switch the drive to the disc in `slot` (counting from 1)
+    fn load_slot(&mut self, slot: usize) -> Result<(), MediaError>;
 }
 
 /// High-level interface for notifying the user via various system APIs
 /// TODO: Refactor or rename this since prompt() isn't a notification.
 pub trait NotificationProvider {
     /// Play the given audio file, if supported
-    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()>;
+    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<(), MediaError>;
+
+    /// Whether prompts can be answered (Callers should skip optional prompts if not)
+    fn is_interactive(&self) -> bool;
 
     /// Prompt the user for a line of input
-    fn read_line(&self, prompt: &str) -> Result<String>;
+    fn read_line(&self, prompt: &str) -> Result<String, MediaError>;
+
+    /// Prompt the user for a line of input without recording it in the input history
+    ///
+    /// (For sensitive input like CD keys)
+    fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError>;
+
+    /// Pop up a desktop notification, if supported
+    ///
+    /// Failure (eg. no notification daemon running) is logged rather than returned, since the
+    /// user may well be at a text console.
+    fn notify_desktop(&self, summary: &str, body: &str, urgency: Urgency) {
+        debug!(
+            "Desktop notifications aren't supported on this platform ({}): {}: {}",
+            urgency.as_str(),
+            summary,
+            body
+        );
+    }
+}
+
+/// How insistently a desktop notification should demand attention
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    /// Routine news, like a rip finishing
+    Normal,
+    /// Something the user needs to deal with, like a failed rip
+    Critical,
+}
+
+impl Urgency {
+    /// The name `notify-send --urgency` knows this level by
+    fn as_str(self) -> &'static str {
+        match self {
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
 }
 
 /// `MediaProvider` implementation which operates on (possibly GUI-less) Linux systems
@@ -88,6 +953,9 @@ pub struct LinuxPlatformProvider<'devpath> {
     /// Device/file to operate on
     /// TODO: Consider storing a Path internally instead.
     device: Cow<'devpath, OsStr>,
+
+    /// Settings shared by all of the platform providers
+    settings: ProviderSettings,
 }
 
 impl<'devpath> LinuxPlatformProvider<'devpath> {
@@ -96,7 +964,176 @@ impl<'devpath> LinuxPlatformProvider<'devpath> {
     /// TODO: Ask whether I'm using the proper naming convention for this
     pub fn new(device: Cow<'_, OsStr>) -> LinuxPlatformProvider<'_> {
         // TODO: Validate this path
-        LinuxPlatformProvider { device }
+        LinuxPlatformProvider { device, settings: ProviderSettings::default() }
+    }
+
+    /// Get the device's directory under `/sys/class/block`
+    fn sysfs_dir(&self) -> Result<PathBuf> {
+        let canonical = Path::new(&self.device).canonicalize().with_context(|| {
+            format!("Could not resolve device path: {}", self.device.to_string_lossy())
+        })?;
+        Ok(Path::new("/sys/class/block")
+            .join(canonical.file_name().context("Device path has no filename")?))
+    }
+
+    /// Retrieve the properties udev has recorded for the device, if any
+    fn udev_properties(&self) -> Option<String> {
+        Command::new("udevadm")
+            .args(["info", "--query=property", "--name"])
+            .arg(&self.device)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl<'devpath> ProviderBuilder for LinuxPlatformProvider<'devpath> {
+    fn settings_mut(&mut self) -> &mut ProviderSettings {
+        &mut self.settings
+    }
+}
+
+/// Settings which every platform provider takes, configured through `ProviderBuilder`
+pub struct ProviderSettings {
+    /// Prompts (and their history)
+    prompter: Prompter,
+
+    /// Size of the blocks to read in when parsing on-disc filesystem structures
+    read_buffer: usize,
+
+    /// Character set of raw ISO9660 volume labels (`None` to guess)
+    label_encoding: Option<&'static Encoding>,
+
+    /// Whether to prompt the user (`None` to decide based on whether we're attached to a
+    /// terminal)
+    interactive: Option<bool>,
+
+    /// How often to check for a disc in `wait_for_ready`
+    poll_interval: Duration,
+}
+
+impl Default for ProviderSettings {
+    fn default() -> Self {
+        ProviderSettings {
+            prompter: Prompter::default(),
+            read_buffer: DEFAULT_READ_BUFFER,
+            label_encoding: None,
+            interactive: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+}
+
+/// Builder methods for the settings in `ProviderSettings`, shared by the platform providers
+pub trait ProviderBuilder: Sized {
+    /// The provider's settings, for the builder methods to fill in
+    fn settings_mut(&mut self) -> &mut ProviderSettings;
+
+    /// Persist prompt history to the given file so past answers can be recalled with the arrow keys
+    fn with_history(mut self, path: PathBuf) -> Self {
+        self.settings_mut().prompter.history_path = Some(path);
+        self
+    }
+
+    /// Read filesystem structures in blocks of `size` bytes (rounded up to whole sectors)
+    fn with_read_buffer(mut self, size: usize) -> Self {
+        self.settings_mut().read_buffer = size;
+        self
+    }
+
+    /// Decode raw ISO9660 volume labels using `encoding` rather than guessing
+    fn with_label_encoding(mut self, encoding: Option<&'static Encoding>) -> Self {
+        self.settings_mut().label_encoding = encoding;
+        self
+    }
+
+    /// Force prompting on or off rather than checking whether we're attached to a terminal
+    fn with_interactive(mut self, interactive: Option<bool>) -> Self {
+        self.settings_mut().interactive = interactive;
+        self
+    }
+
+    /// Check for a disc every `interval` while waiting for one, rather than every second
+    fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.settings_mut().poll_interval = interval;
+        self
+    }
+
+    /// Show prompts on `stream` rather than the controlling terminal
+    fn with_prompt_stream(mut self, stream: PromptStream) -> Self {
+        self.settings_mut().prompter.stream = stream;
+        self
+    }
+}
+
+/// Prompts with line editing and a history which can be kept between runs, for the platform
+/// providers to share
+#[derive(Default)]
+struct Prompter {
+    /// File to load prompt history from and save it back to (if any)
+    history_path: Option<PathBuf>,
+
+    /// Line editor for prompts (Initialized on first use so history is only loaded if needed)
+    editor: RefCell<Option<DefaultEditor>>,
+
+    /// Where to show prompts
+    stream: PromptStream,
+}
+
+impl Prompter {
+    /// Prompt for a line of input, recording the answer in the history if `record` is set
+    fn read_line(&self, prompt: &str, record: bool) -> Result<String> {
+        let behavior = match self.stream {
+            PromptStream::Tty if tty_available() => Behavior::PreferTerm,
+            PromptStream::Stdout => Behavior::Stdio,
+            PromptStream::Tty | PromptStream::Stderr => {
+                return prompt_plain(io::stderr(), io::stdin().lock(), prompt).with_context(|| {
+                    format!("Failed to request information from user with: {}", prompt)
+                });
+            },
+        };
+
+        let mut editor_slot = self.editor.borrow_mut();
+        if editor_slot.is_none() {
+            let config = Config::builder().behavior(behavior).build();
+            let mut editor = DefaultEditor::with_config(config)
+                .context("Failed to initialize rustyline editor")?;
+            if let Some(path) = &self.history_path {
+                // Not an error, since there won't be a history file on the first run
+                if let Err(e) = editor.load_history(path) {
+                    debug!("Could not load prompt history from {}: {}", path.display(), e);
+                }
+            }
+            *editor_slot = Some(editor);
+        }
+
+        #[allow(clippy::expect_used)]
+        let editor = editor_slot.as_mut().expect("editor was initialized above");
+        let line = editor
+            .readline(prompt)
+            .with_context(|| format!("Failed to request information from user with: {}", prompt))?;
+
+        if record && !line.trim().is_empty() {
+            editor.add_history_entry(line.as_str()).context("Failed to record prompt history")?;
+        }
+        Ok(line)
+    }
+}
+
+impl Drop for Prompter {
+    fn drop(&mut self) {
+        if let (Some(path), Some(editor)) = (&self.history_path, self.editor.get_mut()) {
+            if let Some(parent) = path.parent() {
+                if let Err(e) = create_dir_all(parent) {
+                    warn!("Could not create {}: {}", parent.display(), e);
+                    return;
+                }
+            }
+            if let Err(e) = editor.save_history(path) {
+                warn!("Could not save prompt history to {}: {}", path.display(), e);
+            }
+        }
     }
 }
 
@@ -105,112 +1142,289 @@ impl<'devpath> RawMediaProvider for LinuxPlatformProvider<'devpath> {
     fn device_path(&self) -> OsString {
         self.device.clone().into_owned()
     }
+
+    fn read_buffer(&self) -> usize {
+        self.settings.read_buffer
+    }
+
+    fn device_size(&self) -> Result<u64, MediaError> {
+        let path = Path::new(&self.device);
+        let meta = fs::metadata(path)
+            .with_context(|| format!("Could not stat {}", self.device.to_string_lossy()))?;
+        if meta.is_file() {
+            return Ok(meta.len());
+        }
+
+        // Block devices report a length of zero, so ask the kernel via sysfs instead
+        // (which always counts in 512-byte units, regardless of the device's sector size)
+        let sysfs_path = self.sysfs_dir()?.join("size");
+        let sectors: u64 = fs::read_to_string(&sysfs_path)
+            .with_context(|| format!("Could not read {}", sysfs_path.display()))?
+            .trim()
+            .parse()
+            .with_context(|| format!("Unexpected contents in {}", sysfs_path.display()))?;
+        sectors.checked_mul(512).context("Device size overflowed").map_err(MediaError::from)
+    }
+
+    fn media_identity(&self) -> Option<String> {
+        // Image files can't be swapped mid-read in any way we could detect
+        // TODO: Poll the CDROM_MEDIA_CHANGED ioctl instead, if we ever allow unsafe code
+        if Path::new(&self.device).is_file() {
+            return None;
+        }
+        self.udev_properties().map(|properties| parse_udev_media_identity(&properties))
+    }
+
+    fn drive_info(&self) -> Result<Option<DriveInfo>, MediaError> {
+        if Path::new(&self.device).is_file() {
+            return Ok(None); // Whatever drive an image came from, we weren't told
+        }
+        read_drive_info(&self.sysfs_dir()?.join("device")).map(Some).map_err(MediaError::from)
+    }
 }
 
 impl<'devpath> MediaProvider for LinuxPlatformProvider<'devpath> {
-    fn eject(&mut self) -> Result<()> {
+    fn eject(&mut self) -> Result<(), MediaError> {
         subprocess_call!("eject", &self.device)
             .with_context(|| format!("Could not eject {}", &self.device.to_string_lossy()))
+            .map_err(MediaError::from)
     }
 
-    fn load(&mut self) -> Result<()> {
+    fn load(&mut self) -> Result<(), MediaError> {
         subprocess_call!("eject", "-t", &self.device)
             .with_context(|| format!("Could not load media for {}", &self.device.to_string_lossy()))
+            .map_err(MediaError::from)
     }
 
-    fn unmount(&mut self) -> Result<()> {
-        subprocess_call!("umount", &self.device)
-            .with_context(|| format!("Could not unmount {}", &self.device.to_string_lossy()))
+    fn unmount(&mut self) -> Result<(), MediaError> {
+        let device = fs::canonicalize(&self.device).unwrap_or_else(|_| PathBuf::from(&self.device));
+        let is_block_device = is_block_device(&device);
+        let mounted = fs::read_to_string("/proc/mounts")
+            .is_ok_and(|mounts| is_listed_as_mounted(&mounts, &device));
+        if is_block_device && !mounted {
+            debug!("{} isn't mounted", device.display());
+            return Ok(());
+        }
+
+        let Err(umount_err) = subprocess_call!("umount", &self.device) else { return Ok(()) };
+        let err = anyhow::Error::from(umount_err)
+            .context(format!("Could not unmount {}", self.device.to_string_lossy()));
+        if is_block_device {
+            // Automounted discs usually need udisks (and its polkit rules) to unmount them
+            // without root privileges
+            if subprocess_call!("udisksctl", "unmount", "--block-device", &self.device).is_ok() {
+                return Ok(());
+            }
+        }
+        if !mounted {
+            return Err(err.into());
+        }
+
+        // Detach it so nothing new can open it, even if something already has files open on it
+        if subprocess_call!("umount", "--lazy", &self.device).is_ok() {
+            warn!(
+                "{} was busy, so it was lazily unmounted. Programs with files already open on it \
+                 can still read from it.",
+                device.display()
+            );
+            return Ok(());
+        }
+        Err(err.context(MediaError::Busy(device)).into())
     }
 
-    fn volume_label(&self) -> Result<String> {
-        // TODO: Use UDisks2 via dbus
-        //
+    fn volume_label(&self) -> Result<String, MediaError> {
+        // Image files have nothing but the ISO9660 header to go by
+        if Path::new(&self.device).is_file() {
+            return read_iso9660_label(Path::new(&self.device), self.settings.label_encoding)
+                .map_err(MediaError::from);
+        }
+
+        // Ask UDisks2 first, since blkid can't read most drives without root
+        #[cfg(all(target_os = "linux", feature = "udisks"))]
+        if self.settings.label_encoding.is_none() {
+            match udisks_volume_label(&self.device) {
+                Ok(label) if !label.is_empty() => return Ok(label),
+                Ok(_) => {},
+                Err(e) => debug!("Could not ask UDisks2 for the volume label: {:#}", e),
+            }
+        }
+
         // XXX: Could use libblkid directly:
         // https://www.kernel.org/pub/linux/utils/util-linux/v2.21/libblkid-docs/libblkid-Search-and-iterate.html#blkid-get-tag-value
         // (Use the existing Command::new("blkid") code for functional testing)
 
-        // Allow Linux a chance to read the name (eg. for post-ISO9660 stuff)
-        if let Ok(label) = Command::new("blkid")
-            .args(&["-s", "LABEL", "-o", "value"])
-            .arg(&self.device)
-            .output()
-            .map(|o| String::from_utf8_lossy(o.stdout.as_slice()).trim().to_owned())
-        {
-            // XXX: Handle some types of blkid failure?
-            if !label.is_empty() {
-                return Ok(label); // TODO: Is there a more idiomatic early return for Ok()?
+        // Allow Linux a chance to read the name (eg. for post-ISO9660 stuff), unless we've been
+        // told the raw label uses a character set that blkid won't know to decode
+        if self.settings.label_encoding.is_none() {
+            if let Ok(label) = Command::new("blkid")
+                .args(&["-s", "LABEL", "-o", "value"])
+                .arg(&self.device)
+                .output()
+                .map(|o| String::from_utf8_lossy(o.stdout.as_slice()).trim().to_owned())
+            {
+                // XXX: Handle some types of blkid failure?
+                if !label.is_empty() {
+                    return Ok(label); // TODO: Is there a more idiomatic early return for Ok()?
+                }
             }
         }
 
         // Fall back to reading the raw ISO9660 header
-        // TODO: Move this stuff into an IsoMediaProvider
-        let mut dev = File::open(&self.device).with_context(|| {
-            format!("Could not open for reading: {}", self.device.to_string_lossy())
-        })?;
+        let mut dev = self.open_buffered()?;
 
-        // Safety check for non-ISO9660 filesystems
-        // http://www.cnwrecovery.co.uk/html/iso9660_disks.html
-        #[allow(clippy::use_debug)]
-        let cd_magic = read_exact_at!(dev, 2, SeekFrom::Start(32769));
-        if &cd_magic != b"CD" {
-            bail!("Unrecognized file format");
-        }
-
-        // http://www.commandlinefu.com/commands/view/12178
-        // TODO: Find the spec to see if the split is really needed
-        //       (My test discs were space-padded)
-        #[allow(clippy::use_debug)]
-        Ok(String::from_utf8_lossy(&read_exact_at!(dev, 32, SeekFrom::Start(32808)))
-            .split('\0')
-            .next()
-            .unwrap_or("")
-            .trim()
-            .to_owned())
+        // On multi-session discs, the filesystem that's current is the one in the last session
+        let session_start = match self.sessions() {
+            Ok(sessions) if sessions.count > 1 => sessions.last_offset,
+            _ => 0,
+        };
+
+        raw_volume_label(&mut dev, session_start, self.settings.label_encoding)
+            .map_err(MediaError::from)
     }
 
-    fn wait_for_ready(&self, timeout: &Duration) -> Result<()> {
-        let start_time = Instant::now();
-        loop {
-            // Poll for a disc and return early on success
-            // (According to https://lwn.net/Articles/462178/, this is probably
-            //  something we can't readily and reliably block on)
-            if File::open(&self.device).is_ok() {
-                return Ok(());
-            }
-            if start_time.elapsed() >= *timeout {
-                break;
+    #[cfg(all(target_os = "linux", feature = "udev"))]
+    fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError> {
+        wait_for_udev_change(&self.device, *timeout, self.settings.poll_interval)
+            .map_err(MediaError::from)
+    }
+
+    #[cfg(not(all(target_os = "linux", feature = "udev")))]
+    fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError> {
+        wait_until_openable(&self.device, *timeout, self.settings.poll_interval)
+            .map_err(MediaError::from)
+    }
+
+    fn filesystem(&self) -> Result<Filesystem, MediaError> {
+        // Ask udev first, since the drive knows whether a disc is blank better than we can
+        // guess from reading it (udev's cdrom_id sets this without needing special permissions)
+        if let Some(properties) = self.udev_properties() {
+            if properties.lines().any(|line| line.trim() == "ID_CDROM_MEDIA_STATE=blank") {
+                return Ok(Filesystem::Blank);
             }
+        }
+        read_filesystem(self)
+    }
+
+    fn sessions(&self) -> Result<SessionInfo, MediaError> {
+        // Image files are always a single "session" as far as we're concerned
+        if Path::new(&self.device).is_file() {
+            return Ok(SessionInfo::default());
+        }
+        let properties = self.udev_properties().context("Could not query udev")?;
+        Ok(parse_udev_sessions(&properties).unwrap_or_default())
+    }
 
-            sleep(Duration::new(1, 0));
+    fn media_type(&self) -> Result<MediaType, MediaError> {
+        // Image files don't record what kind of disc they came from
+        if Path::new(&self.device).is_file() {
+            return Ok(MediaType::Unknown);
+        }
+        let properties = self.udev_properties().context("Could not query udev")?;
+        Ok(parse_udev_media_type(&properties))
+    }
+
+    fn disc_serial(&self) -> Result<Option<String>, MediaError> {
+        let mut dev = self.open_buffered()?;
+
+        // Discs without an ISO9660 filesystem (eg. audio CDs) have nothing to fingerprint
+        Ok(iso9660::volume_fingerprint(&mut dev)
+            .map_err(|e| debug!("Could not fingerprint the disc: {:#}", e))
+            .ok())
+    }
+
+    fn eject_delay_hint(&self) -> Duration {
+        // Image files have no tray to reach for
+        if Path::new(&self.device).is_file() {
+            Duration::ZERO
+        } else {
+            TRAY_EJECT_DELAY
         }
-        bail!("Timed out")
+    }
+}
+
+impl<'devpath> ChangerProvider for LinuxPlatformProvider<'devpath> {
+    fn load_slot(&mut self, slot: usize) -> Result<(), MediaError> {
+        // (`eject -c` counts slots from 0 and only handles ATAPI changers. SCSI changers
+        //  need `mtx` and the changer's own device node, which we don't know.)
+        let index = slot.checked_sub(1).context("Changer slots are numbered from 1")?;
+        subprocess_call!("eject", "-c", index.to_string(), &self.device).with_context(|| {
+            format!("Could not load slot {} of {}", slot, &self.device.to_string_lossy())
+        })?;
+        Ok(())
     }
 }
 
 impl<'devpath> NotificationProvider for LinuxPlatformProvider<'devpath> {
-    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<()> {
+    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<(), MediaError> {
         subprocess_call!("play", "-V0", path.as_ref())
             .with_context(|| format!("Could not play {}", path.as_ref().to_string_lossy()))
+            .map_err(MediaError::from)
     }
 
-    fn read_line(&self, prompt: &str) -> Result<String> {
-        DefaultEditor::new()
-            .context("Failed to initialize rustyline editor")?
-            .readline(prompt)
-            .with_context(|| format!("Failed to request information from user with: {}", prompt))
+    fn is_interactive(&self) -> bool {
+        self.settings.interactive.unwrap_or_else(is_interactive)
+    }
+
+    fn read_line(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, true).map_err(MediaError::from)
+    }
+
+    fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, false).map_err(MediaError::from)
+    }
+
+    #[cfg(feature = "desktop-notify")]
+    fn notify_desktop(&self, summary: &str, body: &str, urgency: Urgency) {
+        let result = notify_rust::Notification::new()
+            .appname(APP_DIR_NAME)
+            .summary(summary)
+            .body(body)
+            .urgency(match urgency {
+                Urgency::Normal => notify_rust::Urgency::Normal,
+                Urgency::Critical => notify_rust::Urgency::Critical,
+            })
+            .show();
+        if let Err(e) = result {
+            debug!("Could not show desktop notification: {}", e);
+        }
+    }
+
+    #[cfg(not(feature = "desktop-notify"))]
+    fn notify_desktop(&self, summary: &str, body: &str, urgency: Urgency) {
+        let result = Command::new("notify-send")
+            .args(["--app-name", APP_DIR_NAME, "--urgency", urgency.as_str(), summary, body])
+            .status();
+        match result {
+            Ok(status) if status.success() => {},
+            // (eg. no notification daemon, or no desktop session at all)
+            Ok(status) => debug!("notify-send failed ({}). Skipping desktop notification.", status),
+            Err(e) => debug!("Could not run notify-send ({}). Skipping desktop notification.", e),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{LinuxPlatformProvider, MediaProvider, NotificationProvider, RawMediaProvider};
+    use super::{
+        decode_label, detect_filesystem, is_listed_as_mounted, parse_device_token,
+        parse_simulated_failure, parse_udev_media_identity, parse_udev_media_type,
+        parse_udev_sessions, prompt_plain, raw_volume_label, read_drive_info, read_iso9660_label,
+        resolve_inpath, wait_with_watchdog, xdg_dir, ChangerProvider, DriveInfo, Filesystem,
+        LinuxPlatformProvider, MediaError, MediaGuard, MediaProvider, MediaType,
+        NotificationProvider, ProviderBuilder, RawMediaProvider, SessionInfo, Urgency,
+    };
+    use crate::test_support::scratch_dir;
+    use anyhow::anyhow;
+    use encoding_rs::SHIFT_JIS;
     use std::borrow::Cow;
+    use std::cell::Cell;
     use std::env;
-    use std::ffi::OsStr;
-    use std::io::Result as IOResult;
+    use std::ffi::{OsStr, OsString};
+    use std::fs::{self, File};
+    use std::io::{self, Cursor, Result as IOResult};
     use std::os::unix::ffi::OsStrExt; // TODO: Find a better way to produce invalid UTF-8
     use std::path::{Path, PathBuf};
+    use std::process::Command;
     use std::time::{Duration, Instant};
 
     /// Port of Python's naive `abspath` to be used as a prelude to `Path::display`
@@ -223,7 +1437,64 @@ mod tests {
         env::current_dir().map(|p| p.join(relpath.as_ref()))
     }
 
-    /// TODO: Tests for macros
+    #[test]
+    fn subprocess_call_checks_exit_status() {
+        subprocess_call!("/bin/true",).expect("true succeeds");
+        subprocess_call!("/bin/echo", "-n", "args are passed").expect("echo succeeds");
+
+        let failed = subprocess_call!("/bin/false",).expect_err("false fails");
+        assert_eq!(failed.to_string(), "/bin/false exited with code 1");
+        match MediaError::find(&failed.into()) {
+            Some(MediaError::SubprocessFailed { command, code }) => {
+                assert_eq!((command.as_str(), *code), ("/bin/false", Some(1)));
+            },
+            other => panic!("Expected MediaError::SubprocessFailed, got {:?}", other),
+        }
+        let exited = subprocess_call!("/bin/sh", "-c", "exit 3").expect_err("exit 3 fails");
+        assert_eq!(exited.to_string(), "/bin/sh exited with code 3");
+
+        let missing = subprocess_call!("/nonexistent/command",).expect_err("missing command fails");
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn media_errors_survive_conversion_from_anyhow() {
+        // Bare ones come through as themselves...
+        let blank = MediaError::from(anyhow::Error::from(MediaError::Blank));
+        assert!(matches!(blank, MediaError::Blank), "{:?}", blank);
+
+        // ...while context is kept by wrapping them, without hiding them from `find`
+        let busy = anyhow!("umount: target is busy").context(MediaError::Busy("/dev/sr0".into()));
+        let wrapped = MediaError::from(busy);
+        assert!(matches!(wrapped, MediaError::Other(_)), "{:?}", wrapped);
+        assert_eq!(
+            format!("{:#}", wrapped),
+            "/dev/sr0 is in use, so it could not be unmounted: \
+                                              umount: target is busy"
+        );
+        let err = anyhow::Error::from(wrapped).context("Could not rip the disc");
+        assert!(matches!(MediaError::find(&err), Some(MediaError::Busy(_))), "{:?}", err);
+
+        // Anything else is just a general failure
+        let other = MediaError::from(anyhow!("Something else went wrong"));
+        assert_eq!(other.exit_code(), 1);
+        assert!(MediaError::find(&other.into()).is_none());
+    }
+
+    #[test]
+    fn parse_simulated_failure_matches_command_names() {
+        let spec = "ddrescue=2, flac ,cdrdao=-1";
+        assert_eq!(parse_simulated_failure(spec, OsStr::new("ddrescue")), Ok(Some(2)));
+        assert_eq!(parse_simulated_failure(spec, OsStr::new("/usr/bin/ddrescue")), Ok(Some(2)));
+        assert_eq!(parse_simulated_failure(spec, OsStr::new("flac")), Ok(Some(1)));
+        assert_eq!(parse_simulated_failure(spec, OsStr::new("cdrdao")), Ok(Some(-1)));
+        assert_eq!(parse_simulated_failure(spec, OsStr::new("eject")), Ok(None));
+        assert_eq!(parse_simulated_failure("", OsStr::new("eject")), Ok(None));
+
+        let bogus = "flac, cdrdao=bogus";
+        assert!(parse_simulated_failure(bogus, OsStr::new("cdrdao")).is_err());
+        assert!(parse_simulated_failure(bogus, OsStr::new("flac")).is_err(), "Whole spec is bad");
+    }
 
     /// Helper to deduplicate getting a platform provider pointed at the test fixture
     fn get_iso_provider<'a>() -> LinuxPlatformProvider<'a> {
@@ -245,6 +1516,57 @@ mod tests {
     }
     // TODO: Test abspath with relative paths
 
+    #[test]
+    fn decode_label_handles_padding_and_charsets() {
+        assert_eq!(decode_label(b"MY_GAME                  \0\0\0\0\0\0\0", None), "MY_GAME");
+        // "ゲーム" in Shift-JIS, as found on Japanese discs without Joliet extensions
+        let sjis = b"\x83\x51\x81\x5b\x83\x80      ";
+        assert_eq!(decode_label(sjis, None), "ゲーム");
+        assert_eq!(decode_label(sjis, Some(SHIFT_JIS)), "ゲーム");
+        assert_eq!(decode_label(b"CAF\xc9", Some(encoding_rs::WINDOWS_1252)), "CAFÉ");
+        assert_eq!(decode_label("CAFÉ".as_bytes(), None), "CAFÉ");
+    }
+
+    #[test]
+    fn parse_device_token_recognizes_label_and_uuid() {
+        assert_eq!(parse_device_token("LABEL=MY_GAME"), Some(("-L", "MY_GAME")));
+        assert_eq!(
+            parse_device_token("UUID=2017-02-13-14-05-53-00"),
+            Some(("-U", "2017-02-13-14-05-53-00"))
+        );
+        assert_eq!(parse_device_token("LABEL=A=B"), Some(("-L", "A=B")));
+        for other in ["/dev/sr0", "LABEL=", "label=x", "PARTUUID=x", "fixture.iso"] {
+            assert_eq!(parse_device_token(other), None, "{:?}", other);
+        }
+    }
+
+    #[test]
+    fn resolve_inpath_passes_through_paths_and_reports_missing_devices() {
+        let plain = resolve_inpath(Path::new("/dev/sr0")).expect("plain paths aren't looked up");
+        assert_eq!(plain, Path::new("/dev/sr0"));
+        assert!(resolve_inpath(Path::new("LABEL=rip_media no such label 92af1c")).is_err());
+    }
+
+    #[test]
+    fn xdg_dir_prefers_absolute_env_value() {
+        let home = Some(OsString::from("/home/user"));
+        assert_eq!(
+            xdg_dir(Some(OsString::from("/xdg/data")), home.clone(), ".local/share"),
+            Some(PathBuf::from("/xdg/data"))
+        );
+
+        // The XDG spec says relative and empty values are to be ignored
+        for bad_value in &["", "relative/data"] {
+            assert_eq!(
+                xdg_dir(Some(OsString::from(bad_value)), home.clone(), ".local/share"),
+                Some(PathBuf::from("/home/user/.local/share")),
+                "{:?}",
+                bad_value
+            );
+        }
+        assert_eq!(xdg_dir(None, None, ".local/share"), None);
+    }
+
     #[test]
     fn eject_reports_failure_properly() {
         let mut p_bad = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/etc/shadow")));
@@ -259,6 +1581,13 @@ mod tests {
     }
     // TODO: Find a good way to test the success case for `load`
 
+    #[test]
+    fn load_slot_reports_failure_properly() {
+        let mut p_bad = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/etc/shadow")));
+        assert!(p_bad.load_slot(1).is_err());
+        assert!(p_bad.load_slot(0).is_err(), "Slots are numbered from 1");
+    }
+
     #[test]
     fn play_sound_reports_failure_properly() {
         let mut p_good = get_iso_provider();
@@ -268,7 +1597,31 @@ mod tests {
     }
     // TODO: Find a good way to test the success case for `play_sound`
 
-    // TODO: Find a way to test `read_line`
+    #[test]
+    fn urgency_names_match_notify_send() {
+        assert_eq!(Urgency::Normal.as_str(), "normal");
+        assert_eq!(Urgency::Critical.as_str(), "critical");
+    }
+
+    #[test]
+    fn with_interactive_overrides_tty_detection() {
+        assert!(get_iso_provider().with_interactive(Some(true)).is_interactive());
+        assert!(!get_iso_provider().with_interactive(Some(false)).is_interactive());
+        assert_eq!(get_iso_provider().is_interactive(), super::is_interactive());
+    }
+
+    // TODO: Find a way to test `read_line` with a terminal
+
+    #[test]
+    fn prompt_plain_writes_prompt_and_reads_one_answer() {
+        let mut shown = Vec::new();
+        let answer = prompt_plain(&mut shown, Cursor::new("Yes\r\nNo\n"), "Continue? ");
+        assert_eq!(answer.expect("answered"), "Yes");
+        assert_eq!(shown, b"Continue? ");
+
+        let unanswered = prompt_plain(Vec::new(), Cursor::new(""), "Continue? ");
+        assert!(unanswered.is_err(), "A closed stdin must not look like an empty answer");
+    }
 
     #[test]
     fn unmount_reports_failure_properly() {
@@ -296,11 +1649,269 @@ mod tests {
         assert_eq!(p.device_path(), path_str);
     }
 
+    // -- Tests for detect_filesystem() and LinuxPlatformProvider.filesystem()
+
+    /// Build a fake image with the given magic number in the first volume descriptor
+    fn fake_image(magic: &[u8]) -> Cursor<Vec<u8>> {
+        let mut image = vec![0; 18 * 2048];
+        image[16 * 2048 + 1..16 * 2048 + 1 + magic.len()].copy_from_slice(magic);
+        Cursor::new(image)
+    }
+
+    #[test]
+    fn detect_filesystem_recognizes_known_magic() {
+        for (magic, expected) in &[
+            (b"CD001", Filesystem::Iso9660),
+            (b"BEA01", Filesystem::Udf),
+            (b"NSR02", Filesystem::Udf),
+            (b"XXXXX", Filesystem::Unknown),
+        ] {
+            let detected = detect_filesystem(&mut fake_image(*magic)).expect("in-memory read");
+            assert_eq!(detected, *expected, "{:?}", magic);
+        }
+    }
+
+    #[test]
+    fn detect_filesystem_reports_blank_media() {
+        let empty = detect_filesystem(&mut Cursor::new(vec![])).expect("in-memory read");
+        assert_eq!(empty, Filesystem::Blank);
+        let zeroed = detect_filesystem(&mut fake_image(b"")).expect("in-memory read");
+        assert_eq!(zeroed, Filesystem::Blank);
+    }
+
+    #[test]
+    fn filesystem_basic_function() {
+        assert_eq!(
+            get_iso_provider().filesystem().expect("fixture.iso is readable"),
+            Filesystem::Iso9660
+        );
+    }
+
+    #[test]
+    fn open_buffered_reads_in_read_buffer_blocks() {
+        let dev = get_iso_provider().with_read_buffer(8192).open_buffered().expect("readable");
+        assert!(format!("{:?}", dev).contains("block_size: 8192"), "{:?}", dev);
+
+        let missing = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/nonexist_path")));
+        let err = missing.open_buffered().expect_err("nothing to open");
+        assert!(matches!(MediaError::find(&err), Some(MediaError::DeviceUnavailable(_))));
+    }
+
+    #[test]
+    fn filesystem_reports_failure_properly() {
+        let p_bad = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/nonexist_path")));
+        assert!(p_bad.filesystem().is_err());
+    }
+
+    // -- Tests for session detection
+
+    #[test]
+    fn parse_udev_sessions_reads_cdrom_id_properties() {
+        let props = "DEVNAME=/dev/sr0\nID_CDROM_MEDIA_SESSION_COUNT=3\n\
+                     ID_CDROM_MEDIA_SESSION_LAST_OFFSET=123463680\nID_CDROM_MEDIA_TRACK_COUNT=3\n";
+        assert_eq!(
+            parse_udev_sessions(props),
+            Some(SessionInfo { count: 3, last_offset: 123_463_680 })
+        );
+        assert_eq!(
+            parse_udev_sessions("ID_CDROM_MEDIA_SESSION_COUNT=1\n"),
+            Some(SessionInfo { count: 1, last_offset: 0 })
+        );
+        assert_eq!(parse_udev_sessions("DEVNAME=/dev/sda\n"), None);
+    }
+
+    #[test]
+    fn parse_udev_media_type_reads_cdrom_id_profiles() {
+        // (The drive's own capabilities lack the MEDIA_ and mustn't be mistaken for the disc's)
+        let drive = "ID_CDROM=1\nID_CDROM_CD=1\nID_CDROM_DVD=1\nID_CDROM_BD=1\nID_CDROM_MEDIA=1\n";
+        let audio = "ID_CDROM_MEDIA_CD=1\nID_CDROM_MEDIA_TRACK_COUNT_AUDIO=12\n";
+        assert_eq!(parse_udev_media_type(&format!("{}{}", drive, audio)), MediaType::AudioCd);
+        let mixed = "ID_CDROM_MEDIA_CD=1\nID_CDROM_MEDIA_TRACK_COUNT_AUDIO=11\n\
+                     ID_CDROM_MEDIA_TRACK_COUNT_DATA=1\n";
+        assert_eq!(parse_udev_media_type(mixed), MediaType::DataCd);
+        assert_eq!(parse_udev_media_type("ID_CDROM_MEDIA_CD_R=1\n"), MediaType::DataCd);
+        assert_eq!(parse_udev_media_type("ID_CDROM_MEDIA_DVD_PLUS_R_DL=1\n"), MediaType::Dvd);
+        assert_eq!(parse_udev_media_type("ID_CDROM_MEDIA_BD=1\n"), MediaType::BluRay);
+        assert_eq!(parse_udev_media_type(drive), MediaType::Unknown);
+        assert_eq!(parse_udev_media_type("ID_CDROM_MEDIA_DVD=0\n"), MediaType::Unknown);
+    }
+
+    #[test]
+    fn sessions_of_image_is_single() {
+        assert_eq!(
+            get_iso_provider().sessions().expect("image files always succeed"),
+            SessionInfo::default()
+        );
+    }
+
+    #[test]
+    fn disc_serial_fingerprints_readable_discs() {
+        let serial = get_iso_provider().disc_serial().expect("fixture is readable");
+        assert_eq!(serial.as_deref(), Some("CDROM||1|2017-02-13T09:05:53.00-05:00"));
+        let empty = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/nonexist_path")));
+        assert!(empty.disc_serial().is_err(), "No disc isn't the same as no serial");
+    }
+
+    // -- Tests for wait_with_watchdog()
+
+    #[test]
+    fn wait_with_watchdog_passes_through_exit_status() {
+        let mut child = Command::new("false").spawn().expect("false should be runnable");
+        let status = wait_with_watchdog(
+            &mut child,
+            &[Path::new("/nonexist_path")],
+            Some(Duration::from_secs(5)),
+            Duration::from_millis(10),
+            None,
+        )
+        .expect("exited child isn't a stall");
+        assert_eq!(status.code(), Some(1));
+    }
+
+    #[test]
+    fn wait_with_watchdog_kills_stalled_children() {
+        let mut child = Command::new("sleep").arg("10").spawn().expect("sleep should be runnable");
+        let start = Instant::now();
+        let err = wait_with_watchdog(
+            &mut child,
+            &[Path::new("/nonexist_path")],
+            Some(Duration::from_millis(100)),
+            Duration::from_millis(10),
+            None,
+        )
+        .expect_err("sleep never produces output");
+        assert!(start.elapsed() < Duration::from_secs(5), "Watchdog didn't fire");
+        assert!(matches!(err.downcast_ref(), Some(MediaError::Stalled(_))), "{:?}", err);
+        assert!(child.try_wait().expect("child was reaped").is_some());
+    }
+
+    /// A drive whose disc is swapped after a set number of identity checks
+    struct SwappingDrive(Cell<u32>);
+
+    impl RawMediaProvider for SwappingDrive {
+        fn device_path(&self) -> OsString {
+            OsString::from("/dev/swapping")
+        }
+
+        fn device_size(&self) -> Result<u64, MediaError> {
+            Ok(0)
+        }
+
+        fn media_identity(&self) -> Option<String> {
+            let checks = self.0.get().saturating_sub(1);
+            self.0.set(checks);
+            Some(if checks == 0 { "ID_FS_LABEL=OTHER" } else { "ID_FS_LABEL=ORIGINAL" }.into())
+        }
+    }
+
+    #[test]
+    fn wait_with_watchdog_kills_children_when_media_changes() {
+        let drive = SwappingDrive(Cell::new(3));
+        let guard = MediaGuard::new(&drive);
+        assert!(guard.is_active());
+
+        let mut child = Command::new("sleep").arg("10").spawn().expect("sleep should be runnable");
+        let start = Instant::now();
+        let err =
+            wait_with_watchdog(&mut child, &[], None, Duration::from_millis(10), Some(&guard))
+                .expect_err("the disc was swapped");
+        assert!(start.elapsed() < Duration::from_secs(5), "Media change wasn't noticed");
+        assert!(matches!(err.downcast_ref(), Some(MediaError::MediaChanged)), "{:?}", err);
+        assert!(child.try_wait().expect("child was reaped").is_some());
+    }
+
+    #[test]
+    fn media_guard_is_inert_for_image_files() {
+        let provider = get_iso_provider();
+        let guard = MediaGuard::new(&provider);
+        assert!(!guard.is_active());
+        guard.check().expect("nothing to compare against");
+    }
+
+    #[test]
+    fn parse_udev_media_identity_ignores_drive_properties() {
+        let loaded = "DEVNAME=/dev/sr0\nID_CDROM_MEDIA_TRACK_COUNT=1\nID_FS_LABEL=GAME\n\
+                      ID_CDROM_MEDIA=1\nID_MODEL=DVD-RW\nID_FS_TYPE=iso9660\n";
+        assert_eq!(
+            parse_udev_media_identity(loaded),
+            "ID_CDROM_MEDIA=1\nID_CDROM_MEDIA_TRACK_COUNT=1\nID_FS_LABEL=GAME\nID_FS_TYPE=iso9660"
+        );
+        assert_ne!(
+            parse_udev_media_identity(loaded),
+            parse_udev_media_identity(&loaded.replace("GAME", "DEMO"))
+        );
+        assert_eq!(parse_udev_media_identity("DEVNAME=/dev/sr0\nID_MODEL=DVD-RW\n"), "");
+    }
+
+    #[test]
+    fn is_listed_as_mounted_checks_sources() {
+        let mounts = "proc /proc proc rw,nosuid 0 0
+/dev/sr1 /run/media/user/GAME iso9660 ro,nosuid,nodev 0 0
+/dev/My\\040Disc /mnt/disc udf ro 0 0
+";
+        assert!(is_listed_as_mounted(mounts, Path::new("/dev/sr1")));
+        assert!(is_listed_as_mounted(mounts, Path::new("/dev/My Disc")));
+        assert!(!is_listed_as_mounted(mounts, Path::new("/dev/sr0")));
+        assert!(!is_listed_as_mounted(mounts, Path::new("/run/media/user/GAME")));
+        assert!(!is_listed_as_mounted("", Path::new("/dev/sr1")));
+    }
+
+    // -- Tests for LinuxPlatformProvider.device_size()
+
+    #[test]
+    fn device_size_of_image_is_file_length() {
+        let expected = std::fs::metadata("fixture.iso").expect("fixture.iso exists").len();
+        assert_eq!(get_iso_provider().device_size().expect("fixture.iso is readable"), expected);
+    }
+
+    #[test]
+    fn device_size_reports_failure_properly() {
+        for path_str in &["/nonexist_path", "/dev/null"] {
+            let p_bad = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new(path_str)));
+            assert!(p_bad.device_size().is_err(), "Expected Error for {:?}", path_str);
+        }
+    }
+
+    // -- Tests for LinuxPlatformProvider.drive_info()
+
+    #[test]
+    fn drive_info_is_read_from_sysfs() {
+        let dir = scratch_dir("drive_info");
+        fs::write(dir.join("vendor"), "PLEXTOR \n").expect("write vendor");
+        fs::write(dir.join("model"), "DVDR   PX-716A  \n").expect("write model");
+        assert!(read_drive_info(&dir).is_err(), "Missing firmware revision");
+        fs::write(dir.join("rev"), "1.11\n").expect("write rev");
+        assert_eq!(
+            read_drive_info(&dir).expect("complete drive info"),
+            DriveInfo {
+                vendor: "PLEXTOR".to_owned(),
+                model: "DVDR   PX-716A".to_owned(),
+                revision: "1.11".to_owned()
+            }
+        );
+        fs::remove_dir_all(&dir).expect("cleanup");
+
+        assert_eq!(get_iso_provider().drive_info().expect("image files need no drive"), None);
+    }
+
+    // -- Tests for LinuxPlatformProvider.eject_delay_hint()
+
+    #[test]
+    fn eject_delay_hint_is_zero_for_image_files() {
+        assert_eq!(get_iso_provider().eject_delay_hint(), Duration::ZERO);
+    }
+
+    #[test]
+    fn eject_delay_hint_allows_time_for_devices() {
+        let p = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/dev/null")));
+        assert!(p.eject_delay_hint() > Duration::ZERO);
+    }
+
     // -- Tests for LinuxPlatformProvider.volume_label()
 
-    fn test_label_failure(path_str: &str) {
+    fn test_label_failure(path_str: &str) -> anyhow::Error {
         let p_bad = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new(path_str)));
-        assert!(p_bad.volume_label().is_err(), "Expected Error for {:?}", path_str);
+        p_bad.volume_label().expect_err(&format!("Expected Error for {:?}", path_str)).into()
     }
 
     #[test]
@@ -308,11 +1919,29 @@ mod tests {
         assert_eq!(get_iso_provider().volume_label().expect("fixture.iso has label"), "CDROM");
     }
 
+    #[test]
+    fn read_iso9660_label_reads_images_directly() {
+        let label =
+            read_iso9660_label(Path::new("fixture.iso"), None).expect("fixture has a label");
+        assert_eq!(label, "CDROM");
+        let joliet = crate::iso9660::tests::joliet_fixture("Café ゲーム");
+        let sjis = Some(SHIFT_JIS);
+        assert_eq!(
+            raw_volume_label(&mut Cursor::new(joliet), 0, sjis).expect("ISO"),
+            "Café ゲーム"
+        );
+        let err = read_iso9660_label(Path::new("/nonexist_path"), None).expect_err("missing");
+        assert!(matches!(MediaError::find(&err), Some(MediaError::DeviceUnavailable(_))));
+    }
+
     #[test]
     fn volume_label_bad_format() {
-        test_label_failure("/dev/null");
-        test_label_failure("/etc/passwd"); // "can't seek that far" code branch
-        test_label_failure("/bin/bash"); // "bad magic number" code branch
+        for path in ["/dev/null", "/etc/passwd", "/bin/bash"] {
+            // (Too short to reach the volume descriptor, or a bad magic number there)
+            let err = test_label_failure(path);
+            let found = MediaError::find(&err);
+            assert!(matches!(found, Some(MediaError::UnrecognizedFormat)), "{}: {:?}", path, err);
+        }
     }
     #[test]
     fn volume_label_not_a_file() {
@@ -320,11 +1949,25 @@ mod tests {
     }
     #[test]
     fn volume_label_permission_denied() {
-        test_label_failure("/etc/shadow");
+        let err = test_label_failure("/etc/shadow");
+        match MediaError::find(&err) {
+            Some(MediaError::DeviceUnavailable(cause)) => {
+                assert_eq!(cause.kind(), io::ErrorKind::PermissionDenied);
+            },
+            // (Unless we're root, in which case it's readable, but not an ISO)
+            Some(MediaError::UnrecognizedFormat) if File::open("/etc/shadow").is_ok() => {},
+            _ => panic!("Expected MediaError::DeviceUnavailable, got {:?}", err),
+        }
     }
     #[test]
     fn volume_label_nonexistant() {
-        test_label_failure("/nonexist_path");
+        let err = test_label_failure("/nonexist_path");
+        match MediaError::find(&err) {
+            Some(MediaError::DeviceUnavailable(cause)) => {
+                assert_eq!(cause.kind(), io::ErrorKind::NotFound);
+            },
+            _ => panic!("Expected MediaError::DeviceUnavailable, got {:?}", err),
+        }
     }
 
     // -- Tests for LinuxPlatformProvider.wait_for_ready()
@@ -336,8 +1979,28 @@ mod tests {
         let timeout = Duration::new(2, 0); // Allow at least one sleep() call
 
         let start = Instant::now();
-        assert!(p_bad.wait_for_ready(&timeout).is_err());
+        let err = p_bad.wait_for_ready(&timeout).expect_err("/etc/shadow should be unreadable");
         assert!(start.elapsed() > timeout);
+        match err {
+            MediaError::Timeout { device, elapsed } => {
+                assert_eq!(device, Path::new("/etc/shadow"));
+                assert!(elapsed >= timeout);
+            },
+            _ => panic!("Expected MediaError::Timeout, got {:?}", err),
+        }
+    }
+
+    #[test]
+    fn wait_for_ready_polls_at_the_configured_interval() {
+        let provider = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("/nonexist_path")))
+            .with_poll_interval(Duration::from_millis(10));
+        let timeout = Duration::from_millis(50);
+
+        let start = Instant::now();
+        let err = provider.wait_for_ready(&timeout).expect_err("no such device");
+        assert!(matches!(err, MediaError::Timeout { .. }), "{:?}", err);
+        assert!(start.elapsed() >= timeout);
+        assert!(start.elapsed() < Duration::from_millis(500), "Slept for the default interval");
     }
 
     #[test]
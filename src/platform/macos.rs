@@ -0,0 +1,355 @@
+//! `MediaProvider` implementation for macOS
+//!
+//! The drive is driven with `drutil` and the disc's volume with `diskutil`, much as the Linux
+//! provider uses `eject` and `udisksctl`.
+
+// The parsers are built on every platform so their tests run everywhere, but the provider
+// itself is only used on macOS
+#![cfg_attr(not(target_os = "macos"), allow(dead_code))]
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+
+use super::{
+    is_interactive, raw_volume_label, read_filesystem, wait_until_openable, ChangerProvider,
+    Filesystem, MediaError, MediaProvider, NotificationProvider, ProviderBuilder, ProviderSettings,
+    RawMediaProvider, TRAY_EJECT_DELAY,
+};
+use crate::subprocess_call;
+
+/// Undo the escaping XML applies to text
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Get the value of the top-level `key` from the property list `diskutil info -plist` printed
+///
+/// (Only `<string>` and `<integer>` values are supported, since that's all we need.)
+fn plist_value(plist: &str, key: &str) -> Option<String> {
+    let (_, after_key) = plist.split_once(&format!("<key>{}</key>", key))?;
+    let value = after_key.trim_start();
+    if value.starts_with("<string/>") {
+        return Some(String::new());
+    }
+    let contents = value.strip_prefix("<string>").or_else(|| value.strip_prefix("<integer>"))?;
+    let (text, _) = contents.split_once("</")?;
+    Some(xml_unescape(text))
+}
+
+/// Get the numbers of the drives `drutil list` lists
+fn parse_drutil_list(output: &str) -> Vec<usize> {
+    output.lines().filter_map(|line| line.split_whitespace().next()?.parse().ok()).collect()
+}
+
+/// Get the device node (eg. `/dev/disk2`) of the disc `drutil status` reports, if there is one
+fn parse_drutil_status(output: &str) -> Option<&str> {
+    let (_, after) = output.split_once("Name:")?;
+    after.split_whitespace().next()
+}
+
+/// Get the whole disk (eg. `disk2`) a device path like `/dev/rdisk2s1` refers to
+fn whole_disk(device: &str) -> Option<String> {
+    let name = device.strip_prefix("/dev/").unwrap_or(device);
+    let digits = name.strip_prefix("rdisk").or_else(|| name.strip_prefix("disk"))?;
+    let number: String = digits.chars().take_while(char::is_ascii_digit).collect();
+    (!number.is_empty()).then(|| format!("disk{}", number))
+}
+
+/// Run `drutil` with `args` and return what it printed
+fn drutil_output(args: &[&str]) -> Result<String> {
+    let output = Command::new("drutil").args(args).output().context("Could not run drutil")?;
+    if !output.status.success() {
+        bail!("drutil {} failed ({})", args.join(" "), output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `MediaProvider` implementation which operates on macOS systems
+///
+/// `drutil` only knows drives by number, so trays are opened and closed on whichever drive
+/// reports holding `device`, or the first drive if none does (eg. because the tray was already
+/// open before we ever saw the disc).
+pub struct MacOsPlatformProvider<'devpath> {
+    /// Device (eg. `/dev/disk2`) or file to operate on
+    device: Cow<'devpath, OsStr>,
+
+    /// The `drutil` number of the drive holding `device`, once it's been found (since the
+    /// device node goes away when the disc is ejected)
+    drive: Option<usize>,
+
+    /// Settings shared by all of the platform providers
+    settings: ProviderSettings,
+}
+
+impl<'devpath> MacOsPlatformProvider<'devpath> {
+    /// Create a `MacOsPlatformProvider` for a given device path
+    pub fn new(device: Cow<'_, OsStr>) -> MacOsPlatformProvider<'_> {
+        MacOsPlatformProvider { device, drive: None, settings: ProviderSettings::default() }
+    }
+
+    /// Whether we're operating on an image file rather than a drive
+    fn is_image(&self) -> bool {
+        Path::new(&self.device).is_file()
+    }
+
+    /// Find the `drutil` number of the drive holding `device`, if any drive reports holding it
+    fn find_drive(&self) -> Result<Option<usize>> {
+        let Some(wanted) = self.device.to_str().and_then(whole_disk) else { return Ok(None) };
+        for number in parse_drutil_list(&drutil_output(&["list"])?) {
+            let status = drutil_output(&["-drive", &number.to_string(), "status"])?;
+            if parse_drutil_status(&status).and_then(whole_disk).as_ref() == Some(&wanted) {
+                return Ok(Some(number));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run `drutil tray <action>` on the drive holding `device` (or the first drive, if there's
+    /// no telling which that is)
+    fn tray(&mut self, action: &str) -> Result<()> {
+        if self.drive.is_none() {
+            self.drive = self
+                .find_drive()
+                .map_err(|e| debug!("Could not look up the drive's number: {:#}", e))
+                .ok()
+                .flatten();
+        }
+        if let Some(number) = self.drive {
+            return Ok(subprocess_call!("drutil", "-drive", number.to_string(), "tray", action)?);
+        }
+        debug!("No drive reports holding {}. Using the first one.", self.device.to_string_lossy());
+        Ok(subprocess_call!("drutil", "tray", action)?)
+    }
+
+    /// Retrieve what `diskutil` knows about the device, as a property list
+    fn disk_info(&self) -> Result<String> {
+        let output = Command::new("diskutil")
+            .args(["info", "-plist"])
+            .arg(&self.device)
+            .output()
+            .context("Could not run diskutil")?;
+        if !output.status.success() {
+            bail!("diskutil could not find {} ({})", self.device.to_string_lossy(), output.status);
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+impl<'devpath> ProviderBuilder for MacOsPlatformProvider<'devpath> {
+    fn settings_mut(&mut self) -> &mut ProviderSettings {
+        &mut self.settings
+    }
+}
+
+impl<'devpath> RawMediaProvider for MacOsPlatformProvider<'devpath> {
+    fn device_path(&self) -> OsString {
+        self.device.clone().into_owned()
+    }
+
+    fn read_buffer(&self) -> usize {
+        self.settings.read_buffer
+    }
+
+    fn device_size(&self) -> Result<u64, MediaError> {
+        if self.is_image() {
+            return fs::metadata(&self.device)
+                .map(|meta| meta.len())
+                .with_context(|| format!("Could not stat {}", self.device.to_string_lossy()))
+                .map_err(MediaError::from);
+        }
+
+        // Like on Linux, block devices report a length of zero
+        plist_value(&self.disk_info()?, "TotalSize")
+            .context("diskutil didn't report a size")?
+            .parse()
+            .with_context(|| format!("Unexpected size for {}", self.device.to_string_lossy()))
+            .map_err(MediaError::from)
+    }
+}
+
+impl<'devpath> MediaProvider for MacOsPlatformProvider<'devpath> {
+    fn eject(&mut self) -> Result<(), MediaError> {
+        self.tray("eject")
+            .with_context(|| format!("Could not eject {}", &self.device.to_string_lossy()))
+            .map_err(MediaError::from)
+    }
+
+    fn load(&mut self) -> Result<(), MediaError> {
+        self.tray("close")
+            .with_context(|| format!("Could not load media for {}", &self.device.to_string_lossy()))
+            .map_err(MediaError::from)
+    }
+
+    fn unmount(&mut self) -> Result<(), MediaError> {
+        if self.is_image() {
+            return Ok(());
+        }
+        let mounted = self
+            .disk_info()
+            .map(|info| plist_value(&info, "MountPoint").is_some_and(|point| !point.is_empty()));
+        if let Ok(false) = mounted {
+            debug!("{} isn't mounted", self.device.to_string_lossy());
+            return Ok(());
+        }
+
+        // (diskutil refuses, rather than detaching lazily, if something has files open on it)
+        subprocess_call!("diskutil", "unmount", &self.device)
+            .with_context(|| format!("Could not unmount {}", self.device.to_string_lossy()))
+            .map_err(|err| match mounted {
+                Ok(true) => err.context(MediaError::Busy(PathBuf::from(&self.device))).into(),
+                _ => err.into(),
+            })
+    }
+
+    fn volume_label(&self) -> Result<String, MediaError> {
+        // Let macOS read the name (eg. for Joliet), unless we've been told the raw label uses
+        // a character set that diskutil won't know to decode
+        if self.settings.label_encoding.is_none() && !self.is_image() {
+            match self.disk_info() {
+                Ok(info) => match plist_value(&info, "VolumeName") {
+                    Some(label) if !label.is_empty() => return Ok(label),
+                    _ => {},
+                },
+                Err(e) => debug!("Could not ask diskutil for the volume label: {:#}", e),
+            }
+        }
+
+        // Fall back to reading the raw ISO9660 header
+        raw_volume_label(&mut self.open_buffered()?, 0, self.settings.label_encoding)
+            .map_err(MediaError::from)
+    }
+
+    fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError> {
+        wait_until_openable(&self.device, *timeout, self.settings.poll_interval)
+            .map_err(MediaError::from)
+    }
+
+    fn filesystem(&self) -> Result<Filesystem, MediaError> {
+        read_filesystem(self)
+    }
+
+    fn eject_delay_hint(&self) -> Duration {
+        // Image files have no tray to reach for
+        if self.is_image() {
+            Duration::ZERO
+        } else {
+            TRAY_EJECT_DELAY
+        }
+    }
+}
+
+impl<'devpath> ChangerProvider for MacOsPlatformProvider<'devpath> {
+    fn load_slot(&mut self, slot: usize) -> Result<(), MediaError> {
+        Err(anyhow!("Can't load slot {}: disc changers aren't supported on macOS", slot).into())
+    }
+}
+
+impl<'devpath> NotificationProvider for MacOsPlatformProvider<'devpath> {
+    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<(), MediaError> {
+        subprocess_call!("afplay", path.as_ref())
+            .with_context(|| format!("Could not play {}", path.as_ref().to_string_lossy()))
+            .map_err(MediaError::from)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.settings.interactive.unwrap_or_else(is_interactive)
+    }
+
+    fn read_line(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, true).map_err(MediaError::from)
+    }
+
+    fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, false).map_err(MediaError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Abridged `diskutil info -plist` output for a mounted CD
+    const DISK_INFO: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>DeviceNode</key>
+	<string>/dev/disk2</string>
+	<key>MediaName</key>
+	<string>HL-DT-ST DVDRW  GX40N</string>
+	<key>MountPoint</key>
+	<string>/Volumes/Tom &amp; Jerry</string>
+	<key>OpticalMediaType</key>
+	<string>CD-ROM</string>
+	<key>Removable</key>
+	<true/>
+	<key>TotalSize</key>
+	<integer>676233216</integer>
+	<key>VolumeName</key>
+	<string>Tom &amp; Jerry</string>
+</dict>
+</plist>
+"#;
+
+    #[test]
+    fn diskutil_plists_are_parsed() {
+        assert_eq!(plist_value(DISK_INFO, "VolumeName").as_deref(), Some("Tom & Jerry"));
+        assert_eq!(plist_value(DISK_INFO, "TotalSize").as_deref(), Some("676233216"));
+        assert_eq!(plist_value(DISK_INFO, "MediaName").as_deref(), Some("HL-DT-ST DVDRW  GX40N"));
+        assert_eq!(plist_value(DISK_INFO, "Removable"), None, "Booleans aren't supported");
+        assert_eq!(plist_value(DISK_INFO, "BusProtocol"), None);
+
+        let unmounted = "<key>MountPoint</key>\n\t<string></string>\n<key>VolumeName</key>\n\
+                         \t<string/>\n<key>TotalSize</key>\n\t<integer>0</integer>";
+        assert_eq!(plist_value(unmounted, "MountPoint").as_deref(), Some(""));
+        assert_eq!(plist_value(unmounted, "VolumeName").as_deref(), Some(""));
+    }
+
+    #[test]
+    fn drutil_output_is_parsed() {
+        let list = "   Vendor   Product           Rev   Bus       SupportLevel\n\
+                    1  HL-DT-ST DVDRW  GX40N      RQ00  USB       Unsupported\n\
+                    2  MATSHITA DVD-R   UJ-85J    FM0S  ATAPI     Apple Shipping\n";
+        assert_eq!(parse_drutil_list(list), [1, 2]);
+        assert!(parse_drutil_list("").is_empty());
+
+        let status = " Vendor   Product           Rev \n MATSHITA DVD-R   UJ-85J    FM0S\n\n\
+                                 Type: CD-ROM               Name: /dev/disk2\n\
+                             Sessions: 1                  Tracks: 1 \n";
+        assert_eq!(parse_drutil_status(status), Some("/dev/disk2"));
+        assert_eq!(parse_drutil_status(" Vendor   Product\n\n Type: No Media Inserted\n"), None);
+    }
+
+    #[test]
+    fn device_paths_map_to_whole_disks() {
+        for device in ["/dev/disk2", "/dev/rdisk2", "/dev/disk2s1", "disk2"] {
+            assert_eq!(whole_disk(device).as_deref(), Some("disk2"), "{}", device);
+        }
+        assert_eq!(whole_disk("/dev/disk12s3").as_deref(), Some("disk12"));
+        assert_eq!(whole_disk("fixture.iso"), None);
+        assert_eq!(whole_disk("/dev/disk"), None);
+    }
+
+    #[test]
+    fn image_files_skip_diskutil() {
+        let mut image = MacOsPlatformProvider::new(Cow::Borrowed(OsStr::new("fixture.iso")));
+        assert_eq!(image.eject_delay_hint(), Duration::ZERO);
+        assert!(image.device_size().expect("fixture exists") > 0);
+        assert_eq!(image.volume_label().expect("fixture has a label"), "CDROM");
+        assert_eq!(image.filesystem().expect("fixture is readable"), Filesystem::Iso9660);
+        image.unmount().expect("image files are never mounted");
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,313 @@
+//! `MediaProvider` implementation for Windows
+//!
+//! The direct route would be `DeviceIoControl` with `IOCTL_STORAGE_EJECT_MEDIA` for the tray and
+//! `GetVolumeInformationW` for the label, but we `forbid(unsafe_code)` and the `windows` and
+//! `windows-sys` bindings only offer those as `unsafe fn`s. So the Win32 calls are made through
+//! PowerShell instead, the same way the Linux provider shells out to `eject` and `blkid`:
+//!
+//! - The tray is opened and closed with MCI's `set door` command (via `mciSendStringW`).
+//! - The volume label and free space come from .NET's `DriveInfo`, which wraps
+//!   `GetVolumeInformationW` and `GetDiskFreeSpaceExW`.
+//!
+//! Starting PowerShell is slow next to a system call, but it only happens a handful of times
+//! per disc (never while polling for one), so the rip itself still dwarfs it. Raw reads
+//! of the disc (eg. for its size and filesystem) open `\\.\D:` directly, since that needs no FFI.
+
+// The parsers are built on every platform so their tests run everywhere, but the provider
+// itself is only used on Windows
+#![cfg_attr(not(windows), allow(dead_code))]
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+
+use super::{
+    is_interactive, raw_volume_label, read_filesystem, wait_until_openable, ChangerProvider,
+    Filesystem, MediaError, MediaProvider, NotificationProvider, ProviderBuilder, ProviderSettings,
+    RawMediaProvider, TRAY_EJECT_DELAY,
+};
+
+/// Prefix which turns a drive letter like `D:` into the path of the raw device
+const DEVICE_NAMESPACE: &str = r"\\.\";
+
+/// Turn a drive letter given as `D`, `D:`, or `D:\` into the raw device path `\\.\D:`
+///
+/// Anything else (eg. a disc image) is returned unchanged.
+fn normalize_device(path: Cow<'_, OsStr>) -> Cow<'_, OsStr> {
+    match path.to_str().and_then(drive_letter) {
+        Some(letter) => Cow::Owned(format!("{}{}:", DEVICE_NAMESPACE, letter).into()),
+        None => path,
+    }
+}
+
+/// Get the drive letter (uppercased) out of `D`, `D:`, `D:\`, or `\\.\D:`
+fn drive_letter(path: &str) -> Option<char> {
+    let unprefixed = path.strip_prefix(DEVICE_NAMESPACE).unwrap_or(path);
+    let mut chars = unprefixed.chars();
+    let letter = chars.next().filter(char::is_ascii_alphabetic)?;
+    matches!(chars.as_str(), "" | ":" | ":\\" | ":/").then(|| letter.to_ascii_uppercase())
+}
+
+/// Quote `value` as a PowerShell string literal
+fn ps_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Build a PowerShell script which opens or closes the tray of the drive with `letter` via MCI
+fn door_script(letter: char, open: bool) -> String {
+    let mci = |cmd: &str| {
+        format!(
+            "if ($e = [RipMedia.Mci]::mciSendStringW({}, $null, 0, [IntPtr]::Zero)) \
+             {{ throw \"MCI error $e\" }}",
+            ps_quote(cmd)
+        )
+    };
+    format!(
+        "Add-Type -Namespace RipMedia -Name Mci -MemberDefinition '[DllImport(\"winmm.dll\", \
+         CharSet = CharSet.Unicode)] public static extern int mciSendStringW(string command, \
+         System.Text.StringBuilder buffer, int size, System.IntPtr callback);'; {}; try {{ {} }} \
+         finally {{ [void][RipMedia.Mci]::mciSendStringW('close rip_media_drive', $null, 0, \
+         [IntPtr]::Zero) }}",
+        mci(&format!("open {}: type cdaudio alias rip_media_drive", letter)),
+        mci(&format!("set rip_media_drive door {} wait", if open { "open" } else { "closed" })),
+    )
+}
+
+/// Run a PowerShell `script` and return what it printed (trimmed)
+fn powershell(script: &str) -> Result<String> {
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-NonInteractive", "-Command", script])
+        .output()
+        .context("Could not run PowerShell")?;
+    if !output.status.success() {
+        bail!(
+            "PowerShell failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Get how many bytes an unprivileged user can still write to the drive `dir` is on
+pub fn available_space(dir: &Path) -> Result<u64> {
+    let canonical = fs::canonicalize(dir)
+        .with_context(|| format!("Could not check the free space in {}", dir.display()))?;
+    let script = format!(
+        "([System.IO.DriveInfo][System.IO.Path]::GetPathRoot({})).AvailableFreeSpace",
+        ps_quote(&canonical.to_string_lossy())
+    );
+    powershell(&script)?
+        .parse()
+        .with_context(|| format!("Could not check the free space in {}", dir.display()))
+}
+
+/// `MediaProvider` implementation which operates on Windows systems
+pub struct WindowsPlatformProvider<'devpath> {
+    /// Device (as `\\.\D:`) or file to operate on
+    device: Cow<'devpath, OsStr>,
+
+    /// Settings shared by all of the platform providers
+    settings: ProviderSettings,
+}
+
+impl<'devpath> WindowsPlatformProvider<'devpath> {
+    /// Create a `WindowsPlatformProvider` for a drive letter (eg. `D:`) or image file
+    pub fn new(device: Cow<'_, OsStr>) -> WindowsPlatformProvider<'_> {
+        WindowsPlatformProvider {
+            device: normalize_device(device),
+            settings: ProviderSettings::default(),
+        }
+    }
+
+    /// The drive letter being operated on (`None` for image files)
+    fn letter(&self) -> Option<char> {
+        self.device.to_str().and_then(drive_letter)
+    }
+
+    /// Get the drive letter, or fail with a message saying `action` needs one
+    fn require_letter(&self, action: &str) -> Result<char> {
+        self.letter().with_context(|| {
+            format!("Can't {} {}: not a drive", action, self.device.to_string_lossy())
+        })
+    }
+}
+
+impl<'devpath> ProviderBuilder for WindowsPlatformProvider<'devpath> {
+    fn settings_mut(&mut self) -> &mut ProviderSettings {
+        &mut self.settings
+    }
+}
+
+impl<'devpath> RawMediaProvider for WindowsPlatformProvider<'devpath> {
+    fn device_path(&self) -> OsString {
+        self.device.clone().into_owned()
+    }
+
+    fn read_buffer(&self) -> usize {
+        self.settings.read_buffer
+    }
+
+    fn device_size(&self) -> Result<u64, MediaError> {
+        let Some(letter) = self.letter() else {
+            return fs::metadata(&self.device)
+                .map(|meta| meta.len())
+                .with_context(|| format!("Could not stat {}", self.device.to_string_lossy()))
+                .map_err(MediaError::from);
+        };
+
+        // DriveInfo.TotalSize is the size of the filesystem rather than the disc (and audio CDs
+        // have none), so ask the device handle instead. If it won't say, it reports zero, which
+        // would pass for an empty disc, so admit we don't know.
+        let size = File::open(&self.device)
+            .map_err(MediaError::DeviceUnavailable)
+            .and_then(|mut file| file.seek(SeekFrom::End(0)).map_err(MediaError::DeviceUnavailable))
+            .with_context(|| format!("Could not get the size of the disc in {}:", letter))?;
+        if size == 0 {
+            return Err(anyhow!("Windows didn't report the size of the disc in {}:", letter).into());
+        }
+        Ok(size)
+    }
+}
+
+impl<'devpath> MediaProvider for WindowsPlatformProvider<'devpath> {
+    fn eject(&mut self) -> Result<(), MediaError> {
+        let letter = self.require_letter("eject")?;
+        powershell(&door_script(letter, true))
+            .map(drop)
+            .with_context(|| format!("Could not eject {}:", letter))
+            .map_err(MediaError::from)
+    }
+
+    fn load(&mut self) -> Result<(), MediaError> {
+        let letter = self.require_letter("load media for")?;
+        powershell(&door_script(letter, false))
+            .map(drop)
+            .with_context(|| format!("Could not load media for {}:", letter))
+            .map_err(MediaError::from)
+    }
+
+    fn unmount(&mut self) -> Result<(), MediaError> {
+        // Windows lets us read the raw device while the filesystem on it is in use
+        debug!("Not unmounting {}: Windows doesn't need it", self.device.to_string_lossy());
+        Ok(())
+    }
+
+    fn volume_label(&self) -> Result<String, MediaError> {
+        // Let Windows read the label (eg. for Joliet), unless we've been told the raw label
+        // uses a character set it won't know to decode
+        if let (Some(letter), None) = (self.letter(), self.settings.label_encoding) {
+            match powershell(&format!("([System.IO.DriveInfo]'{}:').VolumeLabel", letter)) {
+                Ok(label) if !label.is_empty() => return Ok(label),
+                Ok(_) => {},
+                Err(e) => debug!("Could not ask Windows for the volume label: {:#}", e),
+            }
+        }
+
+        // Fall back to reading the raw ISO9660 header
+        raw_volume_label(&mut self.open_buffered()?, 0, self.settings.label_encoding)
+            .map_err(MediaError::from)
+    }
+
+    fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError> {
+        wait_until_openable(&self.device, *timeout, self.settings.poll_interval)
+            .map_err(MediaError::from)
+    }
+
+    fn filesystem(&self) -> Result<Filesystem, MediaError> {
+        read_filesystem(self)
+    }
+
+    fn eject_delay_hint(&self) -> Duration {
+        // Image files have no tray to reach for
+        if self.letter().is_some() {
+            TRAY_EJECT_DELAY
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+impl<'devpath> ChangerProvider for WindowsPlatformProvider<'devpath> {
+    fn load_slot(&mut self, slot: usize) -> Result<(), MediaError> {
+        Err(anyhow!("Can't load slot {}: disc changers aren't supported on Windows", slot).into())
+    }
+}
+
+impl<'devpath> NotificationProvider for WindowsPlatformProvider<'devpath> {
+    fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<(), MediaError> {
+        let script = format!(
+            "(New-Object System.Media.SoundPlayer {}).PlaySync()",
+            ps_quote(&path.as_ref().to_string_lossy())
+        );
+        powershell(&script)
+            .map(drop)
+            .with_context(|| format!("Could not play {}", path.as_ref().to_string_lossy()))
+            .map_err(MediaError::from)
+    }
+
+    fn is_interactive(&self) -> bool {
+        self.settings.interactive.unwrap_or_else(is_interactive)
+    }
+
+    fn read_line(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, true).map_err(MediaError::from)
+    }
+
+    fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError> {
+        self.settings.prompter.read_line(prompt, false).map_err(MediaError::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drive_letters_become_device_paths() {
+        for spec in ["d", "D:", "D:\\", "D:/", r"\\.\D:"] {
+            let device = normalize_device(Cow::Borrowed(OsStr::new(spec)));
+            assert_eq!(device, OsStr::new(r"\\.\D:"), "{:?}", spec);
+            assert_eq!(drive_letter(spec), Some('D'), "{:?}", spec);
+        }
+        for image in ["fixture.iso", "D:\\fixture.iso", "DE:", "1:", ""] {
+            let device = normalize_device(Cow::Borrowed(OsStr::new(image)));
+            assert!(matches!(device, Cow::Borrowed(path) if path == image), "{:?}", image);
+            assert_eq!(drive_letter(image), None, "{:?}", image);
+        }
+    }
+
+    #[test]
+    fn powershell_arguments_are_quoted() {
+        assert_eq!(ps_quote("C:\\Rips"), "'C:\\Rips'");
+        assert_eq!(ps_quote("Bob's Discs"), "'Bob''s Discs'");
+
+        let script = door_script('E', false);
+        assert!(script.contains("'open E: type cdaudio alias rip_media_drive'"), "{}", script);
+        assert!(script.contains("'set rip_media_drive door closed wait'"), "{}", script);
+        assert!(door_script('E', true).contains("door open wait"));
+    }
+
+    #[test]
+    fn image_files_are_not_drives() {
+        let image = WindowsPlatformProvider::new(Cow::Borrowed(OsStr::new("fixture.iso")));
+        assert_eq!(image.device_path(), OsStr::new("fixture.iso"));
+        assert_eq!(image.eject_delay_hint(), Duration::ZERO);
+        assert!(image.device_size().expect("fixture exists") > 0);
+        assert_eq!(image.filesystem().expect("fixture is readable"), Filesystem::Iso9660);
+        assert_eq!(image.volume_label().expect("fixture has a label"), "CDROM");
+
+        let drive = WindowsPlatformProvider::new(Cow::Borrowed(OsStr::new("E:")));
+        assert_eq!(drive.device_path(), OsStr::new(r"\\.\E:"));
+        assert_eq!(drive.eject_delay_hint(), TRAY_EJECT_DELAY);
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,349 @@
+//! Per-disc settings read from a sidecar file, for ripping a stack of discs which need different
+//! options without re-typing them for each one
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{rip_mode, Command};
+use crate::subcommands::{check_cdparanoia_arg, strip_media_extension, NameCase, RipOptions};
+use crate::validators::path_readable;
+
+/// Name of the profile file looked for in the output directory if `--profile` isn't given
+pub const PROFILE_FILE: &str = "rip_media.toml";
+
+/// Settings for one disc (`None` for anything that should come from the command line)
+///
+/// Keys are named after the command-line options they override, plus `mode` for the subcommand.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DiscProfile {
+    /// Subcommand to rip the disc with (eg. `psx`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Override for `--name`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Override for `--title`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Override for `--platform`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Override for `--notes`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// Override for `--output-name-case`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_name_case: Option<NameCase>,
+    /// Override for `--flac-compression`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flac_compression: Option<u8>,
+    /// Override for `--playlist`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub playlist: Option<bool>,
+    /// Override for `--cd-text`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cd_text: Option<bool>,
+    /// Override for `--keep-going`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_going: Option<bool>,
+    /// Override for `--verify-toc`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_toc: Option<bool>,
+    /// Override for `--split-tracks`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub split_tracks: Option<bool>,
+    /// Override for `--strict`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+    /// Override for `--stall-timeout`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stall_timeout: Option<u64>,
+    /// Override for `--keep-log` (`false` for `--no-keep-log`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_log: Option<bool>,
+    /// Override for `--cdparanoia-args` (separated by spaces, as on the command line)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cdparanoia_args: Option<String>,
+}
+
+impl DiscProfile {
+    /// Reject values the command line would reject
+    fn check(&self) -> Result<()> {
+        if let Some(mode) = &self.mode {
+            if rip_mode(mode).is_none() {
+                bail!("mode must be the name of a subcommand which rips discs, not {:?}", mode);
+            }
+        }
+        if self.flac_compression.is_some_and(|level| level > 8) {
+            bail!("flac-compression must be between 0 and 8");
+        }
+        if self.stall_timeout == Some(0) {
+            bail!("stall-timeout must be at least 1 second");
+        }
+        if let Some(name) = &self.name {
+            if name.trim().is_empty() {
+                bail!("name must not be blank");
+            }
+        }
+        for arg in self.cdparanoia_args.iter().flat_map(|args| args.split(' ')) {
+            check_cdparanoia_arg(arg).map_err(|e| anyhow::anyhow!("cdparanoia-args: {}", e))?;
+        }
+        Ok(())
+    }
+
+    /// Copy `opts`, overriding whatever this profile sets
+    pub fn apply<'prof>(&'prof self, opts: &RipOptions<'prof>) -> RipOptions<'prof> {
+        let mut disc_opts = opts.clone();
+        if let Some(cmd) = self.mode.as_deref().and_then(rip_mode) {
+            disc_opts.media_type = cmd.media_type();
+            disc_opts.ask_metadata &= !matches!(cmd, Command::Audio { .. });
+            disc_opts.mode.mode.clone_from(&self.mode);
+        }
+        if let Some(name) = &self.name {
+            disc_opts.name = Some(strip_media_extension(name));
+        }
+        for (field, value) in [
+            (&mut disc_opts.metadata.title, &self.title),
+            (&mut disc_opts.metadata.platform, &self.platform),
+            (&mut disc_opts.metadata.notes, &self.notes),
+        ] {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+        disc_opts.name_case = self.output_name_case.unwrap_or(opts.name_case);
+
+        let mode = &mut disc_opts.mode;
+        mode.flac_compression = self.flac_compression.unwrap_or(mode.flac_compression);
+        mode.playlist = self.playlist.unwrap_or(mode.playlist);
+        mode.cd_text = self.cd_text.unwrap_or(mode.cd_text);
+        mode.keep_going = self.keep_going.unwrap_or(mode.keep_going);
+        mode.verify_toc = self.verify_toc.unwrap_or(mode.verify_toc);
+        mode.split_tracks = self.split_tracks.unwrap_or(mode.split_tracks);
+        mode.strict = self.strict.unwrap_or(mode.strict);
+        mode.keep_log = self.keep_log.unwrap_or(mode.keep_log);
+        if let Some(secs) = self.stall_timeout {
+            mode.stall_timeout = Some(Duration::from_secs(secs));
+        }
+        if let Some(args) = &self.cdparanoia_args {
+            mode.cdparanoia_args = args.split(' ').map(str::to_owned).collect();
+        }
+        disc_opts
+    }
+}
+
+/// The on-disk form of a profile file
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileFile {
+    /// One profile per disc, in the order the discs will be inserted
+    #[serde(default)]
+    disc: Vec<DiscProfile>,
+}
+
+/// The profiles waiting in a profile file, each used for one disc and then removed
+#[derive(Debug)]
+pub struct ProfileQueue {
+    /// Where the profiles were loaded from
+    path: PathBuf,
+    /// The profiles which haven't been used yet
+    discs: Vec<DiscProfile>,
+}
+
+impl ProfileQueue {
+    /// Parse the contents of a profile file, rejecting values the command line would reject
+    fn parse(path: PathBuf, text: &str) -> Result<Self> {
+        let file: ProfileFile = toml::from_str(text)?;
+        for (idx, profile) in file.disc.iter().enumerate() {
+            profile.check().with_context(|| format!("Invalid profile for disc {}", idx + 1))?;
+        }
+        Ok(ProfileQueue { path, discs: file.disc })
+    }
+
+    /// Load the profile file at `path` (which is treated as empty if it doesn't exist)
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Could not read {}", path.display()));
+            },
+        };
+        Self::parse(path.to_owned(), &text)
+            .with_context(|| format!("Invalid profile file {}", path.display()))
+    }
+
+    /// Find the profile file to use (`given`, or `PROFILE_FILE` in `outdir`), checking
+    /// that it's valid and resolving it to an absolute path
+    ///
+    /// Returns `None` if no path was given and there's no profile file in `outdir`.
+    pub fn find(given: Option<&Path>, outdir: &Path) -> Result<Option<PathBuf>> {
+        let path = match given {
+            Some(path) => {
+                path_readable(path.to_owned())
+                    .map_err(|e| anyhow!("Could not read profile: {}", e))?;
+                path.to_owned()
+            },
+            None => outdir.join(PROFILE_FILE),
+        };
+        if !path.exists() {
+            return Ok(None);
+        }
+        Self::load(&path)?; // Fail before asking for a disc rather than after
+
+        // Ripping happens from inside the output directory, where a relative path won't resolve
+        let resolved = fs::canonicalize(&path)
+            .with_context(|| format!("Could not resolve {}", path.display()))?;
+        Ok(Some(resolved))
+    }
+
+    /// The profile for the upcoming disc, if any are left
+    pub fn next(&self) -> Option<&DiscProfile> {
+        self.discs.first()
+    }
+
+    /// Remove the profile for the disc which was just ripped, deleting the file once it's empty
+    ///
+    /// (Comments in the file don't survive being rewritten.)
+    pub fn pop(&mut self) -> Result<()> {
+        if self.discs.is_empty() {
+            return Ok(());
+        }
+        self.discs.remove(0);
+        if self.discs.is_empty() {
+            return fs::remove_file(&self.path)
+                .with_context(|| format!("Could not remove {}", self.path.display()));
+        }
+        let text = toml::to_string(&ProfileFile { disc: self.discs.clone() })
+            .context("Could not serialize disc profiles")?;
+        fs::write(&self.path, text)
+            .with_context(|| format!("Could not write {}", self.path.display()))
+    }
+}
+
+/// Run `body` with the next profile from `opts.profile` (if any) applied to `opts`, and remove
+/// that profile once `body` succeeds
+pub fn with_next_profile<T, F>(opts: &RipOptions<'_>, body: F) -> Result<T>
+where
+    F: FnOnce(&RipOptions<'_>) -> Result<T>,
+{
+    let Some(path) = opts.profile else { return body(opts) };
+    let mut queue = ProfileQueue::load(path)?;
+    let Some(profile) = queue.next().cloned() else { return body(opts) };
+
+    info!("Using the next disc profile from {}", path.display());
+    let output = body(&profile.apply(opts))?;
+    // Leaving it in place would apply it to the next disc too
+    queue.pop().context("Ripped the disc, but could not remove its profile")?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_path;
+
+    /// Profiles for a mixed stack of discs
+    const STACK: &str = r#"
+[[disc]]
+mode = "psx"
+name = "Final Fantasy VII (Disc 1).cue"
+split-tracks = true
+
+[[disc]]
+mode = "audio"
+name = "Soundtrack"
+flac-compression = 5
+cdparanoia-args = "-S 8"
+title = "Original Soundtrack"
+"#;
+
+    #[test]
+    fn profiles_override_their_own_disc() {
+        let queue = ProfileQueue::parse(PathBuf::from(PROFILE_FILE), STACK).expect("valid");
+        let base = RipOptions { name: Some("Ignored"), media_type: "CD", ..RipOptions::default() };
+
+        let first = queue.next().expect("two profiles").apply(&base);
+        assert_eq!(first.name, Some("Final Fantasy VII (Disc 1)"));
+        assert_eq!(first.media_type, "PSX");
+        assert_eq!(first.mode.mode.as_deref(), Some("psx"));
+        assert!(first.mode.split_tracks);
+        assert_eq!(first.mode.flac_compression, base.mode.flac_compression);
+
+        let second = queue.discs[1].apply(&RipOptions { ask_metadata: true, ..base });
+        assert_eq!(second.media_type, "Audio");
+        assert!(!second.ask_metadata, "Audio CDs have CD-TEXT instead");
+        assert_eq!(second.mode.flac_compression, 5);
+        assert_eq!(second.mode.cdparanoia_args, ["-S", "8"]);
+        assert_eq!(second.metadata.title.as_deref(), Some("Original Soundtrack"));
+        assert!(!second.mode.split_tracks, "Settings don't leak between profiles");
+    }
+
+    #[test]
+    fn profiles_are_checked_like_command_line_args() {
+        let path = PathBuf::from(PROFILE_FILE);
+        assert!(ProfileQueue::parse(path.clone(), "").expect("empty").next().is_none());
+        for bad in [
+            "mode = \"cd\"",
+            "[[disc]]\nmode = \"probe\"",
+            "[[disc]]\nmode = \"floppy\"",
+            "[[disc]]\nflac-compression = 9",
+            "[[disc]]\nstall-timeout = 0",
+            "[[disc]]\ncdparanoia-args = \"-d /dev/sr1\"",
+            "[[disc]]\nname = \" \"",
+            "[[disc]]\ntypo = true",
+        ] {
+            assert!(ProfileQueue::parse(path.clone(), bad).is_err(), "{:?}", bad);
+        }
+        let err = ProfileQueue::parse(path, "[[disc]]\n[[disc]]\nmode = \"x\"").expect_err("bad");
+        assert!(format!("{:#}", err).contains("disc 2"), "{:#}", err);
+    }
+
+    #[test]
+    fn pop_clears_used_profiles() {
+        let path = scratch_path(PROFILE_FILE);
+        fs::write(&path, STACK).expect("write profiles");
+
+        let mut queue = ProfileQueue::load(&path).expect("load profiles");
+        queue.pop().expect("pop first profile");
+        let reloaded = ProfileQueue::load(&path).expect("reload profiles");
+        assert_eq!(reloaded.next().and_then(|profile| profile.name.as_deref()), Some("Soundtrack"));
+        assert_eq!(reloaded.discs.len(), 1);
+
+        queue.pop().expect("pop last profile");
+        assert!(!path.exists(), "An emptied profile file should be removed");
+        assert!(ProfileQueue::load(&path).expect("missing file is empty").next().is_none());
+        queue.pop().expect("popping an empty queue is harmless");
+    }
+
+    #[test]
+    fn profiles_are_only_used_up_by_successful_rips() {
+        let path = scratch_path("with_next_profile.toml");
+        fs::write(&path, STACK).expect("write profiles");
+        let opts = RipOptions { profile: Some(&path), ..RipOptions::default() };
+
+        let failed = with_next_profile(&opts, |disc_opts| -> Result<()> {
+            assert_eq!(disc_opts.mode.mode.as_deref(), Some("psx"));
+            bail!("Rip failed")
+        });
+        assert!(failed.is_err());
+        let name = with_next_profile(&opts, |disc_opts| Ok(disc_opts.name.map(str::to_owned)));
+        assert_eq!(name.expect("rip succeeded").as_deref(), Some("Final Fantasy VII (Disc 1)"));
+
+        let next = ProfileQueue::load(&path).expect("reload profiles");
+        assert_eq!(next.next().and_then(|profile| profile.name.as_deref()), Some("Soundtrack"));
+        fs::remove_file(&path).expect("clean up profiles");
+
+        let unprofiled = RipOptions { profile: None, ..opts };
+        assert!(with_next_profile(&unprofiled, |disc_opts| Ok(disc_opts.mode.mode.is_none()))
+            .expect("no profile"));
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,66 @@
+//! Read offsets of known drives, so audio rips line up with the AccurateRip database
+//!
+//! Most drives return audio samples a fixed distance away from where they were asked to read
+//! from. AccurateRip checksums assume that's been corrected for, so a rip with the wrong offset
+//! won't match them, even if every sample was read perfectly.
+
+use crate::platform::DriveInfo;
+
+/// Read offsets (in samples) of drives, as listed in the AccurateRip drive offset database
+///
+/// Vendors and models are as the drive reports them in response to a SCSI INQUIRY (and so as
+/// Linux shows them in sysfs), with runs of spaces collapsed.
+const KNOWN_READ_OFFSETS: &[(&str, &str, i32)] = &[
+    ("ASUS", "DRW-24B1ST", 6),
+    ("HL-DT-ST", "BD-RE BH16NS40", 667),
+    ("LITE-ON", "iHAS124", 6),
+    ("PIONEER", "BD-RW BDR-209D", 667),
+    ("PLEXTOR", "CD-R PREMIUM", 30),
+    ("PLEXTOR", "DVDR PX-716A", 30),
+    ("PLEXTOR", "DVDR PX-760A", 30),
+    ("TSSTcorp", "CDDVDW SH-224DB", 6),
+];
+
+/// Collapse runs of whitespace the way `KNOWN_READ_OFFSETS` has them
+fn normalize(field: &str) -> String {
+    field.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Look up the read offset of `drive` (`None` if it isn't a drive we know)
+///
+/// (Models are matched by prefix, since some drives append a hardware revision to theirs.)
+pub fn known_read_offset(drive: &DriveInfo) -> Option<i32> {
+    let (vendor, model) = (normalize(&drive.vendor), normalize(&drive.model));
+    KNOWN_READ_OFFSETS
+        .iter()
+        .find(|(known_vendor, known_model, _)| {
+            vendor.eq_ignore_ascii_case(known_vendor)
+                && model.to_ascii_uppercase().starts_with(&known_model.to_ascii_uppercase())
+        })
+        .map(|&(_, _, offset)| offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Describe a drive the way sysfs would
+    fn drive(vendor: &str, model: &str) -> DriveInfo {
+        DriveInfo {
+            vendor: vendor.to_owned(),
+            model: model.to_owned(),
+            revision: "1.00".to_owned(),
+        }
+    }
+
+    #[test]
+    fn offsets_are_found_despite_padding_and_case() {
+        assert_eq!(known_read_offset(&drive("PLEXTOR", "DVDR   PX-716A")), Some(30));
+        assert_eq!(known_read_offset(&drive("ASUS", "DRW-24B1ST   a")), Some(6));
+        assert_eq!(known_read_offset(&drive("Lite-On", "IHAS124   Y")), Some(6));
+        assert_eq!(known_read_offset(&drive("PLEXTOR", "DVDR   PX-712A")), None);
+        assert_eq!(known_read_offset(&drive("ACME", "DVDR   PX-716A")), None);
+    }
+}
+
+// vim: set sw=4 sts=4 :
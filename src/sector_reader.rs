@@ -0,0 +1,250 @@
+//! Block-aligned read buffering for parsing filesystem structures on slow optical media
+
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+
+/// Size of a data sector on optical media (and the alignment read buffers must respect)
+pub const SECTOR_LEN: usize = 2048;
+
+/// Default size of the read buffer used when parsing filesystem structures
+///
+/// 32 sectors (64KiB) is the largest transfer many optical drives will service in a single
+/// command, so smaller blocks leave drive bandwidth on the table while the drive's seek and
+/// spin-up latency dominates, and larger ones mostly read data past the small structures
+/// we're after. Re-check this with `just bench-read-buffer <device>` (which times the probing
+/// a rip starts with at a range of sizes) before changing it, and use `--read-buffer` to tune
+/// it for drives which behave differently.
+pub const DEFAULT_READ_BUFFER: usize = 32 * SECTOR_LEN;
+
+/// A reader which always reads whole, aligned blocks from the underlying device and serves
+/// smaller reads out of the most recently read block
+///
+/// Unlike `BufReader`, seeking doesn't throw away the buffer, so the scattered small reads
+/// involved in parsing volume descriptors and directory records don't each become a separate
+/// (and, for optical drives, slow) device read.
+pub struct SectorReader<R> {
+    /// The device or image file being read
+    inner: R,
+    /// Size of the blocks read from `inner` (A multiple of `SECTOR_LEN`)
+    block_size: usize,
+    /// The most recently read block (Shorter than `block_size` at the end of the input)
+    block: Vec<u8>,
+    /// Offset of `block` within `inner` (`None` if nothing has been read yet)
+    block_start: Option<u64>,
+    /// Current read position, as seen by the caller
+    pos: u64,
+}
+
+impl<R: Read + Seek> SectorReader<R> {
+    /// Wrap `inner` using the default block size
+    pub fn new(inner: R) -> Self {
+        Self::with_block_size(inner, DEFAULT_READ_BUFFER)
+    }
+
+    /// Wrap `inner`, reading it in blocks of `block_size` bytes
+    ///
+    /// `block_size` is rounded up to a whole number of sectors so reads stay aligned.
+    pub fn with_block_size(inner: R, block_size: usize) -> Self {
+        let aligned_size = block_size.max(1).div_ceil(SECTOR_LEN) * SECTOR_LEN;
+        SectorReader {
+            inner,
+            block_size: aligned_size,
+            block: Vec::new(),
+            block_start: None,
+            pos: 0,
+        }
+    }
+
+    /// Make sure `block` contains the block holding `self.pos`
+    fn fill_block(&mut self) -> io::Result<()> {
+        let wanted = self.pos - self.pos % self.block_size as u64;
+        if self.block_start == Some(wanted) {
+            return Ok(());
+        }
+
+        self.block_start = None; // Don't leave a half-filled buffer marked as valid
+        self.block.resize(self.block_size, 0);
+        self.inner.seek(SeekFrom::Start(wanted))?;
+
+        // Read the whole block in as few requests as the device allows
+        let mut filled = 0;
+        while let Some(unfilled) = self.block.get_mut(filled..).filter(|rest| !rest.is_empty()) {
+            match self.inner.read(unfilled) {
+                Ok(0) => break,
+                Ok(len) => filled += len,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e),
+            }
+        }
+        self.block.truncate(filled);
+        self.block_start = Some(wanted);
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SectorReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill_block()?;
+
+        // Both casts are lossless because the offset is always less than `block_size`
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = (self.pos % self.block_size as u64) as usize;
+        let available = self.block.get(offset..).unwrap_or_default();
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<R: Read + Seek> Seek for SectorReader<R> {
+    fn seek(&mut self, target: SeekFrom) -> io::Result<u64> {
+        let new_pos = match target {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.inner.seek(SeekFrom::End(0))?.checked_add_signed(delta),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+impl<R: fmt::Debug> fmt::Debug for SectorReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Leave out the buffer contents, since they'd drown out everything else
+        f.debug_struct("SectorReader")
+            .field("inner", &self.inner)
+            .field("block_size", &self.block_size)
+            .field("pos", &self.pos)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// A `Cursor` which counts how many times it's read from
+    struct CountingReader {
+        /// The data being read
+        inner: Cursor<Vec<u8>>,
+        /// Number of calls to `read`
+        reads: usize,
+    }
+
+    impl Read for CountingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+
+    impl Seek for CountingReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    /// Test data where every byte differs from its neighbours
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|idx| (idx % 251) as u8).collect()
+    }
+
+    #[test]
+    fn with_block_size_rounds_up_to_sectors() {
+        for (requested, actual) in [(0, 2048), (1, 2048), (2048, 2048), (2049, 4096)] {
+            let reader = SectorReader::with_block_size(Cursor::new(Vec::new()), requested);
+            assert_eq!(reader.block_size, actual, "{}", requested);
+        }
+    }
+
+    #[test]
+    fn reads_match_unbuffered_reads_at_any_offset() {
+        let data = pattern(5 * SECTOR_LEN + 100);
+        let mut reader = SectorReader::with_block_size(Cursor::new(data.clone()), SECTOR_LEN * 2);
+        for (offset, len) in [(0, 10), (4090, 12), (32, 4096), (10_200, 140), (1, 0)] {
+            let mut buf = vec![0; len];
+            reader.seek(SeekFrom::Start(offset)).expect("in-memory seek");
+            reader.read_exact(&mut buf).expect("in-memory read");
+            assert_eq!(buf, data[offset as usize..offset as usize + len], "at {}", offset);
+        }
+
+        // Reads past the end come up short rather than failing
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-5)).expect("in-memory seek");
+        reader.read_to_end(&mut rest).expect("in-memory read");
+        assert_eq!(rest, data[data.len() - 5..]);
+        assert!(reader.seek(SeekFrom::Current(-100_000)).is_err());
+    }
+
+    /// Time the filesystem probing a rip starts with at a range of block sizes, to check
+    /// `DEFAULT_READ_BUFFER` against a real drive
+    ///
+    /// Each size starts from a cold page cache, but drives have caches of their own, so it's
+    /// worth running more than once.
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore = "needs a disc in a drive (run it with `just bench-read-buffer <device>`)"]
+    fn bench_read_buffer_sizes() {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+        use std::time::Instant;
+
+        use nix::fcntl::{posix_fadvise, PosixFadviseAdvice};
+
+        use crate::dvd::detect_css;
+        use crate::iso9660::{read_volume_dates, volume_fingerprint};
+        use crate::platform::detect_filesystem;
+
+        let device = std::env::var_os("RIP_MEDIA_BENCH_DEVICE").expect("a device to read");
+        for sectors in [1, 4, 16, 32, 64, 256] {
+            let file = File::open(&device).expect("the device is readable");
+            posix_fadvise(file.as_raw_fd(), 0, 0, PosixFadviseAdvice::POSIX_FADV_DONTNEED)
+                .expect("the page cache can be dropped");
+            let mut reader = SectorReader::with_block_size(file, sectors * SECTOR_LEN);
+
+            let start = Instant::now();
+            let filesystem = detect_filesystem(&mut reader).ok();
+            let found_pvd = volume_fingerprint(&mut reader).is_ok();
+            let dates = read_volume_dates(&mut reader).ok();
+            let css = detect_css(&mut reader).ok();
+            println!(
+                "{:3} sectors ({:3} KiB): {:>10.1?}  [{:?}, PVD: {}, dates: {}, CSS: {:?}]",
+                sectors,
+                sectors * SECTOR_LEN / 1024,
+                start.elapsed(),
+                filesystem,
+                found_pvd,
+                dates.is_some(),
+                css
+            );
+        }
+    }
+
+    #[test]
+    fn nearby_reads_share_a_device_read() {
+        let counting = CountingReader { inner: Cursor::new(pattern(8 * SECTOR_LEN)), reads: 0 };
+        let mut reader = SectorReader::with_block_size(counting, 4 * SECTOR_LEN);
+        let mut buf = [0; 2];
+        for offset in [100, 5000, 32, 8000] {
+            reader.seek(SeekFrom::Start(offset)).expect("in-memory seek");
+            reader.read_exact(&mut buf).expect("in-memory read");
+        }
+        assert_eq!(reader.inner.reads, 1);
+
+        reader.seek(SeekFrom::Start(9000)).expect("in-memory seek");
+        reader.read_exact(&mut buf).expect("in-memory read");
+        assert_eq!(reader.inner.reads, 2);
+    }
+}
+
+// vim: set sw=4 sts=4 :
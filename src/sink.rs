@@ -0,0 +1,129 @@
+//! Destinations for the files a rip produces
+//!
+//! The ripping modes get their output paths from an `OutputSink` and create, finalize, and
+//! remove their outputs through it rather than calling `std::fs` directly, so that other kinds
+//! of destination (and policies like permissions) only have to be handled in one place.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Component::CurDir, Path, PathBuf};
+
+use anyhow::Result;
+
+use crate::platform;
+
+/// Somewhere the outputs of a rip can be written
+///
+/// `path` decides where each output goes, and the other methods take paths it returned.
+pub trait OutputSink: fmt::Debug {
+    /// Get the path to write the output named `name` to (eg. to hand to a subprocess)
+    fn path(&self, name: &Path) -> PathBuf;
+
+    /// Create (or truncate) the output at `path` and open it for writing
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>>;
+
+    /// Mark the output at `from` as complete by moving it to `to` (atomically, if possible)
+    fn finalize(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Remove the output at `path`
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// List the outputs written so far, as the paths `path` returns for them
+    fn list(&self) -> io::Result<Vec<PathBuf>>;
+
+    /// How many bytes of space are left for outputs (`None` if there's no way to tell)
+    fn available_space(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Create (or replace) the output at `path` with `contents`
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = self.create(path)?;
+        file.write_all(contents)?;
+        file.flush()
+    }
+}
+
+/// Writes outputs to the local filesystem, relative to a root directory
+///
+/// The default root is the current directory, which is where the ripping modes have always
+/// written their output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalSink {
+    /// The directory outputs are written into (Empty for the current directory)
+    root: PathBuf,
+}
+
+impl LocalSink {
+    /// Create a `LocalSink` which writes outputs into `root`
+    #[allow(dead_code)] // Only the tests write anywhere but the current directory so far
+    pub fn new(root: PathBuf) -> Self {
+        LocalSink { root }
+    }
+
+    /// The directory outputs are written into, as something `std::fs` will accept
+    fn dir(&self) -> &Path {
+        if self.root.as_os_str().is_empty() {
+            Path::new(CurDir.as_os_str())
+        } else {
+            &self.root
+        }
+    }
+}
+
+impl OutputSink for LocalSink {
+    fn path(&self, name: &Path) -> PathBuf {
+        self.root.join(name)
+    }
+
+    fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        Ok(Box::new(BufWriter::new(File::create(path)?)))
+    }
+
+    fn finalize(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    fn list(&self) -> io::Result<Vec<PathBuf>> {
+        fs::read_dir(self.dir())?
+            .map(|entry| entry.map(|e| self.path(Path::new(&e.file_name()))))
+            .collect()
+    }
+
+    fn available_space(&self) -> Result<Option<u64>> {
+        platform::available_space(self.dir()).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn local_sink_writes_under_its_root() {
+        let root = scratch_dir("sink");
+        let sink = LocalSink::new(root.clone());
+        assert_eq!(LocalSink::default().path(Path::new("Disc.iso")), Path::new("Disc.iso"));
+
+        let (tmp, done) = (sink.path(Path::new("Disc.iso.tmp")), sink.path(Path::new("Disc.iso")));
+        assert_eq!(done, root.join("Disc.iso"));
+        sink.write(&tmp, b"data").expect("write output");
+        sink.finalize(&tmp, &done).expect("finalize output");
+        assert!(!tmp.exists());
+        assert_eq!(fs::read(&done).expect("read output"), b"data");
+        assert_eq!(sink.list().expect("list outputs"), std::slice::from_ref(&done));
+
+        assert!(sink.available_space().expect("statvfs works").is_some_and(|free| free > 0));
+        sink.remove(&done).expect("remove output");
+        assert!(sink.remove(&done).is_err(), "Removing what's already gone is reported");
+        fs::remove_dir_all(&root).expect("cleanup");
+    }
+}
+
+// vim: set sw=4 sts=4 :
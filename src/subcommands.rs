@@ -1,300 +1,5253 @@
 //! Subcommand definitions
 
-use std::fs::remove_file;
-use std::io::ErrorKind as IOErrorKind;
-use std::path::PathBuf;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs::{self, create_dir_all, remove_file, File};
+use std::io::{self, ErrorKind as IOErrorKind, Read, Write};
+use std::path::{
+    Component::{self, CurDir},
+    Path, PathBuf,
+};
 use std::process::{Command, Stdio};
+use std::rc::Rc;
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
-use glob::{glob_with, MatchOptions};
+use anyhow::{anyhow, bail, Context, Result};
+use log::{debug, error, info, warn};
 
-use crate::platform::{MediaProvider, NotificationProvider, RawMediaProvider, DEFAULT_TIMEOUT};
+use crate::compare::compare_streams;
+use crate::cue::{quote_file_names, split_cue_text, CueSheet, RAW_SECTOR_LEN};
+use crate::ddrescue::{format_size, Mapfile};
+use crate::dvd::{detect_css, CssStatus};
+use crate::hash_cache::HashCache;
+use crate::hashing::{
+    dat_rom_entry, hash_reader, hash_while_writing, verify_checksum_files, write_checksums,
+    write_sha256_file,
+};
+use crate::iso9660::{
+    layer_break, logical_block_size, read_volume_dates, volume_size, VolumeDates,
+};
+use crate::messages::{lang, Msg};
+use crate::platform::{
+    wait_with_watchdog, ChangerProvider, Filesystem, MediaError, MediaGuard, MediaProvider,
+    MediaType, NotificationProvider, RawMediaProvider, Urgency, WATCHDOG_POLL_INTERVAL,
+};
 
+use crate::profile::with_next_profile;
+use crate::read_offset::known_read_offset;
+use crate::sector_reader::SectorReader;
+use crate::sink::{LocalSink, OutputSink};
 use crate::subprocess_call;
+use crate::summary::{compress_output, report_rip, DiscMetadata, ARCHIVE_EXTENSION};
+use crate::tagging::TrackTags;
+use crate::toc::{
+    audio_file_ranges, mark_swapped_audio, track_count, track_modes, TrackList, TrackSummary,
+};
+use crate::validators::filename_valid_portable;
 
-/// Sound to play on completion
-/// TODO: Rearchitect once I've finished the basic port
-const DONE_SOUND: &str = "/usr/share/sounds/KDE-Im-Nudge.ogg";
+/// Sound to play on completion, if none is specified
+pub const DONE_SOUND: &str = "/usr/share/sounds/KDE-Im-Nudge.ogg";
 
-/// Sound to play on failure
-const FAIL_SOUND: &str = "/usr/share/sounds/KDE-K3B-Finish-Error.ogg";
+/// Sound to play on failure, if none is specified
+pub const FAIL_SOUND: &str = "/usr/share/sounds/KDE-K3B-Finish-Error.ogg";
 
-/// Dump a disc to as raw a BIN/TOC/CUE set as possible using cdrdao.
-pub fn rip_bin<P: RawMediaProvider>(
-    provider: &P,
-    disc_name: &str,
-    keep_tocfile: bool,
-) -> Result<()> {
-    // TODO: Unit-test this
-    // TODO: Decide how to work in absolute paths
-    // toc2cue doesn't handle spaces in filenames well, so swap in underscores
-    let volbase = PathBuf::from(disc_name.replace(' ', "_"));
-    let tocfile = volbase.with_extension("toc");
-    let cuefile = volbase.with_extension("cue");
+/// Suffix for the file the user-supplied title, platform, and notes are saved to
+const METADATA_SUFFIX: &str = ".metadata.json";
 
-    // Rip it or die
-    // TODO: Verify the "or die"
-    subprocess_call!(
-        "cdrdao",
-        "read-cd",
-        "--read-raw",
-        "--driver",
-        "generic-mmc-raw",
-        "--device",
-        provider.device_path(),
-        "--datafile",
-        volbase.with_extension("bin"),
-        &tocfile
-    )
-    .with_context(|| "Error while dumping BIN/TOC pair")?;
+/// Name of the file a disc's CD key is recorded in (alongside the rest of its outputs)
+const CD_KEY_FILE: &str = "cd_key.txt";
 
-    // Generate a .CUE file
-    // TODO: Find a way to detect if an ISO would be equivalent
-    // TODO: Detect if there are audio tracks and, if so, byte-swap
-    Command::new("toc2cue")
-        .args(&[&tocfile, &cuefile])
-        .stdout(Stdio::null())
-        .status()
-        .with_context(|| {
-            format!(
-                "Could not generate {} file from {}",
-                cuefile.to_string_lossy(),
-                tocfile.to_string_lossy()
-            )
-        })?;
+/// How many times to let the user reseat a disc the drive doesn't detect, if not specified
+pub const DEFAULT_READY_RETRIES: u32 = 3;
 
-    // XXX: Properly quote the cue file contents.
-    // (an alernative to subbing in underscores)
-    // sed -i 's@^FILE \([^"].*[^"]\) BINARY@FILE "\1" BINARY@' .cue
+/// FLAC compression level to use if none is specified (equivalent to `flac --best`)
+pub const DEFAULT_FLAC_COMPRESSION: u8 = 8;
 
-    // TODO: Audit when I want to die and when I want to keep going
-    if !keep_tocfile {
-        remove_file(&tocfile)
-            .with_context(|| format!("Could not remove {}", tocfile.to_string_lossy()))?;
+/// Settings for the parts of a ripping run which are shared by all modes
+#[derive(Debug, Default, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RipOptions<'args> {
+    /// Output name to use instead of the volume label
+    pub name: Option<&'args str>,
+
+    /// How long to wait before ejecting (overriding `MediaProvider::eject_delay_hint`)
+    pub eject_delay: Option<Duration>,
+
+    /// Eject the disc after a failed rip too (`None` to do so only if somebody's there to
+    /// take it out)
+    pub eject_on_error: Option<bool>,
+
+    /// Hash the dump and prompt for ring codes to help with submitting it to Redump
+    pub redump_info: bool,
+
+    /// URL to POST a JSON summary to when the rip finishes or fails
+    pub webhook: Option<&'args str>,
+
+    /// `(disc number, set size)` when ripping one disc of a multi-disc set
+    pub set_position: Option<(u16, u16)>,
+
+    /// The folder (relative to the output directory) the discs of the set go in, once the
+    /// first of them has named it (`None` for flat layouts)
+    pub set_dir: Option<PathBuf>,
+
+    /// `MediaProvider::disc_serial` of the previous disc in the set (if it had one)
+    pub previous_serial: Option<String>,
+
+    /// The changer slot the disc was loaded from (`None` if it's inserted by hand)
+    pub slot: Option<usize>,
+
+    /// Don't start ripping another disc of a set or changer once this passes
+    pub deadline: Option<Instant>,
+
+    /// Letter case to convert output names to
+    pub name_case: NameCase,
+
+    /// How ripped discs are arranged within the output directory
+    pub layout: Layout,
+
+    /// Name of the folder `Layout::ByType` groups this kind of media under (eg. `DVD`)
+    pub media_type: &'args str,
+
+    /// Title, platform, and notes to record alongside the dump
+    pub metadata: DiscMetadata,
+
+    /// Prompt for any parts of `metadata` which weren't provided (if somebody's there to ask)
+    pub ask_metadata: bool,
+
+    /// Name the output after the title in `metadata` rather than the volume label
+    pub name_from_title: bool,
+
+    /// Don't try to close the tray before ripping (eg. for slot-loading drives)
+    pub no_load: bool,
+
+    /// Don't try to unmount the disc before ripping (eg. to keep a loop-mounted image mounted)
+    pub no_unmount: bool,
+
+    /// Fail instead of continuing if the disc can't be unmounted
+    pub require_unmount: bool,
+
+    /// How long to wait for the drive to become ready (Zero to check just once)
+    pub ready_timeout: Duration,
+
+    /// How many times to let the user reseat a disc the drive never became ready with
+    pub ready_retries: u32,
+
+    /// Profile file to take per-disc overrides for these settings from (see `profile`)
+    pub profile: Option<&'args Path>,
+
+    /// What to do if a disc's output already exists (`None` to refuse to rip it)
+    pub overwrite: Option<OverwritePolicy>,
+
+    /// Archival steps to run on the disc's files once it has been dumped
+    pub post: PostProcessOptions,
+
+    /// Compress each disc's output into a 7-Zip archive (see `compress_output`)
+    pub compress: bool,
+
+    /// Settings to pass through to the mode function
+    pub mode: ModeOptions,
+}
+
+/// Archival steps to run on a dump once the drive is done with it (see `post_process`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PostProcessOptions {
+    /// Write a `sha256sum`-compatible `.sha256` file listing every file in the dump
+    pub checksums: bool,
+    /// Write PAR2 recovery data for every file in the dump with `par2create`
+    pub par2: bool,
+}
+
+impl PostProcessOptions {
+    /// Whether no steps were chosen
+    pub fn is_empty(self) -> bool {
+        !self.checksums && !self.par2
     }
+}
 
-    // TODO: Do this without shelling out, then find a better way to make audio tracks obvious
-    let _ = subprocess_call!("cat", cuefile);
-    Ok(())
+/// A way of dumping a damaged disc which `rip_damaged` can try
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RecoveryStrategy {
+    /// Raw BIN/TOC/CUE dump with cdrdao (Any CD)
+    Bin,
+    /// ISO dump of the data track with ddrescue (Data CDs and DVDs)
+    Iso,
+    /// Extraction of the audio tracks with cdparanoia (Audio and mixed-mode CDs)
+    Audio,
 }
 
-/// Dump a disc to an ISO using ddrescue
-pub fn rip_iso<P: RawMediaProvider>(provider: &P, disc_name: &str) -> Result<()> {
-    // TODO: Deduplicate this with rip_bin
-    let volbase = PathBuf::from(disc_name.replace(' ', "_")); // For consistency with rip_bin
-    let isofile = volbase.with_extension("iso");
-    let logfile = volbase.with_extension("log");
-
-    subprocess_call!("ddrescue", "-b", "2048", provider.device_path(), &isofile, &logfile)
-        .with_context(|| "Initial ddrescue run reported failure")?;
-    subprocess_call!(
-        "ddrescue",
-        "--direct",
-        "-M",
-        "-b",
-        "2048",
-        provider.device_path(),
-        &isofile,
-        &logfile
-    )
-    .with_context(|| "Second ddrescue pass reported failure")?;
-    // TODO: Compare ddrescue to the reading modes of dvdiaster for recovering
-    //       non-ECC-agumented discs.
-    Ok(())
+impl fmt::Display for RecoveryStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            RecoveryStrategy::Bin => "bin",
+            RecoveryStrategy::Iso => "iso",
+            RecoveryStrategy::Audio => "audio",
+        })
+    }
 }
 
-/// Rip an audio CD using cdparanoia
-pub fn rip_audio<P: RawMediaProvider>(provider: &mut P, _: &str) -> Result<()> {
-    // TODO: Decide on how to specify policy for skip-control options
-    // TODO: Use whipper instead, since it does everything we want already
-    //       https://github.com/JoeLametta/whipper
-    subprocess_call!("cdparanoia", "-B", "-d", provider.device_path())
-        .with_context(|| "Failed to extract CD audio properly")?;
-
-    let options = MatchOptions { case_sensitive: false, ..Default::default() };
-
-    // TODO: HumanSort before operating on them
-    #[allow(clippy::expect_used)]
-    for wav_result in glob_with("*.wav", options).expect("hard-coded pattern is valid") {
-        match wav_result.with_context(|| "Could not glob path") {
-            Err(e) => return Err(e),
-            Ok(path) => {
-                // TODO: Tidy this up when I'm not so tired
-                // TODO: The following should be async-dispatched in the background
-                // TODO: Extend my subprocess_call! macro to accept a slice somehow
-                // TODO: Add support for metadata retrieval and optional gain normalization
-                // Encode tracks to FLAC
-                Command::new("flac").arg("--best").arg(&path).status().with_context(|| {
-                    format!("Could not encode dumped WAV file to FLAC: {}", path.to_string_lossy())
-                })?;
-                remove_file(&path).or_else(|e|
-                    // FIXME: What was the rationale for the following?
-                    if e.kind() == IOErrorKind::NotFound { Err(e) } else { Ok(()) })
-                    .with_context(|| format!("Could not remove {}", path.to_string_lossy()))?;
-            },
+/// Which of cdrdao's subchannel formats to capture alongside a BIN dump
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum SubchannelMode {
+    /// R-W subchannels, deinterleaved and error-corrected by the drive
+    Rw,
+    /// R-W subchannels exactly as read off the disc
+    #[value(name = "rw_raw")]
+    RwRaw,
+}
+
+impl SubchannelMode {
+    /// The name `cdrdao --read-subchan` knows this mode by
+    fn cdrdao_arg(self) -> &'static str {
+        match self {
+            SubchannelMode::Rw => "rw",
+            SubchannelMode::RwRaw => "rw_raw",
         }
     }
-    Ok(())
 }
 
-// -- interactive --
+/// Letter case conversion for output names (eg. for libraries with a lowercase convention)
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    clap::ValueEnum,
+    serde::Deserialize,
+    serde::Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum NameCase {
+    /// Convert names to lowercase
+    Lower,
+    /// Convert names to uppercase
+    Upper,
+    /// Leave names as they are
+    #[default]
+    Preserve,
+}
 
-/** Ensure we have a volume name, even if it requires manual input
- *
- * Will use (in priority order):
- *
- *  * The `name` argument
- *  * `provider.volume_label()`
- *  * Prompted user input
- *
- *  TODO: Redesign this so it can defer until the end by using mkdtemp()
- *        for maximum convenience and robustness in the face of a busy user
- */
-pub fn ensure_vol_label<P: MediaProvider + NotificationProvider>(
-    provider: &P,
-    name: Option<&str>,
-) -> String {
-    if let Some(x) = name {
-        return x.to_owned();
+impl NameCase {
+    /// Convert `name` to this case
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            NameCase::Lower => name.to_lowercase(),
+            NameCase::Upper => name.to_uppercase(),
+            NameCase::Preserve => name.to_owned(),
+        }
     }
+}
 
-    // Fall back to prompting (and ensure we get a non-empty name)
-    // TODO: but do it with a timeout so the user can run the script and then take
-    //       their time loading the disc if they prefer that order of operations
-    let mut name_str = "".to_owned();
-    while name_str.trim().is_empty() {
-        // Do this inside the loop so I can press Enter to retry reading
-        // TODO: Think of better UX for this
-        name_str = provider.volume_label().unwrap_or_default().trim().to_owned();
+/// How ripped discs are arranged within the output directory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Put each disc's files directly in the output directory
+    Flat,
+    /// Put each disc in its own folder, grouped by type of media (eg. `DVD/<name>/`)
+    ByType,
+    /// Put each disc in its own folder (`<name>/`)
+    #[default]
+    ByName,
+}
+
+impl Layout {
+    /// The directory a disc's files end up in, relative to the output directory
+    pub fn disc_dir(self, disc_name: &str, media_type: &str) -> PathBuf {
+        match self {
+            Layout::Flat => PathBuf::from(CurDir.as_os_str()),
+            Layout::ByType => Path::new(media_type).join(disc_name),
+            Layout::ByName => PathBuf::from(disc_name),
+        }
+    }
 
-        if name_str.is_empty() {
-            // FIXME: Do I make this fallible or swallow the error?
-            // name_str = provider.read_line("Disc Name: ")?.trim().to_owned();
-            unimplemented!()
+    /// The directory a disc is ripped into (before its `.partial` suffix is removed)
+    ///
+    /// (Flat layouts still rip into a folder of their own, so an incomplete rip can't be
+    /// mistaken for a complete one, and move the files out once it succeeds.)
+    fn staging_dir(self, disc_name: &str, media_type: &str) -> PathBuf {
+        match self {
+            Layout::Flat => PathBuf::from(disc_name),
+            Layout::ByType | Layout::ByName => self.disc_dir(disc_name, media_type),
         }
     }
+}
 
-    name_str
+/// What to do when a complete rip of a disc already exists in the output directory
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverwritePolicy {
+    /// Leave the existing output untouched and don't rip the disc again
+    Skip,
+    /// Replace the existing output once the new rip has succeeded
+    Overwrite,
+    /// Rip under the first free name with a `-1`, `-2`, etc. suffix
+    Rename,
 }
 
-/// Robustly prompt the user for a CD key and record it in `cd_key.txt`
-pub fn get_cd_key<P: NotificationProvider>(provider: &P, disc_name: &str) -> Result<()> {
-    loop {
-        let key = provider
-            .read_line(&format!("please enter cd-key for {} (enter for none): ", disc_name))?;
-        let trimmed = key.trim();
+/// How to go about ripping a disc, given what's already in the output directory
+#[derive(Debug, PartialEq, Eq)]
+enum OutputPlan {
+    /// Rip the disc under `name`, replacing any existing output if `replace` is set
+    Rip {
+        /// The name to rip the disc under
+        name: String,
+        /// Whether existing output under that name should be replaced
+        replace: bool,
+    },
+    /// Leave the existing output alone
+    Skip,
+}
 
-        // TODO: Have a non-rustyline one for simple y/n or Enter stuff.
-        let confirm = if trimmed.is_empty() {
-            provider.read_line("no cd key. is this correct? (y/n): ")?
-        } else {
-            provider.read_line(&format!("please confirm \"{}\" (y/n): ", trimmed))?
-        };
+/// Decide how to rip a disc named `disc_name` under `policy`, where `exists` says whether a
+/// complete rip under a given name is already present
+fn plan_output<F: Fn(&str) -> bool>(
+    disc_name: &str,
+    policy: Option<OverwritePolicy>,
+    exists: F,
+) -> Result<OutputPlan> {
+    if !exists(disc_name) {
+        return Ok(OutputPlan::Rip { name: disc_name.to_owned(), replace: false });
+    }
+    match policy {
+        None => bail!(
+            "{} has already been ripped. Use --overwrite-policy to skip, replace, or rename it.",
+            disc_name
+        ),
+        Some(OverwritePolicy::Skip) => Ok(OutputPlan::Skip),
+        Some(OverwritePolicy::Overwrite) => {
+            Ok(OutputPlan::Rip { name: disc_name.to_owned(), replace: true })
+        },
+        Some(OverwritePolicy::Rename) => {
+            let name = (1..=u32::MAX)
+                .map(|suffix| format!("{}-{}", disc_name, suffix))
+                .find(|candidate| !exists(candidate))
+                .context("Ran out of suffixes for the output name")?;
+            Ok(OutputPlan::Rip { name, replace: false })
+        },
+    }
+}
 
-        if confirm.to_lowercase() == "y" {
-            if !trimmed.is_empty() {
-                unimplemented!();
-                // with open('cd_key.txt', 'w') as fobj:
-                //     fobj.write('%s\n' % key)
+/// Whether `root` already holds a complete rip of a disc in `folder` within `set_dir`
+fn set_output_exists(root: &Path, set_dir: &Path, folder: &str) -> bool {
+    let disc_dir = root.join(set_dir).join(folder);
+    disc_dir.exists() || volume_file(&disc_dir, ARCHIVE_EXTENSION).exists()
+}
+
+/// Whether `root` already holds a complete rip of `disc_name`
+///
+/// (For flat layouts, that means a dump named after the disc. Incomplete rips are still in
+/// their `.partial` folders, so they don't count.)
+fn output_exists(root: &Path, layout: Layout, disc_name: &str, media_type: &str) -> bool {
+    match layout {
+        Layout::Flat => MEDIA_EXTENSIONS
+            .iter()
+            .any(|ext| root.join(volume_file(Path::new(disc_name), ext)).exists()),
+        Layout::ByType | Layout::ByName => {
+            let dir = root.join(layout.disc_dir(disc_name, media_type));
+            dir.exists() || volume_file(&dir, ARCHIVE_EXTENSION).exists()
+        },
+    }
+}
+
+/// Create any missing parent directories of `dir`, checking that each name is portable
+fn create_parent_dirs(dir: &Path) -> Result<()> {
+    let Some(parent) = dir.parent().filter(|parent| !parent.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    for component in parent.components() {
+        if let Component::Normal(name) = component {
+            filename_valid_portable(name).map_err(|e| {
+                anyhow!("Can't create {}: {}", parent.display(), e.to_string_lossy())
+            })?;
+        }
+    }
+    create_dir_all(parent).with_context(|| format!("Could not create {}", parent.display()))
+}
+
+/// Move everything in `dir` up into its parent and remove it, returning the names moved
+///
+/// Unless `replace` is set, nothing is moved if any of the names are already taken in the parent.
+fn flatten_into_parent(dir: &Path, replace: bool) -> Result<Vec<String>> {
+    let parent = dir
+        .parent()
+        .filter(|dir_parent| !dir_parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new(CurDir.as_os_str()));
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Could not list {}", dir.display()))? {
+        let name = entry.with_context(|| format!("Could not list {}", dir.display()))?.file_name();
+        if !replace && parent.join(&name).exists() {
+            bail!("Refusing to replace {}", parent.join(&name).display());
+        }
+        names.push(name);
+    }
+    names.sort();
+
+    for name in &names {
+        fs::rename(dir.join(name), parent.join(name)).with_context(|| {
+            format!("Could not move {} into {}", name.to_string_lossy(), parent.display())
+        })?;
+    }
+    fs::remove_dir(dir).with_context(|| format!("Could not remove {}", dir.display()))?;
+    Ok(names.into_iter().map(|name| name.to_string_lossy().into_owned()).collect())
+}
+
+/// Extensions (beyond `MEDIA_EXTENSIONS`) of the files a rip may name after the disc
+const SIDECAR_EXTENSIONS: &[&str] = &["log", "iso.sha256", "layerbreak.txt", "sha256"];
+
+/// Check that nothing already in `root` has the wrong type for where a rip of `disc_name` into
+/// `staging_dir` will write (a file where it needs a folder, or a folder where it will write a
+/// file)
+///
+/// Without this, such collisions only surface partway through the rip as confusing I/O errors.
+fn check_output_paths(root: &Path, layout: Layout, staging: &Path, disc_name: &str) -> Result<()> {
+    let staging_dir = root.join(staging);
+    let mut partial_name = staging_dir.as_os_str().to_owned();
+    partial_name.push(PARTIAL_SUFFIX);
+    let partial_dir = PathBuf::from(partial_name);
+
+    let parents = staging_dir.ancestors().skip(1).take_while(|dir| dir.starts_with(root));
+    for dir in parents.chain([staging_dir.as_path(), partial_dir.as_path()]) {
+        if dir.exists() && !dir.is_dir() {
+            bail!(
+                "{} is a file, but the rip needs a folder there. Move it out of the way or \
+                 choose a different --name.",
+                dir.display()
+            );
+        }
+    }
+
+    // Flat layouts move the files out into the output directory once the rip succeeds
+    let mut file_dirs = vec![partial_dir.as_path()];
+    if layout == Layout::Flat {
+        file_dirs.push(root);
+    }
+    let volbase = Path::new(disc_name);
+    let names = MEDIA_EXTENSIONS
+        .iter()
+        .chain(SIDECAR_EXTENSIONS)
+        .map(|ext| volume_file(volbase, ext))
+        .chain([PathBuf::from(format!("{}{}", disc_name, METADATA_SUFFIX))]);
+    for name in names {
+        for dir in &file_dirs {
+            let path = dir.join(&name);
+            if path.is_dir() {
+                bail!(
+                    "{} is a folder, but the rip will write a file there. Move it out of the way \
+                     or choose a different --name.",
+                    path.display()
+                );
             }
-            break;
         }
     }
     Ok(())
 }
 
-// -- subcommands --
-// TODO: Make these as asynchronous as possible
+/// List the names of the files (but not folders) directly within `dir`
+pub fn file_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Could not list {}", dir.display()))? {
+        let path = entry.with_context(|| format!("Could not list {}", dir.display()))?.path();
+        if path.is_file() {
+            names.push(path.file_name().unwrap_or_default().to_string_lossy().into_owned());
+        }
+    }
+    names.sort();
+    Ok(names)
+}
 
-/// Subcommand to rip a CD-ROM
-pub fn rip_cd<P: RawMediaProvider + NotificationProvider>(
-    provider: &mut P,
-    disc_name: &str,
-) -> Result<()> {
-    // TODO: Make this take options so I can ask for BIN or ISO
-    rip_bin(provider, disc_name, true)?;
-    let _ = provider.play_sound(DONE_SOUND);
-    get_cd_key(provider, disc_name)
+/// Finalize the name a disc will be ripped under, failing if it won't make a portable filename
+fn output_name(name: &str, case: NameCase) -> Result<String> {
+    let converted = case.apply(name);
+    filename_valid_portable(&converted).map_err(|e| {
+        anyhow!("Can't use {:?} as an output name: {}", converted, e.to_string_lossy())
+    })?;
+    Ok(converted)
 }
 
-/// Subcommand to recover a damaged CD
-pub fn rip_damaged<P: RawMediaProvider + NotificationProvider>(
-    provider: &mut P,
-    disc_name: &str,
-) -> Result<()> {
-    // TODO: Look into integrating dvdisaster
-    rip_bin(provider, disc_name, true)?;
-    rip_iso(provider, disc_name)?;
-    rip_audio(provider, disc_name)?;
-    let _ = provider.play_sound(DONE_SOUND);
-    get_cd_key(provider, disc_name)
+/// Make `name` (eg. a volume label) usable as a portable filename, replacing characters
+/// `filename_valid_portable` rejects with `_` and stripping trailing dots and spaces
+fn portable_name(name: &str) -> String {
+    if filename_valid_portable(name).is_ok() {
+        return name.to_owned();
+    }
+
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if c.is_ascii_control() || "\"*<>?|/:\\".contains(c) { '_' } else { c })
+        .collect();
+    while sanitized.len() > 254 {
+        sanitized.pop(); // (Leaving room for the prefix below)
+    }
+    let kept = sanitized.trim_end_matches([' ', '.']).len();
+    sanitized.truncate(kept);
+    if filename_valid_portable(&sanitized).is_err() {
+        // Whatever's left is empty or a name Windows reserves (eg. CON)
+        sanitized.insert(0, '_');
+    }
+    warn!("Using {:?} as the disc name, since {:?} isn't a portable filename", sanitized, name);
+    sanitized
 }
 
-/// Subcommand to rip a DVD-ROM
-pub fn rip_dvd<P: RawMediaProvider + NotificationProvider>(
-    provider: &mut P,
-    disc_name: &str,
-) -> Result<()> {
-    rip_iso(provider, disc_name)?;
-    let _ = provider.play_sound(DONE_SOUND);
-    get_cd_key(provider, disc_name)
+/// Extensions of files `rip_media` (or other dumping tools) produce, which are likely to find
+/// their way into a `--name` by copy-and-paste
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "7z", "bin", "ccd", "chd", "cue", "flac", "gdi", "img", "iso", "m3u8", "mds", "mdf", "nrg",
+    "sub", "toc", "wav",
+];
+
+/// The path of the `extension` file for a dump named `volbase`
+///
+/// (Unlike `Path::with_extension`, this doesn't eat anything after a `.` in the name itself.)
+pub fn volume_file(volbase: &Path, extension: &str) -> PathBuf {
+    let mut name = volbase.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
 }
 
-/// Subcommand to rip a Playstation (PSX/PS1) disc
-pub fn rip_psx<P: RawMediaProvider + NotificationProvider>(
-    provider: &mut P,
-    disc_name: &str,
-) -> Result<()> {
-    rip_bin(provider, disc_name, true)
+/// Remove a recognized media extension (case-insensitively) from a user-supplied output name
+///
+/// Without this, `--name Game.iso` would produce `Game.iso.iso`. Unrecognized extensions are
+/// left alone, since they're more likely to be part of the name (eg. `Vol. 2`).
+pub fn strip_media_extension(name: &str) -> &str {
+    match name.rsplit_once('.') {
+        Some((stem, ext))
+            if !stem.trim().is_empty()
+                && MEDIA_EXTENSIONS.iter().any(|known| known.eq_ignore_ascii_case(ext)) =>
+        {
+            stem
+        },
+        _ => name,
+    }
 }
 
-/// Subcommand to rip a Playstation 2 (PS2) disc
-pub fn rip_ps2<P: RawMediaProvider + NotificationProvider>(
-    provider: &mut P,
-    disc_name: &str,
-) -> Result<()> {
-    rip_iso(provider, disc_name)
+/// Fill in whatever parts of `given` are missing by prompting for them
+///
+/// Blank answers leave a field unset. If `name_from_title` is set, titles which can't be used as
+/// an output name are refused and asked for again.
+fn ask_metadata<P: NotificationProvider>(
+    provider: &P,
+    given: &DiscMetadata,
+    name_from_title: bool,
+    case: NameCase,
+) -> Result<DiscMetadata> {
+    let ask = |prompt: Msg<'_>| -> Result<Option<String>> {
+        let answer = provider.read_line(&prompt.to_string())?;
+        Ok(Some(answer.trim().to_owned()).filter(|trimmed| !trimmed.is_empty()))
+    };
+
+    let mut metadata = given.clone();
+    while metadata.title.is_none() {
+        metadata.title = ask(Msg::AskTitle)?;
+        if !name_from_title {
+            break;
+        }
+        if let Some(Err(e)) = metadata.title.as_deref().map(|title| output_name(title, case)) {
+            warn!("{:#}", e);
+            metadata.title = None;
+        }
+    }
+    if metadata.platform.is_none() {
+        metadata.platform = ask(Msg::AskPlatform)?;
+    }
+    if metadata.notes.is_none() {
+        metadata.notes = ask(Msg::AskNotes)?;
+    }
+    Ok(metadata)
 }
 
-/// Top-level orchestration for doing a ripping run on a single disc
-/// TODO: Provide prompting via a swappable service provider similar to APT's.
-pub fn rip<P, F>(plat_provider: &mut P, mode_func: F, name: Option<&str>) -> Result<()>
-where
-    P: MediaProvider + NotificationProvider,
-    F: Fn(&mut P, &str) -> Result<()>,
-{
-    // TODO: Have a non-rustyline one for simple y/n or Enter stuff.
-    plat_provider.read_line("Insert disc and press Enter...")?;
+/// Save any user-supplied details about `disc_name` into `dir`
+///
+/// (Named after the disc, rather than a fixed name, so discs ripped with `--layout flat` don't
+///  collide.)
+fn write_metadata(dir: &Path, metadata: &DiscMetadata, disc_name: &str) -> Result<()> {
+    if metadata.is_empty() {
+        return Ok(());
+    }
+    metadata.write_json(&dir.join(format!("{}{}", disc_name, METADATA_SUFFIX)))
+}
 
-    // TODO: Perhaps a mode where this presses Enter for you after 30 seconds
-    //       if the disc's serial number has changed?
-    plat_provider.load()?;
-    plat_provider.wait_for_ready(&Duration::new(DEFAULT_TIMEOUT, 0))?;
-    plat_provider.unmount()?; // Ensure we can get exclusive access to the disc
+/// Decide which strategies to follow up a BIN dump with, based on the TOC it produced
+///
+/// (If cdrdao couldn't produce a TOC, it's probably not a CD, so ddrescue is the best bet.)
+fn follow_up_strategies(toc: Option<&str>) -> Vec<RecoveryStrategy> {
+    let Some(toc_text) = toc else { return vec![RecoveryStrategy::Iso] };
+    let tracks = TrackList::parse(toc_text);
+    let mut strategies = Vec::new();
+    if tracks.has_data() {
+        strategies.push(RecoveryStrategy::Iso);
+    }
+    if tracks.has_audio() {
+        strategies.push(RecoveryStrategy::Audio);
+    }
+    strategies
+}
 
-    let name_str = ensure_vol_label(plat_provider, name);
-    assert!(!name_str.trim().is_empty()); // Guard against empty names
-                                          // with _containing_workdir(disc_name):
-    mode_func(plat_provider, &name_str).map_err(|e| {
-        let _ = plat_provider.play_sound(FAIL_SOUND);
-        e
-    })?;
+/// Settings for the tools which the individual ripping modes are built from
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ModeOptions {
+    /// FLAC compression level (0-8) for encoding audio tracks
+    pub flac_compression: u8,
 
-    // Notify completion and eject
-    // TODO: Redesign to deduplicate the audio in PC-related modes.
-    let _ = plat_provider.play_sound(DONE_SOUND);
-    sleep(Duration::new(2, 0)); // Give me time to reach for the door if it got closed
-    let _ = plat_provider.eject(); // TODO: Notify failure here
+    /// Write an M3U playlist of the ripped audio tracks
+    pub playlist: bool,
+
+    /// Tag ripped audio tracks using the disc's CD-TEXT and ISRCs
+    pub cd_text: bool,
+
+    /// Allow a second, slower ddrescue pass to retry any areas the first pass couldn't read
+    pub second_pass: bool,
+
+    /// Check that BIN/TOC/CUE sets are consistent with each other after dumping
+    pub verify_toc: bool,
+
+    /// Treat problems found by consistency checks (and a lack of free space for a dump) as
+    /// errors rather than warnings
+    pub strict: bool,
+
+    /// Kill dumping tools which go this long without writing anything
+    pub stall_timeout: Option<Duration>,
+
+    /// Recovery strategies for `rip_damaged` to attempt, in order (empty to detect from the disc)
+    pub recovery: Vec<RecoveryStrategy>,
+
+    /// Dump CSS-protected DVDs anyway, rather than refusing
+    pub allow_encrypted: bool,
+
+    /// Only rip these audio tracks (`None` for all of them)
+    pub tracks: Option<BTreeSet<usize>>,
+
+    /// Keep encoding the rest of an album's tracks after one fails
+    pub keep_going: bool,
+
+    /// Hash ISO dumps as ddrescue writes them and save a `.sha256` file alongside
+    pub hash_while_reading: bool,
+
+    /// Keep ddrescue's mapfile after dumping (and log a summary of it) rather than deleting it
+    pub keep_log: bool,
+
+    /// Set ISO dumps' modification time to the creation date in their volume descriptor
+    pub stamp_mtime: bool,
+
+    /// How cdparanoia should trade accuracy for the ability to get past damage
+    pub paranoia: ParanoiaPolicy,
+
+    /// Extra arguments to pass to cdparanoia (checked by `check_cdparanoia_arg`)
+    pub cdparanoia_args: Vec<String>,
+
+    /// The drive's read offset in samples, to correct for when ripping audio (`None` to look it
+    /// up from the drive's model)
+    pub read_offset: Option<i32>,
+
+    /// Split BIN dumps into one file per track (rewriting the CUE to match)
+    pub split_tracks: bool,
+
+    /// Split BIN dumps by track and name the track BINs the way Redump does, keeping the TOC
+    pub redump_naming: bool,
+
+    /// Subcommand a disc profile asked for, in place of the one the run was started with
+    pub mode: Option<String>,
+
+    /// Where to write a map of which parts of an ISO dump were recovered (relative to the
+    /// disc's output directory)
+    pub error_map: Option<PathBuf>,
+
+    /// Only dump these sectors in the ISO modes
+    pub range: Option<SectorRange>,
+
+    /// Rip and encode audio tracks one at a time, so only one track's WAV is on disk at once
+    pub encode_as_ripped: bool,
+
+    /// `cdrdao --driver` to dump BIN/TOC pairs with (`None` for `DEFAULT_CDRDAO_DRIVER`)
+    pub cdrdao_driver: Option<String>,
+
+    /// If dumping a BIN/TOC pair with the default driver fails, retry with the driver `cdrdao
+    /// scanbus` suggests for the drive
+    pub driver_autodetect: bool,
+
+    /// Capture this kind of subchannel data into a `.sub` file when dumping BIN/TOC pairs
+    pub subchannel: Option<SubchannelMode>,
+
+    /// Where the dumps and the files that go with them are written
+    pub sink: Rc<dyn OutputSink>,
+
+    /// What to play when a rip finishes or fails
+    pub sounds: Sounds,
+
+    /// Answer confirmation prompts (eg. "Insert disc and press Enter") without asking
+    pub assume_yes: bool,
+}
+
+impl Default for ModeOptions {
+    fn default() -> Self {
+        ModeOptions {
+            flac_compression: DEFAULT_FLAC_COMPRESSION,
+            playlist: false,
+            cd_text: false,
+            second_pass: true,
+            verify_toc: false,
+            strict: false,
+            stall_timeout: None,
+            recovery: Vec::new(),
+            allow_encrypted: false,
+            tracks: None,
+            keep_going: false,
+            hash_while_reading: false,
+            keep_log: true,
+            stamp_mtime: false,
+            paranoia: ParanoiaPolicy::default(),
+            cdparanoia_args: Vec::new(),
+            read_offset: None,
+            split_tracks: false,
+            redump_naming: false,
+            mode: None,
+            error_map: None,
+            range: None,
+            encode_as_ripped: false,
+            cdrdao_driver: None,
+            driver_autodetect: false,
+            subchannel: None,
+            sink: Rc::new(LocalSink::default()),
+            sounds: Sounds::default(),
+            assume_yes: false,
+        }
+    }
+}
+
+/// The sounds which get the user's attention when a rip needs them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sounds {
+    /// Played when a rip finishes (or is about to ask for a CD key)
+    pub done: PathBuf,
+    /// Played when a rip fails
+    pub fail: PathBuf,
+    /// Don't play anything (eg. on a headless server)
+    pub muted: bool,
+}
+
+impl Default for Sounds {
+    fn default() -> Self {
+        Sounds { done: PathBuf::from(DONE_SOUND), fail: PathBuf::from(FAIL_SOUND), muted: false }
+    }
+}
+
+impl Sounds {
+    /// Let the user know a rip finished
+    fn play_done<P: NotificationProvider>(&self, provider: &mut P) {
+        self.play(provider, &self.done);
+    }
+
+    /// Let the user know a rip failed
+    fn play_fail<P: NotificationProvider>(&self, provider: &mut P) {
+        self.play(provider, &self.fail);
+    }
+
+    /// Play `sound` if it exists and we haven't been muted
+    ///
+    /// Sounds are only a courtesy, so a missing file or a failure to play it is only logged (at
+    /// a level nobody will see by default, since the defaults only exist on KDE systems).
+    fn play<P: NotificationProvider>(&self, provider: &mut P, sound: &Path) {
+        if self.muted {
+            return;
+        }
+        if !sound.exists() {
+            debug!("Not playing {}: No such file", sound.display());
+            return;
+        }
+        if let Err(e) = provider.play_sound(sound) {
+            debug!("{:#}", e);
+        }
+    }
+}
 
-    // TODO: Call ['par2create', '-n1', '%s.par2' % name_str, glob.glob('*')]
+/// A range of sectors to dump instead of the whole disc
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SectorRange {
+    /// First sector to read
+    pub offset: u64,
+    /// How many sectors to read (`None` for the rest of the disc)
+    pub length: Option<u64>,
+}
+
+impl SectorRange {
+    /// Convert to a `(start, length)` pair in bytes, checking it against the size of the disc
+    fn to_bytes(self, sector_len: u64, device_size: u64) -> Result<(u64, u64)> {
+        let disc_sectors = device_size / sector_len;
+        let start =
+            self.offset.checked_mul(sector_len).filter(|&start| start < device_size).with_context(
+                || {
+                    format!(
+                        "Sector {} is past the end of the disc ({} sectors)",
+                        self.offset, disc_sectors
+                    )
+                },
+            )?;
+        let len = match self.length {
+            Some(0) => bail!("Can't dump zero sectors"),
+            Some(sectors) => sectors
+                .checked_mul(sector_len)
+                .filter(|&len| len <= device_size - start)
+                .with_context(|| {
+                    format!(
+                        "{} sectors starting at sector {} would run past the end of the disc \
+                         ({} sectors)",
+                        sectors, self.offset, disc_sectors
+                    )
+                })?,
+            None => device_size - start,
+        };
+        Ok((start, len))
+    }
+}
+
+/// Bytes per second of CD-DA audio (44.1kHz, 16-bit, stereo)
+const CDDA_BYTES_PER_SEC: u64 = 44_100 * 2 * 2;
+
+/// Size of the header on the WAV files cdparanoia writes
+const WAV_HEADER_LEN: u64 = 44;
+
+/// Size of a sector on a data CD/DVD as exposed by the OS (Mode 1 user data)
+const DATA_SECTOR_LEN: u64 = 2048;
 
-    // TODO: Optionally compress the image as strongly as possible
-    // ['7z', 'a', '-t7z', '-m0=lzma', '-mx=9', '-mfb=64', '-md=32m', '-ms=on',
-    //  '%s.7z' % name_str, name_str] && shutil.rmtree(name_str)
+/// Smallest plausible BIN dump: one track of the Red Book's 4-second minimum length
+const MIN_BIN_LEN: u64 = 4 * 75 * RAW_SECTOR_LEN;
 
+/// Smallest plausible ISO dump: the 16-sector system area, a primary volume descriptor, and
+/// the volume descriptor set terminator
+const MIN_ISO_LEN: u64 = 18 * DATA_SECTOR_LEN;
+
+/// Smallest plausible WAV file for an audio track: the Red Book's 4-second minimum length
+const MIN_WAV_LEN: u64 = WAV_HEADER_LEN + 4 * CDDA_BYTES_PER_SEC;
+
+/// Fail if a dump is empty or too small to hold even the smallest medium of its type
+///
+/// (A drive which returns no data can leave a dumping tool exiting successfully anyway)
+fn check_dump_len(dump: &Path, minimum: u64) -> Result<()> {
+    let len = fs::metadata(dump)
+        .with_context(|| format!("Could not check the size of {}", dump.display()))?
+        .len();
+    if len == 0 {
+        bail!("{} is empty. The drive may have returned no data.", dump.display());
+    }
+    if len < minimum {
+        bail!(
+            "{} is only {} bytes long, but even the smallest valid dump would be {} bytes. The \
+             drive may have returned no data.",
+            dump.display(),
+            len,
+            minimum
+        );
+    }
     Ok(())
 }
 
+/// How many bytes short of `expected` the `actual` length of a dump is, if any
+fn dump_shortfall(actual: u64, expected: u64) -> Option<u64> {
+    expected.checked_sub(actual).filter(|&missing| missing > 0)
+}
+
+/// Warn if a dump is smaller than the capacity of the medium it was dumped from
+///
+/// (Subprocesses can exit successfully after silently producing a truncated dump)
+fn warn_if_truncated(dump: &Path, expected: u64) {
+    match fs::metadata(dump) {
+        Ok(meta) => {
+            if let Some(missing) = dump_shortfall(meta.len(), expected) {
+                warn!(
+                    "{} is {} bytes shorter than expected ({} of {} bytes). The dump may be \
+                     truncated.",
+                    dump.display(),
+                    missing,
+                    meta.len(),
+                    expected
+                );
+            }
+        },
+        Err(e) => warn!("Could not check the size of {}: {}", dump.display(), e),
+    }
+}
+
+/// Extra room to leave for the logs, TOCs, and other small files that go with a dump
+const FREE_SPACE_MARGIN: u64 = 64 * 1024 * 1024;
+
+/// How many bytes short of room for a `needed`-byte dump (plus `FREE_SPACE_MARGIN`) having
+/// `available` bytes free leaves us (`None` if there's enough)
+fn space_shortfall(available: u64, needed: u64) -> Option<u64> {
+    needed.saturating_add(FREE_SPACE_MARGIN).checked_sub(available).filter(|&short| short > 0)
+}
+
+/// Warn (or fail, with `--strict`) if there isn't room where `mode_opts.sink` writes for a
+/// dump of about `needed` bytes, rather than running out of space partway through the rip
+fn check_free_space(needed: u64, mode_opts: &ModeOptions) -> Result<()> {
+    let available = match mode_opts.sink.available_space() {
+        Ok(Some(available)) => available,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            warn!("{:#}", e);
+            return Ok(());
+        },
+    };
+    let Some(short) = space_shortfall(available, needed) else {
+        return Ok(());
+    };
+    let problem = format!(
+        "The dump needs about {}, but only {} is free ({} short)",
+        format_size(needed),
+        format_size(available),
+        format_size(short)
+    );
+    if mode_opts.strict {
+        bail!("{}. Free up some space or rip somewhere else.", problem);
+    }
+    warn!("{}. The rip may run out of space partway through.", problem);
+    Ok(())
+}
+
+/// Estimate how much space dumping a disc with `device_size` bytes of user data to a BIN will
+/// take at its peak
+///
+/// (Splitting the subchannel data out briefly needs room for the interleaved dump and the
+///  split copy at once.)
+fn bin_dump_len(device_size: u64, subchannel: bool) -> u64 {
+    let sectors = device_size / DATA_SECTOR_LEN;
+    if subchannel {
+        sectors * (RAW_SECTOR_LEN + SUBCHANNEL_LEN) * 2
+    } else {
+        sectors * RAW_SECTOR_LEN
+    }
+}
+
+/// Suffix for the directory a rip is written into until it has completed successfully
+const PARTIAL_SUFFIX: &str = ".partial";
+
+/// How often to check whether the next disc of a set has been inserted, when not prompting
+const DISC_CHANGE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keeps the process's working directory set to a directory being ripped into, restoring the
+/// previous one when dropped (including when unwinding from a panic)
+///
+/// (The equivalent of the Python version's `_containing_workdir` context manager)
+#[derive(Debug)]
+pub struct WorkdirGuard {
+    /// The working directory to restore
+    previous: PathBuf,
+    /// The absolute path of the directory that was entered
+    path: PathBuf,
+}
+
+impl WorkdirGuard {
+    /// Create `dir` (and any missing parents) if it doesn't exist, then make it the working
+    /// directory
+    pub fn enter(dir: &Path) -> Result<Self> {
+        let previous = env::current_dir().context("Could not determine working directory")?;
+        create_dir_all(dir).with_context(|| format!("Could not create {}", dir.display()))?;
+        let path = fs::canonicalize(dir)
+            .with_context(|| format!("Could not resolve {}", dir.display()))?;
+        env::set_current_dir(&path)
+            .with_context(|| format!("Could not enter {}", path.display()))?;
+        debug!("Entered {}", path.display());
+        Ok(WorkdirGuard { previous, path })
+    }
+
+    /// The absolute path of the directory that was entered
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WorkdirGuard {
+    fn drop(&mut self) {
+        // Nothing can be returned from here, and a failure is about to be very noticeable
+        if let Err(e) = env::set_current_dir(&self.previous) {
+            error!("Could not return to {}: {}", self.previous.display(), e);
+        }
+    }
+}
+
+/// Run `body` with `outdir` as the working directory (creating it if needed), so the rips it
+/// performs land there rather than wherever we were started, then return to where we were
+///
+/// The previous working directory is restored whether or not `body` succeeds.
+pub fn with_output_dir<T, F: FnOnce() -> Result<T>>(outdir: &Path, body: F) -> Result<T> {
+    let _guard = WorkdirGuard::enter(outdir)
+        .with_context(|| format!("Could not use {} as the output directory", outdir.display()))?;
+    body()
+}
+
+/// Run `body` to populate `<final_dir>.partial`, then rename that to `final_dir` on success
+///
+/// This keeps half-finished rips from being mistaken for complete ones by anything watching the
+/// output directory. On failure, the `.partial` directory is left in place to be resumed or
+/// inspected, and will be reused by the next attempt. An existing `final_dir` is an error unless
+/// `replace` is set, in which case it's only removed once `body` has succeeded.
+fn with_partial_dir<T, F: FnOnce(&Path) -> Result<T>>(
+    final_dir: &Path,
+    replace: bool,
+    body: F,
+) -> Result<T> {
+    if !replace && final_dir.exists() {
+        bail!("Output directory already exists: {}", final_dir.display());
+    }
+    let mut partial_name = final_dir.as_os_str().to_owned();
+    partial_name.push(PARTIAL_SUFFIX);
+    let partial_dir = PathBuf::from(partial_name);
+
+    create_dir_all(&partial_dir)
+        .with_context(|| format!("Could not create {}", partial_dir.display()))?;
+    let result = body(&partial_dir).map_err(|e| {
+        info!("Leaving incomplete output in {}", partial_dir.display());
+        e
+    })?;
+    if replace && final_dir.exists() {
+        info!("Replacing {}", final_dir.display());
+        fs::remove_dir_all(final_dir)
+            .with_context(|| format!("Could not remove {}", final_dir.display()))?;
+    }
+    fs::rename(&partial_dir, final_dir).with_context(|| {
+        format!("Could not rename {} to {}", partial_dir.display(), final_dir.display())
+    })?;
+    Ok(result)
+}
+
+/// Compare strings the way a human would, treating runs of digits as numbers
+///
+/// (So `track2` comes before `track10`)
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_chars, mut b_chars) = (a.chars().peekable(), b.chars().peekable());
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a_char), Some(b_char)) if a_char.is_ascii_digit() && b_char.is_ascii_digit() => {
+                let mut a_num = String::new();
+                while let Some(digit) = a_chars.next_if(char::is_ascii_digit) {
+                    a_num.push(digit);
+                }
+                let mut b_num = String::new();
+                while let Some(digit) = b_chars.next_if(char::is_ascii_digit) {
+                    b_num.push(digit);
+                }
+
+                // Compare by magnitude without risking overflow, then fall back to the number
+                // of leading zeroes so the ordering is still total
+                let (a_trimmed, b_trimmed) =
+                    (a_num.trim_start_matches('0'), b_num.trim_start_matches('0'));
+                let ordering = a_trimmed
+                    .len()
+                    .cmp(&b_trimmed.len())
+                    .then_with(|| a_trimmed.cmp(b_trimmed))
+                    .then_with(|| a_num.len().cmp(&b_num.len()));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            },
+            (Some(a_char), Some(b_char)) => {
+                if a_char != b_char {
+                    return a_char.cmp(&b_char);
+                }
+                a_chars.next();
+                b_chars.next();
+            },
+        }
+    }
+}
+
+/// Sort `paths` into natural order (as per `natural_cmp`)
+fn sort_paths_naturally(paths: &mut [PathBuf]) {
+    paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+}
+
+/// A track to be listed in an M3U playlist
+#[derive(Debug)]
+struct PlaylistEntry {
+    /// Path to the audio file, relative to the playlist
+    path: PathBuf,
+    /// Length of the track in whole seconds
+    duration_secs: u64,
+    /// Title to display instead of the filename
+    title: Option<String>,
+}
+
+/// Render an extended M3U playlist with the entries in natural order
+fn format_m3u(entries: &mut [PlaylistEntry]) -> String {
+    entries.sort_by(|a, b| natural_cmp(&a.path.to_string_lossy(), &b.path.to_string_lossy()));
+
+    let mut playlist = String::from("#EXTM3U\n");
+    for entry in entries {
+        let stem = entry.path.file_stem().unwrap_or_default().to_string_lossy();
+        let title =
+            entry.title.as_deref().unwrap_or_else(|| stem.strip_suffix(".cdda").unwrap_or(&stem));
+        playlist.push_str(&format!("#EXTINF:{},{}\n", entry.duration_secs, title));
+        playlist.push_str(&format!("{}\n", entry.path.display()));
+    }
+    playlist
+}
+
+/// Run a dumping tool, killing it if `mode_opts.stall_timeout` passes without `watched` changing
+/// or `media` notices the disc being swapped out from under it
+///
+/// (If there's nothing to watch, the tool is left to run for as long as it takes.)
+fn run_watched(
+    mut cmd: Command,
+    watched: &[&Path],
+    mode_opts: &ModeOptions,
+    media: Option<&MediaGuard<'_>>,
+) -> Result<()> {
+    let name = Path::new(cmd.get_program()).display().to_string();
+    #[cfg(any(test, feature = "testing"))]
+    if let Some(err) = crate::platform::simulated_failure(cmd.get_program()) {
+        return Err(err.into());
+    }
+
+    let stall_timeout = mode_opts.stall_timeout.filter(|_| !watched.is_empty());
+    let active_media = media.filter(|guard| guard.is_active());
+    let status = if stall_timeout.is_some() || active_media.is_some() {
+        let mut child = cmd.spawn().with_context(|| format!("Could not run {}", name))?;
+        wait_with_watchdog(
+            &mut child,
+            watched,
+            stall_timeout,
+            WATCHDOG_POLL_INTERVAL,
+            active_media,
+        )?
+    } else {
+        cmd.status().with_context(|| format!("Could not run {}", name))?
+    };
+
+    // The disc may have been swapped between the last check and the tool exiting
+    if let Some(guard) = active_media {
+        guard.check()?;
+    }
+    if status.success() {
+        return Ok(());
+    }
+    Err(MediaError::SubprocessFailed { command: name, code: status.code() }.into())
+}
+
+/// Check a CUE sheet against the BIN file(s) it references and the TOC it was generated from
+///
+/// (Without a TOC, the track count can't be cross-checked, but everything else still is.)
+fn verify_bin_set(cuefile: &Path, tocfile: Option<&Path>) -> Result<Vec<String>> {
+    let cue_text = fs::read_to_string(cuefile)
+        .with_context(|| format!("Could not read {}", cuefile.display()))?;
+    let sheet = CueSheet::parse(&cue_text)
+        .with_context(|| format!("Could not parse {}", cuefile.display()))?;
+    let toc_tracks = match tocfile {
+        Some(path) => track_count(
+            &fs::read_to_string(path)
+                .with_context(|| format!("Could not read {}", path.display()))?,
+        ),
+        None => sheet.tracks.len(),
+    };
+
+    let cue_dir = cuefile.parent().unwrap_or_else(|| Path::new(""));
+    let file_lens: Vec<Option<u64>> = sheet
+        .files
+        .iter()
+        .map(|name| fs::metadata(cue_dir.join(name)).ok().map(|meta| meta.len()))
+        .collect();
+    Ok(sheet.problems(&file_lens, toc_tracks))
+}
+
+/// Name for one track of a split BIN, following Redump's `Name (Track 01).bin` convention
+///
+/// (Track numbers are only zero-padded on discs with ten or more tracks, as Redump does it.)
+fn track_file_name(stem: &str, number: u32, track_count: usize) -> String {
+    let width = if track_count >= 10 { 2 } else { 1 };
+    format!("{} (Track {:0width$}).bin", stem, number, width = width)
+}
+
+/// Name for one track's BIN in a Redump-style set for the disc named `disc_name`
+///
+/// Redump only adds the `(Track N)` suffix when there's more than one track, so a single-track
+/// disc is just `Name.bin`.
+fn redump_track_name(disc_name: &str, number: u32, track_count: usize) -> String {
+    if track_count == 1 {
+        format!("{}.bin", disc_name)
+    } else {
+        track_file_name(disc_name, number, track_count)
+    }
+}
+
+/// Split the single BIN referenced by `cuefile` into one file per track and rewrite the CUE
+///
+/// The tracks are named after the BIN, or after `redump_name` using Redump's conventions if it's
+/// given. The original BIN is only removed once the pieces have been written and their sizes add
+/// up to the original's. Returns the paths of the per-track files.
+fn split_bin(cuefile: &Path, redump_name: Option<&str>) -> Result<Vec<PathBuf>> {
+    let cue_text = fs::read_to_string(cuefile)
+        .with_context(|| format!("Could not read {}", cuefile.display()))?;
+    let sheet = CueSheet::parse(&cue_text)
+        .with_context(|| format!("Could not parse {}", cuefile.display()))?;
+    let cue_dir = cuefile.parent().unwrap_or_else(|| Path::new(""));
+    let binfile = cue_dir.join(sheet.files.first().map(String::as_str).unwrap_or_default());
+    let bin_len = fs::metadata(&binfile)
+        .with_context(|| format!("Could not read {}", binfile.display()))?
+        .len();
+    let ranges = sheet
+        .track_ranges(bin_len)
+        .with_context(|| format!("Could not split {} by track", binfile.display()))?;
+
+    let stem = binfile.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    let names: Vec<String> = sheet
+        .tracks
+        .iter()
+        .map(|track| match redump_name {
+            Some(disc_name) => redump_track_name(disc_name, track.number, sheet.tracks.len()),
+            None => track_file_name(&stem, track.number, sheet.tracks.len()),
+        })
+        .collect();
+    let tracks: Vec<(String, u64)> = names
+        .iter()
+        .cloned()
+        .zip(ranges.iter().map(|range| range.start / RAW_SECTOR_LEN))
+        .collect();
+    let new_cue = split_cue_text(&cue_text, &tracks)?;
+
+    // There's nothing to split off a single track, and copying could clobber the input if the
+    // new name is the same as the old one
+    if let [name] = names.as_slice() {
+        let path = cue_dir.join(name);
+        fs::rename(&binfile, &path).with_context(|| {
+            format!("Could not rename {} to {}", binfile.display(), path.display())
+        })?;
+        fs::write(cuefile, new_cue)
+            .with_context(|| format!("Could not write {}", cuefile.display()))?;
+        return Ok(vec![path]);
+    }
+
+    let mut input =
+        File::open(&binfile).with_context(|| format!("Could not open {}", binfile.display()))?;
+    let mut written = 0;
+    let mut paths = Vec::with_capacity(names.len());
+    for (name, range) in names.iter().zip(&ranges) {
+        let path = cue_dir.join(name);
+        let mut output =
+            File::create(&path).with_context(|| format!("Could not create {}", path.display()))?;
+        let expected = range.end - range.start;
+        let copied = io::copy(&mut (&mut input).take(expected), &mut output)
+            .with_context(|| format!("Could not write {}", path.display()))?;
+        if copied != expected {
+            bail!("{} ended early while writing {}", binfile.display(), path.display());
+        }
+        written += copied;
+        paths.push(path);
+    }
+    if written != bin_len {
+        bail!(
+            "Split tracks total {} bytes but {} is {} bytes",
+            written,
+            binfile.display(),
+            bin_len
+        );
+    }
+
+    fs::write(cuefile, new_cue)
+        .with_context(|| format!("Could not write {}", cuefile.display()))?;
+    remove_file(&binfile).with_context(|| format!("Could not remove {}", binfile.display()))?;
+    Ok(paths)
+}
+
+/// Convert cdrdao's `tocfile` into `cuefile` with `toc2cue`
+fn run_toc2cue(tocfile: &Path, cuefile: &Path) -> Result<()> {
+    Command::new("toc2cue")
+        .args([tocfile, cuefile])
+        .stdout(Stdio::null())
+        .status()
+        .and_then(|status| {
+            if status.success() {
+                return Ok(());
+            }
+            let command = "toc2cue".to_owned();
+            Err(io::Error::other(MediaError::SubprocessFailed { command, code: status.code() }))
+        })
+        .with_context(|| {
+            format!(
+                "Could not generate {} file from {}",
+                cuefile.to_string_lossy(),
+                tocfile.to_string_lossy()
+            )
+        })
+}
+
+/// Dump a disc to as raw a BIN/TOC/CUE set as possible using cdrdao.
+pub fn rip_bin<P: RawMediaProvider>(
+    provider: &P,
+    disc_name: &str,
+    keep_tocfile: bool,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    // TODO: Unit-test this
+    // TODO: Decide how to work in absolute paths
+    let volbase = PathBuf::from(disc_name);
+    let sink = &*mode_opts.sink;
+    let tocfile = sink.path(&volume_file(&volbase, "toc"));
+    let cuefile = sink.path(&volume_file(&volbase, "cue"));
+
+    // Rip it or die
+    // TODO: Verify the "or die"
+    let binfile = sink.path(&volume_file(&volbase, "bin"));
+    match provider.device_size() {
+        Ok(size) => {
+            check_free_space(bin_dump_len(size, mode_opts.subchannel.is_some()), mode_opts)?;
+        },
+        Err(e) => debug!("Could not determine disc size to check for free space: {:#}", e),
+    }
+    let driver = mode_opts.cdrdao_driver.as_deref().unwrap_or(DEFAULT_CDRDAO_DRIVER);
+    info!("Using cdrdao driver {}", driver);
+    let read_cd = |with_driver: &str| {
+        let mut cmd = Command::new("cdrdao");
+        cmd.args(["read-cd", "--read-raw", "--driver", with_driver, "--device"])
+            .arg(provider.device_path())
+            .arg("--datafile")
+            .arg(&binfile);
+        if let Some(mode) = mode_opts.subchannel {
+            cmd.args(["--read-subchan", mode.cdrdao_arg()]);
+        }
+        cmd.arg(&tocfile);
+        run_watched(cmd, &[&binfile], mode_opts, Some(&MediaGuard::new(provider)))
+    };
+    let mut result = read_cd(driver);
+    let device = provider.device_path();
+    if let Some(detected) =
+        result.as_ref().err().and_then(|e| retry_driver(e, driver, &device, mode_opts))
+    {
+        for stale in [&binfile, &tocfile] {
+            if let Err(e) = sink.remove(stale) {
+                if e.kind() != IOErrorKind::NotFound {
+                    warn!("Could not remove {}: {}", stale.display(), e);
+                }
+            }
+        }
+        result = read_cd(detected);
+    }
+    result.with_context(|| "Error while dumping BIN/TOC pair")?;
+    let subfile = sink.path(&volume_file(&volbase, "sub"));
+    let mut toc = fs::read_to_string(&tocfile)
+        .with_context(|| format!("Could not read {}", tocfile.display()))?;
+    if mode_opts.subchannel.is_some() {
+        let sectors = split_subchannel(sink, &binfile, &subfile)?;
+        info!("Moved the subchannel data for {} sectors into {}", sectors, subfile.display());
+        toc = strip_toc_subchannel(&toc);
+        sink.write(&tocfile, toc.as_bytes())
+            .with_context(|| format!("Could not write {}", tocfile.display()))?;
+    }
+    check_dump_len(&binfile, MIN_BIN_LEN)?;
+
+    // Make the audio tracks match the CUE sheet's BINARY file type
+    swap_bin_audio(sink, &binfile, &tocfile, &toc)?;
+
+    // The OS only exposes the user data of data tracks, so this is a lower bound for the BIN
+    match provider.device_size() {
+        Ok(size) => {
+            let sectors = size / DATA_SECTOR_LEN;
+            warn_if_truncated(&binfile, sectors * RAW_SECTOR_LEN);
+            if mode_opts.subchannel.is_some() {
+                warn_if_truncated(&subfile, sectors * SUBCHANNEL_LEN);
+            }
+        },
+        Err(e) => warn!("Could not determine disc size to check the dump: {:#}", e),
+    }
+
+    // Generate a .CUE file
+    // TODO: Find a way to detect if an ISO would be equivalent
+    run_toc2cue(&tocfile, &cuefile)?;
+    let cue = fs::read_to_string(&cuefile)
+        .with_context(|| format!("Could not read {}", cuefile.display()))?;
+    sink.write(&cuefile, quote_file_names(&cue)?.as_bytes())
+        .with_context(|| format!("Could not write {}", cuefile.display()))?;
+
+    if mode_opts.split_tracks || mode_opts.redump_naming {
+        let tracks = split_bin(&cuefile, mode_opts.redump_naming.then_some(disc_name))?;
+        info!("Split {} into {} track files", binfile.display(), tracks.len());
+    }
+
+    if mode_opts.verify_toc {
+        let problems = verify_bin_set(&cuefile, Some(&tocfile))?;
+        for problem in &problems {
+            warn!("{}: {}", cuefile.display(), problem);
+        }
+        if mode_opts.strict && !problems.is_empty() {
+            bail!("{} failed consistency checks", cuefile.display());
+        }
+    }
+
+    // TODO: Audit when I want to die and when I want to keep going
+    if !keep_tocfile && !mode_opts.redump_naming {
+        sink.remove(&tocfile)
+            .with_context(|| format!("Could not remove {}", tocfile.to_string_lossy()))?;
+    }
+
+    // Show the final CUE sheet, so it's obvious which tracks are audio
+    let sheet = fs::read_to_string(&cuefile)
+        .with_context(|| format!("Could not read {}", cuefile.display()))?;
+    info!("{}:\n{}", cuefile.display(), mark_audio_tracks(&sheet).trim_end());
+    Ok(())
+}
+
+/// Prefix the lines of a CUE sheet's text with `*` for `TRACK ... AUDIO` lines and a space for
+/// everything else, so audio tracks stand out when it's shown to the user
+fn mark_audio_tracks(cue: &str) -> String {
+    let mut marked = String::new();
+    for line in cue.lines() {
+        let mut words = line.split_whitespace();
+        let is_audio = words.next().is_some_and(|word| word.eq_ignore_ascii_case("TRACK"))
+            && words.nth(1).is_some_and(|mode| mode.eq_ignore_ascii_case("AUDIO"));
+        marked.push_str(if is_audio { "* " } else { "  " });
+        marked.push_str(line);
+        marked.push('\n');
+    }
+    marked
+}
+
+/// Bytes of subchannel data cdrdao appends to each sector with `--read-subchan`
+const SUBCHANNEL_LEN: u64 = 96;
+
+/// Move the subchannel data `cdrdao --read-subchan` interleaved into `binfile` out into
+/// `subfile`, leaving a plain BIN behind, and return how many sectors there were
+fn split_subchannel(sink: &dyn OutputSink, binfile: &Path, subfile: &Path) -> Result<u64> {
+    let sector_len = RAW_SECTOR_LEN + SUBCHANNEL_LEN;
+    let input =
+        File::open(binfile).with_context(|| format!("Could not open {}", binfile.display()))?;
+    let len =
+        input.metadata().with_context(|| format!("Could not stat {}", binfile.display()))?.len();
+    if len % sector_len != 0 {
+        bail!(
+            "{} is {} bytes, which isn't a whole number of {}-byte sectors with subchannel data",
+            binfile.display(),
+            len,
+            sector_len
+        );
+    }
+
+    let mut tmp_name = binfile.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    let create = |path: &Path| {
+        sink.create(path).with_context(|| format!("Could not create {}", path.display()))
+    };
+    let (mut main_out, mut sub_out) = (create(&tmp_path)?, create(subfile)?);
+    let mut reader = io::BufReader::new(input);
+    for _ in 0..len / sector_len {
+        for (out, part_len) in [(&mut main_out, RAW_SECTOR_LEN), (&mut sub_out, SUBCHANNEL_LEN)] {
+            let copied = io::copy(&mut (&mut reader).take(part_len), out)
+                .with_context(|| format!("Could not split {}", binfile.display()))?;
+            if copied != part_len {
+                bail!("{} was truncated while splitting it", binfile.display());
+            }
+        }
+    }
+    main_out.flush().with_context(|| format!("Could not write {}", tmp_path.display()))?;
+    sub_out.flush().with_context(|| format!("Could not write {}", subfile.display()))?;
+    sink.finalize(&tmp_path, binfile)
+        .with_context(|| format!("Could not replace {}", binfile.display()))?;
+    Ok(len / sector_len)
+}
+
+/// How many bytes of a BIN to byte-swap at a time
+const SWAP_CHUNK_LEN: u64 = 64 * 1024;
+
+/// Byte-swap the samples of the audio tracks `toc` lays out in `binfile` (in place, via the
+/// sink), returning how many bytes were swapped
+///
+/// `cdrdao` dumps audio big-endian, but the `BINARY` files of a CUE sheet are little-endian (as
+/// Redump expects them).
+fn swap_audio_tracks(sink: &dyn OutputSink, binfile: &Path, toc: &str) -> Result<u64> {
+    let ranges = audio_file_ranges(toc);
+    if ranges.is_empty() {
+        return Ok(0);
+    }
+    let input =
+        File::open(binfile).with_context(|| format!("Could not open {}", binfile.display()))?;
+    let mut tmp_name = binfile.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    let mut output = sink
+        .create(&tmp_path)
+        .with_context(|| format!("Could not create {}", tmp_path.display()))?;
+    let write_err = || format!("Could not write {}", tmp_path.display());
+
+    let mut reader = io::BufReader::new(input);
+    let (mut position, mut swapped, mut buf) = (0, 0, Vec::new());
+    'ranges: for range in &ranges {
+        let verbatim = range.start.saturating_sub(position);
+        let copied =
+            io::copy(&mut (&mut reader).take(verbatim), &mut output).with_context(write_err)?;
+        position += copied;
+        if copied < verbatim {
+            break;
+        }
+        while position < range.end {
+            buf.clear();
+            let read = (&mut reader)
+                .take(SWAP_CHUNK_LEN.min(range.end - position))
+                .read_to_end(&mut buf)
+                .with_context(|| format!("Could not read {}", binfile.display()))?;
+            if read == 0 {
+                break 'ranges;
+            }
+            for sample in buf.chunks_exact_mut(2) {
+                sample.swap(0, 1);
+            }
+            output.write_all(&buf).with_context(write_err)?;
+            let len = u64::try_from(read).unwrap_or(u64::MAX);
+            position += len;
+            swapped += len;
+        }
+    }
+    io::copy(&mut reader, &mut output).with_context(write_err)?;
+    output.flush().with_context(write_err)?;
+    sink.finalize(&tmp_path, binfile)
+        .with_context(|| format!("Could not replace {}", binfile.display()))?;
+    info!("Byte-swapped {} of audio in {}", format_size(swapped), binfile.display());
+    Ok(swapped)
+}
+
+/// Byte-swap the audio tracks of a BIN dump (see `swap_audio_tracks`) and mark them `SWAP` in
+/// its TOC file, so cdrdao still reads the samples the right way round if the TOC is kept
+fn swap_bin_audio(sink: &dyn OutputSink, binfile: &Path, tocfile: &Path, toc: &str) -> Result<()> {
+    if swap_audio_tracks(sink, binfile, toc)? > 0 {
+        sink.write(tocfile, mark_swapped_audio(toc).as_bytes())
+            .with_context(|| format!("Could not write {}", tocfile.display()))?;
+    }
+    Ok(())
+}
+
+/// Remove the subchannel modes from the `TRACK` statements of a TOC file, to match a BIN whose
+/// subchannel data has been moved out by `split_subchannel`
+fn strip_toc_subchannel(toc: &str) -> String {
+    toc.lines()
+        .map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                ["TRACK", mode, "RW" | "RW_RAW"] => format!("TRACK {}\n", mode),
+                _ => format!("{}\n", line),
+            }
+        })
+        .collect()
+}
+
+/// Render a GDI index (as used by Dreamcast emulators) for a set of per-track BIN files
+///
+/// `tracks` holds the filename, starting sector, and whether it's an audio track for each track.
+fn format_gdi(tracks: &[(String, u64, bool)]) -> String {
+    let mut gdi = format!("{}\n", tracks.len());
+    for (number, (name, start, is_audio)) in (1..).zip(tracks) {
+        // (Track type is 0 for audio and 4 for data, and all of ours are raw 2352-byte sectors)
+        let kind = if *is_audio { 0 } else { 4 };
+        gdi.push_str(&format!("{} {} {} {} \"{}\" 0\n", number, start, kind, RAW_SECTOR_LEN, name));
+    }
+    gdi
+}
+
+/// Write a GDI index for the split BIN/CUE set at `cuefile`, using the track modes in `tocfile`
+fn write_gdi(sink: &dyn OutputSink, cuefile: &Path, tocfile: &Path, gdifile: &Path) -> Result<()> {
+    let sheet = CueSheet::parse(
+        &fs::read_to_string(cuefile)
+            .with_context(|| format!("Could not read {}", cuefile.display()))?,
+    )
+    .with_context(|| format!("Could not parse {}", cuefile.display()))?;
+    let toc_tracks = TrackList::parse(
+        &fs::read_to_string(tocfile)
+            .with_context(|| format!("Could not read {}", tocfile.display()))?,
+    )
+    .tracks;
+    if sheet.files.len() != sheet.tracks.len() || toc_tracks.len() != sheet.tracks.len() {
+        bail!(
+            "Expected one file per track, but found {} files, {} CUE tracks, and {} TOC tracks",
+            sheet.files.len(),
+            sheet.tracks.len(),
+            toc_tracks.len()
+        );
+    }
+
+    // The split files are contiguous, so each track starts where the previous one ended
+    let cue_dir = cuefile.parent().unwrap_or_else(|| Path::new(""));
+    let mut start = 0;
+    let mut tracks = Vec::with_capacity(sheet.files.len());
+    for (name, toc_track) in sheet.files.iter().zip(&toc_tracks) {
+        let len = fs::metadata(cue_dir.join(name))
+            .with_context(|| format!("Could not read {}", name))?
+            .len();
+        tracks.push((name.clone(), start, toc_track.is_audio()));
+        start += len / RAW_SECTOR_LEN;
+    }
+    sink.write(gdifile, format_gdi(&tracks).as_bytes())
+        .with_context(|| format!("Could not write {}", gdifile.display()))
+}
+
+/// Set an ISO's modification time to the creation date recorded in its volume descriptor
+///
+/// (The volume descriptor is read in blocks of `read_buffer` bytes, as for the disc itself.)
+fn stamp_iso_mtime(isofile: &Path, read_buffer: usize) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(isofile)
+        .with_context(|| format!("Could not open {}", isofile.display()))?;
+    let dates = read_volume_dates(&mut SectorReader::with_block_size(&mut file, read_buffer))?;
+    if let Some(created) = dates.created {
+        file.set_modified(created.to_system_time())
+            .with_context(|| format!("Could not set modification time of {}", isofile.display()))?;
+    }
+    Ok(())
+}
+
+/// Read the dates in the disc's ISO9660 volume descriptor (`None` for discs without one, like
+/// audio CDs, or which leave them all unspecified)
+fn disc_dates<P: RawMediaProvider>(provider: &P) -> Option<VolumeDates> {
+    provider
+        .open_buffered()
+        .and_then(|mut dev| read_volume_dates(&mut dev))
+        .map_err(|e| debug!("Could not read the volume dates: {:#}", e))
+        .ok()
+        .filter(|dates| !dates.is_empty())
+}
+
+/// Record the disc's volume dates in the log for cataloging purposes
+fn log_volume_dates(dates: &VolumeDates) {
+    for (label, date) in [
+        ("created", dates.created),
+        ("modified", dates.modified),
+        ("expires", dates.expires),
+        ("effective", dates.effective),
+    ] {
+        if let Some(when) = date {
+            info!("Volume {}: {}", label, when);
+        }
+    }
+}
+
+/// Post-process a completed ISO dump
+///
+/// Warns if it's smaller than the disc it was dumped from and, with `stamp_mtime`, carries the
+/// disc's creation date over to the file's modification time for provenance.
+fn finish_iso<P: RawMediaProvider>(provider: &P, isofile: &Path, stamp_mtime: bool) {
+    match provider.device_size() {
+        Ok(size) => warn_if_truncated(isofile, size),
+        Err(e) => warn!("Could not determine disc size to check the dump: {:#}", e),
+    }
+    if !stamp_mtime {
+        return;
+    }
+    if let Err(e) = stamp_iso_mtime(isofile, provider.read_buffer()) {
+        warn!("Could not apply volume dates to {}: {:#}", isofile.display(), e);
+    }
+}
+
+/// Dump a disc to an ISO using ddrescue
+///
+/// The second, slower pass to retry unreadable areas is skipped if the first pass's mapfile
+/// shows everything was recovered.
+///
+/// With `hash_while_reading`, the first pass is restricted to a single front-to-back sweep (so
+/// the image can be hashed as it's written) and all trimming and scraping of bad areas is left
+/// to the second pass. Since that pass rewrites parts of the image, the streamed hash is thrown
+/// away and the image re-read if it runs, so only clean discs avoid the extra read.
+pub fn rip_iso<P: RawMediaProvider>(
+    provider: &P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    // TODO: Deduplicate this with rip_bin
+    let mut volbase = PathBuf::from(disc_name);
+    let mut range_args = Vec::new();
+    let partial = match mode_opts.range {
+        Some(range) => {
+            let (name, args, len) = partial_dump(provider, &volbase, range)?;
+            (volbase, range_args) = (name, args);
+            Some(len)
+        },
+        None => None,
+    };
+    match partial.map_or_else(|| provider.device_size(), Ok) {
+        Ok(len) => check_free_space(len, mode_opts)?,
+        Err(e) => debug!("Could not determine disc size to check for free space: {:#}", e),
+    }
+    let sink = &*mode_opts.sink;
+    let isofile = sink.path(&volume_file(&volbase, "iso"));
+    let logfile = sink.path(&volume_file(&volbase, "log"));
+
+    let mut cmd = Command::new("ddrescue");
+    cmd.args(["-b", "2048"]).args(&range_args);
+    if mode_opts.hash_while_reading {
+        cmd.args(["--cpass=1", "--no-trim", "--no-scrape"]);
+    }
+    cmd.arg(provider.device_path()).arg(&isofile).arg(&logfile);
+
+    // Created up front so a disc swapped between passes is caught too
+    let media = MediaGuard::new(provider);
+    let mut streamed_hashes = None;
+    if can_stream_hash(&logfile, mode_opts) {
+        let dump = || run_watched(cmd, &[&isofile, &logfile], mode_opts, Some(&media));
+        streamed_hashes = Some(
+            hash_while_writing(&isofile, dump)
+                .with_context(|| "Initial ddrescue run reported failure")?,
+        );
+    } else {
+        run_watched(cmd, &[&isofile, &logfile], mode_opts, Some(&media))
+            .with_context(|| "Initial ddrescue run reported failure")?;
+    }
+
+    let needs_second_pass = if mode_opts.second_pass {
+        match fs::read_to_string(&logfile)
+            .with_context(|| format!("Could not read {}", logfile.to_string_lossy()))
+            .and_then(|text| Mapfile::parse(&text))
+        {
+            Ok(map) if map.is_complete() => {
+                info!("First ddrescue pass recovered everything. Skipping second pass.");
+                false
+            },
+            Ok(_) => true,
+            Err(e) => {
+                warn!("Could not check ddrescue mapfile, so running second pass: {:#}", e);
+                true
+            },
+        }
+    } else {
+        info!("Skipping second ddrescue pass as requested");
+        false
+    };
+
+    if needs_second_pass {
+        // (Nothing may get written for a while when retrying a bad patch, so the mapfile's
+        //  periodic saves are what keep the watchdog from firing)
+        let mut retry_cmd = Command::new("ddrescue");
+        retry_cmd
+            .args(["--direct", "-M", "-b", "2048"])
+            .args(&range_args)
+            .arg(provider.device_path())
+            .arg(&isofile)
+            .arg(&logfile);
+        run_watched(retry_cmd, &[&isofile, &logfile], mode_opts, Some(&media))
+            .with_context(|| "Second ddrescue pass reported failure")?;
+        streamed_hashes = None; // Stale now that the retries have filled in the gaps
+    }
+    if let Some(len) = partial {
+        // A slice of a disc needn't contain a whole filesystem
+        check_dump_len(&isofile, DATA_SECTOR_LEN)?;
+        warn_if_truncated(&isofile, len);
+    } else {
+        check_dump_len(&isofile, MIN_ISO_LEN)?;
+        finish_iso(provider, &isofile, mode_opts.stamp_mtime);
+    }
+    if mode_opts.hash_while_reading {
+        write_sha256_file(sink, &isofile, streamed_hashes)?;
+    }
+    if let Some(map_path) = &mode_opts.error_map {
+        // Diagnostics mustn't turn a good rip into a failed one
+        if let Err(e) = write_error_map(sink, &logfile, &sink.path(map_path)) {
+            warn!("{:#}", e);
+        }
+    }
+    report_mapfile(sink, &logfile, mode_opts.keep_log);
+    // TODO: Compare ddrescue to the reading modes of dvdiaster for recovering
+    //       non-ECC-agumented discs.
+    Ok(())
+}
+
+/// Whether an ISO dump can be hashed as ddrescue writes it (`--hash-while-reading`), given the
+/// mapfile it will use
+///
+/// If the mapfile already exists, ddrescue is resuming an earlier attempt and will go back to
+/// fill in holes the hashing would already have read past (as zeros), so the image has to be
+/// hashed once it's finished instead.
+fn can_stream_hash(logfile: &Path, mode_opts: &ModeOptions) -> bool {
+    if !mode_opts.hash_while_reading {
+        return false;
+    }
+    if logfile.exists() {
+        info!("Resuming from {}, so hashing the image once it's done", logfile.display());
+        return false;
+    }
+    true
+}
+
+/// Work out how to dump only `range` of the disc, returning the name to give the partial dump
+/// (so it can't be mistaken for a full one), the extra ddrescue arguments, and the expected
+/// length in bytes
+fn partial_dump<P: RawMediaProvider>(
+    provider: &P,
+    volbase: &Path,
+    range: SectorRange,
+) -> Result<(PathBuf, Vec<String>, u64)> {
+    let device_size =
+        provider.device_size().context("Could not determine the disc size to check the range")?;
+    let (start, len) = range.to_bytes(DATA_SECTOR_LEN, device_size)?;
+    let first = start / DATA_SECTOR_LEN;
+    let last = (start + len).div_ceil(DATA_SECTOR_LEN) - 1;
+    info!("Dumping only sectors {} to {} of {}", first, last, device_size / DATA_SECTOR_LEN);
+
+    let mut name = volbase.as_os_str().to_owned();
+    name.push(format!("_sectors_{}-{}", first, last));
+    // (ddrescue would otherwise write at the same offset in the output as in the input)
+    let args = vec![format!("-i{}", start), "-o0".to_owned(), format!("-s{}", len)];
+    Ok((PathBuf::from(name), args, len))
+}
+
+/// Width (and maximum height) in pixels of `--error-map` images
+const ERROR_MAP_SIZE: u64 = 256;
+
+/// Convert ddrescue's mapfile into an error map at `map_path` (a PGM image if its extension is
+/// `.pgm`, otherwise CSV)
+fn write_error_map(sink: &dyn OutputSink, logfile: &Path, map_path: &Path) -> Result<()> {
+    let map = fs::read_to_string(logfile)
+        .with_context(|| format!("Could not read {}", logfile.display()))
+        .and_then(|text| Mapfile::parse(&text))
+        .context("Could not build error map")?;
+    let is_pgm = map_path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pgm"));
+    let contents = if is_pgm {
+        map.error_map_pgm(DATA_SECTOR_LEN, ERROR_MAP_SIZE, ERROR_MAP_SIZE)
+    } else {
+        map.error_map_csv(DATA_SECTOR_LEN).into_bytes()
+    };
+    sink.write(map_path, &contents)
+        .with_context(|| format!("Could not write {}", map_path.display()))
+}
+
+/// Log a summary of how much of the disc ddrescue recovered, or delete its mapfile if unwanted
+fn report_mapfile(sink: &dyn OutputSink, logfile: &Path, keep_log: bool) {
+    if !keep_log {
+        if let Err(e) = sink.remove(logfile) {
+            warn!("Could not remove {}: {}", logfile.display(), e);
+        }
+        return;
+    }
+
+    match fs::read_to_string(logfile)
+        .with_context(|| format!("Could not read {}", logfile.display()))
+        .and_then(|text| Mapfile::parse(&text))
+    {
+        Ok(map) => {
+            let summary = map.summary();
+            info!("ddrescue summary ({}):\n{}", logfile.display(), summary);
+            if summary.error_regions > 0 {
+                warn!("{} area(s) of the disc could not be recovered", summary.error_regions);
+            }
+        },
+        Err(e) => warn!("Could not summarize ddrescue mapfile: {:#}", e),
+    }
+}
+
+/// Build the command to losslessly compress a WAV file to FLAC at the given compression level
+fn flac_command(compression: u8, tags: &[String], path: &Path) -> Command {
+    let mut cmd = Command::new("flac");
+    cmd.arg(format!("-{}", compression));
+    for tag in tags {
+        cmd.arg("-T").arg(tag);
+    }
+    cmd.arg(path);
+    cmd
+}
+
+/// How cdparanoia should trade accuracy for the ability to get past damage
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ParanoiaPolicy {
+    /// Turn off all of cdparanoia's verification and error correction (`--disable-paranoia`)
+    pub disable_paranoia: bool,
+    /// Verify reads, but skip the extra overlap checks (`--disable-extra-paranoia`)
+    pub disable_extra_paranoia: bool,
+    /// Accept skipped, unrecoverable samples rather than aborting the rip
+    pub allow_skip: bool,
+}
+
+impl ParanoiaPolicy {
+    /// The cdparanoia arguments which implement this policy
+    fn args(self) -> Vec<&'static str> {
+        let mut args = Vec::new();
+        if self.disable_paranoia {
+            args.push("--disable-paranoia");
+        } else if self.disable_extra_paranoia {
+            args.push("--disable-extra-paranoia");
+        }
+        if !self.allow_skip {
+            args.push("--abort-on-skip");
+        }
+        args
+    }
+}
+
+/// cdparanoia options which would fight with the `-B -d <device> --sample-offset` we pass or
+/// the WAV files we expect to find afterward
+const RESERVED_CDPARANOIA_OPTS: &[&str] = &[
+    "--batch",
+    "--force-cdrom-device",
+    "--sample-offset",
+    "--output-raw",
+    "--output-raw-little-endian",
+    "--output-raw-big-endian",
+    "--output-aiff",
+    "--output-aifc",
+];
+
+/// Short forms of `RESERVED_CDPARANOIA_OPTS`
+const RESERVED_CDPARANOIA_SHORT_OPTS: &str = "BdOprRfa";
+
+/// Short cdparanoia options which take a value (so the rest of a cluster is that value)
+const CDPARANOIA_SHORT_OPTS_WITH_VALUES: &str = "nodkgStOlz";
+
+/// Check a `--cdparanoia-args` argument for options which would interfere with the rip
+pub fn check_cdparanoia_arg(arg: &str) -> Result<String, String> {
+    if let Some(long) = arg.strip_prefix("--") {
+        let name = long.split('=').next().unwrap_or_default();
+        if RESERVED_CDPARANOIA_OPTS.iter().any(|reserved| reserved[2..] == *name) {
+            return Err(format!("--{} is controlled by rip_media and can't be overridden", name));
+        }
+    } else if let Some(cluster) = arg.strip_prefix('-') {
+        for opt in cluster.chars() {
+            if RESERVED_CDPARANOIA_SHORT_OPTS.contains(opt) {
+                return Err(format!("-{} is controlled by rip_media and can't be overridden", opt));
+            }
+            if CDPARANOIA_SHORT_OPTS_WITH_VALUES.contains(opt) {
+                break;
+            }
+        }
+    }
+    Ok(arg.to_owned())
+}
+
+/// Decide which read offset to correct for when ripping audio from `provider`'s drive
+///
+/// An explicit `--read-offset` wins. Otherwise, it's looked up from the drive's model, falling
+/// back to 0 (with a warning, since that won't match AccurateRip for most drives).
+fn read_offset<P: RawMediaProvider>(provider: &P, mode_opts: &ModeOptions) -> i32 {
+    if let Some(offset) = mode_opts.read_offset {
+        return offset;
+    }
+    match provider.drive_info() {
+        Ok(Some(drive)) => known_read_offset(&drive).unwrap_or_else(|| {
+            warn!(
+                "Unknown read offset for {} {}. Assuming 0, so tracks may not match AccurateRip. \
+                 Use --read-offset to set it.",
+                drive.vendor, drive.model
+            );
+            0
+        }),
+        Ok(None) => 0,
+        Err(e) => {
+            warn!("Could not identify the drive to look up its read offset. Assuming 0: {:#}", e);
+            0
+        },
+    }
+}
+
+/// Build the command to extract audio tracks (all of them, or `span`) into WAV files, shifted
+/// by `offset` samples to correct for the drive's read offset
+fn cdparanoia_command(
+    device: &OsStr,
+    mode_opts: &ModeOptions,
+    offset: i32,
+    span: Option<&str>,
+) -> Command {
+    let mut cmd = Command::new("cdparanoia");
+    cmd.args(mode_opts.paranoia.args())
+        .args(mode_opts.cdparanoia_args.iter().filter(|arg| !arg.is_empty()))
+        .arg("-B")
+        .arg("-d")
+        .arg(device);
+    if offset != 0 {
+        cmd.arg(format!("--sample-offset={}", offset));
+    }
+    cmd.args(span);
+    cmd
+}
+
+/// Get the track number from the name of a file written by `cdparanoia -B`
+fn cdparanoia_track_number(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?;
+    let digits: String =
+        name.strip_prefix("track")?.chars().take_while(char::is_ascii_digit).collect();
+    digits.parse().ok()
+}
+
+/// Parse a list of track numbers and ranges like `3-5,8` (as accepted by `--tracks`)
+pub fn parse_track_list(spec: &str) -> Result<BTreeSet<usize>, String> {
+    parse_number_list(spec, "track")
+}
+
+/// Parse a list of changer slots and ranges like `1-4,6` (as accepted by `--slots`)
+pub fn parse_slot_list(spec: &str) -> Result<BTreeSet<usize>, String> {
+    parse_number_list(spec, "slot")
+}
+
+/// Parse a list of `noun` numbers (counting from 1) and ranges like `3-5,8`
+fn parse_number_list(spec: &str, noun: &str) -> Result<BTreeSet<usize>, String> {
+    let parse_num = |num: &str| match num.trim().parse::<usize>() {
+        Ok(0) | Err(_) => Err(format!("Not a valid {} number: {:?}", noun, num.trim())),
+        Ok(parsed) => Ok(parsed),
+    };
+
+    let mut tracks = BTreeSet::new();
+    for item in spec.split(',') {
+        let (first, last) = match item.split_once('-') {
+            Some((start, end)) => (parse_num(start)?, parse_num(end)?),
+            None => (parse_num(item)?, parse_num(item)?),
+        };
+        if first > last {
+            return Err(format!("Range of {}s is backwards: {}", noun, item.trim()));
+        }
+        tracks.extend(first..=last);
+    }
+    Ok(tracks)
+}
+
+/// Convert a set of track numbers into the fewest cdparanoia span arguments (eg. `3-5`)
+fn cdparanoia_spans(tracks: &BTreeSet<usize>) -> Vec<String> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &track in tracks {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == track => *last = track,
+            _ => ranges.push((track, track)),
+        }
+    }
+    ranges
+        .into_iter()
+        .map(
+            |(first, last)| {
+                if first == last {
+                    first.to_string()
+                } else {
+                    format!("{}-{}", first, last)
+                }
+            },
+        )
+        .collect()
+}
+
+/// Read the table of contents (including CD-TEXT, MCN, and ISRCs) from a disc using cdrdao
+fn read_toc<P: RawMediaProvider>(provider: &P, disc_name: &str) -> Result<String> {
+    read_toc_via(provider, &PathBuf::from(format!("{}.cdtext.toc", disc_name)))
+}
+
+/// Read the disc's table of contents using `tocfile` as a scratch file
+fn read_toc_via<P: RawMediaProvider>(provider: &P, tocfile: &Path) -> Result<String> {
+    subprocess_call!("cdrdao", "read-toc", "--device", provider.device_path(), tocfile)
+        .with_context(|| "Could not read the table of contents")?;
+    let toc = fs::read_to_string(tocfile)
+        .with_context(|| format!("Could not read {}", tocfile.to_string_lossy()));
+    remove_file(tocfile)
+        .with_context(|| format!("Could not remove {}", tocfile.to_string_lossy()))?;
+    toc
+}
+
+/// Rip an audio CD using cdparanoia
+pub fn rip_audio<P: RawMediaProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    // TODO: Use whipper instead, since it does everything we want already
+    //       https://github.com/JoeLametta/whipper
+    // TODO: Fall back to MusicBrainz when there's no CD-TEXT
+    let toc = read_toc(provider, disc_name).unwrap_or_else(|e| {
+        warn!("Could not read the table of contents: {:#}", e);
+        String::new()
+    });
+    let tags = TrackTags::from_toc(&toc, mode_opts.cd_text);
+
+    if let Some(tracks) = &mode_opts.tracks {
+        // Batch mode names files after the disc's own track numbers, so partial rips
+        // can't be mistaken for (or collide with) the start of the album
+        let disc_tracks = track_count(&toc);
+        if disc_tracks == 0 {
+            bail!("Cannot rip selected tracks without the disc's table of contents");
+        }
+        if let Some(bad) = tracks.iter().find(|&&track| track > disc_tracks) {
+            bail!("Track {} requested, but the disc only has {} tracks", bad, disc_tracks);
+        }
+    }
+
+    let offset = read_offset(provider, mode_opts);
+    if offset != 0 {
+        info!("Correcting for a read offset of {:+} samples", offset);
+    }
+    let device = provider.device_path();
+    let extract = |span: Option<&str>| {
+        run_watched(cdparanoia_command(&device, mode_opts, offset, span), &[], mode_opts, None)
+    };
+
+    let mut encoder = TrackEncoder {
+        tags: &tags,
+        mode_opts,
+        playlist_entries: Vec::new(),
+        failed_tracks: Vec::new(),
+    };
+    if mode_opts.encode_as_ripped {
+        // One track at a time, so only one track's WAV is ever on disk
+        for track in tracks_to_stream(&toc, mode_opts.tracks.as_ref())? {
+            extract(Some(&track.to_string()))
+                .with_context(|| format!("Failed to extract track {} properly", track))?;
+            let wav_name = format!("track{:02}.cdda.wav", track);
+            encoder.encode(&mode_opts.sink.path(Path::new(&wav_name)))?;
+        }
+    } else {
+        match &mode_opts.tracks {
+            None => extract(None).with_context(|| "Failed to extract CD audio properly")?,
+            Some(tracks) => {
+                for span in cdparanoia_spans(tracks) {
+                    extract(Some(&span))
+                        .with_context(|| format!("Failed to extract tracks {} properly", span))?;
+                }
+            },
+        }
+
+        // Listing order is arbitrary and alphabetical order would put track10 before track2
+        let mut wavs: Vec<PathBuf> = mode_opts
+            .sink
+            .list()
+            .context("Could not list ripped tracks")?
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wav")))
+            .collect();
+        sort_paths_naturally(&mut wavs);
+        for wav in &wavs {
+            encoder.encode(wav)?;
+        }
+    }
+
+    let TrackEncoder { mut playlist_entries, failed_tracks, .. } = encoder;
+    if playlist_entries.is_empty() && failed_tracks.is_empty() {
+        bail!("cdparanoia reported success, but no tracks were ripped");
+    }
+    if mode_opts.playlist {
+        let playlist_path = mode_opts.sink.path(&volume_file(Path::new(disc_name), "m3u8"));
+        mode_opts
+            .sink
+            .write(&playlist_path, format_m3u(&mut playlist_entries).as_bytes())
+            .with_context(|| format!("Could not write {}", playlist_path.to_string_lossy()))?;
+    }
+    track_report(playlist_entries.len(), &failed_tracks)
+}
+
+/// The tracks to extract one by one for `--encode-as-ripped`: the `selected` ones, or every
+/// audio track on the disc
+fn tracks_to_stream(toc: &str, selected: Option<&BTreeSet<usize>>) -> Result<Vec<usize>> {
+    if let Some(tracks) = selected {
+        return Ok(tracks.iter().copied().collect());
+    }
+    let tracks: Vec<usize> = TrackList::parse(toc)
+        .tracks
+        .iter()
+        .filter(|track| track.is_audio())
+        .map(|track| track.number)
+        .collect();
+    if tracks.is_empty() {
+        bail!("Cannot encode tracks as they're ripped without the disc's table of contents");
+    }
+    Ok(tracks)
+}
+
+/// Encodes ripped WAV files one at a time, keeping track of how it went for the playlist and
+/// the final report
+struct TrackEncoder<'enc> {
+    /// CD-TEXT and ISRCs to tag tracks with
+    tags: &'enc TrackTags,
+    /// Options for the rip as a whole
+    mode_opts: &'enc ModeOptions,
+    /// Tracks which were encoded successfully
+    playlist_entries: Vec<PlaylistEntry>,
+    /// WAV files which failed to encode (with `--keep-going`)
+    failed_tracks: Vec<String>,
+}
+
+impl TrackEncoder<'_> {
+    /// Encode the WAV at `path` to FLAC, only failing if the rip should stop because of it
+    fn encode(&mut self, path: &Path) -> Result<()> {
+        // TODO: The following should be async-dispatched in the background
+        // TODO: Add support for metadata retrieval and optional gain normalization
+        let wav_len = fs::metadata(path).map_or(0, |meta| meta.len());
+        let track_num = cdparanoia_track_number(path);
+        let tags = track_num.map(|num| self.tags.flac_tags(num)).unwrap_or_default();
+        if let Err(e) = encode_track(path, &tags, self.mode_opts) {
+            if !self.mode_opts.keep_going {
+                return Err(e).with_context(|| {
+                    format!(
+                        "Stopped after encoding {} tracks (use --keep-going to encode the rest \
+                         anyway)",
+                        self.playlist_entries.len()
+                    )
+                });
+            }
+            warn!("{:#}", e);
+            self.failed_tracks.push(path.to_string_lossy().into_owned());
+            return Ok(());
+        }
+        self.playlist_entries.push(PlaylistEntry {
+            path: path.with_extension("flac"),
+            duration_secs: wav_len.saturating_sub(WAV_HEADER_LEN) / CDDA_BYTES_PER_SEC,
+            title: track_num.and_then(|num| self.tags.title(num)).map(str::to_owned),
+        });
+        Ok(())
+    }
+}
+
+/// Encode a ripped WAV file to FLAC, removing the WAV once it's safely encoded
+///
+/// (On failure, the WAV is left in place so the track can be encoded by hand.)
+fn encode_track(path: &Path, tags: &[String], mode_opts: &ModeOptions) -> Result<()> {
+    check_dump_len(path, MIN_WAV_LEN)?;
+    let flac_path = path.with_extension("flac");
+    run_watched(
+        flac_command(mode_opts.flac_compression, tags, path),
+        &[&flac_path],
+        mode_opts,
+        None,
+    )
+    .with_context(|| {
+        format!("Could not encode dumped WAV file to FLAC: {}", path.to_string_lossy())
+    })?;
+    mode_opts.sink.remove(path).or_else(|e|
+        // FIXME: What was the rationale for the following?
+        if e.kind() == IOErrorKind::NotFound { Err(e) } else { Ok(()) })
+        .with_context(|| format!("Could not remove {}", path.to_string_lossy()))
+}
+
+/// Summarize the outcome of encoding an album's tracks, failing if any track failed
+fn track_report(succeeded: usize, failed: &[String]) -> Result<()> {
+    if failed.is_empty() {
+        info!("Encoded {} tracks", succeeded);
+        return Ok(());
+    }
+    bail!(
+        "{} of {} tracks failed to encode (their WAV files were kept): {}",
+        failed.len(),
+        succeeded + failed.len(),
+        failed.join(", ")
+    )
+}
+
+/// Extensions of the files which make up a disc image, for the purposes of Redump submissions
+const IMAGE_EXTENSIONS: &[&str] = &["bin", "cue", "iso"];
+
+/// Fields of Redump's submission form which have to be read off the disc's data side by eye
+const RING_CODE_FIELDS: &[&str] = &[
+    "Mastering Code (laser branded/etched)",
+    "Mastering SID Code",
+    "Toolstamp or Mastering Code (engraved/stamped)",
+    "Mould SID Code",
+    "Additional Mould",
+];
+
+/// Hash the image files in `sink` and prompt for ring codes, then write it all to
+/// `<disc_name>.redump.txt` in a form that can be pasted into a Redump submission
+fn write_redump_info<P: NotificationProvider>(
+    provider: &P,
+    sink: &dyn OutputSink,
+    disc_name: &str,
+) -> Result<()> {
+    let mut images: Vec<PathBuf> = sink
+        .list()
+        .context("Could not list dumped files")?
+        .into_iter()
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        })
+        .collect();
+    sort_paths_naturally(&mut images);
+
+    let mut roms = Vec::new();
+    for path in &images {
+        info!("Hashing {}...", path.display());
+        let file =
+            fs::File::open(path).with_context(|| format!("Could not open {}", path.display()))?;
+        let hashes =
+            hash_reader(file).with_context(|| format!("Could not read {}", path.display()))?;
+        let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+        roms.push(dat_rom_entry(&name, &hashes));
+    }
+
+    let mut report = String::from("Ringcode information:\n");
+    for field in RING_CODE_FIELDS {
+        // Leave the ring codes blank to be filled in by hand if there's nobody to ask
+        let value = if provider.is_interactive() {
+            provider.read_line(&format!("{}: ", field))?
+        } else {
+            String::new()
+        };
+        report.push_str(&format!("  {}: {}\n", field, value.trim()));
+    }
+    report.push_str("\nDAT:\n");
+    for rom in roms {
+        report.push_str(&rom);
+        report.push('\n');
+    }
+
+    let report_path = sink.path(Path::new(&format!("{}.redump.txt", disc_name)));
+    sink.write(&report_path, report.as_bytes())
+        .with_context(|| format!("Could not write {}", report_path.display()))
+}
+
+/// A drive reported by `cdrdao scanbus`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ScannedDrive {
+    /// Device path (or `bus,id,lun` triple for older SCSI-style setups)
+    device: String,
+    /// Vendor string as reported by the drive
+    vendor: String,
+    /// Model string as reported by the drive
+    model: String,
+    /// Firmware revision
+    revision: String,
+}
+
+impl ScannedDrive {
+    /// The `cdrdao --driver` value most likely to work with this drive
+    ///
+    /// Plextor drives have a dedicated driver with better raw reading support. Everything
+    /// else made in the last couple of decades speaks MMC.
+    fn suggested_driver(&self) -> &'static str {
+        if self.vendor.eq_ignore_ascii_case("PLEXTOR") {
+            "plextor-scan"
+        } else {
+            DEFAULT_CDRDAO_DRIVER
+        }
+    }
+}
+
+/// The `cdrdao --driver` value used unless `--cdrdao-driver` says otherwise
+pub const DEFAULT_CDRDAO_DRIVER: &str = "generic-mmc-raw";
+
+/// The driver suggested for `device` among the drives `cdrdao scanbus` reported
+///
+/// (Both sides are resolved first, since the device is often given as a symlink like
+/// `/dev/cdrom` while scanbus reports the real node.)
+fn driver_for_device(drives: &[ScannedDrive], device: &Path) -> Option<&'static str> {
+    let resolve = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let wanted = resolve(device);
+    drives
+        .iter()
+        .find(|drive| resolve(Path::new(&drive.device)) == wanted)
+        .map(ScannedDrive::suggested_driver)
+}
+
+/// Ask `cdrdao scanbus` which driver suits `device` (`None` if it didn't list the device)
+fn detect_cdrdao_driver(device: &OsStr) -> Result<Option<&'static str>> {
+    let driver = driver_for_device(&run_scanbus()?, Path::new(device));
+    if let Some(name) = driver {
+        info!("cdrdao scanbus suggests driver {} for {}", name, Path::new(device).display());
+    }
+    Ok(driver)
+}
+
+/// The driver to retry a failed `cdrdao read-cd` with, if `--driver-autodetect` applies and
+/// `cdrdao scanbus` suggests something other than the `tried` driver
+fn retry_driver(
+    err: &anyhow::Error,
+    tried: &str,
+    device: &OsStr,
+    mode_opts: &ModeOptions,
+) -> Option<&'static str> {
+    // Only worth retrying if cdrdao itself failed, rather than the disc or drive
+    let disc_or_drive = matches!(
+        MediaError::find(err),
+        Some(MediaError::Stalled(_) | MediaError::MediaChanged | MediaError::Blank)
+    );
+    if !mode_opts.driver_autodetect || mode_opts.cdrdao_driver.is_some() || disc_or_drive {
+        return None;
+    }
+    match detect_cdrdao_driver(device) {
+        Ok(Some(detected)) if detected != tried => {
+            warn!("cdrdao failed with driver {} ({:#}). Retrying with {}.", tried, err, detected);
+            Some(detected)
+        },
+        Ok(_) => {
+            info!("cdrdao scanbus didn't suggest a different driver to retry with");
+            None
+        },
+        Err(e) => {
+            warn!("Could not autodetect a cdrdao driver: {:#}", e);
+            None
+        },
+    }
+}
+
+/// Run `cdrdao scanbus` and parse the drives it reports
+fn run_scanbus() -> Result<Vec<ScannedDrive>> {
+    let output = Command::new("cdrdao")
+        .arg("scanbus")
+        .output()
+        .with_context(|| "Could not run cdrdao scanbus")?;
+
+    // cdrdao writes its results to stderr, alongside the version banner
+    Ok(parse_scanbus(&format!(
+        "{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )))
+}
+
+/// Parse the `<device> : <vendor>, <model>, <revision>` lines of `cdrdao scanbus` output
+fn parse_scanbus(output: &str) -> Vec<ScannedDrive> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (device, ident) = line.split_once(" : ")?;
+            let mut fields = ident.splitn(3, ',').map(str::trim);
+            Some(ScannedDrive {
+                device: device.trim().to_owned(),
+                vendor: fields.next()?.to_owned(),
+                model: fields.next()?.to_owned(),
+                revision: fields.next().unwrap_or_default().to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Format scanned drives as an aligned table
+fn format_drive_table(drives: &[ScannedDrive]) -> String {
+    let headers = ["Device", "Vendor", "Model", "Revision", "Suggested driver"];
+    let rows: Vec<[&str; 5]> = drives
+        .iter()
+        .map(|d| [&*d.device, &*d.vendor, &*d.model, &*d.revision, d.suggested_driver()])
+        .collect();
+
+    let mut widths = headers.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&headers).chain(&rows) {
+        let cells: Vec<String> =
+            row.iter().zip(widths).map(|(cell, width)| format!("{:width$}", cell)).collect();
+        table.push_str(cells.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Subcommand to list the drives cdrdao can see, to help with choosing devices and drivers
+pub fn scan_bus() -> Result<()> {
+    let drives = run_scanbus()?;
+    let mut stdout = std::io::stdout().lock();
+    if drives.is_empty() {
+        writeln!(stdout, "No drives found").context("Could not write to stdout")?;
+    } else {
+        write!(stdout, "{}", format_drive_table(&drives)).context("Could not write to stdout")?;
+    }
+    Ok(())
+}
+
+/// Nominal read rate of a CD drive at 1x speed in bytes per second (Mode 1 user data)
+const CD_1X_RATE: u64 = 75 * DATA_SECTOR_LEN;
+
+/// Nominal read rate of a DVD drive at 1x speed in bytes per second
+const DVD_1X_RATE: u64 = 1_385_000;
+
+/// Largest amount of data a CD can hold (an overburned 99-minute disc)
+const MAX_CD_LEN: u64 = 99 * 60 * 75 * DATA_SECTOR_LEN;
+
+/// CD read speed to assume when estimating rip times (Typical of mid-range drives)
+const ASSUMED_CD_SPEED: u64 = 24;
+
+/// DVD read speed to assume when estimating rip times
+const ASSUMED_DVD_SPEED: u64 = 8;
+
+/// Roughly estimate how long a straight read of `size` bytes will take, and at what speed
+fn estimate_rip_time(size: u64) -> (Duration, String) {
+    let (rate, speed) = if size > MAX_CD_LEN {
+        (DVD_1X_RATE * ASSUMED_DVD_SPEED, format!("{}x DVD", ASSUMED_DVD_SPEED))
+    } else {
+        (CD_1X_RATE * ASSUMED_CD_SPEED, format!("{}x CD", ASSUMED_CD_SPEED))
+    };
+    (Duration::from_secs(size.div_ceil(rate)), speed)
+}
+
+/// Format a duration as hours, minutes, and seconds (eg. `1h 02m 03s`)
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, sec) => format!("{}s", sec),
+        (0, min, sec) => format!("{}m {:02}s", min, sec),
+        (hour, min, sec) => format!("{}h {:02}m {:02}s", hour, min, sec),
+    }
+}
+
+/// What `probe` could find out about a disc (`None` for anything it couldn't)
+#[derive(Debug, Default)]
+struct DiscProbe {
+    /// Size of the disc in bytes
+    device_size: Option<u64>,
+    /// Size of the disc's logical sectors in bytes
+    sector_size: Option<u64>,
+    /// The mode of each track, from the table of contents
+    track_modes: Option<Vec<String>>,
+    /// What kind of disc it is
+    media_type: Option<MediaType>,
+    /// The filesystem found on the disc
+    filesystem: Option<Filesystem>,
+    /// The disc's volume label
+    volume_label: Option<String>,
+    /// The dates in the disc's ISO9660 volume descriptor
+    volume_dates: Option<VolumeDates>,
+}
+
+impl fmt::Display for DiscProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNKNOWN: &str = "unknown";
+        match self.device_size {
+            Some(size) => writeln!(f, "Device size:     {} ({} bytes)", format_size(size), size)?,
+            None => writeln!(f, "Device size:     {}", UNKNOWN)?,
+        }
+        match self.sector_size {
+            Some(size) => writeln!(f, "Sector size:     {} bytes", size)?,
+            None => writeln!(f, "Sector size:     {}", UNKNOWN)?,
+        }
+        match &self.track_modes {
+            Some(modes) => writeln!(f, "Tracks:          {} ({})", modes.len(), modes.join(", "))?,
+            None => writeln!(f, "Tracks:          {}", UNKNOWN)?,
+        }
+        writeln!(
+            f,
+            "Media type:      {}",
+            match self.media_type {
+                Some(MediaType::AudioCd) => "audio CD",
+                Some(MediaType::DataCd) => "data CD",
+                Some(MediaType::Dvd) => "DVD",
+                Some(MediaType::BluRay) => "Blu-ray",
+                Some(MediaType::Unknown) | None => UNKNOWN,
+            }
+        )?;
+        writeln!(
+            f,
+            "Filesystem:      {}",
+            match self.filesystem {
+                Some(Filesystem::Iso9660) => "ISO 9660",
+                Some(Filesystem::Udf) => "UDF",
+                Some(Filesystem::Blank) => "none (blank disc)",
+                Some(Filesystem::Unknown) | None => UNKNOWN,
+            }
+        )?;
+        writeln!(f, "Volume label:    {}", self.volume_label.as_deref().unwrap_or(UNKNOWN))?;
+        let dates = self.volume_dates.unwrap_or_default();
+        for (label, date) in
+            [("Volume created: ", dates.created), ("Volume modified:", dates.modified)]
+        {
+            match date {
+                Some(when) => writeln!(f, "{} {}", label, when)?,
+                None => writeln!(f, "{} {}", label, UNKNOWN)?,
+            }
+        }
+        match self.device_size {
+            Some(size) => {
+                let (time, speed) = estimate_rip_time(size);
+                write!(f, "Estimated time:  {} (at {})", format_duration(time), speed)
+            },
+            None => write!(f, "Estimated time:  {}", UNKNOWN),
+        }
+    }
+}
+
+/// Load the disc and wait up to `timeout` for it to be readable, for commands which only read
+/// from it
+fn load_for_reading<P: MediaProvider>(
+    provider: &mut P,
+    no_load: bool,
+    timeout: Duration,
+) -> Result<()> {
+    if !no_load {
+        if let Err(e) = provider.load() {
+            warn!("{:#}. Continuing anyway.", e);
+        }
+    }
+    provider.wait_for_ready(&timeout)?;
+    Ok(())
+}
+
+/// Like `load_for_reading`, but if the drive never becomes ready, ask the user to reseat the
+/// disc and try again, up to `retries` times
+///
+/// (Only timeouts are retried, and only if there's somebody there to reseat the disc.)
+fn load_until_ready<P: MediaProvider + NotificationProvider>(
+    provider: &mut P,
+    no_load: bool,
+    timeout: Duration,
+    retries: u32,
+) -> Result<()> {
+    let mut retries_left = retries;
+    loop {
+        let err = match load_for_reading(provider, no_load, timeout) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        let timed_out = is_timeout(&err);
+        if !timed_out || retries_left == 0 || !provider.is_interactive() {
+            return Err(err);
+        }
+        warn!("{:#}", err);
+        let answer = provider.read_line(&Msg::ReinsertDisc.to_string())?;
+        if answer.trim().eq_ignore_ascii_case("q") {
+            return Err(err);
+        }
+        retries_left -= 1;
+    }
+}
+
+/// Unmount the disc to get exclusive access to it, letting the user close whatever is keeping it
+/// busy if somebody's there to ask
+///
+/// Unless `required`, failure only merits a warning, since not everything can be unmounted
+/// (image files, discs which were never mounted, etc.)
+fn ensure_unmounted<P: MediaProvider + NotificationProvider>(
+    provider: &mut P,
+    required: bool,
+) -> Result<()> {
+    loop {
+        let err = match provider.unmount() {
+            Ok(()) => return Ok(()),
+            Err(e) => anyhow::Error::from(e),
+        };
+        let busy = matches!(MediaError::find(&err), Some(MediaError::Busy(_)));
+        if busy && provider.is_interactive() {
+            warn!("{:#}", err);
+            let answer = provider.read_line(&Msg::DiscBusy.to_string())?;
+            if !answer.trim().eq_ignore_ascii_case("s") {
+                continue;
+            }
+        }
+        if required {
+            return Err(err.context("Exclusive access to the disc is required (--require-unmount)"));
+        }
+        warn!("{:#}. Continuing anyway.", err);
+        return Ok(());
+    }
+}
+
+/// Subcommand to report what's on a disc without ripping it
+///
+/// Loads the disc and waits for it, but doesn't unmount it or write anything to the output
+/// directory. (cdrdao's TOC is read via a scratch file in the system temporary directory.)
+pub fn probe<P: MediaProvider + RawMediaProvider>(
+    provider: &mut P,
+    no_load: bool,
+    timeout: Duration,
+) -> Result<()> {
+    load_for_reading(provider, no_load, timeout)?;
+
+    let filesystem =
+        provider.filesystem().map_err(|e| debug!("Could not detect filesystem: {:#}", e)).ok();
+    let iso_block_size = provider.open_buffered().and_then(|mut dev| logical_block_size(&mut dev));
+    let sector_size = match (filesystem, iso_block_size) {
+        (Some(Filesystem::Iso9660), Ok(size)) => Some(u64::from(size)),
+        (Some(Filesystem::Blank), _) => None,
+        _ => Some(DATA_SECTOR_LEN),
+    };
+    let tocfile = env::temp_dir().join(format!("rip_media-{}-probe.toc", std::process::id()));
+    let report = DiscProbe {
+        device_size: provider
+            .device_size()
+            .map_err(|e| debug!("Could not get device size: {:#}", e))
+            .ok(),
+        sector_size,
+        track_modes: read_toc_via(provider, &tocfile)
+            .map_err(|e| debug!("{:#}", e))
+            .ok()
+            .map(|toc| track_modes(&toc)),
+        media_type: provider
+            .media_type()
+            .map_err(|e| debug!("Could not identify the kind of disc: {:#}", e))
+            .ok(),
+        filesystem,
+        volume_label: provider.volume_label().ok().filter(|label| !label.is_empty()),
+        volume_dates: disc_dates(provider),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{}", report).context("Could not write to stdout")
+}
+
+/// Check that the disc still matches a known-good `reference` image, sector by sector
+///
+/// Both are streamed rather than loaded into memory, so this works for images of any size.
+pub fn compare_to_reference<P: MediaProvider + RawMediaProvider>(
+    provider: &mut P,
+    reference: &Path,
+    no_load: bool,
+    timeout: Duration,
+) -> Result<()> {
+    load_for_reading(provider, no_load, timeout)?;
+    let disc = File::open(provider.device_path()).context("Could not open disc")?;
+    let reference_file =
+        File::open(reference).with_context(|| format!("Could not open {}", reference.display()))?;
+    let comparison = compare_streams(disc, reference_file)
+        .with_context(|| format!("Could not compare the disc to {}", reference.display()))?;
+    if !comparison.is_match() {
+        bail!("{}", comparison);
+    }
+    info!("{}", comparison);
+    Ok(())
+}
+
+// -- interactive --
+
+/** Ensure we have a volume name, even if it requires manual input
+ *
+ * Will use (in priority order):
+ *
+ *  * The `name` argument
+ *  * `provider.volume_label()`
+ *  * Prompted user input (unless `assume_yes` is set or nobody's there to answer)
+ *
+ * Whichever it is gets sanitized if it wouldn't make a portable filename.
+ *
+ *  TODO: Redesign this so it can defer until the end by using mkdtemp()
+ *        for maximum convenience and robustness in the face of a busy user
+ */
+pub fn ensure_vol_label<P: MediaProvider + NotificationProvider>(
+    provider: &P,
+    name: Option<&str>,
+    assume_yes: bool,
+) -> Result<String> {
+    if let Some(x) = name {
+        return Ok(portable_name(x));
+    }
+
+    // Fall back to prompting (and ensure we get a non-empty name)
+    // TODO: but do it with a timeout so the user can run the script and then take
+    //       their time loading the disc if they prefer that order of operations
+    loop {
+        // Do this inside the loop so I can press Enter to retry reading
+        // TODO: Think of better UX for this
+        let label = provider.volume_label().unwrap_or_default();
+        if !label.trim().is_empty() {
+            return Ok(portable_name(label.trim()));
+        }
+
+        if assume_yes || !provider.is_interactive() {
+            // Nobody's there to name it, so pick something that won't collide with past rips
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            return Ok(format!("Unlabeled Disc {}", now.as_secs()));
+        }
+        let answer = provider.read_line(&Msg::AskDiscName.to_string())?;
+        if !answer.trim().is_empty() {
+            return Ok(portable_name(answer.trim()));
+        }
+    }
+}
+
+/// Robustly prompt the user for a CD key and record it in `cd_key.txt` in `sink`
+///
+/// (CD keys are deliberately kept out of the prompt history)
+///
+/// If `assume_yes` is set, whatever is entered is taken without asking for confirmation.
+pub fn get_cd_key<P: NotificationProvider>(
+    provider: &P,
+    disc_name: &str,
+    sink: &dyn OutputSink,
+    assume_yes: bool,
+) -> Result<()> {
+    if !provider.is_interactive() {
+        info!("Not prompting for a CD key since nobody is there to enter one");
+        return Ok(());
+    }
+    loop {
+        let key = provider.read_line_unrecorded(&Msg::AskCdKey(disc_name).to_string())?;
+        let trimmed = key.trim();
+
+        // TODO: Have a non-rustyline one for simple y/n or Enter stuff.
+        let confirm =
+            if trimmed.is_empty() { Msg::ConfirmNoCdKey } else { Msg::ConfirmCdKey(trimmed) };
+        if assume_yes || lang().is_yes(&provider.read_line_unrecorded(&confirm.to_string())?) {
+            if !trimmed.is_empty() {
+                let path = sink.path(Path::new(CD_KEY_FILE));
+                sink.write(&path, format!("{}\n", trimmed).as_bytes())
+                    .with_context(|| format!("Could not write {}", path.display()))?;
+            }
+            break;
+        }
+    }
+    Ok(())
+}
+
+// -- validation of existing dumps --
+
+/// A mode's check of an existing dump, given the directory it's in and the name of the disc,
+/// which returns a description of each problem found
+pub type Validator = fn(&Path, &str) -> Result<Vec<String>>;
+
+/// List the files in `dir` with the given extension (case-insensitive), in natural order
+pub fn files_with_extension(dir: &Path, extension: &str) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Could not list {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case(extension))
+        })
+        .collect();
+    sort_paths_naturally(&mut paths);
+    Ok(paths)
+}
+
+/// Whether the file named `name` has the extension `extension` (case-insensitively)
+pub fn has_extension(name: &str, extension: &str) -> bool {
+    Path::new(name).extension().is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Build the `par2create` command to protect `files` with recovery data for `disc_name`
+fn par2_command(disc_name: &str, files: &[String]) -> Command {
+    let mut cmd = Command::new("par2create");
+    cmd.arg("-n1").arg(volume_file(Path::new(disc_name), "par2")).args(files);
+    cmd
+}
+
+/// Write PAR2 recovery data for every file in `dir` (replacing any from an earlier run) and
+/// return whether it was written (`false` if `par2create` isn't installed)
+fn write_par2(dir: &Path, disc_name: &str) -> Result<bool> {
+    let (old, files): (Vec<_>, Vec<_>) =
+        file_names(dir)?.into_iter().partition(|name| has_extension(name, "par2"));
+    for name in old {
+        let path = dir.join(name);
+        fs::remove_file(&path).with_context(|| format!("Could not remove {}", path.display()))?;
+    }
+
+    info!("Writing PAR2 recovery data for {} file(s)...", files.len());
+    let status = match par2_command(disc_name, &files).current_dir(dir).status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == IOErrorKind::NotFound => {
+            warn!("Skipping PAR2 recovery data, since par2create isn't installed");
+            return Ok(false);
+        },
+        Err(e) => return Err(e).context("Could not run par2create"),
+    };
+    if !status.success() {
+        bail!("par2create failed ({}) in {}", status, dir.display());
+    }
+    Ok(true)
+}
+
+/// Run the steps chosen in `post` on the dump of `disc_name` in `dir`
+///
+/// Each step replaces whatever it produced last time, so running this again on a dump which
+/// was already post-processed is harmless.
+pub fn post_process(dir: &Path, disc_name: &str, post: PostProcessOptions) -> Result<()> {
+    if post.checksums {
+        let sumfile = write_checksums(dir, disc_name)?;
+        info!("Wrote checksums to {}", sumfile.display());
+    }
+    if post.par2 && write_par2(dir, disc_name).context("Could not write PAR2 recovery data")? {
+        info!("Wrote PAR2 recovery data to {}", dir.display());
+    }
+    Ok(())
+}
+
+/// Run the steps chosen in `post` on an existing dump in `dir` without touching the drive
+///
+/// `dir` must hold a single disc's files (as with `Layout::ByName` or `Layout::ByType`), and
+/// `disc_name` defaults to the name of the folder.
+pub fn process_existing(
+    dir: &Path,
+    disc_name: Option<&str>,
+    post: PostProcessOptions,
+) -> Result<()> {
+    if post.is_empty() {
+        bail!("Nothing to do. Choose the steps to run (eg. --checksums).");
+    }
+    let full_dir =
+        fs::canonicalize(dir).with_context(|| format!("Could not find {}", dir.display()))?;
+    if !full_dir.is_dir() {
+        bail!("{} is not a folder containing a dump", dir.display());
+    }
+    let dir_name = full_dir.file_name().map(OsStr::to_string_lossy).unwrap_or_default();
+    if dir_name.ends_with(PARTIAL_SUFFIX) {
+        bail!("The rip in {} never finished", dir.display());
+    }
+    let name = disc_name.unwrap_or(&dir_name);
+    if name.is_empty() {
+        bail!("Could not tell which disc is in {}. Use --name to say.", dir.display());
+    }
+    post_process(&full_dir, name, post)?;
+    info!("Finished processing the dump in {}", dir.display());
+    Ok(())
+}
+
+/// Check an existing BIN/CUE dump (as produced by `rip_bin`) for consistency
+pub fn validate_bin(dir: &Path, disc_name: &str) -> Result<Vec<String>> {
+    let volbase = dir.join(disc_name);
+    let cuefile = volume_file(&volbase, "cue");
+    let tocfile = volume_file(&volbase, "toc");
+    if !cuefile.exists() {
+        return Ok(vec![format!("Missing CUE sheet: {}", cuefile.display())]);
+    }
+    verify_bin_set(&cuefile, tocfile.exists().then_some(tocfile.as_path()))
+}
+
+/// Check an existing ISO dump (as produced by `rip_iso`) for truncation and unread areas
+#[allow(clippy::unnecessary_wraps)] // Must match the `Validator` signature
+pub fn validate_iso(dir: &Path, disc_name: &str) -> Result<Vec<String>> {
+    let volbase = dir.join(disc_name);
+    let isofile = volume_file(&volbase, "iso");
+    let logfile = volume_file(&volbase, "log");
+    let mut file = match fs::File::open(&isofile) {
+        Ok(file) => file,
+        Err(e) => return Ok(vec![format!("Could not open {}: {}", isofile.display(), e)]),
+    };
+
+    let mut problems = Vec::new();
+    let actual = file.metadata().map(|meta| meta.len()).unwrap_or_default();
+    match volume_size(&mut SectorReader::new(&mut file)) {
+        Ok(expected) => {
+            if let Some(missing) = dump_shortfall(actual, expected) {
+                problems.push(format!(
+                    "{} is {} bytes shorter than its filesystem ({} of {} bytes)",
+                    isofile.display(),
+                    missing,
+                    actual,
+                    expected
+                ));
+            }
+        },
+        Err(e) => problems.push(format!("{}: {:#}", isofile.display(), e)),
+    }
+
+    // Only present if the rip hasn't been cleaned up, but worth checking if it is
+    if let Ok(text) = fs::read_to_string(&logfile) {
+        match Mapfile::parse(&text) {
+            Ok(map) if !map.is_complete() => problems
+                .push(format!("{} shows areas ddrescue couldn't recover", logfile.display())),
+            Ok(_) => {},
+            Err(e) => problems.push(format!("{}: {:#}", logfile.display(), e)),
+        }
+    }
+    Ok(problems)
+}
+
+/// Check an existing audio rip (as produced by `rip_audio`) using `flac --test`
+pub fn validate_audio(dir: &Path, _disc_name: &str) -> Result<Vec<String>> {
+    let mut problems: Vec<String> = files_with_extension(dir, "wav")?
+        .iter()
+        .map(|path| format!("Track was never encoded: {}", path.display()))
+        .collect();
+    let tracks = files_with_extension(dir, "flac")?;
+    if tracks.is_empty() {
+        problems.push(format!("No FLAC files in {}", dir.display()));
+    }
+    for track in tracks {
+        let status = Command::new("flac")
+            .args(["--test", "--silent"])
+            .arg(&track)
+            .status()
+            .context("Could not run flac")?;
+        if !status.success() {
+            problems.push(format!("{} failed its integrity check", track.display()));
+        }
+    }
+    Ok(problems)
+}
+
+/// Check the outputs of a damaged-disc recovery, whichever strategies produced them
+pub fn validate_damaged(dir: &Path, disc_name: &str) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let mut found_any = false;
+    if volume_file(&dir.join(disc_name), "cue").exists() {
+        found_any = true;
+        problems.extend(validate_bin(dir, disc_name)?);
+    }
+    if volume_file(&dir.join(disc_name), "iso").exists() {
+        found_any = true;
+        problems.extend(validate_iso(dir, disc_name)?);
+    }
+    if !files_with_extension(dir, "flac")?.is_empty() {
+        found_any = true;
+        problems.extend(validate_audio(dir, disc_name)?);
+    }
+    if !found_any {
+        problems.push(format!("No recovered BIN, ISO, or audio files in {}", dir.display()));
+    }
+    Ok(problems)
+}
+
+/// Re-check the dump of `disc_name` in `outdir` using `validator` (plus any checksum files
+/// alongside it) without touching the drive, failing if any problems are found
+///
+/// `layout` and `media_type` must match what the disc was ripped with, to find its files, and
+/// `cache` (if given) saves rehashing files the checksum files list which haven't changed.
+pub fn validate_existing(
+    validator: Validator,
+    outdir: &Path,
+    layout: Layout,
+    media_type: &str,
+    disc_name: &str,
+    cache: Option<&mut HashCache>,
+) -> Result<()> {
+    let dir = outdir.join(layout.disc_dir(disc_name, media_type));
+    if !dir.is_dir() || layout == Layout::Flat {
+        let mut partial_name =
+            outdir.join(layout.staging_dir(disc_name, media_type)).into_os_string();
+        partial_name.push(PARTIAL_SUFFIX);
+        if Path::new(&partial_name).is_dir() {
+            bail!(
+                "The rip of {} never finished: {}",
+                disc_name,
+                Path::new(&partial_name).display()
+            );
+        }
+    }
+    if !dir.is_dir() {
+        bail!("No dump of {} found in {}", disc_name, outdir.display());
+    }
+
+    let mut problems = validator(&dir, disc_name)?;
+    problems.extend(verify_checksum_files(&dir, cache)?);
+    for problem in &problems {
+        warn!("{}", problem);
+    }
+    if !problems.is_empty() {
+        bail!("Found {} problem(s) with the dump in {}", problems.len(), dir.display());
+    }
+    info!("No problems found with the dump in {}", dir.display());
+    Ok(())
+}
+
+// -- subcommands --
+// TODO: Make these as asynchronous as possible
+
+/// Subcommand to rip a CD
+///
+/// Data CDs are dumped as BIN/TOC/CUE sets, audio CDs are ripped with cdparanoia, and
+/// mixed-mode CDs get both.
+pub fn rip_cd<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    let summary = classify_tracks(provider, disc_name).unwrap_or_else(|e| {
+        warn!("{:#}. Ripping it as a data disc.", e);
+        TrackSummary::default()
+    });
+    if !summary.audio.is_empty() || !summary.data.is_empty() {
+        info!("Disc has {}", summary);
+    }
+
+    // Audio tracks get cdparanoia's error correction, which a raw BIN dump lacks
+    if summary.data.is_empty() && !summary.audio.is_empty() {
+        rip_audio(provider, disc_name, mode_opts)?;
+        mode_opts.sounds.play_done(provider);
+        return Ok(());
+    }
+
+    // TODO: Make this take options so I can ask for BIN or ISO
+    rip_bin(provider, disc_name, true, mode_opts)?;
+    if !summary.audio.is_empty() {
+        // Mixed-mode discs keep the BIN (for the data track), but get the audio tracks too
+        let audio_opts = ModeOptions {
+            tracks: mode_opts.tracks.clone().or(Some(summary.audio)),
+            ..mode_opts.clone()
+        };
+        rip_audio(provider, disc_name, &audio_opts)?;
+    }
+    mode_opts.sounds.play_done(provider);
+    get_cd_key(provider, disc_name, &*mode_opts.sink, mode_opts.assume_yes)
+}
+
+/// Read the disc's table of contents to find out which of its tracks are audio and which
+/// are data
+fn classify_tracks<P: RawMediaProvider>(provider: &P, disc_name: &str) -> Result<TrackSummary> {
+    let toc = read_toc(provider, disc_name).context("Could not classify the disc's tracks")?;
+    Ok(TrackList::parse(&toc).summary())
+}
+
+/// Subcommand to recover a damaged CD
+///
+/// Unless specific strategies were requested, this starts with a BIN dump and uses the
+/// resulting TOC to decide whether ISO and/or audio dumps are worth attempting. Failed
+/// strategies don't stop later ones from being tried.
+pub fn rip_damaged<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    // TODO: Look into integrating dvdisaster
+    let run_strategy = |prov: &mut P, strategy| match strategy {
+        RecoveryStrategy::Bin => rip_bin(prov, disc_name, true, mode_opts),
+        RecoveryStrategy::Iso => rip_iso(prov, disc_name, mode_opts),
+        RecoveryStrategy::Audio => rip_audio(prov, disc_name, mode_opts),
+    };
+
+    let mut results = Vec::new();
+    if mode_opts.recovery.is_empty() {
+        let bin_result = run_strategy(provider, RecoveryStrategy::Bin);
+        let tocfile = volume_file(Path::new(disc_name), "toc");
+        let toc = bin_result.is_ok().then(|| fs::read_to_string(&tocfile).ok()).flatten();
+        results.push((RecoveryStrategy::Bin, bin_result));
+        for strategy in follow_up_strategies(toc.as_deref()) {
+            results.push((strategy, run_strategy(provider, strategy)));
+        }
+    } else {
+        for &strategy in &mode_opts.recovery {
+            results.push((strategy, run_strategy(provider, strategy)));
+        }
+    }
+
+    let mut succeeded = Vec::new();
+    for (strategy, result) in &results {
+        match result {
+            Ok(()) => succeeded.push(strategy.to_string()),
+            Err(e) => warn!("Recovery strategy '{}' failed: {:#}", strategy, e),
+        }
+    }
+    if succeeded.is_empty() {
+        bail!("All recovery strategies failed");
+    }
+    info!("Recovery strategies which succeeded: {}", succeeded.join(", "));
+
+    mode_opts.sounds.play_done(provider);
+    get_cd_key(provider, disc_name, &*mode_opts.sink, mode_opts.assume_yes)
+}
+
+/// Check a DVD for CSS before dumping it, since the dump of a protected disc stays encrypted
+fn check_css<P: RawMediaProvider>(provider: &P, allow_encrypted: bool) -> Result<()> {
+    let status = provider.open_buffered().and_then(|mut dev| detect_css(&mut dev));
+    let protected = match status {
+        Ok(CssStatus::Scrambled) => "is CSS-encrypted",
+        Ok(CssStatus::Unreadable) => "appears to be CSS-encrypted (the drive refused to read it)",
+        Ok(CssStatus::NotVideo | CssStatus::Clear) => return Ok(()),
+        Err(e) => {
+            debug!("Could not check for CSS encryption: {:#}", e);
+            return Ok(());
+        },
+    };
+
+    if !allow_encrypted {
+        bail!(
+            "This DVD-Video disc {}. The dump would stay encrypted and need libdvdcss to play. \
+             Pass --allow-encrypted to dump it anyway.",
+            protected
+        );
+    }
+    warn!("This DVD-Video disc {}. The dump will need libdvdcss to play.", protected);
+    Ok(())
+}
+
+/// Subcommand to rip a DVD-ROM
+pub fn rip_dvd<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    check_css(provider, mode_opts.allow_encrypted)?;
+    rip_iso(provider, disc_name, mode_opts)?;
+    mode_opts.sounds.play_done(provider);
+    get_cd_key(provider, disc_name, &*mode_opts.sink, mode_opts.assume_yes)
+}
+
+/// Subcommand to rip a Playstation (PSX/PS1) disc
+pub fn rip_psx<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    rip_bin(provider, disc_name, true, mode_opts)
+}
+
+/// Subcommand to rip the low-density area of a Sega Dreamcast GD-ROM into a GDI/BIN set
+///
+/// Ordinary drives can only read the first ~35MB (the CD-compatible session). The high-density
+/// area holding the actual game needs a drive which understands GD-ROMs or the "swap trick", so
+/// the result is only a partial dump, suitable for identifying the disc.
+pub fn rip_dreamcast<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    warn!(
+        "Only the low-density area of a GD-ROM can be read by ordinary drives. The \
+         high-density area (with the game itself) will be missing from this dump."
+    );
+    rip_bin(provider, disc_name, true, mode_opts)?;
+
+    let volbase = PathBuf::from(disc_name);
+    let sink = &*mode_opts.sink;
+    let cuefile = sink.path(&volume_file(&volbase, "cue"));
+    if !mode_opts.split_tracks {
+        split_bin(&cuefile, None)?;
+    }
+    let tocfile = sink.path(&volume_file(&volbase, "toc"));
+    write_gdi(sink, &cuefile, &tocfile, &sink.path(&volume_file(&volbase, "gdi")))
+}
+
+/// The kinds of disc PS2 games were released on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ps2Disc {
+    /// A CD (or a DVD small enough to pass for one, which dumps the same way)
+    Cd,
+    /// A single-layer DVD (DVD-5)
+    DvdSingleLayer,
+    /// A dual-layer DVD (DVD-9), with the sector the second layer starts at
+    DvdDualLayer(u64),
+}
+
+/// Work out what kind of disc the PS2 image at `isofile` was dumped from
+fn detect_ps2_disc(isofile: &Path) -> Result<Ps2Disc> {
+    let mut iso =
+        File::open(isofile).with_context(|| format!("Could not open {}", isofile.display()))?;
+    if let Some(sector) = layer_break(&mut iso)? {
+        return Ok(Ps2Disc::DvdDualLayer(sector));
+    }
+    let len = iso.metadata().with_context(|| format!("Could not stat {}", isofile.display()))?;
+    Ok(if len.len() > MAX_CD_LEN { Ps2Disc::DvdSingleLayer } else { Ps2Disc::Cd })
+}
+
+/// Subcommand to rip a Playstation 2 (PS2) disc
+///
+/// Dual-layer DVDs also get their layer break written to a `.layerbreak.txt` file, since the
+/// ISO can't record it and emulators like PCSX2 need it to get the second layer right.
+pub fn rip_ps2<P: RawMediaProvider + NotificationProvider>(
+    provider: &mut P,
+    disc_name: &str,
+    mode_opts: &ModeOptions,
+) -> Result<()> {
+    rip_iso(provider, disc_name, mode_opts)?;
+    if mode_opts.range.is_some() {
+        return Ok(()); // A slice of the disc says nothing about its layers
+    }
+
+    let volbase = Path::new(disc_name);
+    let sink = &*mode_opts.sink;
+    let isofile = sink.path(&volume_file(volbase, "iso"));
+    match detect_ps2_disc(&isofile) {
+        Ok(Ps2Disc::Cd) => info!("Dumped a PS2 CD"),
+        Ok(Ps2Disc::DvdSingleLayer) => info!("Dumped a single-layer PS2 DVD"),
+        Ok(Ps2Disc::DvdDualLayer(sector)) => {
+            let breakfile = sink.path(&volume_file(volbase, "layerbreak.txt"));
+            sink.write(&breakfile, format!("{}\n", sector).as_bytes())
+                .with_context(|| format!("Could not write {}", breakfile.display()))?;
+            warn!(
+                "Dumped a dual-layer PS2 DVD with its layer break at sector {}. Keep {} with the \
+                 image, since emulators need the layer break and the ISO can't record it.",
+                sector,
+                breakfile.display()
+            );
+        },
+        Err(e) => warn!("Could not tell what kind of disc {} is: {:#}", isofile.display(), e),
+    }
+    Ok(())
+}
+
+/// Rip the disc in each of `slots` of a changer in turn, without prompting for any of them
+///
+/// Empty slots are skipped, and failed rips don't stop the rest of the magazine from being
+/// ripped, but are reported at the end.
+pub fn rip_slots<P, F>(
+    plat_provider: &mut P,
+    mode_func: F,
+    mut opts: RipOptions<'_>,
+    slots: &BTreeSet<usize>,
+) -> Result<()>
+where
+    P: MediaProvider + RawMediaProvider + NotificationProvider + ChangerProvider,
+    F: Fn(&mut P, &str, &ModeOptions) -> Result<()>,
+{
+    let mut failed = Vec::new();
+    let mut ripped = 0;
+    for &slot in slots {
+        if out_of_time(opts.deadline, ripped) {
+            let rest: Vec<String> = slots.range(slot..).map(ToString::to_string).collect();
+            info!("Not ripping changer slot(s) {}", rest.join(", "));
+            break;
+        }
+        info!("Loading changer slot {}...", slot);
+        if let Err(e) = plat_provider.load_slot(slot) {
+            warn!("{:#}", e);
+            failed.push(slot);
+            continue;
+        }
+
+        opts.slot = Some(slot);
+        match rip(plat_provider, &mode_func, &opts) {
+            Ok(output) => {
+                info!("Ripped slot {} as {}", slot, output.name);
+                ripped += 1;
+            },
+            Err(e) if is_timeout(&e) => info!("Slot {} seems to be empty. Skipping.", slot),
+            Err(e) => {
+                warn!("Failed to rip slot {}: {:#}", slot, e);
+                failed.push(slot);
+            },
+        }
+    }
+
+    if !failed.is_empty() {
+        let failed_list: Vec<String> = failed.iter().map(ToString::to_string).collect();
+        bail!("Could not rip changer slot(s) {}", failed_list.join(", "));
+    }
+    Ok(())
+}
+
+/// Whether `deadline` has passed, so no more discs should be started (after logging as much,
+/// along with how many discs were ripped before it did)
+pub fn out_of_time(deadline: Option<Instant>, ripped: usize) -> bool {
+    if deadline.is_none_or(|limit| Instant::now() < limit) {
+        return false;
+    }
+    warn!("Out of time (--max-total-time) after ripping {} disc(s). Stopping.", ripped);
+    true
+}
+
+/// Whether `err` was caused by the drive never becoming ready (eg. because it has no disc)
+fn is_timeout(err: &anyhow::Error) -> bool {
+    matches!(MediaError::find(err), Some(MediaError::Timeout { .. }))
+}
+
+/// Eject the disc, warning (rather than failing) if that doesn't work, since it's the last step
+fn eject_or_warn<P: MediaProvider>(provider: &mut P) {
+    if let Err(e) = provider.eject() {
+        warn!("{:#}. You may need to remove the disc by hand.", e);
+    }
+}
+
+/// Where the output of a successful rip ended up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RipOutput {
+    /// The name the disc was ripped under
+    pub name: String,
+    /// The directory its files are in, relative to the output directory
+    pub dir: PathBuf,
+    /// The names of the files it produced within `dir`
+    pub files: Vec<String>,
+    /// The disc's `MediaProvider::disc_serial` (if it had one)
+    pub serial: Option<String>,
+    /// The folder of the set the disc belongs to, if it was ripped into one
+    pub set_dir: Option<PathBuf>,
+}
+
+/// Where the output of a rip goes, relative to the output directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct OutputTarget {
+    /// The name to rip the disc under (which its files are named after)
+    name: String,
+    /// The directory its files end up in
+    dir: PathBuf,
+    /// The directory it's ripped into (before its `.partial` suffix is removed)
+    staging: PathBuf,
+    /// The folder of the set the disc belongs to, if it's being ripped into one
+    set_dir: Option<PathBuf>,
+}
+
+impl OutputTarget {
+    /// Rip a disc named `name` following `layout`
+    fn new(name: &str, layout: Layout, media_type: &str) -> Self {
+        OutputTarget {
+            name: name.to_owned(),
+            dir: layout.disc_dir(name, media_type),
+            staging: layout.staging_dir(name, media_type),
+            set_dir: None,
+        }
+    }
+
+    /// Rip a disc named `name` into `folder` (eg. `disc1`) within the folder of its set
+    fn in_set(name: &str, set_dir: &Path, folder: &str) -> Self {
+        let dir = set_dir.join(folder);
+        OutputTarget {
+            name: name.to_owned(),
+            staging: dir.clone(),
+            dir,
+            set_dir: Some(set_dir.into()),
+        }
+    }
+
+    /// The output of a disc that was left as-is rather than ripped into this target
+    fn skipped(self, serial: Option<String>) -> RipOutput {
+        let OutputTarget { name, dir, set_dir, .. } = self;
+        RipOutput { name, dir, files: Vec::new(), serial, set_dir }
+    }
+}
+
+/// The folder the discs of a set named `set_name` go in (`disc1`, `disc2`, etc. within it), or
+/// `None` when not ripping a set or when the layout is flat
+fn set_folder(opts: &RipOptions<'_>, set_name: &str) -> Result<Option<PathBuf>> {
+    if opts.set_position.is_none() || opts.layout == Layout::Flat {
+        return Ok(None);
+    }
+    match &opts.set_dir {
+        Some(dir) => Ok(Some(dir.clone())),
+        None => {
+            Ok(Some(opts.layout.disc_dir(&output_name(set_name, opts.name_case)?, opts.media_type)))
+        },
+    }
+}
+
+/// Top-level orchestration for doing a ripping run on a single disc, returning where its
+/// output ended up
+/// TODO: Provide prompting via a swappable service provider similar to APT's.
+pub fn rip<P, F>(plat_provider: &mut P, mode_func: F, opts: &RipOptions<'_>) -> Result<RipOutput>
+where
+    P: MediaProvider + RawMediaProvider + NotificationProvider,
+    F: Fn(&mut P, &str, &ModeOptions) -> Result<()>,
+{
+    let started = SystemTime::now();
+    let mut disc_name = None;
+    let mut metadata = opts.metadata.clone();
+    let result = with_next_profile(opts, |disc_opts| {
+        metadata.clone_from(&disc_opts.metadata);
+        let output = rip_disc(plat_provider, mode_func, disc_opts, &mut disc_name, &mut metadata)?;
+        // (Skipped discs have no new files to compress)
+        if disc_opts.compress && !output.files.is_empty() {
+            compress_output(&output)
+        } else {
+            Ok(output)
+        }
+    });
+    match &result {
+        Ok(output) => plat_provider.notify_desktop(
+            &Msg::RipFinished.to_string(),
+            &Msg::FinishedRipping(&output.name).to_string(),
+            Urgency::Normal,
+        ),
+        Err(e) => plat_provider.notify_desktop(
+            &Msg::RipFailed.to_string(),
+            &Msg::CouldNotRip(disc_name.as_deref(), &format!("{:#}", e)).to_string(),
+            Urgency::Critical,
+        ),
+    }
+
+    if let Some(url) = opts.webhook {
+        report_rip(url, disc_name, started, &result, metadata);
+    }
+    result
+}
+
+/// Decide how to rip a disc named `disc_name` into the current directory, given
+/// `opts.overwrite` and what's already there
+///
+/// (For the discs of a set being ripped into `set_dir`, `disc_name` is the name of the disc's
+/// folder within it, eg. `disc1`.)
+fn choose_output(
+    disc_name: &str,
+    set_dir: Option<&Path>,
+    opts: &RipOptions<'_>,
+) -> Result<OutputPlan> {
+    let outdir = Path::new(CurDir.as_os_str());
+    let plan = plan_output(disc_name, opts.overwrite, |candidate| match set_dir {
+        Some(dir) => set_output_exists(outdir, dir, candidate),
+        None => output_exists(outdir, opts.layout, candidate, opts.media_type),
+    })?;
+    let label = set_dir
+        .map_or_else(|| disc_name.to_owned(), |dir| dir.join(disc_name).display().to_string());
+    match &plan {
+        OutputPlan::Rip { name, .. } if name != disc_name => {
+            info!("{} has already been ripped. Ripping this disc as {}.", label, name);
+        },
+        OutputPlan::Rip { replace: true, .. } => {
+            info!("{} has already been ripped. It will be replaced.", label);
+        },
+        OutputPlan::Rip { .. } => {},
+        OutputPlan::Skip => info!("{} has already been ripped. Leaving it as-is.", label),
+    }
+    Ok(plan)
+}
+
+/// Rip the disc into `target` within the current directory, replacing any existing output if
+/// `replace` is set, and return where the output ended up
+fn rip_into_outdir<P, F>(
+    plat_provider: &mut P,
+    mode_func: F,
+    opts: &RipOptions<'_>,
+    target: &OutputTarget,
+    metadata: &DiscMetadata,
+    replace: bool,
+) -> Result<RipOutput>
+where
+    P: MediaProvider + NotificationProvider,
+    F: Fn(&mut P, &str, &ModeOptions) -> Result<()>,
+{
+    let outdir = Path::new(CurDir.as_os_str());
+    let (disc_name, workdir) = (target.name.as_str(), &target.staging);
+    check_output_paths(outdir, opts.layout, workdir, disc_name)?;
+    create_parent_dirs(workdir)?;
+    with_partial_dir(workdir, replace, |partial_dir| {
+        write_metadata(partial_dir, metadata, disc_name)?;
+        let workdir_guard = WorkdirGuard::enter(partial_dir)?;
+        info!("{}", Msg::RippingInto(workdir_guard.path()));
+        mode_func(plat_provider, disc_name, &opts.mode)?;
+        if opts.redump_info {
+            write_redump_info(plat_provider, &*opts.mode.sink, disc_name)?;
+        }
+        post_process(outdir, disc_name, opts.post)
+    })?;
+
+    let files = match opts.layout {
+        Layout::Flat => flatten_into_parent(workdir, replace)?,
+        Layout::ByType | Layout::ByName => file_names(workdir)?,
+    };
+    Ok(RipOutput {
+        name: disc_name.to_owned(),
+        dir: target.dir.clone(),
+        files,
+        serial: None,
+        set_dir: target.set_dir.clone(),
+    })
+}
+
+/// Wait for the disc in the drive to be something other than the one whose
+/// `MediaProvider::disc_serial` was `previous`, checking every `interval`
+fn wait_for_disc_change<P: MediaProvider>(plat_provider: &P, previous: &str, interval: Duration) {
+    loop {
+        match plat_provider.disc_serial() {
+            Ok(serial) if serial.as_deref() != Some(previous) => return,
+            Ok(_) => {}, // The last disc hasn't been taken out yet
+            Err(e) => debug!("No disc to fingerprint yet: {:#}", e),
+        }
+        sleep(interval);
+    }
+}
+
+/// Ask for the disc to be inserted (or, for changers and `--yes`, assume it already is)
+///
+/// With `--yes`, later discs of a set are waited for by watching for the disc to change.
+fn ask_for_disc<P>(plat_provider: &P, opts: &RipOptions<'_>) -> Result<()>
+where
+    P: MediaProvider + NotificationProvider,
+{
+    // TODO: Have a non-rustyline one for simple y/n or Enter stuff.
+    match (plat_provider.is_interactive(), opts.set_position) {
+        _ if opts.slot.is_some() => {}, // The changer has already loaded it
+        (_, Some((number, set_size))) if opts.mode.assume_yes => {
+            if let Some(previous) = &opts.previous_serial {
+                info!("Waiting for disc {} of {} to be inserted (--yes)", number, set_size);
+                wait_for_disc_change(plat_provider, previous, DISC_CHANGE_POLL_INTERVAL);
+            } else {
+                info!("Assuming disc {} of {} is inserted (--yes)", number, set_size);
+            }
+        },
+        _ if opts.mode.assume_yes => debug!("Ripping whatever disc is already inserted (--yes)"),
+        (true, Some((number, set_size))) => {
+            plat_provider.read_line(&Msg::InsertDiscOf { number, set_size }.to_string())?;
+        },
+        (true, None) => {
+            plat_provider.read_line(&Msg::InsertDisc.to_string())?;
+        },
+        (false, Some((number, set_size))) => {
+            bail!(
+                "Can't ask for disc {} of {} to be inserted when not interactive",
+                number,
+                set_size
+            )
+        },
+        (false, None) => debug!("Not interactive. Ripping whatever disc is already inserted."),
+    }
+    Ok(())
+}
+
+/// The part of `rip` which actually rips, reporting the name it chose via `disc_name`
+fn rip_disc<P, F>(
+    plat_provider: &mut P,
+    mode_func: F,
+    opts: &RipOptions<'_>,
+    disc_name: &mut Option<String>,
+    metadata: &mut DiscMetadata,
+) -> Result<RipOutput>
+where
+    P: MediaProvider + RawMediaProvider + NotificationProvider,
+    F: Fn(&mut P, &str, &ModeOptions) -> Result<()>,
+{
+    let interactive = plat_provider.is_interactive();
+    ask_for_disc(plat_provider, opts)?;
+
+    // Neither loading nor unmounting applies to everything (slot-loading drives, image files,
+    // discs that were never mounted), so failures only merit a warning.
+    let no_load = opts.no_load || opts.slot.is_some();
+    load_until_ready(plat_provider, no_load, opts.ready_timeout, opts.ready_retries)?;
+    if !opts.no_unmount {
+        ensure_unmounted(plat_provider, opts.require_unmount)?;
+    }
+
+    // Fail early rather than letting a blank disc fail confusingly partway through the rip
+    match plat_provider.filesystem() {
+        Ok(Filesystem::Blank) => return Err(MediaError::Blank.into()),
+        Ok(_) => {},
+        Err(e) => warn!("Could not check for a blank disc: {:#}", e),
+    }
+    match plat_provider.sessions() {
+        Ok(sessions) if sessions.count > 1 => warn!(
+            "Disc has {} sessions. ISO dumps will only capture the filesystem as of the last \
+             one (starting at byte {}), so a raw BIN dump (the cd mode) is advisable.",
+            sessions.count, sessions.last_offset
+        ),
+        Ok(_) => {},
+        Err(e) => debug!("Could not check for a multi-session disc: {:#}", e),
+    }
+    let serial = plat_provider
+        .disc_serial()
+        .map_err(|e| debug!("Could not fingerprint the disc: {:#}", e))
+        .ok()
+        .flatten();
+
+    if opts.ask_metadata && plat_provider.is_interactive() {
+        *metadata = ask_metadata(plat_provider, metadata, opts.name_from_title, opts.name_case)?;
+    }
+    match plat_provider.drive_info() {
+        Ok(Some(drive)) => {
+            info!("Ripping with {} {} (firmware {})", drive.vendor, drive.model, drive.revision);
+            metadata.drive = Some(drive);
+        },
+        Ok(None) => {},
+        Err(e) => warn!("Could not identify the drive for the disc's metadata: {:#}", e),
+    }
+    metadata.volume_dates = disc_dates(plat_provider);
+    if let Some(dates) = &metadata.volume_dates {
+        log_volume_dates(dates);
+    }
+    let title_name = metadata.title.as_deref().filter(|_| opts.name_from_title);
+    let mut name_str =
+        ensure_vol_label(plat_provider, opts.name.or(title_name), opts.mode.assume_yes)?;
+    assert!(!name_str.trim().is_empty()); // Guard against empty names
+    let set_name = name_str.clone();
+    if let Some((number, _)) = opts.set_position {
+        // Discs in a set may share a volume label and will share any --name
+        name_str = format!("{} (Disc {})", name_str, number);
+    }
+    if let (Some(slot), Some(_)) = (opts.slot, opts.name) {
+        // Every disc in the changer would otherwise get the same --name
+        name_str = format!("{} (Slot {})", name_str, slot);
+    }
+    name_str = output_name(&name_str, opts.name_case)?;
+    *disc_name = Some(name_str.clone());
+
+    // The discs of a set go in disc1, disc2, etc. within a folder named after the first of them
+    let set_dir = set_folder(opts, &set_name)?;
+    let planned = match (&set_dir, opts.set_position) {
+        (Some(_), Some((number, _))) => format!("disc{}", number),
+        _ => name_str.clone(),
+    };
+    let target_for = |folder: &str| match &set_dir {
+        Some(dir) => OutputTarget::in_set(&name_str, dir, folder),
+        None => OutputTarget::new(folder, opts.layout, opts.media_type),
+    };
+    let (target, replace) = match choose_output(&planned, set_dir.as_deref(), opts)? {
+        OutputPlan::Rip { name, replace } => (target_for(&name), replace),
+        OutputPlan::Skip => {
+            if opts.slot.is_none() {
+                eject_or_warn(plat_provider);
+            }
+            return Ok(target_for(&planned).skipped(serial));
+        },
+    };
+    *disc_name = Some(target.name.clone());
+    let ripped = rip_into_outdir(plat_provider, mode_func, opts, &target, metadata, replace);
+    let output = match ripped {
+        Ok(output) => RipOutput { serial, ..output },
+        Err(e) => {
+            opts.mode.sounds.play_fail(plat_provider);
+            if opts.slot.is_none() && opts.eject_on_error.unwrap_or(interactive) {
+                eject_or_warn(plat_provider); // Don't leave the disc trapped in the drive
+            }
+            return Err(e);
+        },
+    };
+
+    // Notify completion and eject
+    // TODO: Redesign to deduplicate the audio in PC-related modes.
+    opts.mode.sounds.play_done(plat_provider);
+    if opts.slot.is_some() {
+        return Ok(output); // Ejecting a changer ejects the whole magazine
+    }
+    // (Nobody's waiting to grab the disc when running unattended)
+    sleep(opts.eject_delay.unwrap_or_else(|| {
+        if interactive {
+            plat_provider.eject_delay_hint()
+        } else {
+            Duration::ZERO
+        }
+    }));
+    eject_or_warn(plat_provider);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing::sha256sum_line;
+    use crate::iso9660::DecDateTime;
+    use crate::platform::LinuxPlatformProvider;
+    use crate::test_support::scratch_path;
+    use std::borrow::Cow;
+
+    #[test]
+    fn flac_command_passes_compression_level() {
+        for level in 0..=8 {
+            let cmd = flac_command(level, &[], Path::new("track01.cdda.wav"));
+            let args: Vec<_> = cmd.get_args().collect();
+            assert_eq!(args, [format!("-{}", level).as_ref(), "track01.cdda.wav"]);
+        }
+    }
+
+    #[test]
+    fn flac_command_passes_tags() {
+        let tags = ["TITLE=A Song".to_owned(), "TRACKNUMBER=1".to_owned()];
+        let cmd = flac_command(8, &tags, Path::new("track01.cdda.wav"));
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-8", "-T", "TITLE=A Song", "-T", "TRACKNUMBER=1", "track01.cdda.wav"]);
+    }
+
+    #[test]
+    fn cdparanoia_command_applies_policy_and_passthrough() {
+        let device = OsStr::new("/dev/sr0");
+        let defaults = ModeOptions::default();
+        let cmd = cdparanoia_command(device, &defaults, 0, None);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--abort-on-skip", "-B", "-d", "/dev/sr0"]);
+
+        let mode_opts = ModeOptions {
+            paranoia: ParanoiaPolicy {
+                allow_skip: true,
+                disable_extra_paranoia: true,
+                ..Default::default()
+            },
+            cdparanoia_args: vec!["-S".to_owned(), "8".to_owned(), String::new()],
+            ..ModeOptions::default()
+        };
+        let span_cmd = cdparanoia_command(device, &mode_opts, 0, Some("3-5"));
+        let span_args: Vec<_> = span_cmd.get_args().collect();
+        assert_eq!(
+            span_args,
+            ["--disable-extra-paranoia", "-S", "8", "-B", "-d", "/dev/sr0", "3-5"]
+        );
+
+        let offset_cmd = cdparanoia_command(device, &defaults, -6, Some("2"));
+        let offset_args: Vec<_> = offset_cmd.get_args().collect();
+        assert_eq!(
+            offset_args,
+            ["--abort-on-skip", "-B", "-d", "/dev/sr0", "--sample-offset=-6", "2"]
+        );
+    }
+
+    #[test]
+    fn paranoia_policy_disabling_all_implies_extra() {
+        let policy = ParanoiaPolicy {
+            disable_paranoia: true,
+            disable_extra_paranoia: true,
+            allow_skip: false,
+        };
+        assert_eq!(policy.args(), ["--disable-paranoia", "--abort-on-skip"]);
+    }
+
+    #[test]
+    fn check_cdparanoia_arg_rejects_controlled_options() {
+        for ok in ["-v", "-S8", "-Sd", "--force-read-speed=4", "-Z", "--never-skip=5", "8", ""] {
+            assert_eq!(check_cdparanoia_arg(ok).as_deref(), Ok(ok), "{:?}", ok);
+        }
+        for bad in [
+            "-B",
+            "-vB",
+            "-d",
+            "-d/dev/sr1",
+            "--batch",
+            "--force-cdrom-device=/dev/sr1",
+            "-p",
+            "--output-aiff",
+            "-O6",
+            "--sample-offset=6",
+        ] {
+            assert!(check_cdparanoia_arg(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn cdparanoia_track_number_parses_output_names() {
+        assert_eq!(cdparanoia_track_number(Path::new("track01.cdda.wav")), Some(1));
+        assert_eq!(cdparanoia_track_number(Path::new("./track12.cdda.wav")), Some(12));
+        assert_eq!(cdparanoia_track_number(Path::new("track00.cdda.wav")), Some(0));
+        assert_eq!(cdparanoia_track_number(Path::new("other.wav")), None);
+    }
+
+    #[test]
+    fn validate_existing_checks_bin_sets_and_checksums() {
+        let outdir = scratch_path("validate");
+        let dir = outdir.join("My Game");
+        create_dir_all(&dir).expect("create dump dir");
+        fs::write(dir.join("My Game.bin"), vec![0_u8; 2352 * 4]).expect("write bin");
+        fs::write(
+            dir.join("My Game.cue"),
+            "FILE \"My Game.bin\" BINARY\n  TRACK 01 MODE2/2352\n    INDEX 01 00:00:00\n",
+        )
+        .expect("write cue");
+        validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "My Game", None)
+            .expect("consistent BIN/CUE set");
+        assert!(
+            validate_existing(validate_iso, &outdir, Layout::ByName, "CD", "My Game", None)
+                .is_err(),
+            "No ISO here"
+        );
+        assert!(validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "Other Game", None)
+            .is_err());
+
+        let hashes = hash_reader(&b"tampered"[..]).expect("in-memory reads can't fail");
+        fs::write(dir.join("My Game.bin.sha256"), sha256sum_line(&hashes, "My Game.bin"))
+            .expect("write checksum");
+        assert!(validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "My Game", None)
+            .is_err());
+        write_sha256_file(&LocalSink::default(), &dir.join("My Game.bin"), None)
+            .expect("rehash bin");
+        validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "My Game", None)
+            .expect("checksum matches again");
+
+        fs::write(dir.join("My Game.bin"), vec![0_u8; 2352 * 4 + 1]).expect("corrupt bin");
+        assert!(validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "My Game", None)
+            .is_err());
+        fs::remove_dir_all(&outdir).expect("cleanup");
+    }
+
+    #[test]
+    fn validate_iso_detects_truncation() {
+        let dir = scratch_path("validate_iso");
+        create_dir_all(&dir).expect("create dump dir");
+        let isofile = dir.join("Disc.iso");
+        fs::copy("fixture.iso", &isofile).expect("copy fixture");
+        assert_eq!(validate_iso(&dir, "Disc").expect("readable"), Vec::<String>::new());
+
+        let mut data = fs::read(&isofile).expect("read copy");
+        data.truncate(20 * 2048);
+        fs::write(&isofile, data).expect("truncate copy");
+        assert_eq!(validate_iso(&dir, "Disc").expect("readable").len(), 1);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn sha256sum_line_matches_coreutils_format() {
+        let hashes = hash_reader(&b"abc"[..]).expect("in-memory reads can't fail");
+        assert_eq!(
+            sha256sum_line(&hashes, "My_Disc.iso"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  My_Disc.iso\n"
+        );
+    }
+
+    #[test]
+    fn track_report_names_failed_tracks() {
+        assert!(track_report(12, &[]).is_ok());
+        let failed = ["track09.cdda.wav".to_owned(), "track11.cdda.wav".to_owned()];
+        let err = track_report(10, &failed).expect_err("failed tracks must be reported");
+        assert_eq!(
+            err.to_string(),
+            "2 of 12 tracks failed to encode (their WAV files were kept): track09.cdda.wav, \
+             track11.cdda.wav"
+        );
+    }
+
+    #[test]
+    fn simulated_flac_failures_are_kept_going_past_or_stop_the_rip() {
+        let dir = scratch_path("simulated_flac");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let wavs: Vec<PathBuf> =
+            (1..=2).map(|num| dir.join(format!("track{:02}.cdda.wav", num))).collect();
+        let wav_len = usize::try_from(MIN_WAV_LEN).expect("a few seconds of audio fits in memory");
+        for wav in &wavs {
+            fs::write(wav, vec![0_u8; wav_len]).expect("write WAV");
+        }
+        let tags = TrackTags::default();
+        let keep_going = ModeOptions { keep_going: true, ..ModeOptions::default() };
+        let strict = ModeOptions::default();
+        let encoder = |mode_opts| TrackEncoder {
+            tags: &tags,
+            mode_opts,
+            playlist_entries: Vec::new(),
+            failed_tracks: Vec::new(),
+        };
+        let (mut lenient, mut stopping) = (encoder(&keep_going), encoder(&strict));
+
+        env::set_var(crate::platform::SIMULATE_ERRORS_VAR, "flac=2");
+        let kept_going: Vec<_> = wavs.iter().map(|wav| lenient.encode(wav)).collect();
+        let stopped = stopping.encode(&dir.join("track01.cdda.wav"));
+        env::remove_var(crate::platform::SIMULATE_ERRORS_VAR);
+
+        assert!(kept_going.iter().all(Result::is_ok), "{:?}", kept_going);
+        assert_eq!(lenient.failed_tracks.len(), 2);
+        assert!(track_report(lenient.playlist_entries.len(), &lenient.failed_tracks).is_err());
+        let err = stopped.expect_err("without --keep-going, the rip stops");
+        assert!(
+            matches!(
+                MediaError::find(&err),
+                Some(MediaError::SubprocessFailed { code: Some(2), .. })
+            ),
+            "{:#}",
+            err
+        );
+        assert!(wavs.iter().all(|wav| wav.exists()), "WAVs are kept to be encoded by hand");
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn parse_track_list_accepts_ranges() {
+        assert_eq!(parse_track_list("3-5,8"), Ok(BTreeSet::from([3, 4, 5, 8])));
+        assert_eq!(parse_track_list(" 2 , 1-2 "), Ok(BTreeSet::from([1, 2])));
+        for bad in ["", "0", "5-3", "1-", "a", "1,,2", "-1"] {
+            assert!(parse_track_list(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parse_slot_list_names_slots_in_errors() {
+        assert_eq!(parse_slot_list("1-3,6"), Ok(BTreeSet::from([1, 2, 3, 6])));
+        assert_eq!(parse_slot_list("0"), Err("Not a valid slot number: \"0\"".to_owned()));
+        assert_eq!(parse_slot_list("4-2"), Err("Range of slots is backwards: 4-2".to_owned()));
+    }
+
+    #[test]
+    fn is_timeout_looks_through_context() {
+        let timeout = anyhow::Error::from(MediaError::Timeout {
+            device: PathBuf::from("/dev/sr0"),
+            elapsed: Duration::from_secs(60),
+        });
+        assert!(is_timeout(&timeout.context("Failed to rip disc")));
+        assert!(!is_timeout(&anyhow::Error::from(MediaError::Blank)));
+    }
+
+    #[test]
+    fn cdparanoia_spans_merges_consecutive_tracks() {
+        let tracks = parse_track_list("8,3-5,6,10").expect("valid track list");
+        assert_eq!(cdparanoia_spans(&tracks), ["3-6", "8", "10"]);
+        assert_eq!(cdparanoia_spans(&BTreeSet::new()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn tracks_to_stream_skips_data_tracks() {
+        let toc = "CD_ROM\nTRACK MODE1\nDATAFILE \"data.bin\" 00:02:00\n\
+                   TRACK AUDIO\nFILE \"audio.wav\" 0 00:10:00\n\
+                   TRACK AUDIO\nFILE \"audio.wav\" 00:10:00 00:05:00\n";
+        assert_eq!(tracks_to_stream(toc, None).expect("has audio"), [2, 3]);
+        let selected = parse_track_list("3").expect("valid track list");
+        assert_eq!(tracks_to_stream(toc, Some(&selected)).expect("selected"), [3]);
+        assert_eq!(tracks_to_stream("", Some(&selected)).expect("selected"), [3]);
+        assert!(tracks_to_stream("", None).is_err(), "Nothing to go on without a TOC");
+    }
+
+    #[test]
+    fn dump_shortfall_only_reports_short_dumps() {
+        assert_eq!(dump_shortfall(2048, 4096), Some(2048));
+        assert_eq!(dump_shortfall(0, 4096), Some(4096));
+        assert_eq!(dump_shortfall(4096, 4096), None);
+        assert_eq!(dump_shortfall(8192, 4096), None);
+    }
+
+    #[test]
+    fn layout_places_disc_dirs() {
+        let name = "My Game (Disc 1)";
+        assert_eq!(Layout::ByName.disc_dir(name, "DVD"), Path::new("My Game (Disc 1)"));
+        assert_eq!(Layout::ByType.disc_dir(name, "DVD"), Path::new("DVD/My Game (Disc 1)"));
+        assert_eq!(Layout::Flat.disc_dir(name, "DVD"), Path::new("."));
+        assert_eq!(Layout::ByType.staging_dir(name, "DVD"), Path::new("DVD/My Game (Disc 1)"));
+        assert_eq!(Layout::Flat.staging_dir(name, "DVD"), Path::new("My Game (Disc 1)"));
+    }
+
+    #[test]
+    fn create_parent_dirs_validates_components() {
+        let base = scratch_path("layout_parents");
+        create_parent_dirs(&base.join("DVD").join("Disc")).expect("portable names");
+        assert!(base.join("DVD").is_dir());
+        assert!(!base.join("DVD").join("Disc").exists(), "Only the parents are created");
+        assert!(create_parent_dirs(&base.join("bad:type").join("Disc")).is_err());
+        assert!(!base.join("bad:type").exists());
+        create_parent_dirs(Path::new("Disc")).expect("no parents to create");
+        fs::remove_dir_all(&base).expect("cleanup");
+    }
+
+    #[test]
+    fn output_path_collisions_are_caught_before_ripping() {
+        let root = scratch_path("output_collisions");
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).expect("create scratch dir");
+        let check = |layout: Layout| {
+            check_output_paths(&root, layout, &layout.staging_dir("My Game", "DVD"), "My Game")
+        };
+        for layout in [Layout::Flat, Layout::ByType, Layout::ByName] {
+            check(layout).expect("nothing in the way");
+        }
+
+        // A file where a folder will be created...
+        for (blocker, layout) in [
+            ("DVD", Layout::ByType),
+            ("My Game", Layout::ByName),
+            ("My Game.partial", Layout::Flat),
+        ] {
+            fs::write(root.join(blocker), b"").expect("create blocking file");
+            let err = check(layout).expect_err("blocked by a file");
+            assert!(err.to_string().contains("is a file"), "{}", err);
+            fs::remove_file(root.join(blocker)).expect("remove blocking file");
+        }
+
+        // ...or a folder where a file will be written
+        fs::create_dir_all(root.join("My Game.iso")).expect("create blocking dir");
+        let err = check(Layout::Flat).expect_err("blocked by a folder");
+        assert!(err.to_string().contains("is a folder"), "{}", err);
+        check(Layout::ByName).expect("only flat layouts write into the output directory");
+        fs::create_dir_all(root.join("My Game.partial").join("My Game.log")).expect("create dir");
+        assert!(check(Layout::ByName).is_err(), "resumed rips write into the .partial folder");
+        fs::remove_dir_all(&root).expect("cleanup");
+    }
+
+    #[test]
+    fn flatten_into_parent_moves_files_without_clobbering() {
+        let base = scratch_path("layout_flat");
+        let staging = base.join("Game");
+        fs::create_dir_all(&staging).expect("create staging dir");
+        fs::write(staging.join("Game.iso"), b"iso").expect("write iso");
+        fs::write(staging.join("Game.log"), b"log").expect("write log");
+
+        let moved = flatten_into_parent(&staging, false).expect("nothing in the way");
+        assert_eq!(moved, ["Game.iso", "Game.log"]);
+        assert_eq!(fs::read(base.join("Game.iso")).expect("moved iso"), b"iso");
+        assert!(!staging.exists());
+
+        fs::create_dir_all(&staging).expect("recreate staging dir");
+        fs::write(staging.join("Game.iso"), b"new").expect("write iso");
+        assert!(flatten_into_parent(&staging, false).is_err());
+        assert_eq!(fs::read(base.join("Game.iso")).expect("kept iso"), b"iso");
+        assert!(staging.join("Game.iso").exists(), "Nothing moves if anything would collide");
+
+        assert_eq!(flatten_into_parent(&staging, true).expect("replacing"), ["Game.iso"]);
+        assert_eq!(fs::read(base.join("Game.iso")).expect("replaced iso"), b"new");
+        assert_eq!(fs::read(base.join("Game.log")).expect("untouched log"), b"log");
+        fs::remove_dir_all(&base).expect("cleanup");
+    }
+
+    #[test]
+    fn validate_existing_follows_layout() {
+        let outdir = scratch_path("validate_layout");
+        let by_type = outdir.join("CD").join("My Game");
+        fs::create_dir_all(&by_type).expect("create disc dir");
+        fs::write(by_type.join("My Game.bin"), vec![0_u8; 2352]).expect("write bin");
+        fs::write(by_type.join("My Game.cue"), "FILE \"My Game.bin\" BINARY\n").expect("cue");
+        validate_existing(validate_bin, &outdir, Layout::ByType, "CD", "My Game", None)
+            .expect("found under the media type's folder");
+        assert!(validate_existing(validate_bin, &outdir, Layout::ByName, "CD", "My Game", None)
+            .is_err());
+
+        let flat = outdir.join("CD");
+        validate_existing(validate_bin, &flat, Layout::ByName, "CD", "My Game", None)
+            .expect("found in its own folder");
+        assert!(validate_existing(validate_bin, &flat, Layout::Flat, "CD", "Other", None).is_err());
+        fs::create_dir_all(flat.join("My Game.partial")).expect("create partial dir");
+        let err = validate_existing(validate_bin, &flat, Layout::Flat, "CD", "My Game", None)
+            .expect_err("unfinished flat rip");
+        assert!(err.to_string().contains("never finished"), "{}", err);
+
+        fs::remove_dir_all(&outdir).expect("cleanup");
+    }
+
+    #[test]
+    fn output_name_converts_case() {
+        let label = "FINAL_FANTASY_VII (Disc 1)";
+        assert_eq!(
+            output_name(label, NameCase::Lower).expect("valid"),
+            "final_fantasy_vii (disc 1)"
+        );
+        assert_eq!(output_name("Ys Origin", NameCase::Upper).expect("valid"), "YS ORIGIN");
+        assert_eq!(output_name(label, NameCase::Preserve).expect("valid"), label);
+        assert_eq!(output_name("ÉTÉ", NameCase::Lower).expect("valid"), "été");
+    }
+
+    #[test]
+    fn output_name_rejects_unportable_names() {
+        for bad in ["AC/DC", "Disc.", "a:b"] {
+            for case in [NameCase::Lower, NameCase::Upper, NameCase::Preserve] {
+                assert!(output_name(bad, case).is_err(), "{:?} {:?}", bad, case);
+            }
+        }
+    }
+
+    #[test]
+    fn sector_range_checks_against_disc_size() {
+        let disc = 100 * DATA_SECTOR_LEN;
+        let range = |offset, length| SectorRange { offset, length };
+        assert_eq!(range(0, None).to_bytes(DATA_SECTOR_LEN, disc).expect("whole disc"), (0, disc));
+        assert_eq!(
+            range(10, Some(20)).to_bytes(DATA_SECTOR_LEN, disc).expect("middle"),
+            (10 * DATA_SECTOR_LEN, 20 * DATA_SECTOR_LEN)
+        );
+        assert_eq!(
+            range(90, None).to_bytes(DATA_SECTOR_LEN, disc).expect("tail"),
+            (90 * DATA_SECTOR_LEN, 10 * DATA_SECTOR_LEN)
+        );
+        assert!(range(90, Some(10)).to_bytes(DATA_SECTOR_LEN, disc).is_ok());
+
+        for (offset, length) in [(100, None), (90, Some(11)), (5, Some(0)), (u64::MAX, None)] {
+            assert!(range(offset, length).to_bytes(DATA_SECTOR_LEN, disc).is_err(), "{}", offset);
+        }
+        let err = range(0, Some(u64::MAX)).to_bytes(DATA_SECTOR_LEN, disc).expect_err("huge");
+        assert!(err.to_string().contains("(100 sectors)"), "{}", err);
+    }
+
+    #[test]
+    fn partial_dumps_are_named_and_positioned() {
+        let image = LinuxPlatformProvider::new(Cow::Borrowed(OsStr::new("fixture.iso")));
+        let disc_sectors = image.device_size().expect("fixture.iso is readable") / DATA_SECTOR_LEN;
+
+        let range = SectorRange { offset: 16, length: Some(2) };
+        let (name, args, len) = partial_dump(&image, Path::new("My_Disc"), range).expect("valid");
+        assert_eq!(name, Path::new("My_Disc_sectors_16-17"));
+        assert_eq!(args, ["-i32768", "-o0", "-s4096"]);
+        assert_eq!(len, 4096);
+
+        let rest = SectorRange { offset: 1, length: None };
+        let (rest_name, _, _) = partial_dump(&image, Path::new("My_Disc"), rest).expect("valid");
+        assert_eq!(rest_name, PathBuf::from(format!("My_Disc_sectors_1-{}", disc_sectors - 1)));
+        let past_end = SectorRange { offset: disc_sectors, length: None };
+        assert!(partial_dump(&image, Path::new("My_Disc"), past_end).is_err());
+    }
+
+    #[test]
+    fn write_error_map_picks_format_by_extension() {
+        let dir = scratch_path("error_map");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let logfile = dir.join("Disc.log");
+        fs::write(&logfile, "0 +\n0 4096 +\n4096 2048 -\n").expect("write mapfile");
+
+        write_error_map(&LocalSink::default(), &logfile, &dir.join("errors.csv"))
+            .expect("write CSV");
+        let csv = fs::read_to_string(dir.join("errors.csv")).expect("read CSV");
+        assert!(csv.ends_with("2,1,bad-sector\n"), "{}", csv);
+
+        write_error_map(&LocalSink::default(), &logfile, &dir.join("errors.PGM"))
+            .expect("write PGM");
+        let pgm = fs::read(dir.join("errors.PGM")).expect("read PGM");
+        assert!(pgm.starts_with(b"P5\n") && pgm.ends_with(b"\xff\xff\x00"), "{:?}", pgm);
+
+        assert!(write_error_map(
+            &LocalSink::default(),
+            &dir.join("missing.log"),
+            &dir.join("x.csv")
+        )
+        .is_err());
+        assert!(!dir.join("x.csv").exists());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn workdir_guard_restores_cwd_on_drop() {
+        // The working directory is shared by every test thread, so changing it would break tests
+        // which open relative paths like fixture.iso. Re-run just this test in a child process.
+        if env::var_os("RIP_MEDIA_CWD_TEST").is_none() {
+            let child = Command::new(env::current_exe().expect("find test binary"))
+                .args(["--exact", "subcommands::tests::workdir_guard_restores_cwd_on_drop"])
+                .env("RIP_MEDIA_CWD_TEST", "1")
+                .output()
+                .expect("run test binary");
+            assert!(child.status.success(), "{}", String::from_utf8_lossy(&child.stdout));
+            return;
+        }
+        let outer = scratch_path("workdir_guard");
+        let before = env::current_dir().expect("get cwd");
+
+        let guard = WorkdirGuard::enter(&outer.join("Disc_1")).expect("create and enter dir");
+        let resolved = fs::canonicalize(outer.join("Disc_1")).expect("dir was created");
+        assert_eq!(guard.path(), resolved);
+        assert!(guard.path().is_absolute());
+        assert_eq!(env::current_dir().expect("get cwd"), resolved);
+        drop(guard);
+        assert_eq!(env::current_dir().expect("get cwd"), before);
+
+        // ...even when unwinding from a panic
+        let result = std::panic::catch_unwind(|| {
+            let _guard = WorkdirGuard::enter(&outer.join("Disc_2")).expect("enter dir");
+            panic!("mode function blew up");
+        });
+        assert!(result.is_err());
+        assert_eq!(env::current_dir().expect("get cwd"), before);
+
+        assert!(WorkdirGuard::enter(Path::new("/dev/null/nope")).is_err());
+        assert_eq!(env::current_dir().expect("get cwd"), before);
+
+        // ...and with_output_dir returns to it when the rip fails
+        let ripped: Result<()> = with_output_dir(&outer.join("Out"), || {
+            let cwd = env::current_dir().expect("get cwd");
+            assert_eq!(cwd, fs::canonicalize(outer.join("Out")).expect("dir was created"));
+            bail!("Could not rip")
+        });
+        assert!(ripped.is_err());
+        assert_eq!(env::current_dir().expect("get cwd"), before);
+        fs::remove_dir_all(&outer).expect("cleanup");
+    }
+
+    #[test]
+    fn strip_media_extension_prevents_double_extensions() {
+        assert_eq!(strip_media_extension("Game.iso"), "Game");
+        assert_eq!(strip_media_extension("Game.ISO"), "Game");
+        assert_eq!(
+            strip_media_extension("Final Fantasy VII (Disc 1).cue"),
+            "Final Fantasy VII (Disc 1)"
+        );
+        assert_eq!(strip_media_extension("Album"), "Album");
+        assert_eq!(strip_media_extension("foo.bar"), "foo.bar");
+        assert_eq!(strip_media_extension("Greatest Hits Vol. 2"), "Greatest Hits Vol. 2");
+
+        // Only one extension is removed, and never the whole name
+        assert_eq!(strip_media_extension("Game.iso.iso"), "Game.iso");
+        assert_eq!(strip_media_extension(".iso"), ".iso");
+
+        // ...so the canonical extension can be appended without doubling up or eating dots
+        let volbase = Path::new(strip_media_extension("Game.iso"));
+        assert_eq!(volume_file(volbase, "iso"), Path::new("Game.iso"));
+        assert_eq!(volume_file(Path::new("foo.bar"), "iso"), Path::new("foo.bar.iso"));
+        assert_eq!(volume_file(Path::new("Vol._2"), "cue"), Path::new("Vol._2.cue"));
+    }
+
+    #[test]
+    fn estimate_rip_time_picks_cd_or_dvd_speed() {
+        let (cd_time, cd_speed) = estimate_rip_time(700 * 1024 * 1024);
+        assert_eq!(cd_speed, "24x CD");
+        assert_eq!(cd_time, Duration::from_secs(200));
+
+        let (dvd_time, dvd_speed) = estimate_rip_time(4_700_000_000);
+        assert_eq!(dvd_speed, "8x DVD");
+        assert_eq!(dvd_time, Duration::from_secs(425));
+        assert_eq!(estimate_rip_time(0).0, Duration::ZERO);
+    }
+
+    #[test]
+    fn format_duration_omits_leading_zero_units() {
+        assert_eq!(format_duration(Duration::from_secs(7)), "7s");
+        assert_eq!(format_duration(Duration::from_secs(199)), "3m 19s");
+        assert_eq!(format_duration(Duration::from_secs(3723)), "1h 02m 03s");
+    }
+
+    #[test]
+    fn disc_probe_reports_known_and_unknown_fields() {
+        let report = DiscProbe {
+            device_size: Some(700 * 1024 * 1024),
+            sector_size: Some(2048),
+            track_modes: Some(vec!["MODE1".to_owned(), "AUDIO".to_owned()]),
+            media_type: Some(MediaType::DataCd),
+            filesystem: Some(Filesystem::Iso9660),
+            volume_label: Some("GAME_DISC".to_owned()),
+            volume_dates: Some(VolumeDates {
+                created: DecDateTime::parse(b"2017021309055342\xec"),
+                ..VolumeDates::default()
+            }),
+        };
+        assert_eq!(
+            report.to_string(),
+            "Device size:     700.0 MiB (734003200 bytes)\n\
+             Sector size:     2048 bytes\n\
+             Tracks:          2 (MODE1, AUDIO)\n\
+             Media type:      data CD\n\
+             Filesystem:      ISO 9660\n\
+             Volume label:    GAME_DISC\n\
+             Volume created:  2017-02-13T09:05:53.42-05:00\n\
+             Volume modified: unknown\n\
+             Estimated time:  3m 20s (at 24x CD)"
+        );
+
+        let blank = DiscProbe::default().to_string();
+        assert!(blank.lines().all(|line| line.ends_with("unknown")), "{}", blank);
+    }
+
+    #[test]
+    fn minimum_dump_lens_are_whole_units() {
+        assert_eq!(MIN_BIN_LEN % RAW_SECTOR_LEN, 0);
+        assert_eq!(MIN_ISO_LEN % DATA_SECTOR_LEN, 0);
+        assert_eq!((MIN_WAV_LEN - WAV_HEADER_LEN) % CDDA_BYTES_PER_SEC, 0);
+
+        // A 4-second track is 300 sectors whether it's data or audio
+        assert_eq!(MIN_BIN_LEN, 300 * RAW_SECTOR_LEN);
+        assert_eq!(MIN_WAV_LEN - WAV_HEADER_LEN, 300 * RAW_SECTOR_LEN);
+    }
+
+    #[test]
+    fn ps2_discs_are_told_apart() {
+        let single = fs::read("fixture.iso").expect("fixture.iso exists");
+        let cd = scratch_path("ps2_cd.iso");
+        fs::write(&cd, &single).expect("write image");
+        assert_eq!(detect_ps2_disc(&cd).expect("readable"), Ps2Disc::Cd);
+
+        // A DVD-sized image without a second volume descriptor is single-layer...
+        let dvd5 = File::options().append(true).open(&cd).expect("open image");
+        dvd5.set_len(MAX_CD_LEN + DATA_SECTOR_LEN).expect("grow image");
+        assert_eq!(detect_ps2_disc(&cd).expect("readable"), Ps2Disc::DvdSingleLayer);
+
+        // ...while one which starts over where the first volume ends is dual-layer
+        let mut dual = single[..25 * 2048].to_vec();
+        dual.extend_from_slice(&single);
+        fs::write(&cd, dual).expect("write image");
+        assert_eq!(detect_ps2_disc(&cd).expect("readable"), Ps2Disc::DvdDualLayer(25));
+        fs::remove_file(&cd).expect("cleanup");
+        assert!(detect_ps2_disc(&cd).is_err());
+    }
+
+    #[test]
+    fn audio_tracks_are_marked_in_cue_sheets() {
+        let cue = "FILE \"My Game.bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n  \
+                   TRACK 02 AUDIO\n    INDEX 01 00:10:00\n";
+        assert_eq!(
+            mark_audio_tracks(cue),
+            "  FILE \"My Game.bin\" BINARY\n    TRACK 01 MODE1/2352\n      INDEX 01 00:00:00\n\
+             *   TRACK 02 AUDIO\n      INDEX 01 00:10:00\n"
+        );
+        assert_eq!(mark_audio_tracks("TITLE \"TRACK 1 AUDIO\"\n"), "  TITLE \"TRACK 1 AUDIO\"\n");
+    }
+
+    #[test]
+    fn subchannel_data_is_split_out() {
+        let dir = scratch_path("split_subchannel");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let (binfile, subfile) = (dir.join("Disc.bin"), dir.join("Disc.sub"));
+        let sector = usize::try_from(RAW_SECTOR_LEN).expect("small constant");
+        let subchannel = usize::try_from(SUBCHANNEL_LEN).expect("small constant");
+        let mut interleaved = Vec::new();
+        for fill in [1, 2] {
+            interleaved.extend(vec![fill; sector]);
+            interleaved.extend(vec![fill + 10; subchannel]);
+        }
+        fs::write(&binfile, &interleaved).expect("write bin");
+
+        assert_eq!(
+            split_subchannel(&LocalSink::default(), &binfile, &subfile).expect("whole sectors"),
+            2
+        );
+        let bin = fs::read(&binfile).expect("read bin");
+        let sub = fs::read(&subfile).expect("read sub");
+        assert_eq!(bin.len(), 2 * sector);
+        assert!(bin[..sector].iter().all(|&byte| byte == 1));
+        assert!(bin[sector..].iter().all(|&byte| byte == 2));
+        assert_eq!(sub.len(), 2 * subchannel);
+        assert!(sub[..subchannel].iter().all(|&byte| byte == 11));
+        assert!(sub[subchannel..].iter().all(|&byte| byte == 12));
+
+        // What's left is no longer a whole number of sectors with subchannel data
+        assert!(split_subchannel(&LocalSink::default(), &binfile, &subfile).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+
+        assert_eq!(
+            strip_toc_subchannel(
+                "CD_ROM\nTRACK MODE1_RAW RW_RAW\nDATAFILE \"x.bin\"\nTRACK AUDIO RW\n"
+            ),
+            "CD_ROM\nTRACK MODE1_RAW\nDATAFILE \"x.bin\"\nTRACK AUDIO\n"
+        );
+    }
+
+    #[test]
+    fn audio_ranges_are_byte_swapped() {
+        let dir = scratch_path("swap_audio");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let binfile = dir.join("Disc.bin");
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(200_000).collect();
+        fs::write(&binfile, &data).expect("write bin");
+
+        // The second audio track crosses chunk boundaries and the last runs to the end of the file
+        let toc = "CD_ROM\nTRACK AUDIO\nFILE \"Disc.bin\" #4 0 2\nTRACK MODE1_RAW\n\
+                   DATAFILE \"Disc.bin\" #12 00:00:01\nTRACK AUDIO\nFILE \"Disc.bin\" #1000 0 37250\n\
+                   TRACK AUDIO\nFILE \"Disc.bin\" #199996 0\n";
+        assert_eq!(
+            swap_audio_tracks(&LocalSink::default(), &binfile, toc).expect("swappable"),
+            8 + 149_000 + 4
+        );
+        let swapped = fs::read(&binfile).expect("read bin");
+        assert_eq!(swapped.len(), data.len());
+        assert_eq!(swapped[..12], [0, 1, 2, 3, 5, 4, 7, 6, 9, 8, 11, 10]);
+        assert_eq!(swapped[12..1000], data[12..1000]);
+        assert_eq!(swapped[1000..1004], [data[1001], data[1000], data[1003], data[1002]]);
+        assert_eq!(swapped[150_000..199_996], data[150_000..199_996]);
+        assert_eq!(
+            swapped[199_996..],
+            [data[199_997], data[199_996], data[199_999], data[199_998]]
+        );
+        assert!(swapped[1000..150_000]
+            .chunks(2)
+            .eq(data[1000..150_000].chunks(2).map(|pair| [pair[1], pair[0]])));
+
+        // Data-only dumps are left alone
+        assert_eq!(
+            swap_audio_tracks(&LocalSink::default(), &binfile, "TRACK MODE1\n").ok(),
+            Some(0)
+        );
+        assert_eq!(fs::read(&binfile).expect("read bin"), swapped);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn swapped_bins_keep_a_matching_toc() {
+        let dir = scratch_path("swap_toc");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let (binfile, tocfile) = (dir.join("Disc.bin"), dir.join("Disc.toc"));
+        let data: Vec<u8> = (0..=u8::MAX).cycle().take(2352 * 3).collect();
+        fs::write(&binfile, &data).expect("write bin");
+        let toc = "CD_ROM\nTRACK MODE1_RAW\nDATAFILE \"Disc.bin\" 00:00:01\n\
+                   TRACK AUDIO\nFILE \"Disc.bin\" #2352 0 00:00:02\n";
+        fs::write(&tocfile, toc).expect("write toc");
+
+        swap_bin_audio(&LocalSink::default(), &binfile, &tocfile, toc).expect("swappable");
+        let kept = fs::read_to_string(&tocfile).expect("read toc");
+        assert_eq!(kept, mark_swapped_audio(toc));
+        assert!(kept.contains("FILE \"Disc.bin\" SWAP #2352"), "{}", kept);
+
+        // Reading the samples the way the kept TOC says to gives back what cdrdao dumped
+        let swapped = fs::read(&binfile).expect("read bin");
+        assert_eq!(audio_file_ranges(&kept).first(), Some(&(2352..2352 * 3)));
+        assert!(swapped[2352..]
+            .chunks(2)
+            .eq(data[2352..].chunks(2).map(|pair| [pair[1], pair[0]])));
+        assert_eq!(swapped[..2352], data[..2352]);
+
+        // Data-only dumps keep their TOC as-is
+        let data_toc = "TRACK MODE1_RAW\nDATAFILE \"Disc.bin\" 00:00:03\n";
+        fs::write(&tocfile, data_toc).expect("write toc");
+        swap_bin_audio(&LocalSink::default(), &binfile, &tocfile, data_toc).expect("no audio");
+        assert_eq!(fs::read_to_string(&tocfile).expect("read toc"), data_toc);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn par2_command_covers_the_given_files() {
+        let files = ["My Game.iso".to_owned(), "My Game.sha256".to_owned()];
+        let cmd = par2_command("My Game", &files);
+        assert_eq!(cmd.get_program(), "par2create");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["-n1", "My Game.par2", "My Game.iso", "My Game.sha256"]);
+        assert!(has_extension("My Game.vol0+1.PAR2", "par2"));
+        assert!(!has_extension("My Game.par2.txt", "par2"));
+    }
+
+    #[test]
+    fn existing_dumps_can_be_processed_again() {
+        let root = scratch_path("process_existing");
+        let dir = root.join("My Game");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        fs::copy("fixture.iso", dir.join("My Game.iso")).expect("copy fixture");
+        fs::write(dir.join("My Game.log"), "# Mapfile\n").expect("write log");
+        let post = PostProcessOptions { checksums: true, ..PostProcessOptions::default() };
+
+        process_existing(&dir, None, post).expect("process dump");
+        let sumfile = dir.join("My Game.sha256");
+        let sums = fs::read_to_string(&sumfile).expect("checksums written");
+        assert_eq!(sums.lines().count(), 2, "{}", sums);
+        assert!(sums.contains("  My Game.iso\n") && sums.contains("  My Game.log\n"));
+        assert!(verify_checksum_files(&dir, None).expect("readable").is_empty());
+
+        // Running it again gives the same result rather than listing the checksum file too
+        process_existing(&dir, Some("My Game"), post).expect("process again");
+        assert_eq!(fs::read_to_string(&sumfile).expect("checksums rewritten"), sums);
+
+        assert!(process_existing(&dir, None, PostProcessOptions::default()).is_err());
+        let partial = root.join("Other.partial");
+        fs::create_dir_all(&partial).expect("create partial dir");
+        assert!(process_existing(&partial, None, post).is_err());
+        assert!(process_existing(&root.join("Missing"), None, post).is_err());
+        fs::remove_dir_all(&root).expect("cleanup");
+    }
+
+    /// A sink which writes locally, but claims to have a fixed amount of free space
+    #[derive(Debug)]
+    struct FixedSpaceSink(u64);
+
+    impl OutputSink for FixedSpaceSink {
+        fn path(&self, name: &Path) -> PathBuf {
+            LocalSink::default().path(name)
+        }
+        fn create(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+            LocalSink::default().create(path)
+        }
+        fn finalize(&self, from: &Path, to: &Path) -> io::Result<()> {
+            LocalSink::default().finalize(from, to)
+        }
+        fn remove(&self, path: &Path) -> io::Result<()> {
+            LocalSink::default().remove(path)
+        }
+        fn list(&self) -> io::Result<Vec<PathBuf>> {
+            LocalSink::default().list()
+        }
+        fn available_space(&self) -> Result<Option<u64>> {
+            Ok(Some(self.0))
+        }
+    }
+
+    #[test]
+    fn free_space_is_checked_against_the_dump_size() {
+        assert_eq!(space_shortfall(10 * FREE_SPACE_MARGIN, 9 * FREE_SPACE_MARGIN), None);
+        assert_eq!(
+            space_shortfall(9 * FREE_SPACE_MARGIN, 9 * FREE_SPACE_MARGIN),
+            Some(FREE_SPACE_MARGIN)
+        );
+        assert_eq!(space_shortfall(0, u64::MAX), Some(u64::MAX));
+
+        let dvd = 4_700_000_000;
+        let roomy =
+            ModeOptions { sink: Rc::new(FixedSpaceSink(2 * dvd)), ..ModeOptions::default() };
+        check_free_space(dvd, &roomy).expect("plenty of room");
+        let cramped = ModeOptions { sink: Rc::new(FixedSpaceSink(dvd / 2)), ..roomy };
+        check_free_space(dvd, &cramped).expect("only a warning by default");
+        let strict = ModeOptions { strict: true, ..cramped };
+        let err = check_free_space(dvd, &strict).expect_err("--strict refuses");
+        assert!(err.to_string().contains("only 2.2 GiB is free"), "{}", err);
+    }
+
+    #[test]
+    fn bin_dumps_are_estimated_from_the_user_data_size() {
+        assert_eq!(bin_dump_len(10 * DATA_SECTOR_LEN, false), 10 * RAW_SECTOR_LEN);
+        assert_eq!(bin_dump_len(10 * DATA_SECTOR_LEN + 5, false), 10 * RAW_SECTOR_LEN);
+        assert_eq!(
+            bin_dump_len(10 * DATA_SECTOR_LEN, true),
+            20 * (RAW_SECTOR_LEN + SUBCHANNEL_LEN)
+        );
+    }
+
+    #[test]
+    fn check_dump_len_rejects_empty_and_tiny_dumps() {
+        let dir = scratch_path("dump_len");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let dump = dir.join("disc.iso");
+
+        let missing = check_dump_len(&dump, MIN_ISO_LEN).expect_err("no dump at all");
+        assert!(missing.to_string().contains("Could not check"), "{}", missing);
+
+        fs::write(&dump, b"").expect("write empty dump");
+        let empty = check_dump_len(&dump, MIN_ISO_LEN).expect_err("empty dump");
+        assert!(empty.to_string().contains("is empty"), "{}", empty);
+
+        fs::write(&dump, vec![0; 2048]).expect("write tiny dump");
+        let tiny = check_dump_len(&dump, MIN_ISO_LEN).expect_err("tiny dump");
+        assert!(tiny.to_string().contains("only 2048 bytes"), "{}", tiny);
+
+        fs::write(&dump, vec![0; 18 * 2048]).expect("write minimal dump");
+        check_dump_len(&dump, MIN_ISO_LEN).expect("smallest valid ISO is accepted");
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn track_file_name_follows_redump() {
+        assert_eq!(track_file_name("Game", 2, 3), "Game (Track 2).bin");
+        assert_eq!(track_file_name("Game", 2, 12), "Game (Track 02).bin");
+    }
+
+    #[test]
+    fn split_bin_writes_one_file_per_track() {
+        let dir = scratch_path("split_bin");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let cuefile = dir.join("Game.cue");
+        fs::write(
+            &cuefile,
+            "FILE Game.bin BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n  \
+             TRACK 02 AUDIO\n    INDEX 00 00:00:03\n    INDEX 01 00:00:05\n",
+        )
+        .expect("write CUE");
+        let sector = usize::try_from(RAW_SECTOR_LEN).expect("small constant");
+        let bin: Vec<u8> = (0_u8..8).flat_map(|idx| vec![idx; sector]).collect();
+        fs::write(dir.join("Game.bin"), &bin).expect("write BIN");
+
+        let tracks = split_bin(&cuefile, None).expect("consistent BIN/CUE pair");
+        assert_eq!(tracks, [dir.join("Game (Track 1).bin"), dir.join("Game (Track 2).bin")]);
+        assert!(!dir.join("Game.bin").exists());
+        let pieces: Vec<Vec<u8>> =
+            tracks.iter().map(|path| fs::read(path).expect("read track")).collect();
+        assert_eq!(pieces[0].len(), 3 * sector);
+        assert_eq!(pieces.concat(), bin);
+
+        let cue = fs::read_to_string(&cuefile).expect("read rewritten CUE");
+        assert!(cue.contains("INDEX 00 00:00:00\n    INDEX 01 00:00:02"), "{}", cue);
+        assert!(verify_bin_set(&cuefile, None).expect("check split set").is_empty());
+
+        // Refuse to split a BIN which doesn't match its CUE, leaving it untouched
+        fs::write(&cuefile, "FILE Bad.bin BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:09\n")
+            .expect("write CUE");
+        fs::write(dir.join("Bad.bin"), &bin).expect("write BIN");
+        assert!(split_bin(&cuefile, None).is_err());
+        assert!(dir.join("Bad.bin").exists());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn redump_names_keep_spaces_and_skip_lone_track_numbers() {
+        assert_eq!(redump_track_name("Game (USA)", 1, 1), "Game (USA).bin");
+        assert_eq!(redump_track_name("Game (USA)", 2, 3), "Game (USA) (Track 2).bin");
+        assert_eq!(redump_track_name("Game (USA)", 4, 12), "Game (USA) (Track 04).bin");
+
+        let dir = scratch_path("split_bin_redump");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let cuefile = dir.join("Game_(USA).cue");
+        fs::write(
+            &cuefile,
+            "FILE Game_(USA).bin BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n",
+        )
+        .expect("write CUE");
+        let sector = usize::try_from(RAW_SECTOR_LEN).expect("small constant");
+        fs::write(dir.join("Game_(USA).bin"), vec![7; 2 * sector]).expect("write BIN");
+
+        let tracks = split_bin(&cuefile, Some("Game (USA)")).expect("consistent BIN/CUE pair");
+        assert_eq!(tracks, [dir.join("Game (USA).bin")]);
+        assert!(!dir.join("Game_(USA).bin").exists());
+        assert_eq!(fs::read(&tracks[0]).expect("read track").len(), 2 * sector);
+        let cue = fs::read_to_string(&cuefile).expect("read rewritten CUE");
+        assert!(cue.starts_with("FILE \"Game (USA).bin\" BINARY"), "{}", cue);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    /// A user who gives canned answers to prompts, in order
+    struct ScriptedAnswers(std::cell::RefCell<Vec<&'static str>>);
+
+    impl NotificationProvider for ScriptedAnswers {
+        fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, _path: &P) -> Result<(), MediaError> {
+            Ok(())
+        }
+
+        fn is_interactive(&self) -> bool {
+            true
+        }
+
+        fn read_line(&self, _prompt: &str) -> Result<String, MediaError> {
+            let mut answers = self.0.borrow_mut();
+            if answers.is_empty() {
+                return Err(anyhow!("Asked more questions than expected").into());
+            }
+            Ok(answers.remove(0).to_owned())
+        }
+
+        fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError> {
+            self.read_line(prompt)
+        }
+    }
+
+    /// A drive which times out the first `timeouts` times it's waited on
+    struct FlakyDrive {
+        /// The user at the keyboard
+        user: ScriptedAnswers,
+        /// How many more waits should time out
+        timeouts: std::cell::Cell<u32>,
+        /// How many times the tray has been closed
+        loads: u32,
+        /// How many more unmounts should fail because the disc is in use
+        busy: u32,
+        /// The volume label of the disc
+        label: &'static str,
+        /// The sounds which have been played
+        played: Vec<PathBuf>,
+        /// What `disc_serial` will return each time it's called (`""` for no disc, and `"-"` for
+        /// one without a serial)
+        serials: std::cell::RefCell<std::collections::VecDeque<&'static str>>,
+    }
+
+    impl MediaProvider for FlakyDrive {
+        fn eject(&mut self) -> Result<(), MediaError> {
+            Ok(())
+        }
+
+        fn load(&mut self) -> Result<(), MediaError> {
+            self.loads += 1;
+            Ok(())
+        }
+
+        fn unmount(&mut self) -> Result<(), MediaError> {
+            if self.busy == 0 {
+                return Ok(());
+            }
+            self.busy -= 1;
+            Err(MediaError::Busy(PathBuf::from("/dev/sr0")))
+        }
+
+        fn volume_label(&self) -> Result<String, MediaError> {
+            Ok(self.label.to_owned())
+        }
+
+        fn wait_for_ready(&self, timeout: &Duration) -> Result<(), MediaError> {
+            let remaining = self.timeouts.get();
+            if remaining == 0 {
+                return Ok(());
+            }
+            self.timeouts.set(remaining - 1);
+            Err(MediaError::Timeout { device: PathBuf::from("/dev/sr0"), elapsed: *timeout })
+        }
+
+        fn filesystem(&self) -> Result<Filesystem, MediaError> {
+            Ok(Filesystem::Iso9660)
+        }
+
+        fn disc_serial(&self) -> Result<Option<String>, MediaError> {
+            match self.serials.borrow_mut().pop_front() {
+                Some("") | None => Err(anyhow!("No disc in the drive").into()),
+                Some("-") => Ok(None),
+                Some(serial) => Ok(Some(serial.to_owned())),
+            }
+        }
+    }
+
+    impl NotificationProvider for FlakyDrive {
+        fn play_sound<P: AsRef<Path> + ?Sized>(&mut self, path: &P) -> Result<(), MediaError> {
+            self.played.push(path.as_ref().to_owned());
+            Ok(())
+        }
+
+        fn is_interactive(&self) -> bool {
+            self.user.is_interactive()
+        }
+
+        fn read_line(&self, prompt: &str) -> Result<String, MediaError> {
+            self.user.read_line(prompt)
+        }
+
+        fn read_line_unrecorded(&self, prompt: &str) -> Result<String, MediaError> {
+            self.user.read_line_unrecorded(prompt)
+        }
+    }
+
+    /// A drive which needs `timeouts` retries, with a user who will answer `answers`
+    fn flaky_drive(timeouts: u32, answers: Vec<&'static str>) -> FlakyDrive {
+        FlakyDrive {
+            user: ScriptedAnswers(answers.into()),
+            timeouts: std::cell::Cell::new(timeouts),
+            loads: 0,
+            busy: 0,
+            label: "FLAKY",
+            played: Vec::new(),
+            serials: std::cell::RefCell::default(),
+        }
+    }
+
+    #[test]
+    fn sounds_skip_missing_files_and_respect_muting() {
+        let mut drive = flaky_drive(0, vec![]);
+        let mut sounds = Sounds {
+            done: PathBuf::from("fixture.iso"),
+            fail: PathBuf::from("/nonexistant_test_path.ogg"),
+            muted: false,
+        };
+        sounds.play_done(&mut drive);
+        sounds.play_fail(&mut drive);
+        assert_eq!(drive.played, [Path::new("fixture.iso")]);
+
+        sounds.muted = true;
+        sounds.play_done(&mut drive);
+        assert_eq!(drive.played.len(), 1, "Muted sounds aren't played");
+    }
+
+    #[test]
+    fn load_until_ready_retries_after_reseating() {
+        let mut drive = flaky_drive(2, vec!["", ""]);
+        load_until_ready(&mut drive, false, Duration::ZERO, 3).expect("ready on the third try");
+        assert_eq!(drive.loads, 3, "The tray should be closed again for each retry");
+
+        let mut no_load = flaky_drive(1, vec![""]);
+        load_until_ready(&mut no_load, true, Duration::ZERO, 1).expect("ready on the second try");
+        assert_eq!(no_load.loads, 0);
+    }
+
+    #[test]
+    fn ensure_unmounted_lets_the_user_free_the_disc() {
+        let mut drive = FlakyDrive { busy: 2, ..flaky_drive(0, vec!["", ""]) };
+        ensure_unmounted(&mut drive, true).expect("unmounted on the third try");
+
+        let mut skipped = FlakyDrive { busy: 5, ..flaky_drive(0, vec!["s", "s"]) };
+        ensure_unmounted(&mut skipped, false).expect("skipping is fine unless required");
+        let err = ensure_unmounted(&mut skipped, true).expect_err("required");
+        assert!(matches!(err.downcast_ref(), Some(MediaError::Busy(_))), "{:?}", err);
+    }
+
+    #[test]
+    fn ensure_vol_label_prompts_for_unlabeled_discs() {
+        let drive = flaky_drive(0, vec![]);
+        assert_eq!(ensure_vol_label(&drive, Some("Given"), false).expect("named"), "Given");
+        assert_eq!(ensure_vol_label(&drive, None, false).expect("labeled"), "FLAKY");
+
+        let unlabeled = FlakyDrive { label: "  ", ..flaky_drive(0, vec!["", " My Disc "]) };
+        assert_eq!(ensure_vol_label(&unlabeled, None, false).expect("named by user"), "My Disc");
+        assert!(ensure_vol_label(&unlabeled, None, false).is_err(), "Prompt failures are reported");
+
+        // --yes names it without asking, like when nobody's there
+        let name = ensure_vol_label(&unlabeled, None, true).expect("named automatically");
+        assert!(name.starts_with("Unlabeled Disc "), "{}", name);
+    }
+
+    #[test]
+    fn portable_name_sanitizes_unusable_labels() {
+        assert_eq!(portable_name("Final Fantasy VII"), "Final Fantasy VII");
+        assert_eq!(portable_name("CD:ROM"), "CD_ROM");
+        assert_eq!(portable_name("What? <Disc> 1/2"), "What_ _Disc_ 1_2");
+        assert_eq!(portable_name("Tab\tSeparated"), "Tab_Separated");
+        assert_eq!(portable_name("Ends Badly. . "), "Ends Badly");
+        assert_eq!(portable_name("CON"), "_CON");
+        assert_eq!(portable_name("..."), "_");
+
+        let long = portable_name(&"\u{e9}".repeat(200));
+        assert!(long.len() <= 255 && filename_valid_portable(&long).is_ok(), "{:?}", long);
+
+        let unlabeled = FlakyDrive { label: "AUX:", ..flaky_drive(0, vec![]) };
+        assert_eq!(ensure_vol_label(&unlabeled, None, false).expect("labeled"), "AUX_");
+    }
+
+    #[test]
+    fn ask_for_disc_waits_for_the_next_disc_of_a_set_with_yes() {
+        let drive = flaky_drive(0, vec![]);
+        *drive.serials.borrow_mut() = ["DISC1", "", "", "DISC1", "DISC2"].into();
+        wait_for_disc_change(&drive, "DISC1", Duration::ZERO);
+        assert!(drive.serials.borrow().is_empty(), "Stopped waiting before the disc changed");
+
+        // Without a serial to compare to, the next disc is assumed to be inserted
+        let mut opts = RipOptions { set_position: Some((2, 3)), ..RipOptions::default() };
+        opts.mode.assume_yes = true;
+        ask_for_disc(&drive, &opts).expect("nothing to wait for");
+
+        // Discs without a serial (eg. audio CDs) count as a change
+        *drive.serials.borrow_mut() = ["", "-"].into();
+        wait_for_disc_change(&drive, "DISC2", Duration::ZERO);
+        assert!(drive.serials.borrow().is_empty());
+    }
+
+    #[test]
+    fn out_of_time_only_after_the_deadline() {
+        assert!(!out_of_time(None, 0));
+        let later = Instant::now().checked_add(Duration::from_secs(100));
+        assert!(!out_of_time(later, 3));
+        assert!(out_of_time(Some(Instant::now()), 3));
+    }
+
+    #[test]
+    fn load_until_ready_gives_up() {
+        let mut out_of_retries = flaky_drive(3, vec!["", ""]);
+        let err = load_until_ready(&mut out_of_retries, false, Duration::ZERO, 2)
+            .expect_err("never ready");
+        assert!(matches!(err.downcast_ref(), Some(MediaError::Timeout { .. })), "{:?}", err);
+
+        let mut quitter = flaky_drive(3, vec![" Q "]);
+        assert!(load_until_ready(&mut quitter, false, Duration::ZERO, 5).is_err());
+        assert_eq!(quitter.loads, 1);
+    }
+
+    #[test]
+    fn ask_metadata_only_asks_for_missing_fields() {
+        let user = ScriptedAnswers(vec!["  PSX ", ""].into());
+        let given = DiscMetadata { title: Some("Some Game".to_owned()), ..DiscMetadata::default() };
+        let answered = ask_metadata(&user, &given, false, NameCase::Preserve).expect("answered");
+        assert_eq!(answered.title.as_deref(), Some("Some Game"));
+        assert_eq!(answered.platform.as_deref(), Some("PSX"));
+        assert_eq!(answered.notes, None);
+        assert!(user.0.borrow().is_empty());
+    }
+
+    #[test]
+    fn ask_metadata_reasks_for_unusable_titles_when_naming() {
+        let user = ScriptedAnswers(vec!["Bad/Title", "Good Title", "", ""].into());
+        let answered = ask_metadata(&user, &DiscMetadata::default(), true, NameCase::Preserve)
+            .expect("answered");
+        assert_eq!(answered.title.as_deref(), Some("Good Title"));
+
+        // Without naming, any title is fine and a blank one isn't re-asked for
+        let relaxed = ScriptedAnswers(vec!["", "", ""].into());
+        let blank = ask_metadata(&relaxed, &DiscMetadata::default(), false, NameCase::Preserve)
+            .expect("answered");
+        assert!(blank.is_empty());
+    }
+
+    #[test]
+    fn get_cd_key_records_confirmed_keys_in_the_disc_folder() {
+        let dir = scratch_path("cd_key");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let sink = LocalSink::new(dir.clone());
+
+        let user = ScriptedAnswers(vec!["WRONG-KEY", "n", " ABCD-1234 ", "y"].into());
+        get_cd_key(&user, "My Game", &sink, false).expect("key entered");
+        assert!(user.0.borrow().is_empty());
+        let key = fs::read_to_string(dir.join(CD_KEY_FILE)).expect("read key");
+        assert_eq!(key, "ABCD-1234\n");
+
+        fs::remove_file(dir.join(CD_KEY_FILE)).expect("remove key");
+        get_cd_key(&ScriptedAnswers(vec!["", "y"].into()), "My Game", &sink, false)
+            .expect("no key");
+        assert!(file_names(&dir).expect("list").is_empty(), "No key means no file");
+
+        // --yes takes the first answer without confirming it
+        let hasty_user = ScriptedAnswers(vec!["EFGH-5678"].into());
+        get_cd_key(&hasty_user, "My Game", &sink, true).expect("key entered");
+        assert!(hasty_user.0.borrow().is_empty());
+        let unconfirmed = fs::read_to_string(dir.join(CD_KEY_FILE)).expect("read key");
+        assert_eq!(unconfirmed, "EFGH-5678\n");
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn write_redump_info_hashes_the_images_in_the_sink() {
+        let dir = scratch_path("redump_info");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let sink = LocalSink::new(dir.clone());
+        sink.write(&sink.path(Path::new("My Game.iso")), b"data").expect("write image");
+        sink.write(&sink.path(Path::new("My Game.log")), b"# mapfile").expect("write log");
+
+        let user = ScriptedAnswers(vec!["IFPI L555", "", "", "", ""].into());
+        write_redump_info(&user, &sink, "My Game").expect("write report");
+        let report = fs::read_to_string(dir.join("My Game.redump.txt")).expect("read report");
+        assert!(report.contains("(laser branded/etched): IFPI L555\n"), "{}", report);
+        assert!(report.contains("My Game.iso"), "{}", report);
+        assert!(!report.contains("My Game.log"), "{}", report);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn write_metadata_names_file_after_disc() {
+        let dir = scratch_path("metadata");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        write_metadata(&dir, &DiscMetadata::default(), "My Game").expect("nothing to write");
+        assert!(file_names(&dir).expect("list").is_empty());
+        let notes = DiscMetadata { notes: Some("Cracked case".to_owned()), ..Default::default() };
+        write_metadata(&dir, &notes, "My Game").expect("write metadata");
+        assert_eq!(file_names(&dir).expect("list"), ["My Game.metadata.json"]);
+        let json = fs::read_to_string(dir.join("My Game.metadata.json")).expect("read metadata");
+        assert_eq!(json, "{\n  \"notes\": \"Cracked case\"\n}\n");
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn format_gdi_lists_tracks() {
+        let tracks = [
+            ("Game (Track 1).bin".to_owned(), 0, false),
+            ("Game (Track 2).bin".to_owned(), 756, true),
+        ];
+        assert_eq!(
+            format_gdi(&tracks),
+            "2\n1 0 4 2352 \"Game (Track 1).bin\" 0\n2 756 0 2352 \"Game (Track 2).bin\" 0\n"
+        );
+    }
+
+    #[test]
+    fn write_gdi_uses_file_sizes_and_toc_modes() {
+        let dir = scratch_path("gdi");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let sink = LocalSink::new(dir.clone());
+        let cuefile = sink.path(Path::new("Game.cue"));
+        let tocfile = sink.path(Path::new("Game.toc"));
+        fs::write(
+            &cuefile,
+            "FILE \"Game (Track 1).bin\" BINARY\n  TRACK 01 MODE1/2352\n    INDEX 01 00:00:00\n\
+             FILE \"Game (Track 2).bin\" BINARY\n  TRACK 02 AUDIO\n    INDEX 01 00:00:00\n",
+        )
+        .expect("write CUE");
+        fs::write(&tocfile, "CD_ROM\nTRACK MODE1_RAW\nDATAFILE \"x\"\nTRACK AUDIO\n")
+            .expect("write TOC");
+        let sector = usize::try_from(RAW_SECTOR_LEN).expect("small constant");
+        fs::write(dir.join("Game (Track 1).bin"), vec![0; 3 * sector]).expect("write track 1");
+        fs::write(dir.join("Game (Track 2).bin"), vec![0; sector]).expect("write track 2");
+
+        let gdifile = sink.path(Path::new("Game.gdi"));
+        write_gdi(&sink, &cuefile, &tocfile, &gdifile).expect("consistent set");
+        let gdi = fs::read_to_string(&gdifile).expect("read GDI");
+        assert_eq!(
+            gdi.lines().collect::<Vec<_>>(),
+            ["2", "1 0 4 2352 \"Game (Track 1).bin\" 0", "2 3 0 2352 \"Game (Track 2).bin\" 0"]
+        );
+
+        fs::write(&tocfile, "CD_ROM\nTRACK MODE1_RAW\n").expect("write mismatched TOC");
+        assert!(write_gdi(&sink, &cuefile, &tocfile, &gdifile).is_err());
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn with_partial_dir_renames_only_on_success() {
+        let final_dir = scratch_path("partial_ok");
+        let partial_dir = final_dir.with_extension("partial");
+        with_partial_dir(&final_dir, false, |workdir| {
+            assert_eq!(workdir, partial_dir);
+            assert!(!final_dir.exists(), "Final name must not appear until the rip succeeds");
+            fs::write(workdir.join("disc.iso"), b"data").map_err(Into::into)
+        })
+        .expect("rip should succeed");
+        assert!(!partial_dir.exists());
+        assert!(final_dir.join("disc.iso").is_file());
+
+        // Refuse to clobber an existing rip unless asked to, and then only once the new one is done
+        assert!(with_partial_dir(&final_dir, false, |_| Ok(())).is_err());
+        let failed: Result<()> = with_partial_dir(&final_dir, true, |_| bail!("Simulated error"));
+        assert!(failed.is_err());
+        assert!(final_dir.join("disc.iso").is_file(), "A failed rip mustn't replace a good one");
+        with_partial_dir(&final_dir, true, |workdir| {
+            fs::write(workdir.join("disc.bin"), b"data").map_err(Into::into)
+        })
+        .expect("replacing rip should succeed");
+        assert!(final_dir.join("disc.bin").is_file());
+        assert!(!final_dir.join("disc.iso").exists(), "The old rip is replaced, not merged");
+        fs::remove_dir_all(&final_dir).expect("cleanup");
+    }
+
+    #[test]
+    fn overwrite_policies_handle_existing_output() {
+        let outdir = scratch_path("overwrite_policy");
+        fs::create_dir_all(outdir.join("Game")).expect("create earlier rip");
+        fs::create_dir_all(outdir.join("Game-1")).expect("create earlier rename");
+        fs::create_dir_all(outdir.join("Other.partial")).expect("create incomplete rip");
+        fs::write(outdir.join("Flat Game.iso"), b"iso").expect("create earlier flat rip");
+        let plan = |name: &str, layout: Layout, policy: Option<OverwritePolicy>| {
+            plan_output(name, policy, |candidate| output_exists(&outdir, layout, candidate, "CD"))
+        };
+
+        let fresh = OutputPlan::Rip { name: "Other".to_owned(), replace: false };
+        for policy in [None, Some(OverwritePolicy::Skip), Some(OverwritePolicy::Rename)] {
+            assert_eq!(plan("Other", Layout::ByName, policy).expect("nothing there"), fresh);
+        }
+        assert!(plan("Game", Layout::ByName, None).is_err());
+        assert_eq!(
+            plan("Game", Layout::ByName, Some(OverwritePolicy::Skip)).expect("skip"),
+            OutputPlan::Skip
+        );
+        assert_eq!(
+            plan("Game", Layout::ByName, Some(OverwritePolicy::Overwrite)).expect("overwrite"),
+            OutputPlan::Rip { name: "Game".to_owned(), replace: true }
+        );
+        assert_eq!(
+            plan("Game", Layout::ByName, Some(OverwritePolicy::Rename)).expect("rename"),
+            OutputPlan::Rip { name: "Game-2".to_owned(), replace: false }
+        );
+        assert_eq!(
+            plan("Flat Game", Layout::Flat, Some(OverwritePolicy::Rename)).expect("rename"),
+            OutputPlan::Rip { name: "Flat Game-1".to_owned(), replace: false }
+        );
+        assert!(plan("Game", Layout::ByType, None).is_ok(), "CD/Game doesn't exist yet");
+
+        // Compressed rips count too
+        fs::create_dir_all(outdir.join("CD")).expect("create type folder");
+        fs::write(outdir.join("CD").join("Packed Game.7z"), b"7z").expect("create archive");
+        fs::write(outdir.join("Flat Packed.7z"), b"7z").expect("create flat archive");
+        assert!(output_exists(&outdir, Layout::ByType, "Packed Game", "CD"));
+        assert!(output_exists(&outdir, Layout::Flat, "Flat Packed", "CD"));
+        assert!(!output_exists(&outdir, Layout::ByName, "Packed Game", "CD"));
+        fs::remove_dir_all(&outdir).expect("cleanup");
+    }
+
+    #[test]
+    fn set_discs_go_in_numbered_folders() {
+        let mut opts = RipOptions { set_position: Some((1, 2)), ..RipOptions::default() };
+        for (layout, expected) in [
+            (Layout::Flat, None),
+            (Layout::ByName, Some(PathBuf::from("My Game"))),
+            (Layout::ByType, Some(Path::new("DVD").join("My Game"))),
+        ] {
+            opts.layout = layout;
+            opts.media_type = "DVD";
+            assert_eq!(set_folder(&opts, "My Game").expect("named"), expected, "{:?}", layout);
+        }
+
+        // Later discs go wherever the first one went, even if their labels differ
+        opts.set_dir = Some(PathBuf::from("Elsewhere"));
+        assert_eq!(set_folder(&opts, "Other").expect("named"), opts.set_dir);
+        opts.set_position = None;
+        assert_eq!(set_folder(&opts, "My Game").expect("named"), None, "Not a set");
+
+        let target = OutputTarget::in_set("My Game (Disc 2)", Path::new("My Game"), "disc2");
+        assert_eq!(target.name, "My Game (Disc 2)", "Files are still named after the disc");
+        assert_eq!(target.dir, Path::new("My Game").join("disc2"));
+        assert_eq!(target.staging, target.dir);
+        assert_eq!(target.set_dir.as_deref(), Some(Path::new("My Game")));
+
+        let outdir = scratch_path("set_layout");
+        fs::create_dir_all(outdir.join("My Game").join("disc1")).expect("create earlier rip");
+        fs::write(outdir.join("My Game").join("disc2.7z"), b"7z").expect("create archive");
+        let set_dir = Path::new("My Game");
+        assert!(set_output_exists(&outdir, set_dir, "disc1"));
+        assert!(set_output_exists(&outdir, set_dir, "disc2"));
+        assert!(!set_output_exists(&outdir, set_dir, "disc3"));
+        assert_eq!(
+            plan_output("disc1", Some(OverwritePolicy::Rename), |candidate| {
+                set_output_exists(&outdir, set_dir, candidate)
+            })
+            .expect("rename"),
+            OutputPlan::Rip { name: "disc1-1".to_owned(), replace: false }
+        );
+        fs::remove_dir_all(&outdir).expect("cleanup");
+    }
+
+    #[test]
+    fn with_partial_dir_leaves_partial_on_failure() {
+        let final_dir = scratch_path("partial_err");
+        let partial_dir = final_dir.with_extension("partial");
+        let result: Result<()> = with_partial_dir(&final_dir, false, |workdir| {
+            fs::write(workdir.join("disc.iso"), b"da")?;
+            bail!("Simulated read error")
+        });
+        assert!(result.is_err());
+        assert!(!final_dir.exists());
+        assert!(partial_dir.join("disc.iso").is_file());
+
+        // ...and the next attempt picks up where it left off
+        with_partial_dir(&final_dir, false, |workdir| {
+            assert!(workdir.join("disc.iso").is_file());
+            Ok(())
+        })
+        .expect("retry should succeed");
+        assert!(final_dir.join("disc.iso").is_file());
+        fs::remove_dir_all(&final_dir).expect("cleanup");
+    }
+
+    /// Output from `cdrdao scanbus` on a system with two drives
+    const SCANBUS_OUTPUT: &str = "Cdrdao version 1.2.4 - (C) Andreas Mueller <andreas@daneb.de>
+/dev/sr0 : HL-DT-ST, DVDRAM GH24NSD1 , LG00
+/dev/sr1 : PLEXTOR , CD-R   PX-W4012A, 1.07
+";
+
+    #[test]
+    fn parse_scanbus_reads_drives() {
+        let drives = parse_scanbus(SCANBUS_OUTPUT);
+        assert_eq!(
+            drives,
+            [
+                ScannedDrive {
+                    device: "/dev/sr0".to_owned(),
+                    vendor: "HL-DT-ST".to_owned(),
+                    model: "DVDRAM GH24NSD1".to_owned(),
+                    revision: "LG00".to_owned(),
+                },
+                ScannedDrive {
+                    device: "/dev/sr1".to_owned(),
+                    vendor: "PLEXTOR".to_owned(),
+                    model: "CD-R   PX-W4012A".to_owned(),
+                    revision: "1.07".to_owned(),
+                },
+            ]
+        );
+        assert_eq!(drives[0].suggested_driver(), "generic-mmc-raw");
+        assert_eq!(drives[1].suggested_driver(), "plextor-scan");
+        assert!(parse_scanbus("Cdrdao version 1.2.4\n").is_empty());
+    }
+
+    #[test]
+    fn driver_for_device_matches_scanbus_entries() {
+        let drives = parse_scanbus(SCANBUS_OUTPUT);
+        assert_eq!(driver_for_device(&drives, Path::new("/dev/sr1")), Some("plextor-scan"));
+        assert_eq!(driver_for_device(&drives, Path::new("/dev/sr0")), Some(DEFAULT_CDRDAO_DRIVER));
+        assert_eq!(driver_for_device(&drives, Path::new("/dev/sr9")), None);
+        assert_eq!(driver_for_device(&[], Path::new("/dev/sr0")), None);
+    }
+
+    #[test]
+    fn resumed_dumps_are_not_hashed_while_reading() {
+        let dir = scratch_path("stream_hash");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let logfile = dir.join("My Game.log");
+        let streaming = ModeOptions { hash_while_reading: true, ..ModeOptions::default() };
+        assert!(can_stream_hash(&logfile, &streaming));
+        assert!(!can_stream_hash(&logfile, &ModeOptions::default()));
+
+        // Left behind by an earlier attempt which was interrupted partway through
+        fs::write(
+            &logfile,
+            "0x00000000  ?  1\n0x00000000  0x00100000  +\n0x00100000  0x00100000  -\n",
+        )
+        .expect("seed mapfile");
+        assert!(!can_stream_hash(&logfile, &streaming));
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn finish_iso_only_stamps_mtime_when_asked() {
+        let dir = scratch_path("stamp_mtime");
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        let isofile = dir.join("My Game.iso");
+        fs::copy("fixture.iso", &isofile).expect("copy fixture.iso");
+        let provider = LinuxPlatformProvider::new(Cow::Borrowed(isofile.as_os_str()));
+        let mtime = || fs::metadata(&isofile).and_then(|meta| meta.modified()).expect("stat");
+
+        let dates = disc_dates(&provider).expect("fixture.iso has volume dates");
+        let created = dates.created.expect("fixture.iso has a creation date").to_system_time();
+        let copied = mtime();
+        assert_ne!(copied, created);
+        finish_iso(&provider, &isofile, false);
+        assert_eq!(mtime(), copied);
+        finish_iso(&provider, &isofile, true);
+        assert_eq!(mtime(), created);
+        fs::remove_dir_all(&dir).expect("cleanup");
+    }
+
+    #[test]
+    fn run_watched_reports_failed_tools_as_media_errors() {
+        let opts = ModeOptions::default();
+        run_watched(Command::new("true"), &[], &opts, None).expect("true succeeds");
+
+        let failed = run_watched(Command::new("false"), &[], &opts, None).expect_err("fails");
+        let media_err = MediaError::find(&failed).expect("a MediaError");
+        assert_eq!(media_err.exit_code(), 69);
+        assert!(matches!(media_err, MediaError::SubprocessFailed { code: Some(1), .. }));
+
+        let mut killed = Command::new("sh");
+        killed.args(["-c", "kill -9 $$"]);
+        let err = run_watched(killed, &[], &opts, None).expect_err("killed");
+        assert!(matches!(
+            MediaError::find(&err),
+            Some(MediaError::SubprocessFailed { code: None, .. })
+        ));
+    }
+
+    #[test]
+    fn retry_driver_only_retries_cdrdao_failures() {
+        let failed = anyhow::Error::from(MediaError::SubprocessFailed {
+            command: "cdrdao".to_owned(),
+            code: Some(1),
+        });
+        let device = OsStr::new("/dev/sr0");
+        let tried = DEFAULT_CDRDAO_DRIVER;
+        assert_eq!(retry_driver(&failed, tried, device, &ModeOptions::default()), None);
+
+        let autodetect = ModeOptions { driver_autodetect: true, ..ModeOptions::default() };
+        let stalled = anyhow::Error::from(MediaError::Stalled(Duration::from_secs(5)));
+        assert_eq!(retry_driver(&stalled, tried, device, &autodetect), None);
+        let explicit = ModeOptions { cdrdao_driver: Some("generic-mmc".to_owned()), ..autodetect };
+        assert_eq!(retry_driver(&failed, "generic-mmc", device, &explicit), None);
+    }
+
+    #[test]
+    fn format_drive_table_aligns_columns() {
+        assert_eq!(
+            format_drive_table(&parse_scanbus(SCANBUS_OUTPUT)),
+            "Device    Vendor    Model             Revision  Suggested driver
+/dev/sr0  HL-DT-ST  DVDRAM GH24NSD1   LG00      generic-mmc-raw
+/dev/sr1  PLEXTOR   CD-R   PX-W4012A  1.07      plextor-scan
+"
+        );
+    }
+
+    #[test]
+    fn follow_up_strategies_match_track_layout() {
+        use RecoveryStrategy::{Audio, Iso};
+        assert_eq!(follow_up_strategies(None), [Iso]);
+        assert_eq!(follow_up_strategies(Some("CD_ROM\nTRACK MODE1_RAW\n")), [Iso]);
+        assert_eq!(follow_up_strategies(Some("CD_DA\nTRACK AUDIO\nTRACK AUDIO\n")), [Audio]);
+        assert_eq!(
+            follow_up_strategies(Some("CD_ROM_XA\nTRACK MODE2_RAW\nTRACK AUDIO\n")),
+            [Iso, Audio]
+        );
+    }
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        let mut names = vec!["track10", "track2", "track1", "track02", "Track3", "track"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["Track3", "track", "track1", "track2", "track02", "track10"]);
+        assert_eq!(natural_cmp("disc 1 track 9", "disc 1 track 10"), Ordering::Less);
+        assert_eq!(natural_cmp("a99999999999999999999999", "a1"), Ordering::Greater);
+    }
+
+    #[test]
+    fn wavs_are_sorted_into_track_order() {
+        let mut wavs: Vec<PathBuf> = [7, 12, 1, 10, 3, 11, 2, 9, 5, 8, 4, 6]
+            .iter()
+            .map(|track| PathBuf::from(format!("track{}.wav", track)))
+            .collect();
+        sort_paths_naturally(&mut wavs);
+        let expected: Vec<PathBuf> =
+            (1..=12).map(|track| PathBuf::from(format!("track{}.wav", track))).collect();
+        assert_eq!(wavs, expected);
+    }
+
+    #[test]
+    fn format_m3u_lists_tracks_in_order() {
+        let mut entries = vec![
+            PlaylistEntry {
+                path: PathBuf::from("track10.cdda.flac"),
+                duration_secs: 61,
+                title: Some("Finale".to_owned()),
+            },
+            PlaylistEntry {
+                path: PathBuf::from("track9.cdda.flac"),
+                duration_secs: 200,
+                title: None,
+            },
+        ];
+        assert_eq!(
+            format_m3u(&mut entries),
+            "#EXTM3U\n#EXTINF:200,track9\ntrack9.cdda.flac\n#EXTINF:61,Finale\ntrack10.cdda.flac\n"
+        );
+    }
+}
+
 // vim: set sw=4 sts=4 :
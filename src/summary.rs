@@ -0,0 +1,291 @@
+//! Wrapping up a ripping run: compressing its output and reporting on the outcome
+
+use std::fs::{self, remove_file};
+use std::io::ErrorKind as IOErrorKind;
+use std::path::{Component::CurDir, Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::iso9660::VolumeDates;
+use crate::platform::DriveInfo;
+use crate::subcommands::{volume_file, RipOutput};
+
+/// Extension of the archives `compress_output` produces
+pub const ARCHIVE_EXTENSION: &str = "7z";
+
+/// Details about a disc to record alongside its rip: what the user supplied, the drive it was
+/// ripped with, and the dates its filesystem was stamped with
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DiscMetadata {
+    /// The title of the game/program/etc. on the disc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The platform the disc is for (eg. `PlayStation` or `Windows 95`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub platform: Option<String>,
+    /// Free-form notes (eg. condition, edition, or where it came from)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    /// The drive the disc was ripped with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drive: Option<DriveInfo>,
+    /// The dates in the disc's ISO9660 volume descriptor (omitted for other filesystems)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volume_dates: Option<VolumeDates>,
+}
+
+impl DiscMetadata {
+    /// Whether none of the fields have been filled in
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.platform.is_none()
+            && self.notes.is_none()
+            && self.drive.is_none()
+            && self.volume_dates.is_none()
+    }
+
+    /// Save as pretty-printed JSON to `path`
+    pub fn write_json(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Could not serialize metadata")?;
+        fs::write(path, format!("{}\n", json))
+            .with_context(|| format!("Could not write {}", path.display()))
+    }
+}
+
+/// The outcome of ripping a single disc
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RipSummary {
+    /// The name the disc was ripped under (`None` if it failed before one was chosen)
+    pub disc_name: Option<String>,
+    /// Whether the rip completed successfully
+    pub success: bool,
+    /// The error which ended the rip, with its chain of causes
+    pub error: Option<String>,
+    /// When the rip started, in seconds since the Unix epoch
+    pub started: u64,
+    /// How long the rip took, in whole seconds
+    pub duration_secs: u64,
+    /// Details about the disc and the drive it was ripped with (omitted if there aren't any)
+    #[serde(skip_serializing_if = "DiscMetadata::is_empty")]
+    pub metadata: DiscMetadata,
+    /// The files the rip produced (omitted if it failed)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+}
+
+impl RipSummary {
+    /// Summarize a rip which started at `started` and just finished with `result`
+    pub fn new<T>(disc_name: Option<String>, started: SystemTime, result: &Result<T>) -> Self {
+        RipSummary {
+            disc_name,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            started: started.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            duration_secs: started.elapsed().unwrap_or(Duration::ZERO).as_secs(),
+            metadata: DiscMetadata::default(),
+            files: Vec::new(),
+        }
+    }
+
+    /// Attach the user-supplied details about the disc
+    pub fn with_metadata(mut self, metadata: DiscMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Attach the names of the files the rip produced
+    pub fn with_files(mut self, files: Vec<String>) -> Self {
+        self.files = files;
+        self
+    }
+}
+
+/// POST `summary` as JSON to `url` (eg. a Discord/Slack/ntfy bridge)
+#[cfg(feature = "webhook")]
+pub fn send_webhook(url: &str, summary: &RipSummary) -> Result<()> {
+    use anyhow::Context;
+    ureq::post(url)
+        .timeout(Duration::from_secs(30))
+        .send_json(summary)
+        .with_context(|| format!("Could not deliver notification to {}", url))?;
+    Ok(())
+}
+
+/// Stub for builds without HTTP support (`--webhook` is rejected at startup in these builds)
+#[cfg(not(feature = "webhook"))]
+pub fn send_webhook(_url: &str, _summary: &RipSummary) -> Result<()> {
+    anyhow::bail!("rip_media was built without the `webhook` feature")
+}
+
+/// Summarize the rip of `disc_name` which started at `started` and POST it to `url`
+///
+/// A notification failure mustn't turn a good rip into a failed one, so it's only a warning.
+pub fn report_rip(
+    url: &str,
+    disc_name: Option<String>,
+    started: SystemTime,
+    result: &Result<RipOutput>,
+    metadata: DiscMetadata,
+) {
+    let files = result.as_ref().map(|output| output.files.clone()).unwrap_or_default();
+    let summary =
+        RipSummary::new(disc_name, started, result).with_metadata(metadata).with_files(files);
+    if let Err(e) = send_webhook(url, &summary) {
+        warn!("{:#}", e);
+    }
+}
+
+/// Build the command to compress `targets` (relative to the current directory) into `archive`
+/// as strongly as 7-Zip can
+fn sevenzip_command(archive: &Path, targets: &[PathBuf]) -> Command {
+    let mut cmd = Command::new("7z");
+    cmd.args(["a", "-t7z", "-m0=lzma", "-mx=9", "-mfb=64", "-md=32m", "-ms=on"]);
+    cmd.arg(archive).args(targets);
+    cmd
+}
+
+/// Compress the output of a rip into a `.7z` archive named after it, alongside it, and
+/// remove the originals once the archive has been written
+///
+/// Returns where the archive ended up. If 7-Zip fails, the originals are left alone.
+pub fn compress_output(output: &RipOutput) -> Result<RipOutput> {
+    let volbase = PathBuf::from(&output.name);
+    // Folders are archived whole, while flat layouts only archive this disc's files
+    let (base, targets, archive) = if output.dir == Path::new(CurDir.as_os_str()) {
+        let files = output.files.iter().map(PathBuf::from).collect();
+        (output.dir.clone(), files, volume_file(&volbase, ARCHIVE_EXTENSION))
+    } else {
+        let base = output.dir.parent().map(Path::to_path_buf).unwrap_or_default();
+        let dir_name = PathBuf::from(output.dir.file_name().unwrap_or_default());
+        let archive = volume_file(&dir_name, ARCHIVE_EXTENSION);
+        (base, vec![dir_name], archive)
+    };
+    let archive_path = base.join(&archive);
+
+    // 7z would add to an existing archive rather than replacing it
+    if let Err(e) = remove_file(&archive_path) {
+        if e.kind() != IOErrorKind::NotFound {
+            return Err(e).with_context(|| format!("Could not replace {}", archive_path.display()));
+        }
+    }
+
+    info!("Compressing {} into {}...", output.name, archive_path.display());
+    let mut cmd = sevenzip_command(&archive, &targets);
+    if !base.as_os_str().is_empty() {
+        cmd.current_dir(&base);
+    }
+    let failure = match cmd.status() {
+        Ok(status) if status.success() => None,
+        Ok(status) => Some(format!("7z failed ({})", status)),
+        Err(e) => Some(format!("Could not run 7z: {}", e)),
+    };
+    if let Some(problem) = failure {
+        let _ = remove_file(&archive_path); // Don't leave a truncated archive behind
+        bail!("{}. The uncompressed output was kept.", problem);
+    }
+
+    for target in &targets {
+        let path = base.join(target);
+        if path.is_dir() { fs::remove_dir_all(&path) } else { remove_file(&path) }
+            .with_context(|| format!("Compressed, but could not remove {}", path.display()))?;
+    }
+    let name = archive.to_string_lossy().into_owned();
+    Ok(RipOutput {
+        name: output.name.clone(),
+        dir: base,
+        files: vec![name],
+        serial: output.serial.clone(),
+        set_dir: output.set_dir.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iso9660::DecDateTime;
+    use anyhow::anyhow;
+
+    #[test]
+    fn new_records_success_and_failure() {
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let ok = RipSummary::new(Some("DISC".to_owned()), started, &Ok(()));
+        assert!(ok.success);
+        assert_eq!(ok.error, None);
+        assert_eq!(ok.started, 1_700_000_000);
+
+        let failed: Result<()> = Err(anyhow!("Drive fell over").context("Could not rip"));
+        let summary = RipSummary::new(None, started, &failed);
+        assert!(!summary.success);
+        assert_eq!(summary.error.as_deref(), Some("Could not rip: Drive fell over"));
+    }
+
+    #[test]
+    fn serializes_with_field_names() {
+        let summary = RipSummary {
+            disc_name: Some("GAME_DISC".to_owned()),
+            success: true,
+            error: None,
+            started: 10,
+            duration_secs: 5,
+            metadata: DiscMetadata::default(),
+            files: Vec::new(),
+        };
+        assert_eq!(
+            serde_json::to_string(&summary).expect("plain data always serializes"),
+            r#"{"disc_name":"GAME_DISC","success":true,"error":null,"started":10,"duration_secs":5}"#
+        );
+
+        let metadata = DiscMetadata {
+            title: Some("Some Game".to_owned()),
+            notes: Some("Scratched".to_owned()),
+            drive: Some(DriveInfo {
+                vendor: "PLEXTOR".to_owned(),
+                model: "PX-716A".to_owned(),
+                revision: "1.11".to_owned(),
+            }),
+            volume_dates: Some(VolumeDates {
+                created: DecDateTime::parse(b"1998111923595900\x24"),
+                ..VolumeDates::default()
+            }),
+            ..DiscMetadata::default()
+        };
+        let with_metadata = summary.with_metadata(metadata);
+        assert!(serde_json::to_string(&with_metadata)
+            .expect("plain data always serializes")
+            .ends_with(
+                r#""metadata":{"title":"Some Game","notes":"Scratched","drive":{"vendor":"PLEXTOR","model":"PX-716A","revision":"1.11"},"volume_dates":{"created":"1998-11-19T23:59:59.00+09:00"}}}"#
+            ));
+
+        let files = vec!["GAME_DISC.bin".to_owned(), "GAME_DISC.sub".to_owned()];
+        assert!(serde_json::to_string(&with_metadata.with_files(files))
+            .expect("plain data always serializes")
+            .ends_with(r#""files":["GAME_DISC.bin","GAME_DISC.sub"]}"#));
+    }
+
+    #[test]
+    fn sevenzip_command_compresses_as_strongly_as_possible() {
+        let cmd = sevenzip_command(Path::new("My Game.7z"), &[PathBuf::from("My Game")]);
+        assert_eq!(cmd.get_program(), "7z");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "a",
+                "-t7z",
+                "-m0=lzma",
+                "-mx=9",
+                "-mfb=64",
+                "-md=32m",
+                "-ms=on",
+                "My Game.7z",
+                "My Game"
+            ]
+        );
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,122 @@
+//! Tagging ripped audio tracks with what the disc says about itself (CD-TEXT, MCN, and ISRCs)
+
+use log::{info, warn};
+
+use crate::toc::{parse_cd_text, parse_disc_codes, CdText, DiscCodes};
+
+/// The metadata an audio CD's table of contents carries for its tracks
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrackTags {
+    /// CD-TEXT for the disc and its tracks (empty unless it was asked for)
+    cd_text: CdText,
+    /// MCN and ISRCs for the disc
+    codes: DiscCodes,
+    /// Whether tracks should be tagged at all (`--cd-text`)
+    enabled: bool,
+}
+
+impl TrackTags {
+    /// Gather the metadata in `toc`, logging the disc's codes for cataloging purposes
+    ///
+    /// CD-TEXT is only read (and tracks only tagged) if `use_cd_text` is set.
+    pub fn from_toc(toc: &str, use_cd_text: bool) -> Self {
+        let codes = parse_disc_codes(toc);
+        log_disc_codes(&codes);
+
+        let cd_text = if use_cd_text { parse_cd_text(toc) } else { CdText::default() };
+        if use_cd_text && cd_text.is_empty() {
+            warn!("No CD-TEXT found. Tracks will only be tagged with their ISRCs, if any.");
+        }
+        TrackTags { cd_text, codes, enabled: use_cd_text }
+    }
+
+    /// Build Vorbis comment tags (in `flac -T` syntax) for a track, or none if it shouldn't be
+    /// tagged or the disc has nothing to tag it with
+    pub fn flac_tags(&self, track: usize) -> Vec<String> {
+        let TrackTags { cd_text, codes, enabled } = self;
+        if !enabled || (cd_text.is_empty() && codes.is_empty()) {
+            return Vec::new();
+        }
+
+        let mut tags = vec![format!("TRACKNUMBER={}", track)];
+        if let Some(album) = &cd_text.album.title {
+            tags.push(format!("ALBUM={}", album));
+        }
+        if let Some(title) = self.title(track) {
+            tags.push(format!("TITLE={}", title));
+        }
+        if let Some(artist) = cd_text.track(track).and_then(|f| f.performer.as_ref()) {
+            tags.push(format!("ARTIST={}", artist));
+        } else if let Some(artist) = &cd_text.album.performer {
+            tags.push(format!("ARTIST={}", artist));
+        }
+        if let Some(isrc) = codes.isrc(track) {
+            tags.push(format!("ISRC={}", isrc));
+        }
+        tags
+    }
+
+    /// The CD-TEXT title of a track, if it has one
+    pub fn title(&self, track: usize) -> Option<&str> {
+        self.cd_text.track(track).and_then(|fields| fields.title.as_deref())
+    }
+}
+
+/// Record the disc's MCN and ISRCs (if any) in the log for cataloging purposes
+fn log_disc_codes(codes: &DiscCodes) {
+    if codes.is_empty() {
+        info!("Disc has no Media Catalog Number or ISRCs");
+        return;
+    }
+    if let Some(mcn) = &codes.mcn {
+        info!("Media Catalog Number: {}", mcn);
+    }
+    for (idx, maybe_isrc) in codes.isrcs.iter().enumerate() {
+        if let Some(isrc) = maybe_isrc {
+            info!("Track {:02} ISRC: {}", idx + 1, isrc);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flac_tags_prefers_track_performer() {
+        let tags = TrackTags::from_toc(
+            "CD_TEXT { LANGUAGE 0 { TITLE \"Album\" PERFORMER \"Band\" } }
+            TRACK AUDIO CD_TEXT { LANGUAGE 0 { TITLE \"One\" PERFORMER \"Guest\" } }
+            TRACK AUDIO CD_TEXT { LANGUAGE 0 { TITLE \"Two\" } }
+            TRACK AUDIO",
+            true,
+        );
+        assert_eq!(
+            tags.flac_tags(1),
+            ["TRACKNUMBER=1", "ALBUM=Album", "TITLE=One", "ARTIST=Guest"]
+        );
+        assert_eq!(tags.flac_tags(2), ["TRACKNUMBER=2", "ALBUM=Album", "TITLE=Two", "ARTIST=Band"]);
+        assert_eq!(tags.flac_tags(3), ["TRACKNUMBER=3", "ALBUM=Album", "ARTIST=Band"]);
+        assert_eq!(tags.title(2), Some("Two"));
+        assert_eq!(tags.title(3), None);
+    }
+
+    #[test]
+    fn flac_tags_includes_isrc() {
+        let toc = "TRACK AUDIO ISRC \"GBAYE9700001\" TRACK AUDIO";
+        let tags = TrackTags::from_toc(toc, true);
+        assert_eq!(tags.flac_tags(1), ["TRACKNUMBER=1", "ISRC=GBAYE9700001"]);
+        assert_eq!(tags.flac_tags(2), ["TRACKNUMBER=2"]);
+    }
+
+    #[test]
+    fn flac_tags_are_only_added_with_cd_text() {
+        let toc = "CD_TEXT { LANGUAGE 0 { TITLE \"Album\" } } TRACK AUDIO ISRC \"GBAYE9700001\"";
+        let untagged = TrackTags::from_toc(toc, false);
+        assert!(untagged.flac_tags(1).is_empty());
+        assert_eq!(untagged.title(1), None);
+        assert!(TrackTags::from_toc("TRACK AUDIO", true).flac_tags(1).is_empty());
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,25 @@
+//! Helpers shared by the unit tests of several modules
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Get a path in the system temporary directory which is unique to this test run and `name`
+///
+/// Anything an earlier, interrupted run left there is cleared away first (including the
+/// `.partial` staging folder `with_partial_dir` would put next to it), but nothing is created.
+pub fn scratch_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("rip_media-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&path);
+    let _ = fs::remove_file(&path);
+    let _ = fs::remove_dir_all(path.with_extension("partial"));
+    path
+}
+
+/// Create an empty directory at `scratch_path(name)` and return its path
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let path = scratch_path(name);
+    fs::create_dir_all(&path).expect("create scratch dir");
+    path
+}
+
+// vim: set sw=4 sts=4 :
@@ -0,0 +1,819 @@
+//! Parsing for the TOC files written by `cdrdao read-toc` and `cdrdao read-cd`
+//!
+//! See the `cdrdao(1)` manpage for the format.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// Number of CD frames (sectors) per second of MSF time
+const FRAMES_PER_SEC: u64 = 75;
+
+/// Number of stereo audio samples in a CD frame (`44_100 / FRAMES_PER_SEC`)
+const SAMPLES_PER_FRAME: u64 = 588;
+
+/// A lexical token from a TOC file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// A bare keyword or number
+    Word(String),
+    /// A double-quoted string, with escapes decoded
+    Str(String),
+    /// `{`
+    Open,
+    /// `}`
+    Close,
+    /// `:` (as used in `LANGUAGE_MAP` and MSF timestamps)
+    Colon,
+}
+
+/// Split a TOC file into tokens, discarding `//` comments
+///
+/// cdrdao writes non-ASCII bytes in strings as octal escapes and CD-TEXT is Latin-1 encoded,
+/// so escaped bytes are decoded as Latin-1.
+fn tokenize(toc: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = toc.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Open),
+            '}' => tokens.push(Token::Close),
+            ':' => tokens.push(Token::Colon),
+            '/' if chars.peek() == Some(&'/') => {
+                // Skip to end of line
+                for comment_char in chars.by_ref() {
+                    if comment_char == '\n' {
+                        break;
+                    }
+                }
+            },
+            '"' => {
+                let mut string = String::new();
+                while let Some(str_char) = chars.next() {
+                    match str_char {
+                        '"' => break,
+                        '\\' => {
+                            let mut octal = String::new();
+                            while octal.len() < 3 {
+                                match chars.next_if(|x| x.is_digit(8)) {
+                                    Some(digit) => octal.push(digit),
+                                    None => break,
+                                }
+                            }
+                            if octal.is_empty() {
+                                if let Some(escaped) = chars.next() {
+                                    string.push(escaped);
+                                }
+                            } else if let Ok(byte) = u8::from_str_radix(&octal, 8) {
+                                string.push(char::from(byte));
+                            }
+                        },
+                        _ => string.push(str_char),
+                    }
+                }
+                tokens.push(Token::Str(string));
+            },
+            _ if c.is_whitespace() => {},
+            _ => {
+                let mut word = String::from(c);
+                while let Some(word_char) =
+                    chars.next_if(|x| !x.is_whitespace() && !matches!(x, '{' | '}' | ':' | '"'))
+                {
+                    word.push(word_char);
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+    tokens
+}
+
+/// Consume tokens up to and including the `}` matching an already-consumed `{`
+fn skip_block<'tok, I: Iterator<Item = &'tok Token>>(tokens: &mut I) {
+    let mut depth = 1_usize;
+    for token in tokens {
+        match token {
+            Token::Open => depth += 1,
+            Token::Close => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// The CD-TEXT fields we care about for a disc or track
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CdTextFields {
+    /// Album or track title
+    pub title: Option<String>,
+    /// Artist, band, or performer
+    pub performer: Option<String>,
+}
+
+impl CdTextFields {
+    /// Whether none of the fields were provided
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.performer.is_none()
+    }
+}
+
+/// CD-TEXT metadata for a disc (Only the first language block is used)
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CdText {
+    /// Fields for the disc as a whole
+    pub album: CdTextFields,
+    /// Fields for each track, with track 1 at index 0
+    pub tracks: Vec<CdTextFields>,
+}
+
+impl CdText {
+    /// Whether the disc had no usable CD-TEXT at all
+    pub fn is_empty(&self) -> bool {
+        self.album.is_empty() && self.tracks.iter().all(CdTextFields::is_empty)
+    }
+
+    /// Look up the fields for a 1-based track number
+    pub fn track(&self, number: usize) -> Option<&CdTextFields> {
+        number.checked_sub(1).and_then(|idx| self.tracks.get(idx))
+    }
+}
+
+/// Parse the body of a `CD_TEXT { ... }` block, starting just after the opening `{`
+fn parse_cd_text_block<'tok, I: Iterator<Item = &'tok Token>>(tokens: &mut I) -> CdTextFields {
+    let mut fields = CdTextFields::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Close => break,
+            Token::Open => skip_block(tokens),
+            Token::Word(word) if word == "LANGUAGE" => {
+                let is_first_language =
+                    matches!(tokens.next(), Some(Token::Word(num)) if num == "0");
+                if !matches!(tokens.next(), Some(Token::Open)) {
+                    break;
+                }
+                while let Some(item) = tokens.next() {
+                    match item {
+                        Token::Close => break,
+                        Token::Open => skip_block(tokens), // Binary data like GENRE or SIZE_INFO
+                        Token::Word(key) => {
+                            if let Some(Token::Str(value)) = tokens.next() {
+                                if is_first_language && !value.is_empty() {
+                                    match key.as_str() {
+                                        "TITLE" => fields.title = Some(value.clone()),
+                                        "PERFORMER" => fields.performer = Some(value.clone()),
+                                        _ => {},
+                                    }
+                                }
+                            }
+                        },
+                        _ => {},
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+    fields
+}
+
+/// Extract the CD-TEXT metadata from the contents of a TOC file
+pub fn parse_cd_text(toc: &str) -> CdText {
+    let tokens = tokenize(toc);
+    let mut cd_text = CdText::default();
+    let mut iter = tokens.iter();
+    while let Some(token) = iter.next() {
+        match token {
+            Token::Word(word) if word == "TRACK" => cd_text.tracks.push(CdTextFields::default()),
+            Token::Word(word) if word == "CD_TEXT" => {
+                if iter.next() == Some(&Token::Open) {
+                    let fields = parse_cd_text_block(&mut iter);
+                    match cd_text.tracks.last_mut() {
+                        Some(track) => *track = fields,
+                        None => cd_text.album = fields,
+                    }
+                }
+            },
+            Token::Open => skip_block(&mut iter),
+            _ => {},
+        }
+    }
+    cd_text
+}
+
+/// The identification codes embedded in an audio CD's subchannel data
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DiscCodes {
+    /// Media Catalog Number (The disc's UPC/EAN barcode)
+    pub mcn: Option<String>,
+    /// International Standard Recording Code for each track, with track 1 at index 0
+    pub isrcs: Vec<Option<String>>,
+}
+
+impl DiscCodes {
+    /// Whether the disc had no MCN or ISRCs at all
+    pub fn is_empty(&self) -> bool {
+        self.mcn.is_none() && self.isrcs.iter().all(Option::is_none)
+    }
+
+    /// Look up the ISRC for a 1-based track number
+    pub fn isrc(&self, number: usize) -> Option<&str> {
+        number.checked_sub(1).and_then(|idx| self.isrcs.get(idx)).and_then(Option::as_deref)
+    }
+}
+
+/// Extract the MCN (`CATALOG`) and per-track `ISRC` codes from the contents of a TOC file
+pub fn parse_disc_codes(toc: &str) -> DiscCodes {
+    let tokens = tokenize(toc);
+    let mut codes = DiscCodes::default();
+    let mut iter = tokens.iter();
+    while let Some(token) = iter.next() {
+        match token {
+            Token::Word(word) if word == "TRACK" => codes.isrcs.push(None),
+            Token::Word(word) if word == "CATALOG" || word == "ISRC" => {
+                if let Some(Token::Str(value)) = iter.next() {
+                    let code = Some(value.clone()).filter(|code| !code.trim().is_empty());
+                    match (word.as_str(), codes.isrcs.last_mut()) {
+                        ("CATALOG", None) => codes.mcn = code,
+                        ("ISRC", Some(isrc)) => *isrc = code,
+                        _ => {},
+                    }
+                }
+            },
+            Token::Open => skip_block(&mut iter),
+            _ => {},
+        }
+    }
+    codes
+}
+
+/// A track as laid out by a TOC file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Track {
+    /// 1-based track number
+    pub number: usize,
+    /// Track mode (`AUDIO`, `MODE1`, `MODE2_RAW`, etc.)
+    pub mode: String,
+    /// Sector the track (including its pregap) starts at, counting from the start of the disc
+    pub start: u64,
+    /// Length in sectors, including the pregap (Data the TOC leaves to the file size isn't
+    /// counted, which only happens for the last part of hand-written TOCs)
+    pub length: u64,
+    /// Length of the pregap (the part before index 1) in sectors
+    pub pregap: u64,
+    /// International Standard Recording Code, if the disc has one for this track
+    pub isrc: Option<String>,
+}
+
+impl Track {
+    /// Whether this is a CD-DA audio track
+    pub fn is_audio(&self) -> bool {
+        self.mode == "AUDIO"
+    }
+
+    /// Size of this track's sectors as stored in its data file
+    pub fn sector_len(&self) -> u64 {
+        sector_len(&self.mode)
+    }
+}
+
+/// Size of the sectors of a track in `mode` as stored in its data file
+fn sector_len(mode: &str) -> u64 {
+    match mode {
+        "MODE1" | "MODE2_FORM1" => 2048,
+        "MODE2_FORM2" => 2324,
+        "MODE2" | "MODE2_FORM_MIX" => 2336,
+        _ => 2352, // AUDIO, MODE0, and the *_RAW modes
+    }
+}
+
+/// The tracks of a disc, as parsed from the contents of a TOC file
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrackList {
+    /// The tracks, in order
+    pub tracks: Vec<Track>,
+}
+
+/// Which of a disc's tracks are audio and which are data, for deciding how to rip it
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct TrackSummary {
+    /// Numbers of the CD-DA audio tracks
+    pub audio: BTreeSet<usize>,
+    /// Numbers of the data tracks
+    pub data: BTreeSet<usize>,
+}
+
+impl fmt::Display for TrackSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} audio and {} data track(s)", self.audio.len(), self.data.len())
+    }
+}
+
+/// A length or position given in a TOC file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Amount {
+    /// An `mm:ss:ff` timestamp, converted to frames
+    Frames(u64),
+    /// A bare number (samples for audio files, bytes for data files)
+    Count(u64),
+}
+
+/// Consume an `Amount` if one comes next
+fn take_amount<'tok, I: Iterator<Item = &'tok Token>>(tokens: &mut Peekable<I>) -> Option<Amount> {
+    let first = match tokens.peek() {
+        Some(Token::Word(word)) => word.parse::<u64>().ok()?,
+        _ => return None,
+    };
+    tokens.next();
+    if tokens.next_if_eq(&&Token::Colon).is_none() {
+        return Some(Amount::Count(first));
+    }
+    let mut rest = [0; 2];
+    for (idx, part) in rest.iter_mut().enumerate() {
+        if idx > 0 && tokens.next_if_eq(&&Token::Colon).is_none() {
+            return None;
+        }
+        match tokens.next() {
+            Some(Token::Word(word)) => *part = word.parse().ok()?,
+            _ => return None,
+        }
+    }
+    Some(Amount::Frames((first * 60 + rest[0]) * FRAMES_PER_SEC + rest[1]))
+}
+
+/// Skip the file name, `SWAP` flag, and optional `#byte_offset` at the start of a `FILE` or
+/// `DATAFILE` statement, returning the offset if there was one
+fn skip_file_name<'tok, I: Iterator<Item = &'tok Token>>(tokens: &mut Peekable<I>) -> Option<u64> {
+    tokens.next_if(|next| matches!(next, Token::Str(_)));
+    tokens.next_if(|next| matches!(next, Token::Word(word) if word == "SWAP"));
+    match tokens.next_if(|next| matches!(next, Token::Word(word) if word.starts_with('#'))) {
+        Some(Token::Word(offset)) => offset[1..].parse().ok(),
+        _ => None,
+    }
+}
+
+/// Whether a token is a keyword (eg. a mode name) rather than a number or timestamp
+fn is_keyword(token: &Token) -> bool {
+    matches!(token, Token::Word(word) if word.starts_with(|c: char| c.is_ascii_alphabetic()))
+}
+
+/// Convert an `Amount` of MSF time into frames (for items where bare numbers aren't allowed)
+fn frames_only(amount: Option<Amount>) -> u64 {
+    match amount {
+        Some(Amount::Frames(frames)) => frames,
+        _ => 0,
+    }
+}
+
+impl TrackList {
+    /// Parse the track layout out of the contents of a TOC file
+    ///
+    /// Malformed statements are skipped rather than treated as errors, like the other parsers
+    /// here, since a partial track list is still more useful than none.
+    pub fn parse(toc: &str) -> Self {
+        let tokens = tokenize(toc);
+        let mut list = TrackList::default();
+        let mut iter = tokens.iter().peekable();
+        while let Some(token) = iter.next() {
+            let word = match token {
+                Token::Word(word) => word.as_str(),
+                Token::Open => {
+                    skip_block(&mut iter);
+                    continue;
+                },
+                _ => continue,
+            };
+            if word == "TRACK" {
+                let mode = match iter.next() {
+                    Some(Token::Word(mode)) => mode.clone(),
+                    _ => continue,
+                };
+                let start = list.tracks.last().map_or(0, |prev| prev.start + prev.length);
+                let number = list.tracks.len() + 1;
+                list.tracks.push(Track { number, mode, start, ..Track::default() });
+                continue;
+            }
+            let Some(track) = list.tracks.last_mut() else { continue };
+            match word {
+                "ISRC" => {
+                    if let Some(Token::Str(code)) = iter.next() {
+                        track.isrc = Some(code.clone()).filter(|isrc| !isrc.trim().is_empty());
+                    }
+                },
+                "FILE" | "AUDIOFILE" => {
+                    skip_file_name(&mut iter);
+                    let _start = take_amount(&mut iter);
+                    track.length += match take_amount(&mut iter) {
+                        Some(Amount::Frames(frames)) => frames,
+                        Some(Amount::Count(samples)) => samples / SAMPLES_PER_FRAME,
+                        None => 0,
+                    };
+                },
+                "DATAFILE" => {
+                    skip_file_name(&mut iter);
+                    track.length += match take_amount(&mut iter) {
+                        Some(Amount::Frames(frames)) => frames,
+                        Some(Amount::Count(bytes)) => bytes / track.sector_len(),
+                        None => 0,
+                    };
+                },
+                "ZERO" => {
+                    // Optional data mode and sub-channel mode before the length
+                    while iter.next_if(|next| is_keyword(next)).is_some() {}
+                    track.length += frames_only(take_amount(&mut iter));
+                },
+                "SILENCE" => track.length += frames_only(take_amount(&mut iter)),
+                "PREGAP" => {
+                    let frames = frames_only(take_amount(&mut iter));
+                    track.length += frames;
+                    track.pregap = frames;
+                },
+                "START" => {
+                    // Without a timestamp, everything so far is pregap
+                    track.pregap = match take_amount(&mut iter) {
+                        Some(Amount::Frames(frames)) => frames,
+                        _ => track.length,
+                    };
+                },
+                _ => {},
+            }
+        }
+        list
+    }
+
+    /// Whether any of the tracks are audio tracks
+    pub fn has_audio(&self) -> bool {
+        self.tracks.iter().any(Track::is_audio)
+    }
+
+    /// Whether any of the tracks are data tracks
+    pub fn has_data(&self) -> bool {
+        self.tracks.iter().any(|track| !track.is_audio())
+    }
+
+    /// Sort the tracks into audio and data tracks
+    pub fn summary(&self) -> TrackSummary {
+        let (audio, data) = self.tracks.iter().partition::<Vec<_>, _>(|track| track.is_audio());
+        TrackSummary {
+            audio: audio.iter().map(|track| track.number).collect(),
+            data: data.iter().map(|track| track.number).collect(),
+        }
+    }
+}
+
+/// Find the byte ranges of a disc's data file which hold audio samples, as laid out in the
+/// contents of its TOC file
+///
+/// Only the file statements of `AUDIO` tracks count, since the silence `ZERO`, `SILENCE`, and
+/// `PREGAP` insert isn't stored. Every statement is assumed to refer to the same file (as
+/// with `cdrdao read-cd`), and a range with no length given runs to `u64::MAX`.
+pub fn audio_file_ranges(toc: &str) -> Vec<Range<u64>> {
+    let tokens = tokenize(toc);
+    let mut ranges = Vec::new();
+    let mut mode = String::new();
+    let mut position = 0_u64;
+    let mut iter = tokens.iter().peekable();
+    while let Some(token) = iter.next() {
+        let keyword = match token {
+            Token::Word(word) => word.as_str(),
+            Token::Open => {
+                skip_block(&mut iter);
+                continue;
+            },
+            _ => continue,
+        };
+        let is_datafile = match keyword {
+            "TRACK" => {
+                if let Some(Token::Word(track_mode)) = iter.next() {
+                    mode.clone_from(track_mode);
+                }
+                continue;
+            },
+            "FILE" | "AUDIOFILE" => false,
+            "DATAFILE" => true,
+            _ => continue,
+        };
+
+        // (Sample counts are only allowed for audio files, and byte counts for data files)
+        let to_bytes = |amount| match amount {
+            Amount::Frames(frames) => frames * sector_len(&mode),
+            Amount::Count(count) if is_datafile => count,
+            Amount::Count(samples) => samples * 4, // 16-bit stereo
+        };
+        position = skip_file_name(&mut iter).unwrap_or(position);
+        if !is_datafile {
+            position += take_amount(&mut iter).map_or(0, to_bytes);
+        }
+        let start = position;
+        position =
+            take_amount(&mut iter).map_or(u64::MAX, |len| start.saturating_add(to_bytes(len)));
+        if mode == "AUDIO" {
+            ranges.push(start..position);
+        }
+    }
+    ranges
+}
+
+/// Add the `SWAP` flag to the file statements of a TOC file's `AUDIO` tracks, so it matches a
+/// data file whose samples have been byte-swapped to little-endian
+///
+/// This works line by line, since `cdrdao read-cd` writes one statement per line, so the rest
+/// of the file (comments included) is left exactly as it was.
+pub fn mark_swapped_audio(toc: &str) -> String {
+    let mut marked = String::new();
+    let mut mode = "";
+    for line in toc.split_inclusive('\n') {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("TRACK") => mode = words.next().unwrap_or_default(),
+            Some("FILE" | "AUDIOFILE") if mode == "AUDIO" => {
+                if let Some(end) = file_name_end(line) {
+                    if line[end..].split_whitespace().next() != Some("SWAP") {
+                        marked.push_str(&line[..end]);
+                        marked.push_str(" SWAP");
+                        marked.push_str(&line[end..]);
+                        continue;
+                    }
+                }
+            },
+            _ => {},
+        }
+        marked.push_str(line);
+    }
+    marked
+}
+
+/// The byte index just past the quoted file name in a line holding a file statement
+fn file_name_end(line: &str) -> Option<usize> {
+    let open = line.find('"')? + 1;
+    let mut escaped = false;
+    for (idx, c) in line[open..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(open + idx + 1),
+            _ => {},
+        }
+    }
+    None
+}
+
+/// Count the tracks declared in the contents of a TOC file
+pub fn track_count(toc: &str) -> usize {
+    TrackList::parse(toc).tracks.len()
+}
+
+/// List the mode (`AUDIO`, `MODE1`, `MODE2_RAW`, etc.) of each track in the contents of a TOC file
+pub fn track_modes(toc: &str) -> Vec<String> {
+    TrackList::parse(toc).tracks.into_iter().map(|track| track.mode).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Abridged output from `cdrdao read-toc` for a disc with CD-TEXT
+    const CD_TEXT_TOC: &str = r#"CD_DA
+CATALOG "0724384260927"
+
+CD_TEXT {
+  LANGUAGE_MAP {
+    0 : EN
+  }
+
+  LANGUAGE 0 {
+    TITLE "Greatest \"Hits\""
+    PERFORMER "Caf\351 Band"
+    SIZE_INFO { 0,  1,  2,  0, 10,  0,  0,  0,  0,  0,  0,  0,
+                3, 30,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,
+                0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0,  0}
+  }
+}
+
+// Track 1
+TRACK AUDIO
+ISRC "GBAYE9700001"
+NO COPY
+NO PRE_EMPHASIS
+TWO_CHANNEL_AUDIO
+CD_TEXT {
+  LANGUAGE 0 {
+    TITLE "Opening // Not a comment"
+    PERFORMER "Caf\351 Band"
+  }
+}
+FILE "data.wav" 0 03:12:41
+
+// Track 2
+TRACK AUDIO
+CD_TEXT {
+  LANGUAGE 0 {
+    TITLE "Closing"
+  }
+}
+FILE "data.wav" 03:12:41 04:00:00
+START 00:02:00
+"#;
+
+    #[test]
+    fn tokenize_handles_strings_and_comments() {
+        assert_eq!(
+            tokenize("// Track 1\nTITLE \"a \\\"b\\\" \\351\" {0 : EN}"),
+            [
+                Token::Word("TITLE".to_owned()),
+                Token::Str("a \"b\" \u{e9}".to_owned()),
+                Token::Open,
+                Token::Word("0".to_owned()),
+                Token::Colon,
+                Token::Word("EN".to_owned()),
+                Token::Close,
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_cd_text_reads_album_and_tracks() {
+        let cd_text = parse_cd_text(CD_TEXT_TOC);
+        assert_eq!(cd_text.album.title.as_deref(), Some("Greatest \"Hits\""));
+        assert_eq!(cd_text.album.performer.as_deref(), Some("Caf\u{e9} Band"));
+        assert_eq!(cd_text.tracks.len(), 2);
+        assert_eq!(
+            cd_text.track(1).and_then(|t| t.title.as_deref()),
+            Some("Opening // Not a comment")
+        );
+        assert_eq!(
+            cd_text.track(2),
+            Some(&CdTextFields { title: Some("Closing".to_owned()), performer: None })
+        );
+        assert_eq!(cd_text.track(0), None);
+        assert_eq!(cd_text.track(3), None);
+    }
+
+    #[test]
+    fn parse_cd_text_ignores_other_languages() {
+        let cd_text = parse_cd_text(
+            "CD_TEXT { LANGUAGE_MAP { 0 : 9 1 : 7 }
+                LANGUAGE 1 { TITLE \"Titel\" }
+                LANGUAGE 0 { TITLE \"Title\" } }",
+        );
+        assert_eq!(cd_text.album.title.as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn parse_cd_text_handles_discs_without_cd_text() {
+        let cd_text =
+            parse_cd_text("CD_DA\n\n// Track 1\nTRACK AUDIO\nFILE \"data.wav\" 0 03:12:41\n");
+        assert!(cd_text.is_empty());
+        assert_eq!(cd_text.tracks.len(), 1);
+        assert!(parse_cd_text("").is_empty());
+    }
+
+    #[test]
+    fn parse_disc_codes_reads_mcn_and_isrcs() {
+        let codes = parse_disc_codes(CD_TEXT_TOC);
+        assert_eq!(codes.mcn.as_deref(), Some("0724384260927"));
+        assert_eq!(codes.isrcs, [Some("GBAYE9700001".to_owned()), None]);
+        assert_eq!(codes.isrc(1), Some("GBAYE9700001"));
+        assert_eq!(codes.isrc(2), None);
+        assert_eq!(codes.isrc(0), None);
+        assert!(!codes.is_empty());
+    }
+
+    #[test]
+    fn parse_disc_codes_handles_discs_without_codes() {
+        let codes = parse_disc_codes("CD_DA\nTRACK AUDIO\nCD_TEXT { LANGUAGE 0 { ISRC \"X\" } }");
+        assert!(codes.is_empty());
+        assert_eq!(codes.isrcs.len(), 1);
+        assert!(parse_disc_codes("").is_empty());
+    }
+
+    #[test]
+    fn track_count_ignores_comments_and_strings() {
+        assert_eq!(track_count(CD_TEXT_TOC), 2);
+        assert_eq!(track_count("// TRACK AUDIO\nCD_TEXT { LANGUAGE 0 { TITLE \"TRACK\" } }"), 0);
+    }
+
+    /// Abridged output from `cdrdao read-cd` for a mixed-mode disc
+    const MIXED_MODE_TOC: &str = r#"CD_ROM
+
+
+// Track 1
+TRACK MODE1_RAW
+NO COPY
+DATAFILE "Some_Game.bin" 04:30:37 // length in bytes: 47671584
+
+
+// Track 2
+TRACK AUDIO
+NO COPY
+NO PRE_EMPHASIS
+TWO_CHANNEL_AUDIO
+ISRC "USRC17607839"
+ZERO AUDIO 00:02:00
+FILE "Some_Game.bin" #47671584 0 03:00:00
+START 00:02:00
+
+
+// Track 3
+TRACK AUDIO
+PREGAP 00:02:00
+FILE "Some_Game.bin" #80000000 0 1058400
+"#;
+
+    #[test]
+    fn track_list_lays_out_tracks() {
+        let list = TrackList::parse(MIXED_MODE_TOC);
+        assert_eq!(list.tracks.len(), 3);
+        assert_eq!(
+            list.tracks.first(),
+            Some(&Track {
+                number: 1,
+                mode: "MODE1_RAW".to_owned(),
+                start: 0,
+                length: 20_287,
+                pregap: 0,
+                isrc: None,
+            })
+        );
+
+        let track2 = &list.tracks[1];
+        assert_eq!((track2.start, track2.length, track2.pregap), (20_287, 150 + 13_500, 150));
+        assert_eq!(track2.isrc.as_deref(), Some("USRC17607839"));
+
+        // Bare numbers in FILE statements are counts of samples
+        let track3 = &list.tracks[2];
+        assert_eq!((track3.start, track3.length, track3.pregap), (33_937, 150 + 1800, 150));
+        assert_eq!(track3.number, 3);
+        assert!(list.has_audio() && list.has_data());
+
+        let summary = list.summary();
+        assert_eq!(summary.data, BTreeSet::from([1]));
+        assert_eq!(summary.audio, BTreeSet::from([2, 3]));
+        assert_eq!(summary.to_string(), "2 audio and 1 data track(s)");
+    }
+
+    #[test]
+    fn audio_file_ranges_skip_data_tracks_and_silence() {
+        assert_eq!(
+            audio_file_ranges(MIXED_MODE_TOC),
+            [47_671_584..47_671_584 + 13_500 * 2352, 80_000_000..80_000_000 + 1_058_400 * 4]
+        );
+
+        // Without offsets, files follow on from each other, and DATAFILE lengths are in bytes
+        let toc = "TRACK MODE1\nDATAFILE \"x.bin\" 00:00:02\nTRACK AUDIO\nZERO 00:02:00\n\
+                   DATAFILE \"x.bin\" 4704\nTRACK AUDIO\nFILE \"x.bin\" 10\n";
+        assert_eq!(audio_file_ranges(toc), [4096..8800, 8840..u64::MAX]);
+        assert!(audio_file_ranges(CD_TEXT_TOC.replace("AUDIO", "MODE1").as_str()).is_empty());
+    }
+
+    #[test]
+    fn mark_swapped_audio_flags_only_audio_files() {
+        let marked = mark_swapped_audio(MIXED_MODE_TOC);
+        assert!(marked.contains("DATAFILE \"Some_Game.bin\" 04:30:37 //"));
+        assert!(marked.contains("FILE \"Some_Game.bin\" SWAP #47671584 0 03:00:00\n"));
+        assert!(marked.contains("FILE \"Some_Game.bin\" SWAP #80000000 0 1058400\n"));
+        assert_eq!(marked.len(), MIXED_MODE_TOC.len() + 2 * " SWAP".len());
+        assert_eq!(mark_swapped_audio(&marked), marked, "Already-marked files are left alone");
+
+        // The flag doesn't change how the TOC is laid out
+        assert_eq!(audio_file_ranges(&marked), audio_file_ranges(MIXED_MODE_TOC));
+        assert_eq!(TrackList::parse(&marked), TrackList::parse(MIXED_MODE_TOC));
+
+        let quoted = "TRACK AUDIO\nFILE \"a \\\"b\\\".bin\" 0\n";
+        assert_eq!(mark_swapped_audio(quoted), "TRACK AUDIO\nFILE \"a \\\"b\\\".bin\" SWAP 0\n");
+    }
+
+    #[test]
+    fn track_list_handles_data_lengths_and_odd_input() {
+        // DATAFILE lengths in bytes are divided by the track's own sector size
+        let list = TrackList::parse("TRACK MODE1\nDATAFILE \"x.iso\" 4096\nSTART\n");
+        assert_eq!(list.tracks[0].length, 2);
+        assert_eq!(list.tracks[0].pregap, 2, "START without a time makes everything pregap");
+        assert!(!list.has_audio());
+
+        // Audio-only disc from read-toc, with CD-TEXT blocks to skip over
+        let audio = TrackList::parse(CD_TEXT_TOC);
+        assert_eq!(audio.tracks.iter().map(|t| t.start).collect::<Vec<_>>(), [0, 14_441]);
+        assert_eq!(audio.tracks[1].pregap, 150);
+        assert_eq!(audio.tracks[0].isrc.as_deref(), Some("GBAYE9700001"));
+
+        assert_eq!(TrackList::parse(""), TrackList::default());
+        assert_eq!(TrackList::parse("ISRC \"X\"\nFILE \"a.wav\" 0 00:01:00\n").tracks.len(), 0);
+        assert_eq!(TrackList::parse("TRACK AUDIO\nSILENCE 00:99\n").tracks[0].length, 0);
+    }
+
+    #[test]
+    fn track_modes_lists_each_track() {
+        assert_eq!(track_modes(CD_TEXT_TOC), ["AUDIO", "AUDIO"]);
+        assert_eq!(
+            track_modes("CD_ROM_XA\nTRACK MODE2_RAW\nDATAFILE \"x.bin\"\nTRACK AUDIO\n"),
+            ["MODE2_RAW", "AUDIO"]
+        );
+    }
+}
+
+// vim: set sw=4 sts=4 :
@@ -205,7 +205,6 @@ pub fn path_valid_portable<P: AsRef<Path> + ?Sized>(value: &P) -> Result<(), OsS
 /// path validator in favour of more specialized validators for filename patterns, prefixes, and/or
 /// suffixes, to properly account for how "you can specify a name bu not a path" generally
 /// comes about.
-#[allow(dead_code)] // TEMPLATE:REMOVE
 pub fn filename_valid_portable<P: AsRef<Path> + ?Sized>(value: &P) -> Result<(), OsString> {
     #![allow(clippy::match_same_arms, clippy::else_if_without_else)]
     let path = value.as_ref();